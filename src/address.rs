@@ -0,0 +1,298 @@
+// Bitcoin addresses and their scriptPubKeys
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::script::{witness_program, ScriptType};
+use crate::{
+    base58, bech32, hashes, BitcoinError, CryptoError, Hash160, Network, ParseError, PublicKey,
+    Script, TaprootSpendInfo,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    P2pkh {
+        network: Network,
+        pubkey_hash: Hash160,
+    },
+    P2sh {
+        network: Network,
+        script_hash: Hash160,
+    },
+    // Native segwit: v0 (P2WPKH/P2WSH), v1 (P2TR) and future versions
+    Segwit {
+        network: Network,
+        version: u8,
+        program: Vec<u8>,
+    },
+}
+
+// Kinds of address a single public key can be paid through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressType {
+    P2pkh,
+    // P2WPKH nested in P2SH, for wallets without bech32 support
+    P2shP2wpkh,
+    P2wpkh,
+    // BIP86 key-path only taproot output
+    P2tr,
+}
+
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AddressType::P2pkh => "p2pkh",
+            AddressType::P2shP2wpkh => "p2sh-p2wpkh",
+            AddressType::P2wpkh => "p2wpkh",
+            AddressType::P2tr => "p2tr",
+        })
+    }
+}
+
+impl FromStr for AddressType {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "p2pkh" | "legacy" => Ok(AddressType::P2pkh),
+            "p2sh-p2wpkh" | "p2sh-segwit" => Ok(AddressType::P2shP2wpkh),
+            "p2wpkh" | "bech32" => Ok(AddressType::P2wpkh),
+            "p2tr" | "bech32m" => Ok(AddressType::P2tr),
+            _ => Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Unknown address type {s:?}"
+            )))),
+        }
+    }
+}
+
+impl Address {
+    // Address of the given type for `pubkey`. Segwit outputs require a
+    // compressed key.
+    pub fn from_public_key(
+        pubkey: &PublicKey,
+        address_type: AddressType,
+        network: Network,
+    ) -> Result<Self, BitcoinError> {
+        let serialized = pubkey.serialize();
+        if address_type != AddressType::P2pkh && serialized.len() != 33 {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        Ok(match address_type {
+            AddressType::P2pkh => Address::p2pkh(&serialized, network),
+            AddressType::P2shP2wpkh => {
+                let redeem_script = Address::p2wpkh(&serialized, network).script_pubkey();
+                Address::p2sh(redeem_script.as_bytes(), network)
+            }
+            AddressType::P2wpkh => Address::p2wpkh(&serialized, network),
+            AddressType::P2tr => {
+                let (internal_key, _) = pubkey.x_only_public_key();
+                let spend_info = TaprootSpendInfo::new_key_spend(internal_key.serialize())?;
+                Address::p2tr(&spend_info.output_key, network)
+            }
+        })
+    }
+
+    pub fn network(&self) -> Network {
+        match self {
+            Address::P2pkh { network, .. }
+            | Address::P2sh { network, .. }
+            | Address::Segwit { network, .. } => *network,
+        }
+    }
+
+    // Pays to the hash160 of a serialized public key
+    pub fn p2pkh(pubkey: &[u8], network: Network) -> Self {
+        Address::P2pkh {
+            network,
+            pubkey_hash: hashes::hash160(pubkey),
+        }
+    }
+
+    // Pays to the hash160 of a redeem script
+    pub fn p2sh(redeem_script: &[u8], network: Network) -> Self {
+        Address::P2sh {
+            network,
+            script_hash: hashes::hash160(redeem_script),
+        }
+    }
+
+    // Native segwit v0 key hash; the key should be compressed to be spendable
+    pub fn p2wpkh(pubkey: &[u8], network: Network) -> Self {
+        Address::Segwit {
+            network,
+            version: 0,
+            program: hashes::hash160(pubkey).as_bytes().to_vec(),
+        }
+    }
+
+    // Native segwit v0 script hash, a single SHA-256 of the witness script
+    pub fn p2wsh(witness_script: &[u8], network: Network) -> Self {
+        Address::Segwit {
+            network,
+            version: 0,
+            program: hashes::sha256(witness_script).as_bytes().to_vec(),
+        }
+    }
+
+    // Segwit v1 output for an already tweaked x-only key
+    pub fn p2tr(output_key: &[u8; 32], network: Network) -> Self {
+        Address::Segwit {
+            network,
+            version: 1,
+            program: output_key.to_vec(),
+        }
+    }
+
+    // The address paying to a scriptPubKey, if it has one; P2PK, OP_RETURN and
+    // non-standard outputs don't
+    pub fn from_script(script: &[u8], network: Network) -> Result<Self, BitcoinError> {
+        match ScriptType::classify(script) {
+            ScriptType::P2PKH => {
+                return Ok(Address::P2pkh {
+                    network,
+                    pubkey_hash: Hash160::from_slice(&script[3..23])?,
+                });
+            }
+            ScriptType::P2SH => {
+                return Ok(Address::P2sh {
+                    network,
+                    script_hash: Hash160::from_slice(&script[2..22])?,
+                });
+            }
+            _ => {}
+        }
+        // Unknown future witness versions still have an address
+        match witness_program(script) {
+            Some((version, program)) if version != 0 || matches!(program.len(), 20 | 32) => {
+                Ok(Address::Segwit {
+                    network,
+                    version,
+                    program: program.to_vec(),
+                })
+            }
+            _ => Err(BitcoinError::Parse(ParseError::InvalidAddress(
+                "Script has no address form".to_string(),
+            ))),
+        }
+    }
+
+    pub fn script_type(&self) -> ScriptType {
+        ScriptType::classify(&self.script_pubkey())
+    }
+
+    pub fn script_pubkey(&self) -> Script {
+        match self {
+            Address::P2pkh { pubkey_hash, .. } => Script::new_p2pkh(pubkey_hash),
+            Address::P2sh { script_hash, .. } => Script::new_p2sh(script_hash),
+            Address::Segwit {
+                version, program, ..
+            } => Script::new_witness_program(*version, program),
+        }
+    }
+
+    // Base58 prefixes are shared by testnet, signet and regtest, and the bech32
+    // prefix by testnet and signet, so an address parsed as testnet may also be
+    // valid on the other test networks
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        match self {
+            Address::Segwit { .. } => self.network().bech32_hrp() == network.bech32_hrp(),
+            _ => self.network().p2pkh_prefix() == network.p2pkh_prefix(),
+        }
+    }
+
+    fn from_bech32(s: &str) -> Result<Self, BitcoinError> {
+        let (hrp, version, program) = bech32::decode_segwit(s)
+            .map_err(|e| BitcoinError::Parse(ParseError::InvalidAddress(e.to_string())))?;
+        let network = Network::ALL
+            .into_iter()
+            .find(|network| network.params().bech32_hrp == hrp)
+            .ok_or_else(|| {
+                BitcoinError::Parse(ParseError::InvalidAddress(format!(
+                    "Unknown address prefix {hrp:?}"
+                )))
+            })?;
+        Ok(Address::Segwit {
+            network,
+            version,
+            program,
+        })
+    }
+
+    fn from_base58(s: &str) -> Result<Self, BitcoinError> {
+        let data = base58::decode_check(s)
+            .map_err(|e| BitcoinError::Parse(ParseError::InvalidAddress(e.to_string())))?;
+        if data.len() != 21 {
+            return Err(BitcoinError::Parse(ParseError::InvalidAddress(
+                "Invalid base58 payload length".to_string(),
+            )));
+        }
+        let hash = Hash160::from_slice(&data[1..])?;
+        for params in Network::ALL.map(Network::params) {
+            if data[0] == params.p2pkh_prefix {
+                return Ok(Address::P2pkh {
+                    network: params.network,
+                    pubkey_hash: hash,
+                });
+            }
+            if data[0] == params.p2sh_prefix {
+                return Ok(Address::P2sh {
+                    network: params.network,
+                    script_hash: hash,
+                });
+            }
+        }
+        Err(BitcoinError::Parse(ParseError::InvalidAddress(format!(
+            "Unknown address version byte {:#04x}",
+            data[0]
+        ))))
+    }
+}
+
+impl FromStr for Address {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let is_bech32 = Network::ALL
+            .iter()
+            .any(|network| lower.starts_with(&format!("{}1", network.bech32_hrp())));
+        if is_bech32 {
+            Address::from_bech32(s)
+        } else {
+            Address::from_base58(s)
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = Vec::with_capacity(21);
+        match self {
+            Address::P2pkh {
+                network,
+                pubkey_hash,
+            } => {
+                data.push(network.p2pkh_prefix());
+                data.extend(pubkey_hash.as_bytes());
+            }
+            Address::P2sh {
+                network,
+                script_hash,
+            } => {
+                data.push(network.p2sh_prefix());
+                data.extend(script_hash.as_bytes());
+            }
+            Address::Segwit {
+                network,
+                version,
+                program,
+            } => {
+                // Only fails for programs that could not have been parsed
+                let s = bech32::encode_segwit(network.bech32_hrp(), *version, program)
+                    .map_err(|_| fmt::Error)?;
+                return f.write_str(&s);
+            }
+        }
+        f.write_str(&base58::encode_check(&data))
+    }
+}
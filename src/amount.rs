@@ -0,0 +1,189 @@
+// Amounts of bitcoin, counted in satoshis
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{AmountError, BitcoinError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const ONE_SAT: Amount = Amount(1);
+    pub const ONE_BTC: Amount = Amount(100_000_000);
+    // The 21 million bitcoin that will ever exist
+    pub const MAX_MONEY: Amount = Amount(21_000_000 * 100_000_000);
+
+    // Any u64 is accepted, as values read off the wire can be anything;
+    // consensus checks reject those over MAX_MONEY
+    pub const fn from_sat(sat: u64) -> Self {
+        Amount(sat)
+    }
+
+    // from_sat for values that must respect the cap, such as user input
+    pub fn try_from_sat(sat: u64) -> Result<Self, BitcoinError> {
+        Some(Amount(sat))
+            .filter(Amount::is_valid)
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))
+    }
+
+    pub const fn to_sat(self) -> u64 {
+        self.0
+    }
+
+    // Addition and multiplication fail past MAX_MONEY, not just on overflow
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Amount)
+            .filter(Amount::is_valid)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_mul(rhs).map(Amount).filter(Amount::is_valid)
+    }
+
+    fn is_valid(&self) -> bool {
+        *self <= Amount::MAX_MONEY
+    }
+
+    // None if the sum goes past MAX_MONEY
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Amount>) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, Amount::checked_add)
+    }
+}
+
+// In BTC with all eight decimals, e.g. "0.00100000 BTC"
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} BTC", self.display_in(Denomination::Bitcoin))
+    }
+}
+
+// Units amounts are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Denomination {
+    Bitcoin,
+    MilliBitcoin,
+    MicroBitcoin,
+    // Same size as MicroBitcoin; 100 satoshis
+    Bit,
+    Satoshi,
+}
+
+impl Denomination {
+    // Decimal places a BTC amount needs to be exact in this unit
+    pub fn precision(self) -> u32 {
+        match self {
+            Denomination::Bitcoin => 8,
+            Denomination::MilliBitcoin => 5,
+            Denomination::MicroBitcoin | Denomination::Bit => 2,
+            Denomination::Satoshi => 0,
+        }
+    }
+}
+
+impl fmt::Display for Denomination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Denomination::Bitcoin => "BTC",
+            Denomination::MilliBitcoin => "mBTC",
+            Denomination::MicroBitcoin => "uBTC",
+            Denomination::Bit => "bits",
+            Denomination::Satoshi => "sat",
+        })
+    }
+}
+
+// Case-insensitive, so "BTC" and "btc" both work
+impl FromStr for Denomination {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "btc" => Ok(Denomination::Bitcoin),
+            "mbtc" => Ok(Denomination::MilliBitcoin),
+            "ubtc" => Ok(Denomination::MicroBitcoin),
+            "bit" | "bits" => Ok(Denomination::Bit),
+            "sat" | "sats" | "satoshi" | "satoshis" => Ok(Denomination::Satoshi),
+            _ => Err(BitcoinError::Amount(AmountError::InvalidAmount)),
+        }
+    }
+}
+
+impl Amount {
+    // Parses a plain decimal number such as "0.015" in the given unit.
+    // Integer arithmetic only; more decimals than the unit allows are an
+    // error unless they are zeros.
+    pub fn from_str_in(s: &str, denomination: Denomination) -> Result<Amount, BitcoinError> {
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if (int.is_empty() && frac.is_empty()) || !is_digits(int) || !is_digits(frac) {
+            return Err(BitcoinError::Amount(AmountError::InvalidAmount));
+        }
+        let precision = denomination.precision() as usize;
+        let (frac, excess) = frac.split_at(frac.len().min(precision));
+        if excess.bytes().any(|b| b != b'0') {
+            return Err(BitcoinError::Amount(AmountError::InvalidAmount));
+        }
+        let sat = format!("{int}{frac:0<precision$}")
+            .parse()
+            .map_err(|_| BitcoinError::Amount(AmountError::InvalidAmount))?;
+        Amount::try_from_sat(sat)
+    }
+
+    // The number alone, with the unit's full precision: 0.015 BTC shows as
+    // "0.01500000" in BTC and "15.00000" in mBTC
+    pub fn display_in(self, denomination: Denomination) -> impl fmt::Display {
+        Display {
+            amount: self,
+            denomination,
+        }
+    }
+}
+
+struct Display {
+    amount: Amount,
+    denomination: Denomination,
+}
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = self.denomination.precision();
+        let unit = 10u64.pow(precision);
+        let sat = self.amount.0;
+        match precision {
+            0 => write!(f, "{sat}"),
+            _ => write!(
+                f,
+                "{}.{:0width$}",
+                sat / unit,
+                sat % unit,
+                width = precision as usize
+            ),
+        }
+    }
+}
+
+// A number followed by its unit, e.g. "0.015btc", "1500000 sat"
+impl FromStr for Amount {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let unit_at = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+        let (number, unit) = s.split_at(unit_at);
+        Amount::from_str_in(number.trim_end(), unit.trim_start().parse()?)
+    }
+}
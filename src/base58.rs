@@ -0,0 +1,83 @@
+// Base58 and Base58Check encoding, as used by legacy addresses and WIF keys
+
+use crate::{hashes, BitcoinError, ParseError};
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+pub fn encode(data: &[u8]) -> String {
+    // Each leading zero byte is written as a leading '1'
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    // Base58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 138 / 100 + 1);
+    for byte in &data[zeros..] {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut s = String::with_capacity(zeros + digits.len());
+    s.extend(std::iter::repeat_n('1', zeros));
+    s.extend(digits.iter().rev().map(|d| ALPHABET[*d as usize] as char));
+    s
+}
+
+pub fn decode(s: &str) -> Result<Vec<u8>, BitcoinError> {
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    // Bytes, least significant first
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len() * 733 / 1000 + 1);
+    for c in s.bytes().skip(zeros) {
+        let mut carry = ALPHABET.iter().position(|a| *a == c).ok_or_else(|| {
+            BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid base58 character {:?}",
+                c as char
+            )))
+        })? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xFF) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xFF) as u8);
+            carry >>= 8;
+        }
+    }
+    let mut v = vec![0u8; zeros];
+    v.extend(bytes.iter().rev());
+    Ok(v)
+}
+
+fn checksum(data: &[u8]) -> [u8; 4] {
+    let hash = hashes::sha256d(data);
+    let bytes = hash.as_bytes();
+    [bytes[0], bytes[1], bytes[2], bytes[3]]
+}
+
+// Appends the first four bytes of sha256d(data) before encoding
+pub fn encode_check(data: &[u8]) -> String {
+    let mut v = data.to_vec();
+    v.extend(checksum(data));
+    encode(&v)
+}
+
+pub fn decode_check(s: &str) -> Result<Vec<u8>, BitcoinError> {
+    let mut v = decode(s)?;
+    if v.len() < 4 {
+        return Err(BitcoinError::Parse(ParseError::Message(
+            "Base58Check data too short".to_string(),
+        )));
+    }
+    let expected = v.split_off(v.len() - 4);
+    if checksum(&v)[..] != expected[..] {
+        return Err(BitcoinError::Parse(ParseError::Message(
+            "Invalid base58 checksum".to_string(),
+        )));
+    }
+    Ok(v)
+}
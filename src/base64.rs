@@ -0,0 +1,59 @@
+// Standard base64 with padding (RFC 4648), as used by PSBTs and signed messages
+
+use crate::{BitcoinError, ParseError};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut s = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+// Padding is required and no whitespace is accepted
+pub fn decode(s: &str) -> Result<Vec<u8>, BitcoinError> {
+    let bytes = s.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return Err(BitcoinError::Parse(ParseError::Message(
+            "Invalid base64 length".to_string(),
+        )));
+    }
+    let mut v = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let last = i == bytes.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(BitcoinError::Parse(ParseError::Message(
+                "Invalid base64 padding".to_string(),
+            )));
+        }
+        let mut n = 0u32;
+        for c in &chunk[..4 - padding] {
+            let digit = ALPHABET.iter().position(|a| a == c).ok_or_else(|| {
+                BitcoinError::Parse(ParseError::Message(format!(
+                    "Invalid base64 character {:?}",
+                    *c as char
+                )))
+            })?;
+            n = n << 6 | digit as u32;
+        }
+        n <<= 6 * padding;
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        v.extend(&decoded[..3 - padding]);
+    }
+    Ok(v)
+}
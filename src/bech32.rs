@@ -0,0 +1,203 @@
+// Bech32 (BIP173) and Bech32m (BIP350) encoding, used by segwit addresses
+
+use crate::{BitcoinError, ParseError};
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+// Maximum length of a segwit address string
+pub const MAX_LENGTH: usize = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Bech32,
+    Bech32m,
+}
+
+impl Variant {
+    fn constant(self) -> u32 {
+        match self {
+            Variant::Bech32 => 1,
+            Variant::Bech32m => 0x2bc830a3,
+        }
+    }
+}
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    let mut chk: u32 = 1;
+    for v in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ v as u32;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut v: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    v.push(0);
+    v.extend(hrp.bytes().map(|b| b & 0x1f));
+    v
+}
+
+fn create_checksum(hrp: &str, data: &[u8], variant: Variant) -> [u8; 6] {
+    let values = hrp_expand(hrp)
+        .into_iter()
+        .chain(data.iter().copied())
+        .chain([0u8; 6]);
+    let modulus = polymod(values) ^ variant.constant();
+    let mut checksum = [0u8; 6];
+    for (i, c) in checksum.iter_mut().enumerate() {
+        *c = ((modulus >> (5 * (5 - i))) & 0x1f) as u8;
+    }
+    checksum
+}
+
+fn parse_error(message: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(message.to_string()))
+}
+
+// `data` holds 5-bit values; the output is always lowercase
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> Result<String, BitcoinError> {
+    if hrp.is_empty() || hrp.len() > 83 || !hrp.bytes().all(|b| (33..=126).contains(&b)) {
+        return Err(parse_error("Invalid bech32 human-readable part"));
+    }
+    if data.iter().any(|d| *d > 31) {
+        return Err(parse_error("Invalid bech32 data value"));
+    }
+    let hrp = hrp.to_lowercase();
+    let checksum = create_checksum(&hrp, data, variant);
+    let mut s = String::with_capacity(hrp.len() + 1 + data.len() + 6);
+    s.push_str(&hrp);
+    s.push('1');
+    s.extend(
+        data.iter()
+            .chain(&checksum)
+            .map(|d| CHARSET[*d as usize] as char),
+    );
+    Ok(s)
+}
+
+// Returns the lowercase human-readable part, the 5-bit data without the
+// checksum, and which checksum variant matched
+pub fn decode(s: &str) -> Result<(String, Vec<u8>, Variant), BitcoinError> {
+    decode_with_max_length(s, MAX_LENGTH)
+}
+
+// decode for formats allowing strings longer than addresses, such as
+// silent payment addresses
+pub fn decode_with_max_length(
+    s: &str,
+    max_length: usize,
+) -> Result<(String, Vec<u8>, Variant), BitcoinError> {
+    if s.len() > max_length {
+        return Err(parse_error("Bech32 string too long"));
+    }
+    let has_lower = s.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = s.bytes().any(|b| b.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(parse_error("Mixed-case bech32 string"));
+    }
+    if !s.bytes().all(|b| (33..=126).contains(&b)) {
+        return Err(parse_error("Invalid bech32 character"));
+    }
+    let s = s.to_lowercase();
+    let separator = s
+        .rfind('1')
+        .ok_or_else(|| parse_error("Missing bech32 separator"))?;
+    if separator == 0 || separator + 7 > s.len() {
+        return Err(parse_error("Invalid bech32 separator position"));
+    }
+    let hrp = &s[..separator];
+    let data = s[separator + 1..]
+        .bytes()
+        .map(|c| CHARSET.iter().position(|x| *x == c).map(|p| p as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| parse_error("Invalid bech32 character"))?;
+    let modulus = polymod(hrp_expand(hrp).into_iter().chain(data.iter().copied()));
+    let variant = if modulus == Variant::Bech32.constant() {
+        Variant::Bech32
+    } else if modulus == Variant::Bech32m.constant() {
+        Variant::Bech32m
+    } else {
+        return Err(parse_error("Invalid bech32 checksum"));
+    };
+    Ok((hrp.to_string(), data[..data.len() - 6].to_vec(), variant))
+}
+
+// Regroups bits, e.g. bytes into 5-bit values and back
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, BitcoinError> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max = (1u32 << to) - 1;
+    let mut v = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data {
+        let value = *value as u32;
+        if value >> from != 0 {
+            return Err(parse_error("Invalid value for bit conversion"));
+        }
+        acc = (acc << from) | value;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            v.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            v.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return Err(parse_error("Invalid padding in bit conversion"));
+    }
+    Ok(v)
+}
+
+// Witness version 0 uses Bech32, later versions Bech32m
+pub fn encode_segwit(hrp: &str, version: u8, program: &[u8]) -> Result<String, BitcoinError> {
+    check_witness_program(version, program)?;
+    let variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true)?);
+    encode(hrp, &data, variant)
+}
+
+// Returns the human-readable part, witness version and witness program
+pub fn decode_segwit(s: &str) -> Result<(String, u8, Vec<u8>), BitcoinError> {
+    let (hrp, data, variant) = decode(s)?;
+    let (version, program) = data
+        .split_first()
+        .ok_or_else(|| parse_error("Empty witness program"))?;
+    let program = convert_bits(program, 5, 8, false)?;
+    check_witness_program(*version, &program)?;
+    let expected = if *version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    if variant != expected {
+        return Err(parse_error("Wrong checksum variant for witness version"));
+    }
+    Ok((hrp, *version, program))
+}
+
+fn check_witness_program(version: u8, program: &[u8]) -> Result<(), BitcoinError> {
+    if version > 16 {
+        return Err(parse_error("Invalid witness version"));
+    }
+    if program.len() < 2 || program.len() > 40 {
+        return Err(parse_error("Invalid witness program length"));
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return Err(parse_error("Invalid witness v0 program length"));
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+// Compact block filters (BIP158): a Golomb-coded set of the scripts a block
+// pays to and spends, which light clients download instead of the block and
+// test their own scripts against
+
+use std::collections::BTreeSet;
+
+use crate::hashes::{sha256d, siphash24};
+use crate::{
+    BitcoinError, Block, BlockHash, CompactSize, FilterHash, FilterHeader, Opcode, ParseError,
+};
+
+// Golomb-Rice parameter and false-positive rate (1/M) of the basic filter
+const P: u32 = 19;
+const M: u64 = 784_931;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockFilter {
+    // Item count as a CompactSize, then the Golomb-Rice coded deltas
+    pub content: Vec<u8>,
+}
+
+impl BlockFilter {
+    // The basic filter: every output script except OP_RETURN ones, and the
+    // scripts of the outputs the block's inputs spend, given in
+    // `spent_scripts` in any order
+    pub fn new_basic(block: &Block, spent_scripts: &[Vec<u8>]) -> Self {
+        let outputs = block
+            .txdata
+            .iter()
+            .flat_map(|tx| &tx.outputs)
+            .map(|output| output.script_pubkey.as_slice())
+            .filter(|script| script.first() != Some(&(Opcode::OP_RETURN as u8)));
+        let items: BTreeSet<&[u8]> = outputs
+            .chain(spent_scripts.iter().map(Vec::as_slice))
+            .filter(|script| !script.is_empty())
+            .collect();
+
+        let n = items.len() as u64;
+        let mut content = CompactSize(n).encode();
+        let mut writer = BitWriter::new(&mut content);
+        let mut previous = 0;
+        for value in hashed_set(&block.block_hash(), n, items.into_iter()) {
+            let delta = value - previous;
+            previous = value;
+            // Quotient in unary, then the remainder's low P bits
+            for _ in 0..delta >> P {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta, P);
+        }
+        writer.flush();
+        BlockFilter { content }
+    }
+
+    pub fn filter_hash(&self) -> FilterHash {
+        sha256d(&self.content).into()
+    }
+
+    // The header for this filter, chained onto the previous block's (all
+    // zeros before the genesis block)
+    pub fn filter_header(&self, previous: &FilterHeader) -> FilterHeader {
+        let mut data = self.filter_hash().to_byte_array().to_vec();
+        data.extend(previous.as_bytes());
+        sha256d(&data).into()
+    }
+
+    // Whether any of `scripts` may be in the filter of the block with the
+    // given hash. False positives happen at a rate of 1 in 784931.
+    pub fn match_any(
+        &self,
+        block_hash: &BlockHash,
+        scripts: &[&[u8]],
+    ) -> Result<bool, BitcoinError> {
+        let (CompactSize(n), offset) = CompactSize::decode(&self.content)?;
+        if n == 0 || scripts.is_empty() {
+            return Ok(false);
+        }
+        let mut queries = hashed_set(block_hash, n, scripts.iter().copied()).into_iter();
+        let mut query = queries.next();
+        let mut reader = BitReader::new(&self.content, offset);
+        let mut value = 0u64;
+        for _ in 0..n {
+            let mut quotient = 0u64;
+            while reader.read_bit()? {
+                quotient += 1;
+            }
+            value += (quotient << P) | reader.read_bits(P)?;
+            while let Some(q) = query {
+                if q > value {
+                    break;
+                }
+                if q == value {
+                    return Ok(true);
+                }
+                query = queries.next();
+            }
+            if query.is_none() {
+                break;
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn match_script(
+        &self,
+        block_hash: &BlockHash,
+        script: &[u8],
+    ) -> Result<bool, BitcoinError> {
+        self.match_any(block_hash, &[script])
+    }
+}
+
+// Items hashed into [0, N * M) for a filter of `n` items, sorted
+fn hashed_set<'a>(
+    block_hash: &BlockHash,
+    n: u64,
+    items: impl Iterator<Item = &'a [u8]>,
+) -> Vec<u64> {
+    // The SipHash key is the first 16 bytes of the block hash
+    let key = block_hash.as_bytes();
+    let k0 = u64::from_le_bytes(key[..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(key[8..16].try_into().unwrap());
+    let range = n as u128 * M as u128;
+    let mut values: Vec<u64> = items
+        .map(|item| ((siphash24(k0, k1, item) as u128 * range) >> 64) as u64)
+        .collect();
+    values.sort_unstable();
+    values
+}
+
+// Most significant bit first
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    byte: u8,
+    used: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        BitWriter {
+            out,
+            byte: 0,
+            used: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.byte |= (bit as u8) << (7 - self.used);
+        self.used += 1;
+        if self.used == 8 {
+            self.out.push(self.byte);
+            self.byte = 0;
+            self.used = 0;
+        }
+    }
+
+    fn write_bits(&mut self, value: u64, count: u32) {
+        for i in (0..count).rev() {
+            self.write_bit(value >> i & 1 == 1);
+        }
+    }
+
+    // Pads the last byte with zeros
+    fn flush(&mut self) {
+        if self.used > 0 {
+            self.out.push(self.byte);
+            self.byte = 0;
+            self.used = 0;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    // In bits from the start of `data`
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        BitReader {
+            data,
+            position: offset * 8,
+        }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, BitcoinError> {
+        let offset = self.position / 8;
+        let byte = self
+            .data
+            .get(offset)
+            .ok_or(BitcoinError::Parse(ParseError::UnexpectedEof {
+                needed: 1,
+                offset,
+            }))?;
+        let bit = byte >> (7 - self.position % 8) & 1 == 1;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u64, BitcoinError> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | self.read_bit()? as u64;
+        }
+        Ok(value)
+    }
+}
@@ -0,0 +1,173 @@
+// Payment URIs (BIP21), e.g. bitcoin:1A1z...?amount=0.015&label=Coffee,
+// as shared in links and QR codes
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Address, Amount, BitcoinError, Denomination, ParseError};
+
+const SCHEME: &str = "bitcoin:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip21Uri {
+    pub address: Address,
+    pub amount: Option<Amount>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+    // Other parameters, decoded, in the order given. Unknown ones with the
+    // req- prefix are rejected when parsing, as BIP21 requires.
+    pub extras: Vec<(String, String)>,
+}
+
+impl Bip21Uri {
+    // A URI with no parameters; add them with the setters below
+    pub fn new(address: Address) -> Self {
+        Bip21Uri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            extras: Vec::new(),
+        }
+    }
+
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extras.push((key.into(), value.into()));
+        self
+    }
+
+    // The scheme is case-insensitive; amounts are in BTC
+    pub fn parse(s: &str) -> Result<Self, BitcoinError> {
+        let invalid = |reason: &str| {
+            BitcoinError::Parse(ParseError::Message(format!("Invalid URI {s:?}: {reason}")))
+        };
+        if !Self::is_uri(s) {
+            return Err(invalid("expected the bitcoin: scheme"));
+        }
+        let rest = &s[SCHEME.len()..];
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut uri = Bip21Uri::new(address.parse()?);
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let (key, value) = (percent_decode(key)?, percent_decode(value)?);
+            let duplicate = match key.as_str() {
+                "amount" => uri
+                    .amount
+                    .replace(Amount::from_str_in(&value, Denomination::Bitcoin)?)
+                    .is_some(),
+                "label" => uri.label.replace(value).is_some(),
+                "message" => uri.message.replace(value).is_some(),
+                _ if key.starts_with("req-") => {
+                    return Err(invalid(&format!("unsupported required parameter {key:?}")))
+                }
+                _ => {
+                    uri.extras.push((key, value));
+                    false
+                }
+            };
+            if duplicate {
+                return Err(invalid("parameter given more than once"));
+            }
+        }
+        Ok(uri)
+    }
+
+    // Whether `s` looks like a URI rather than a bare address
+    pub fn is_uri(s: &str) -> bool {
+        s.get(..SCHEME.len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+    }
+}
+
+impl FromStr for Bip21Uri {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Bip21Uri::parse(s)
+    }
+}
+
+impl fmt::Display for Bip21Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{SCHEME}{}", self.address)?;
+        let mut separator = '?';
+        let mut param = |f: &mut fmt::Formatter<'_>, key: &str, value: &str| {
+            let result = write!(
+                f,
+                "{separator}{}={}",
+                percent_encode(key),
+                percent_encode(value)
+            );
+            separator = '&';
+            result
+        };
+        if let Some(amount) = self.amount {
+            // Trailing zeros are dropped: 0.015, not 0.01500000
+            let btc = amount.display_in(Denomination::Bitcoin).to_string();
+            param(f, "amount", btc.trim_end_matches('0').trim_end_matches('.'))?;
+        }
+        if let Some(label) = &self.label {
+            param(f, "label", label)?;
+        }
+        if let Some(message) = &self.message {
+            param(f, "message", message)?;
+        }
+        for (key, value) in &self.extras {
+            param(f, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Everything but RFC 3986 unreserved characters is escaped
+pub(crate) fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String, BitcoinError> {
+    let invalid = || {
+        BitcoinError::Parse(ParseError::Message(format!(
+            "Invalid percent-encoding in {s:?}"
+        )))
+    };
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let digits = s
+                .get(i + 1..i + 3)
+                .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+                .ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(digits, 16).expect("checked hex digits"));
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
@@ -0,0 +1,494 @@
+// Hierarchical deterministic keys (BIP32)
+
+use std::fmt;
+use std::str::FromStr;
+
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::elliptic_curve::PrimeField;
+use k256::{ProjectivePoint, Scalar};
+
+use crate::{
+    base58, hashes, BitcoinError, CryptoError, Hash160, Network, ParseError, PrivateKey, PublicKey,
+};
+
+// Child numbers at or above this use hardened derivation
+pub const HARDENED: u32 = 0x8000_0000;
+
+// Script type an extended key is meant for, signalled by its SLIP-132
+// version bytes (xpub/ypub/zpub and friends)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScriptType {
+    #[default]
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+    P2shP2wsh,
+    P2wsh,
+}
+
+impl ScriptType {
+    // Output descriptor wrapping the key, e.g. "sh(wpkh)" for ypub keys
+    pub fn descriptor_name(self) -> &'static str {
+        match self {
+            ScriptType::P2pkh => "pkh",
+            ScriptType::P2shP2wpkh => "sh(wpkh)",
+            ScriptType::P2wpkh => "wpkh",
+            ScriptType::P2shP2wsh => "sh(wsh)",
+            ScriptType::P2wsh => "wsh",
+        }
+    }
+}
+
+// (script type, mainnet, private version, public version)
+const VERSIONS: [(ScriptType, bool, [u8; 4], [u8; 4]); 10] = [
+    (
+        ScriptType::P2pkh,
+        true,
+        [0x04, 0x88, 0xAD, 0xE4],
+        [0x04, 0x88, 0xB2, 0x1E],
+    ),
+    (
+        ScriptType::P2pkh,
+        false,
+        [0x04, 0x35, 0x83, 0x94],
+        [0x04, 0x35, 0x87, 0xCF],
+    ),
+    (
+        ScriptType::P2shP2wpkh,
+        true,
+        [0x04, 0x9D, 0x78, 0x78],
+        [0x04, 0x9D, 0x7C, 0xB2],
+    ),
+    (
+        ScriptType::P2shP2wpkh,
+        false,
+        [0x04, 0x4A, 0x4E, 0x28],
+        [0x04, 0x4A, 0x52, 0x62],
+    ),
+    (
+        ScriptType::P2wpkh,
+        true,
+        [0x04, 0xB2, 0x43, 0x0C],
+        [0x04, 0xB2, 0x47, 0x46],
+    ),
+    (
+        ScriptType::P2wpkh,
+        false,
+        [0x04, 0x5F, 0x18, 0xBC],
+        [0x04, 0x5F, 0x1C, 0xF6],
+    ),
+    (
+        ScriptType::P2shP2wsh,
+        true,
+        [0x02, 0x95, 0xB0, 0x05],
+        [0x02, 0x95, 0xB4, 0x3F],
+    ),
+    (
+        ScriptType::P2shP2wsh,
+        false,
+        [0x02, 0x42, 0x85, 0xB5],
+        [0x02, 0x42, 0x89, 0xEF],
+    ),
+    (
+        ScriptType::P2wsh,
+        true,
+        [0x02, 0xAA, 0x7A, 0x99],
+        [0x02, 0xAA, 0x7E, 0xD3],
+    ),
+    (
+        ScriptType::P2wsh,
+        false,
+        [0x02, 0x57, 0x50, 0x48],
+        [0x02, 0x57, 0x54, 0x83],
+    ),
+];
+
+// Returns (private, public) version bytes
+fn versions(network: Network, script_type: ScriptType) -> ([u8; 4], [u8; 4]) {
+    let mainnet = network == Network::Mainnet;
+    VERSIONS
+        .iter()
+        .find(|(t, m, _, _)| *t == script_type && *m == mainnet)
+        .map(|(_, _, private, public)| (*private, *public))
+        .expect("every script type has mainnet and testnet versions")
+}
+
+// Testnet versions are shared by all test networks and decode as Testnet
+fn lookup_version(version: [u8; 4], private: bool) -> Option<(Network, ScriptType)> {
+    VERSIONS
+        .iter()
+        .find(|(_, _, prv, pb)| version == if private { *prv } else { *pb })
+        .map(|(t, mainnet, _, _)| {
+            let network = if *mainnet {
+                Network::Mainnet
+            } else {
+                Network::Testnet
+            };
+            (network, *t)
+        })
+}
+
+// A path like m/84'/0'/0'/0/5 (h is accepted as well as ')
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DerivationPath(pub Vec<u32>);
+
+impl FromStr for DerivationPath {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid derivation path {s:?}"
+            )))
+        };
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+        parts
+            .map(|part| {
+                let (digits, hardened) = match part.strip_suffix(['\'', 'h', 'H']) {
+                    Some(digits) => (digits, true),
+                    None => (part, false),
+                };
+                let index: u32 = digits.parse().map_err(|_| invalid())?;
+                if index >= HARDENED {
+                    return Err(invalid());
+                }
+                Ok(if hardened { index | HARDENED } else { index })
+            })
+            .collect::<Result<_, _>>()
+            .map(DerivationPath)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("m")?;
+        for index in &self.0 {
+            if index & HARDENED != 0 {
+                write!(f, "/{}'", index & !HARDENED)?;
+            } else {
+                write!(f, "/{index}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Extended private key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xpriv {
+    pub network: Network,
+    pub script_type: ScriptType,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub private_key: PrivateKey,
+}
+
+// Extended public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xpub {
+    pub network: Network,
+    pub script_type: ScriptType,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+fn split(i: [u8; 64]) -> ([u8; 32], [u8; 32]) {
+    let mut left = [0u8; 32];
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&i[..32]);
+    right.copy_from_slice(&i[32..]);
+    (left, right)
+}
+
+// parse256(IL), which must be below the curve order
+fn tweak_scalar(il: [u8; 32]) -> Result<Scalar, BitcoinError> {
+    Option::from(Scalar::from_repr(il.into()))
+        .ok_or(BitcoinError::Crypto(CryptoError::InvalidPrivateKey))
+}
+
+impl Xpriv {
+    pub fn new_master(network: Network, seed: &[u8]) -> Result<Self, BitcoinError> {
+        let (key, chain_code) = split(hashes::hmac_sha512(b"Bitcoin seed", seed));
+        Ok(Xpriv {
+            network,
+            script_type: ScriptType::P2pkh,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code,
+            private_key: PrivateKey::from_slice(&key)?,
+        })
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<Self, BitcoinError> {
+        let mut data = Vec::with_capacity(37);
+        if index & HARDENED != 0 {
+            data.push(0x00);
+            data.extend(self.private_key.to_bytes());
+        } else {
+            data.extend(key_data(&self.private_key.public_key()));
+        }
+        data.extend(&index.to_be_bytes());
+        let (il, chain_code) = split(hashes::hmac_sha512(&self.chain_code, &data));
+
+        let parent = tweak_scalar(self.private_key.to_bytes())?;
+        let child = tweak_scalar(il)? + parent;
+        Ok(Xpriv {
+            network: self.network,
+            script_type: self.script_type,
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            // Fails for the (astronomically unlikely) zero key
+            private_key: PrivateKey::from_slice(&child.to_repr())?,
+        })
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, BitcoinError> {
+        path.0
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    // Same key re-encoded with another SLIP-132 version
+    pub fn with_script_type(mut self, script_type: ScriptType) -> Self {
+        self.script_type = script_type;
+        self
+    }
+
+    pub fn to_xpub(&self) -> Xpub {
+        Xpub {
+            network: self.network,
+            script_type: self.script_type,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.private_key.public_key(),
+        }
+    }
+
+    pub fn identifier(&self) -> Hash160 {
+        hashes::hash160(&key_data(&self.private_key.public_key()))
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        fingerprint(&self.identifier())
+    }
+
+    // 78-byte serialization
+    pub fn encode(&self) -> [u8; 78] {
+        let (version, _) = versions(self.network, self.script_type);
+        let mut key_data = [0u8; 33];
+        key_data[1..].copy_from_slice(&self.private_key.to_bytes());
+        encode_extended(
+            version,
+            self.depth,
+            self.parent_fingerprint,
+            self.child_number,
+            &self.chain_code,
+            &key_data,
+        )
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, BitcoinError> {
+        let fields = decode_extended(data)?;
+        let (network, script_type) = lookup_version(fields.version, true)
+            .ok_or_else(|| invalid_key("Unknown extended private key version"))?;
+        if fields.key_data[0] != 0x00 {
+            return Err(invalid_key("Invalid private key prefix"));
+        }
+        Ok(Xpriv {
+            network,
+            script_type,
+            depth: fields.depth,
+            parent_fingerprint: fields.parent_fingerprint,
+            child_number: fields.child_number,
+            chain_code: fields.chain_code,
+            private_key: PrivateKey::from_slice(&fields.key_data[1..])?,
+        })
+    }
+}
+
+impl Xpub {
+    // Only non-hardened children can be derived from a public key
+    pub fn derive_child(&self, index: u32) -> Result<Self, BitcoinError> {
+        if index & HARDENED != 0 {
+            return Err(invalid_key("Cannot derive hardened child from public key"));
+        }
+        let mut data = key_data(&self.public_key).to_vec();
+        data.extend(&index.to_be_bytes());
+        let (il, chain_code) = split(hashes::hmac_sha512(&self.chain_code, &data));
+
+        let parent = k256::PublicKey::from_sec1_bytes(&self.public_key.serialize())
+            .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+        let point = ProjectivePoint::GENERATOR * tweak_scalar(il)? + parent.to_projective();
+        let encoded = point.to_affine().to_encoded_point(true);
+        Ok(Xpub {
+            network: self.network,
+            script_type: self.script_type,
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            // The point at infinity doesn't encode to 33 bytes and is rejected
+            public_key: PublicKey::from_slice(encoded.as_bytes())?,
+        })
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, BitcoinError> {
+        path.0
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    pub fn with_script_type(mut self, script_type: ScriptType) -> Self {
+        self.script_type = script_type;
+        self
+    }
+
+    pub fn identifier(&self) -> Hash160 {
+        hashes::hash160(&key_data(&self.public_key))
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        fingerprint(&self.identifier())
+    }
+
+    pub fn encode(&self) -> [u8; 78] {
+        let (_, version) = versions(self.network, self.script_type);
+        let key_data = key_data(&self.public_key);
+        encode_extended(
+            version,
+            self.depth,
+            self.parent_fingerprint,
+            self.child_number,
+            &self.chain_code,
+            &key_data,
+        )
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, BitcoinError> {
+        let fields = decode_extended(data)?;
+        let (network, script_type) = lookup_version(fields.version, false)
+            .ok_or_else(|| invalid_key("Unknown extended public key version"))?;
+        if !matches!(fields.key_data[0], 0x02 | 0x03) {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        Ok(Xpub {
+            network,
+            script_type,
+            depth: fields.depth,
+            parent_fingerprint: fields.parent_fingerprint,
+            child_number: fields.child_number,
+            chain_code: fields.chain_code,
+            public_key: PublicKey::from_slice(&fields.key_data)?,
+        })
+    }
+}
+
+// BIP32 always serializes keys compressed, even one stored uncompressed
+fn key_data(pubkey: &PublicKey) -> [u8; 33] {
+    let (x_only, odd) = pubkey.x_only_public_key();
+    let mut data = [if odd { 0x03 } else { 0x02 }; 33];
+    data[1..].copy_from_slice(&x_only.serialize());
+    data
+}
+
+fn fingerprint(identifier: &Hash160) -> [u8; 4] {
+    let bytes = identifier.as_bytes();
+    [bytes[0], bytes[1], bytes[2], bytes[3]]
+}
+
+fn invalid_key(msg: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(msg.to_string()))
+}
+
+struct ExtendedKeyFields {
+    version: [u8; 4],
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    key_data: [u8; 33],
+}
+
+fn encode_extended(
+    version: [u8; 4],
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: &[u8; 32],
+    key_data: &[u8; 33],
+) -> [u8; 78] {
+    let mut v = [0u8; 78];
+    v[..4].copy_from_slice(&version);
+    v[4] = depth;
+    v[5..9].copy_from_slice(&parent_fingerprint);
+    v[9..13].copy_from_slice(&child_number.to_be_bytes());
+    v[13..45].copy_from_slice(chain_code);
+    v[45..].copy_from_slice(key_data);
+    v
+}
+
+fn decode_extended(data: &[u8]) -> Result<ExtendedKeyFields, BitcoinError> {
+    if data.len() != 78 {
+        return Err(invalid_key("Extended keys are 78 bytes"));
+    }
+    let fields = ExtendedKeyFields {
+        version: data[..4].try_into().unwrap(),
+        depth: data[4],
+        parent_fingerprint: data[5..9].try_into().unwrap(),
+        child_number: u32::from_be_bytes(data[9..13].try_into().unwrap()),
+        chain_code: data[13..45].try_into().unwrap(),
+        key_data: data[45..].try_into().unwrap(),
+    };
+    // Master keys have no parent
+    if fields.depth == 0 && (fields.parent_fingerprint != [0; 4] || fields.child_number != 0) {
+        return Err(invalid_key("Invalid master key"));
+    }
+    Ok(fields)
+}
+
+impl fmt::Display for Xpriv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&base58::encode_check(&self.encode()))
+    }
+}
+
+impl FromStr for Xpriv {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(&base58::decode_check(s)?)
+    }
+}
+
+impl fmt::Display for Xpub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&base58::encode_check(&self.encode()))
+    }
+}
+
+impl FromStr for Xpub {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(&base58::decode_check(s)?)
+    }
+}
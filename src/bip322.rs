@@ -0,0 +1,138 @@
+// Generic signed messages (BIP322): a message is signed by spending a
+// virtual output locked to the address, so any address a wallet can spend
+// from can prove ownership, not just P2PKH. Neither transaction is valid
+// on chain: to_spend spends no real coin and to_sign creates nothing.
+//
+// A simple signature is the base64 of the witness spending the output; a
+// full one is the base64 of the whole to_sign transaction, for addresses
+// that need a scriptSig, lock times or other inputs.
+
+use std::collections::HashMap;
+
+use crate::hashes::tagged_hash;
+use crate::message::verify_message;
+use crate::{
+    base64, Address, Amount, BitcoinError, BitcoinSerialize, CryptoError, Hash256,
+    LegacyTransaction, LockTime, Opcode, OutPoint, ParseError, ScriptBuilder, ScriptFlags,
+    Sequence, Signer, TxInput, TxOutput, Txid, Witness,
+};
+
+pub fn message_hash(message: &[u8]) -> Hash256 {
+    tagged_hash("BIP0322-signed-message", &[message])
+}
+
+// The virtual transaction creating the output to spend, committing to the
+// message in its scriptSig
+pub fn to_spend(script_pubkey: &[u8], message: &[u8]) -> LegacyTransaction {
+    let script_sig = ScriptBuilder::new()
+        .push_opcode(Opcode::OP_0)
+        .push_bytes(message_hash(message).as_bytes())
+        .build();
+    LegacyTransaction::builder()
+        .version(0)
+        .add_input(TxInput {
+            previous_output: OutPoint::new(Txid::all_zeros(), u32::MAX),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        })
+        .add_output(TxOutput {
+            value: Amount::from_sat(0),
+            script_pubkey: script_pubkey.to_vec(),
+        })
+        .build()
+}
+
+// The unsigned virtual transaction spending to_spend's output
+pub fn to_sign(to_spend: &LegacyTransaction) -> LegacyTransaction {
+    LegacyTransaction::builder()
+        .version(0)
+        .add_input(TxInput {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Vec::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        })
+        .add_output(TxOutput {
+            value: Amount::from_sat(0),
+            script_pubkey: vec![Opcode::OP_RETURN as u8],
+        })
+        .lock_time(LockTime::ZERO)
+        .build()
+}
+
+// Signs with whichever of the signer's keys spends the address, which
+// must be a segwit one, as a simple signature carries no scriptSig
+pub fn sign_simple(
+    address: &Address,
+    message: &[u8],
+    signer: &Signer,
+) -> Result<String, BitcoinError> {
+    let input = sign_to_sign(address, message, signer)?.inputs.remove(0);
+    if !input.script_sig.is_empty() {
+        return Err(BitcoinError::Parse(ParseError::InvalidAddress(format!(
+            "{address} needs a full BIP322 signature"
+        ))));
+    }
+    Ok(base64::encode(&input.witness.serialize()))
+}
+
+pub fn sign_full(
+    address: &Address,
+    message: &[u8],
+    signer: &Signer,
+) -> Result<String, BitcoinError> {
+    Ok(base64::encode(
+        &sign_to_sign(address, message, signer)?.serialize(),
+    ))
+}
+
+fn sign_to_sign(
+    address: &Address,
+    message: &[u8],
+    signer: &Signer,
+) -> Result<LegacyTransaction, BitcoinError> {
+    let to_spend = to_spend(address.script_pubkey().as_bytes(), message);
+    let mut tx = to_sign(&to_spend);
+    let prevouts = HashMap::from([(
+        tx.inputs[0].previous_output.clone(),
+        to_spend.outputs[0].clone(),
+    )]);
+    if signer.sign(&mut tx, &prevouts)?.is_empty() {
+        return Err(BitcoinError::Crypto(CryptoError::KeyMismatch));
+    }
+    Ok(tx)
+}
+
+// Checks a simple or full signature of the message by the address, under
+// the standard script rules. Full signatures spending more inputs than the
+// virtual one (BIP322's proof of funds) aren't supported. For P2PKH
+// addresses, a signmessage signature passes too, as BIP322 allows.
+pub fn verify(address: &Address, message: &[u8], signature: &str) -> Result<(), BitcoinError> {
+    let bytes = base64::decode(signature)?;
+    if matches!(address, Address::P2pkh { .. }) && bytes.len() == 65 {
+        return verify_message(address, signature, message);
+    }
+    let to_spend = to_spend(address.script_pubkey().as_bytes(), message);
+    let mut tx = to_sign(&to_spend);
+    match Witness::parse(&bytes) {
+        Ok((witness, used)) if used == bytes.len() => tx.inputs[0].witness = witness,
+        _ => {
+            let signed = LegacyTransaction::parse_exact(&bytes)?;
+            let expected = &tx.inputs[0].previous_output;
+            let is_to_sign = matches!(
+                (signed.inputs.as_slice(), signed.outputs.as_slice()),
+                ([input], [output])
+                    if input.previous_output == *expected && *output == tx.outputs[0]
+            );
+            if !is_to_sign {
+                return Err(BitcoinError::Parse(ParseError::InvalidTransaction));
+            }
+            tx = signed;
+        }
+    }
+    tx.verify_with_flags(ScriptFlags::STANDARD, |outpoint| {
+        (*outpoint == tx.inputs[0].previous_output).then(|| to_spend.outputs[0].clone())
+    })
+    .map_err(|mut errors| errors.remove(0).1)
+}
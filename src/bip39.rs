@@ -0,0 +1,111 @@
+// Mnemonic seed phrases (BIP39), English wordlist only
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{hashes, BitcoinError, Network, ParseError, Xpriv};
+
+const WORDLIST: &str = include_str!("bip39_english.txt");
+
+fn wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+
+impl Mnemonic {
+    // 16 to 32 bytes of entropy in steps of 4, giving 12 to 24 words
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, BitcoinError> {
+        if !(16..=32).contains(&entropy.len()) || !entropy.len().is_multiple_of(4) {
+            return Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid mnemonic entropy length {}",
+                entropy.len()
+            ))));
+        }
+        Ok(Mnemonic {
+            entropy: entropy.to_vec(),
+        })
+    }
+
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    pub fn words(&self) -> Vec<&'static str> {
+        // Entropy followed by the first len/4 bits of its SHA256, in 11-bit groups
+        let checksum = hashes::sha256(&self.entropy);
+        let mut bits: Vec<bool> = bytes_to_bits(&self.entropy).collect();
+        bits.extend(bytes_to_bits(checksum.as_bytes()).take(self.entropy.len() / 4));
+        let list = wordlist();
+        bits.chunks(11)
+            .map(|group| list[group.iter().fold(0, |acc, bit| acc << 1 | *bit as usize)])
+            .collect()
+    }
+
+    // 64-byte seed: PBKDF2-HMAC-SHA512 over the phrase, salted with
+    // "mnemonic" + passphrase. The passphrase is not NFKD-normalized, so pass
+    // it already normalized if it contains non-ASCII characters.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let salt = format!("mnemonic{passphrase}");
+        hashes::pbkdf2_hmac_sha512(self.to_string().as_bytes(), salt.as_bytes(), 2048)
+    }
+
+    // BIP32 master key for this phrase
+    pub fn to_xpriv(&self, passphrase: &str, network: Network) -> Result<Xpriv, BitcoinError> {
+        Xpriv::new_master(network, &self.to_seed(passphrase))
+    }
+}
+
+fn bytes_to_bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1))
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.words().join(" "))
+    }
+}
+
+// Words may be separated by any whitespace and are case-insensitive
+impl FromStr for Mnemonic {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let phrase: Vec<String> = s.split_whitespace().map(str::to_lowercase).collect();
+        if !matches!(phrase.len(), 12 | 15 | 18 | 21 | 24) {
+            return Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid mnemonic word count {}",
+                phrase.len()
+            ))));
+        }
+        // The list is sorted
+        let list = wordlist();
+        let mut bits = Vec::with_capacity(phrase.len() * 11);
+        for word in &phrase {
+            let index = list.binary_search(&word.as_str()).map_err(|_| {
+                BitcoinError::Parse(ParseError::Message(format!(
+                    "Unknown mnemonic word {word:?}"
+                )))
+            })?;
+            bits.extend((0..11).rev().map(|i| index >> i & 1 == 1));
+        }
+
+        let entropy_bits = bits.len() * 32 / 33;
+        let entropy: Vec<u8> = bits[..entropy_bits]
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, bit| acc << 1 | *bit as u8))
+            .collect();
+        let mnemonic = Mnemonic::from_entropy(&entropy)?;
+        if mnemonic.words() != phrase {
+            return Err(BitcoinError::Parse(ParseError::Message(
+                "Invalid mnemonic checksum".to_string(),
+            )));
+        }
+        Ok(mnemonic)
+    }
+}
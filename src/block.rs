@@ -0,0 +1,195 @@
+// Blocks and block headers
+
+use crate::{
+    hashes, merkle, BitcoinError, BitcoinSerialize, BlockHash, CompactSize, CompactTarget,
+    CryptoError, Hash256, ParseError, Target, Transaction, Txid,
+};
+
+// Serialized size of a block header
+pub const HEADER_SIZE: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_blockhash: BlockHash,
+    pub merkle_root: Hash256,
+    pub time: u32,
+    // Proof-of-work target in compact form
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(HEADER_SIZE);
+        v.extend(&self.version.to_le_bytes());
+        v.extend(self.prev_blockhash.as_bytes());
+        v.extend(self.merkle_root.as_bytes());
+        v.extend(&self.time.to_le_bytes());
+        v.extend(&self.bits.to_le_bytes());
+        v.extend(&self.nonce.to_le_bytes());
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let data = data.get(..HEADER_SIZE).ok_or_else(|| {
+            BitcoinError::Parse(ParseError::Message(
+                "Block header must be 80 bytes".to_string(),
+            ))
+        })?;
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        Ok((
+            BlockHeader {
+                version: u32_at(0) as i32,
+                prev_blockhash: BlockHash::from_byte_array(data[4..36].try_into().unwrap()),
+                merkle_root: Hash256::from_byte_array(data[36..68].try_into().unwrap()),
+                time: u32_at(68),
+                bits: u32_at(72),
+                nonce: u32_at(76),
+            },
+            HEADER_SIZE,
+        ))
+    }
+
+    pub fn block_hash(&self) -> BlockHash {
+        hashes::sha256d(&self.serialize()).into()
+    }
+
+    // Checks the hash against the target in `bits` and returns it. Invalid
+    // and zero targets can never be met.
+    pub fn validate_pow(&self) -> Result<BlockHash, BitcoinError> {
+        let target = CompactTarget(self.bits)
+            .to_target()
+            .ok_or(BitcoinError::Crypto(CryptoError::BadProofOfWork))?;
+        let hash = self.block_hash();
+        if target == Target::ZERO || !target.is_met_by(hash) {
+            return Err(BitcoinError::Crypto(CryptoError::BadProofOfWork));
+        }
+        Ok(hash)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub txdata: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    pub fn compute_merkle_root(&self) -> Hash256 {
+        let txids: Vec<Txid> = self.txdata.iter().map(|tx| tx.txid()).collect();
+        merkle::compute_root(&txids)
+    }
+
+    // Whether the header commits to exactly these transactions. A list
+    // with duplicated transactions fails even when its root matches, as it
+    // would for the same list without them.
+    pub fn check_merkle_root(&self) -> bool {
+        let txids: Vec<Txid> = self.txdata.iter().map(|tx| tx.txid()).collect();
+        let (root, mutated) = merkle::compute_root_mutated(&txids);
+        !self.txdata.is_empty() && !mutated && root == self.header.merkle_root
+    }
+
+    // Size in bytes with all witness data stripped
+    pub fn base_size(&self) -> usize {
+        let txs: usize = self.txdata.iter().map(|tx| tx.base_size()).sum();
+        HEADER_SIZE + CompactSize(self.txdata.len() as u64).encode().len() + txs
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.serialized_size()
+    }
+
+    // BIP141 block weight, limited to consensus::MAX_BLOCK_WEIGHT
+    pub fn weight(&self) -> u64 {
+        (self.base_size() * 3 + self.total_size()) as u64
+    }
+
+    // Sized up front so the buffer is allocated once
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(self.serialized_size());
+        self.serialize_to(&mut v)
+            .expect("writing to a Vec never fails");
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (header, mut transactions) = Self::stream(data)?;
+        let mut txdata = Vec::with_capacity(transactions.remaining());
+        for tx in transactions.by_ref() {
+            txdata.push(tx?);
+        }
+        Ok((Block { header, txdata }, transactions.position()))
+    }
+
+    // Parses only the header and transaction count; transactions are decoded
+    // one at a time as the returned iterator is advanced
+    pub fn stream(data: &[u8]) -> Result<(BlockHeader, Transactions<'_>), BitcoinError> {
+        let (header, mut offset) = BlockHeader::parse(data)?;
+        let (count, used) =
+            CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        // The smallest possible transaction is 10 bytes
+        if count.0 > ((data.len() - offset) / 10) as u64 {
+            return Err(BitcoinError::Parse(ParseError::Message(
+                "Block transaction count exceeds block size".to_string(),
+            )));
+        }
+        let transactions = Transactions {
+            data,
+            offset,
+            remaining: count.0 as usize,
+        };
+        Ok((header, transactions))
+    }
+}
+
+// Transactions of a serialized block, decoded lazily. Stops after the first
+// error.
+pub struct Transactions<'a> {
+    data: &'a [u8],
+    offset: usize,
+    remaining: usize,
+}
+
+impl Transactions<'_> {
+    // Transactions not yet read
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    // Offset into the block of the next transaction (the end of the block
+    // once every transaction has been read)
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Iterator for Transactions<'_> {
+    type Item = Result<Transaction, BitcoinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        match Transaction::parse(&self.data[self.offset..]) {
+            Ok((tx, used)) => {
+                self.offset += used;
+                self.remaining -= 1;
+                Some(Ok(tx))
+            }
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e.offset_by(self.offset)))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
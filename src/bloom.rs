@@ -0,0 +1,186 @@
+// BIP37 bloom filters, which SPV clients load into a peer (the filterload
+// message) so it relays only the transactions that may interest them
+
+use std::f64::consts::LN_2;
+
+use crate::hashes::murmur3_32;
+use crate::script::{self, instructions, Instruction};
+use crate::{read_array, BitcoinError, CompactSize, LegacyTransaction, OutPoint, ParseError};
+
+// Limits a peer enforces on a loaded filter
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+// Seed multiplier separating the filter's hash functions
+const HASH_SEED_STEP: u32 = 0xFBA4_C795;
+
+// How matching a transaction output adds its outpoint to the filter, so
+// that later transactions spending it match too
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BloomUpdate {
+    #[default]
+    None,
+    All,
+    // Only outputs paying to a public key or bare multisig
+    P2PubkeyOnly,
+}
+
+impl BloomUpdate {
+    // The low two bits of the flags byte; 3 is undefined and ignored
+    fn from_flags(flags: u8) -> Self {
+        match flags & 3 {
+            1 => BloomUpdate::All,
+            2 => BloomUpdate::P2PubkeyOnly,
+            _ => BloomUpdate::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    pub update: BloomUpdate,
+}
+
+impl BloomFilter {
+    // Sized for `elements` items at the given false-positive rate, within
+    // the limits peers accept. `tweak` varies the hash functions, so
+    // filters for the same items differ between connections.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, update: BloomUpdate) -> Self {
+        let elements = elements.max(1);
+        let bits = (-1.0 / (LN_2 * LN_2) * elements as f64 * fp_rate.ln()) as usize;
+        let size = (bits.min(MAX_BLOOM_FILTER_SIZE * 8) / 8).max(1);
+        // The division truncates first, as in Bitcoin Core
+        let hash_funcs = (((size * 8 / elements) as f64 * LN_2) as u32).min(MAX_HASH_FUNCS);
+        BloomFilter {
+            data: vec![0; size],
+            hash_funcs,
+            tweak,
+            update,
+        }
+    }
+
+    fn bit_index(&self, hash_num: u32, item: &[u8]) -> usize {
+        let seed = hash_num
+            .wrapping_mul(HASH_SEED_STEP)
+            .wrapping_add(self.tweak);
+        murmur3_32(seed, item) as usize % (self.data.len() * 8)
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        for i in 0..self.hash_funcs {
+            let index = self.bit_index(i, item);
+            self.data[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&outpoint.serialize());
+    }
+
+    // May be a false positive; never a false negative
+    pub fn contains(&self, item: &[u8]) -> bool {
+        (0..self.hash_funcs).all(|i| {
+            let index = self.bit_index(i, item);
+            self.data[index / 8] & (1 << (index % 8)) != 0
+        })
+    }
+
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&outpoint.serialize())
+    }
+
+    // The filterload message payload
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = CompactSize(self.data.len() as u64).encode();
+        v.extend(&self.data);
+        v.extend(&self.hash_funcs.to_le_bytes());
+        v.extend(&self.tweak.to_le_bytes());
+        v.push(self.update as u8);
+        v
+    }
+
+    // Parses a filterload payload, rejecting filters over the size limits
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (CompactSize(len), mut offset) = CompactSize::decode(data)?;
+        if len == 0 || len > MAX_BLOOM_FILTER_SIZE as u64 {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "bloom filter size",
+                offset: 0,
+            }));
+        }
+        let len = len as usize;
+        let filter = data.get(offset..offset + len).ok_or(BitcoinError::Parse(
+            ParseError::UnexpectedEof {
+                needed: len,
+                offset,
+            },
+        ))?;
+        offset += len;
+        let hash_funcs = u32::from_le_bytes(read_array(data, offset)?);
+        if hash_funcs > MAX_HASH_FUNCS {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "bloom filter hash function count",
+                offset,
+            }));
+        }
+        let tweak = u32::from_le_bytes(read_array(data, offset + 4)?);
+        let [flags] = read_array(data, offset + 8)?;
+        Ok((
+            BloomFilter {
+                data: filter.to_vec(),
+                hash_funcs,
+                tweak,
+                update: BloomUpdate::from_flags(flags),
+            },
+            offset + 9,
+        ))
+    }
+}
+
+impl LegacyTransaction {
+    // Whether a peer with this filter loaded would relay the transaction
+    // (Core's IsRelevantAndUpdate): its txid, a data push in one of its
+    // output scripts, a spent outpoint or a push in a scriptSig is in the
+    // filter. Matching outputs are added per the filter's update flags.
+    pub fn matches_filter(&self, filter: &mut BloomFilter) -> bool {
+        let txid = self.txid();
+        let mut found = filter.contains(txid.as_bytes());
+        for (vout, output) in self.outputs.iter().enumerate() {
+            let script = &output.script_pubkey;
+            if !pushes(script).any(|data| filter.contains(data)) {
+                continue;
+            }
+            found = true;
+            let add = match filter.update {
+                BloomUpdate::None => false,
+                BloomUpdate::All => true,
+                BloomUpdate::P2PubkeyOnly => {
+                    script::p2pk_pubkey(script).is_some() || script::is_bare_multisig(script)
+                }
+            };
+            if add {
+                filter.insert_outpoint(&OutPoint::new(txid, vout as u32));
+            }
+        }
+        if found {
+            return true;
+        }
+        self.inputs.iter().any(|input| {
+            filter.contains_outpoint(&input.previous_output)
+                || pushes(&input.script_sig).any(|data| filter.contains(data))
+        })
+    }
+}
+
+// The non-empty data pushes of a script, up to any malformed push
+fn pushes(script: &[u8]) -> impl Iterator<Item = &[u8]> {
+    instructions(script)
+        .map_while(Result::ok)
+        .filter_map(|instruction| match instruction {
+            Instruction::PushBytes(_, data) if !data.is_empty() => Some(data),
+            _ => None,
+        })
+}
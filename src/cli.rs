@@ -0,0 +1,906 @@
+// Command line interface: argument parsing and the output of each command
+
+use std::collections::HashMap;
+
+use crate::bip32::{self, ScriptType};
+use crate::json::Json;
+use crate::{
+    hex, Address, AddressType, Amount, AmountError, Bip21Uri, BitcoinError, BitcoinSerialize,
+    Config, ConfigOverrides, DerivationPath, FeeRate, LegacyTransaction, LegacyTransactionBuilder,
+    Network, OutPoint, ParseError, PrivateKey, Script, Sequence, SigHashType, TxInput, TxOutput,
+    UsageError, Wallet, Weight, Witness, Xpub,
+};
+
+// Arguments a command accepts. Positionals are required unless written as
+// "[name]", which only trailing ones may be; options may appear anywhere
+// after the command name as `--name value` or `--name=value`.
+pub(crate) struct CommandSpec {
+    pub(crate) name: &'static str,
+    pub(crate) summary: &'static str,
+    pub(crate) positionals: &'static [&'static str],
+    pub(crate) options: &'static [OptionSpec],
+}
+
+pub(crate) struct OptionSpec {
+    name: &'static str,
+    kind: OptionKind,
+    // Placeholder for the value in usage text
+    value_name: &'static str,
+}
+
+#[derive(PartialEq)]
+pub(crate) enum OptionKind {
+    // Present or absent, takes no value
+    Switch,
+    Optional,
+    Required,
+    // May be given several times; at least once
+    Repeated,
+}
+
+pub(crate) const fn switch(name: &'static str) -> OptionSpec {
+    OptionSpec {
+        name,
+        kind: OptionKind::Switch,
+        value_name: "",
+    }
+}
+
+pub(crate) const fn option(
+    name: &'static str,
+    kind: OptionKind,
+    value_name: &'static str,
+) -> OptionSpec {
+    OptionSpec {
+        name,
+        kind,
+        value_name,
+    }
+}
+
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "send",
+        summary: "Send an amount (satoshis, or e.g. 0.015btc) to an address, or pay a bitcoin: URI",
+        positionals: &["amount", "[address]"],
+        options: &[option("network", OptionKind::Optional, "network")],
+    },
+    CommandSpec {
+        name: "balance",
+        summary: "Show the wallet balance",
+        positionals: &[],
+        options: &[],
+    },
+    CommandSpec {
+        name: "repl",
+        summary: "Read commands from stdin until exit",
+        positionals: &[],
+        options: &[],
+    },
+    CommandSpec {
+        name: "config",
+        summary: "Show the settings in effect",
+        positionals: &[],
+        options: &[],
+    },
+    CommandSpec {
+        name: "new-address",
+        summary: "Generate a key and its address, or derive one from an xpub",
+        positionals: &[],
+        options: &[
+            option("type", OptionKind::Optional, "type"),
+            option("network", OptionKind::Optional, "network"),
+            option("xpub", OptionKind::Optional, "xpub"),
+            option("path", OptionKind::Optional, "path"),
+            switch("show-wif"),
+        ],
+    },
+    CommandSpec {
+        name: "decode",
+        summary: "Show the fields of a serialized transaction",
+        positionals: &["tx_hex"],
+        options: &[],
+    },
+    CommandSpec {
+        name: "estimate-fee",
+        summary: "Fee for a transaction at a rate in sat/vB",
+        positionals: &["tx_hex"],
+        options: &[option("rate", OptionKind::Optional, "sat/vB")],
+    },
+    CommandSpec {
+        name: "create-tx",
+        summary: "Build an unsigned transaction",
+        positionals: &[],
+        options: &[
+            option("input", OptionKind::Repeated, "txid:vout"),
+            option("output", OptionKind::Repeated, "address:amount"),
+            switch("rbf"),
+        ],
+    },
+    CommandSpec {
+        name: "sign",
+        summary: "Sign a P2PKH or P2PK input with SIGHASH_ALL",
+        positionals: &["tx_hex"],
+        options: &[
+            option("key", OptionKind::Required, "wif"),
+            option("prevout-script", OptionKind::Required, "hex"),
+            option("input", OptionKind::Optional, "index"),
+        ],
+    },
+];
+
+impl CommandSpec {
+    // e.g. "sign <tx_hex> --key <wif> [--input <index>]"
+    pub(crate) fn usage(&self) -> String {
+        let mut parts = vec![self.name.to_string()];
+        parts.extend(self.positionals.iter().map(|name| match optional(name) {
+            Some(name) => format!("[<{name}>]"),
+            None => format!("<{name}>"),
+        }));
+        for option in self.options {
+            let flag = match option.kind {
+                OptionKind::Switch => format!("--{}", option.name),
+                _ => format!("--{} <{}>", option.name, option.value_name),
+            };
+            parts.push(match option.kind {
+                OptionKind::Switch | OptionKind::Optional => format!("[{flag}]"),
+                OptionKind::Required => flag,
+                OptionKind::Repeated => format!("{flag}..."),
+            });
+        }
+        format!("{}\n    {}", parts.join(" "), self.summary)
+    }
+}
+
+// Arguments checked against a CommandSpec
+pub(crate) struct ParsedArgs<'a> {
+    positionals: Vec<&'a str>,
+    options: HashMap<&'static str, Vec<&'a str>>,
+}
+
+impl<'a> ParsedArgs<'a> {
+    pub(crate) fn parse(spec: &CommandSpec, args: &'a [String]) -> Result<Self, BitcoinError> {
+        let mut positionals = Vec::new();
+        let mut options: HashMap<&'static str, Vec<&'a str>> = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                if positionals.len() == spec.positionals.len() {
+                    return Err(invalid_argument(arg, "unexpected argument"));
+                }
+                positionals.push(arg.as_str());
+                continue;
+            };
+            let (name, inline_value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (flag, None),
+            };
+            let flag = format!("--{name}");
+            let option = spec
+                .options
+                .iter()
+                .find(|option| option.name == name)
+                .ok_or_else(|| {
+                    invalid_argument(&flag, &format!("unknown option for {}", spec.name))
+                })?;
+            let value = match (&option.kind, inline_value) {
+                (OptionKind::Switch, Some(_)) => {
+                    return Err(invalid_argument(&flag, "switch takes no value"))
+                }
+                (OptionKind::Switch, None) => "",
+                (_, Some(value)) => value,
+                (_, None) => args
+                    .next()
+                    .map(String::as_str)
+                    .ok_or_else(|| invalid_argument(&flag, "missing value"))?,
+            };
+            let values = options.entry(option.name).or_default();
+            if option.kind != OptionKind::Repeated && !values.is_empty() {
+                return Err(invalid_argument(&flag, "given more than once"));
+            }
+            values.push(value);
+        }
+
+        if let Some(missing) = spec.positionals.get(positionals.len()) {
+            if optional(missing).is_none() {
+                return Err(BitcoinError::Usage(UsageError::MissingArgument(
+                    missing.to_string(),
+                )));
+            }
+        }
+        for option in spec.options {
+            let required = matches!(option.kind, OptionKind::Required | OptionKind::Repeated);
+            if required && !options.contains_key(option.name) {
+                return Err(BitcoinError::Usage(UsageError::MissingArgument(format!(
+                    "--{}",
+                    option.name
+                ))));
+            }
+        }
+        Ok(ParsedArgs {
+            positionals,
+            options,
+        })
+    }
+
+    pub(crate) fn positional(&self, index: usize) -> &'a str {
+        self.positionals[index]
+    }
+
+    // For positionals that may be left out
+    pub(crate) fn optional_positional(&self, index: usize) -> Option<&'a str> {
+        self.positionals.get(index).copied()
+    }
+
+    pub(crate) fn value(&self, name: &str) -> Option<&'a str> {
+        self.values(name).first().copied()
+    }
+
+    pub(crate) fn values(&self, name: &str) -> &[&'a str] {
+        self.options.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub(crate) fn switch(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+}
+
+// The name of an optional positional, without its brackets
+fn optional(positional: &str) -> Option<&str> {
+    positional.strip_prefix('[')?.strip_suffix(']')
+}
+
+pub(crate) fn invalid_argument(argument: &str, reason: &str) -> BitcoinError {
+    BitcoinError::Usage(UsageError::InvalidArgument {
+        argument: argument.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+pub(crate) fn is_help(arg: &str) -> bool {
+    arg == "-h" || arg == "--help"
+}
+
+// Simple CLI argument parser. Global options are ignored; see
+// `parse_global_options`.
+pub fn parse_cli_args(args: &[String]) -> Result<CliCommand, BitcoinError> {
+    parse_cli_args_with_config(args, &Config::default())
+}
+
+// As `parse_cli_args`, with defaults for omitted arguments taken from
+// `config`
+pub fn parse_cli_args_with_config(
+    args: &[String],
+    config: &Config,
+) -> Result<CliCommand, BitcoinError> {
+    let (_, args) = parse_global_options(args)?;
+    if args.is_empty() || is_help(&args[0]) {
+        return Ok(CliCommand::Help { command: None });
+    }
+    let spec = COMMANDS
+        .iter()
+        .find(|spec| spec.name == args[0])
+        .ok_or_else(|| BitcoinError::Parse(ParseError::Message("Unknown command".to_string())))?;
+    if args[1..].iter().any(|arg| is_help(arg)) {
+        return Ok(CliCommand::Help {
+            command: Some(spec.name.to_string()),
+        });
+    }
+    let parsed = ParsedArgs::parse(spec, &args[1..])?;
+    match spec.name {
+        "send" => {
+            // Either <amount> <address>, or a URI giving both
+            let (amount, address) = match parsed.optional_positional(1) {
+                Some(address) => (parse_amount(parsed.positional(0))?, address.parse()?),
+                None if Bip21Uri::is_uri(parsed.positional(0)) => {
+                    let uri = Bip21Uri::parse(parsed.positional(0))?;
+                    let amount = uri.amount.ok_or_else(|| {
+                        BitcoinError::Usage(UsageError::MissingArgument("amount".to_string()))
+                    })?;
+                    (amount, uri.address)
+                }
+                None => {
+                    return Err(BitcoinError::Usage(UsageError::MissingArgument(
+                        "address".to_string(),
+                    )))
+                }
+            };
+            // The address must belong to the network, from --network or
+            // the config
+            let network = match parsed.value("network") {
+                Some(name) => name.parse()?,
+                None => config.network,
+            };
+            if !address.is_valid_for_network(network) {
+                return Err(BitcoinError::Parse(ParseError::InvalidAddress(format!(
+                    "Address is not valid on {network}"
+                ))));
+            }
+            Ok(CliCommand::Send { amount, address })
+        }
+        "balance" => Ok(CliCommand::Balance),
+        "repl" => Ok(CliCommand::Repl),
+        "config" => Ok(CliCommand::Config {
+            config: config.clone(),
+        }),
+        "new-address" => parse_new_address(&parsed, config),
+        "decode" => Ok(CliCommand::Decode {
+            tx: parse_cli_tx(parsed.positional(0))?,
+        }),
+        "estimate-fee" => {
+            let sat_per_vb = match parsed.value("rate") {
+                Some(rate) => rate
+                    .parse()
+                    .map_err(|_| invalid_argument("--rate", "expected a rate in sat/vB"))?,
+                None => config.fee_rate,
+            };
+            let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb)
+                .ok_or_else(|| invalid_argument("--rate", "rate too large"))?;
+            Ok(CliCommand::EstimateFee {
+                tx: parse_cli_tx(parsed.positional(0))?,
+                fee_rate,
+            })
+        }
+        "create-tx" => {
+            let sequence = if parsed.switch("rbf") {
+                Sequence::ENABLE_RBF_NO_LOCKTIME
+            } else {
+                Sequence::MAX
+            };
+            let mut builder = LegacyTransactionBuilder::new();
+            for input in parsed.values("input") {
+                builder = builder.add_input(parse_cli_input(input, sequence)?);
+            }
+            for output in parsed.values("output") {
+                builder = builder.add_output(parse_cli_output(output)?);
+            }
+            Ok(CliCommand::CreateTx {
+                tx: builder.build(),
+            })
+        }
+        "sign" => {
+            let input_index = match parsed.value("input") {
+                Some(index) => index
+                    .parse()
+                    .map_err(|_| invalid_argument("--input", "expected an input index"))?,
+                None => 0,
+            };
+            let prevout_script = parsed.value("prevout-script").unwrap_or_default();
+            Ok(CliCommand::Sign {
+                tx: parse_cli_tx(parsed.positional(0))?,
+                input_index,
+                key: PrivateKey::from_wif(parsed.value("key").unwrap_or_default())?,
+                prevout_script: hex::decode(prevout_script)?,
+            })
+        }
+        _ => unreachable!("every command in COMMANDS is handled"),
+    }
+}
+
+pub enum CliCommand {
+    // Usage of one command, or of all of them
+    Help {
+        command: Option<String>,
+    },
+    Send {
+        amount: Amount,
+        address: Address,
+    },
+    Balance,
+    // Interactive session; see `Repl`
+    Repl,
+    Config {
+        config: Config,
+    },
+    // Without an xpub a new key is generated each time the command runs
+    NewAddress {
+        address_type: AddressType,
+        network: Network,
+        xpub: Option<Xpub>,
+        // Relative to the xpub, m/0/0 (the first receive address of an
+        // account) by default
+        path: DerivationPath,
+        show_wif: bool,
+    },
+    Decode {
+        tx: LegacyTransaction,
+    },
+    // Inputs should be signed, or the estimate is too low
+    EstimateFee {
+        tx: LegacyTransaction,
+        fee_rate: FeeRate,
+    },
+    CreateTx {
+        tx: LegacyTransaction,
+    },
+    // Signs one P2PKH or P2PK input with SIGHASH_ALL
+    Sign {
+        tx: LegacyTransaction,
+        input_index: usize,
+        key: PrivateKey,
+        prevout_script: Vec<u8>,
+    },
+}
+
+fn parse_new_address(parsed: &ParsedArgs, config: &Config) -> Result<CliCommand, BitcoinError> {
+    let xpub: Option<Xpub> = parsed.value("xpub").map(str::parse).transpose()?;
+    let network = match parsed.value("network") {
+        Some(name) => name.parse()?,
+        None => xpub.as_ref().map_or(config.network, |xpub| xpub.network),
+    };
+    // tpub keys serve every test network
+    if let Some(xpub) = &xpub {
+        if (xpub.network == Network::Mainnet) != (network == Network::Mainnet) {
+            return Err(invalid_argument("--xpub", &format!("not a {network} key")));
+        }
+    }
+    // An ypub or zpub implies its script type
+    let address_type = match (parsed.value("type"), &xpub) {
+        (Some(name), _) => name.parse()?,
+        (None, Some(xpub)) => match xpub.script_type {
+            ScriptType::P2pkh => AddressType::P2pkh,
+            ScriptType::P2shP2wpkh => AddressType::P2shP2wpkh,
+            ScriptType::P2wpkh => AddressType::P2wpkh,
+            ScriptType::P2shP2wsh | ScriptType::P2wsh => {
+                return Err(invalid_argument("--xpub", "multisig keys need --type"))
+            }
+        },
+        (None, None) => AddressType::P2wpkh,
+    };
+    let show_wif = parsed.switch("show-wif");
+    match (show_wif, &xpub) {
+        (true, Some(_)) => {
+            return Err(invalid_argument(
+                "--show-wif",
+                "an xpub has no private keys",
+            ))
+        }
+        // Without it the generated key would be lost, and anything paid
+        // to the address with it
+        (false, None) => {
+            return Err(invalid_argument(
+                "--show-wif",
+                "needed to keep a generated key",
+            ))
+        }
+        _ => {}
+    }
+    let path: DerivationPath = match parsed.value("path") {
+        Some(_) if xpub.is_none() => {
+            return Err(invalid_argument("--path", "only used with --xpub"))
+        }
+        Some(path) => path.parse()?,
+        None => DerivationPath(vec![0, 0]),
+    };
+    if path.0.iter().any(|index| index & bip32::HARDENED != 0) {
+        return Err(invalid_argument(
+            "--path",
+            "an xpub has no hardened children",
+        ));
+    }
+    Ok(CliCommand::NewAddress {
+        address_type,
+        network,
+        xpub,
+        path,
+        show_wif,
+    })
+}
+
+fn parse_cli_tx(tx_hex: &str) -> Result<LegacyTransaction, BitcoinError> {
+    tx_hex.parse()
+}
+
+// `txid:vout`, spent with an empty scriptSig
+pub(crate) fn parse_cli_input(s: &str, sequence: Sequence) -> Result<TxInput, BitcoinError> {
+    let invalid = || invalid_argument("--input", &format!("expected txid:vout, got {s:?}"));
+    let (txid, vout) = s.split_once(':').ok_or_else(invalid)?;
+    Ok(TxInput {
+        previous_output: OutPoint::new(txid.parse()?, vout.parse().map_err(|_| invalid())?),
+        script_sig: Vec::new(),
+        sequence,
+        witness: Witness::new(),
+    })
+}
+
+// A number with its unit, such as "0.015btc" or "1500000sat". A bare
+// number is in satoshis.
+fn parse_amount(s: &str) -> Result<Amount, BitcoinError> {
+    match s.parse() {
+        Ok(sat) => Amount::try_from_sat(sat),
+        Err(_) => s.parse(),
+    }
+}
+
+// `address:amount`, with the amount as parse_amount takes it
+pub(crate) fn parse_cli_output(s: &str) -> Result<TxOutput, BitcoinError> {
+    let (address, amount) = s.rsplit_once(':').ok_or_else(|| {
+        invalid_argument("--output", &format!("expected address:amount, got {s:?}"))
+    })?;
+    let address: Address = address.parse()?;
+    Ok(TxOutput {
+        value: parse_amount(amount)?,
+        script_pubkey: address.script_pubkey().into_bytes(),
+    })
+}
+
+impl CliCommand {
+    // Usage text listing every command
+    pub fn usage() -> String {
+        let commands: Vec<String> = COMMANDS
+            .iter()
+            .map(|spec| format!("  {}", spec.usage()))
+            .collect();
+        format!(
+            "Usage: <command> [arguments]\n\nCommands:\n{}",
+            commands.join("\n")
+        )
+    }
+
+    // Text the command prints
+    pub fn run(&self) -> Result<String, BitcoinError> {
+        self.run_as(OutputFormat::Text)
+    }
+
+    pub fn run_as(&self, format: OutputFormat) -> Result<String, BitcoinError> {
+        self.run_with_wallet(format, None)
+    }
+
+    // `balance` reports the wallet's balance, if there is one
+    pub(crate) fn run_with_wallet(
+        &self,
+        format: OutputFormat,
+        wallet: Option<&Wallet>,
+    ) -> Result<String, BitcoinError> {
+        Ok(self.output(wallet)?.render(format))
+    }
+
+    // With `send` paying from, and `balance` reporting, the wallet of the
+    // node behind `client`; other commands run as usual
+    #[cfg(feature = "rpc")]
+    pub fn run_with_node(
+        &self,
+        format: OutputFormat,
+        client: &crate::rpc::Client,
+    ) -> Result<String, BitcoinError> {
+        let output = match self {
+            CliCommand::Send { amount, address } => {
+                Output::Sent(client.send_to_address(address, *amount)?)
+            }
+            CliCommand::Balance => Output::Balance(Some(client.get_balance()?)),
+            _ => self.output(None)?,
+        };
+        Ok(output.render(format))
+    }
+
+    fn output(&self, wallet: Option<&Wallet>) -> Result<Output<'_>, BitcoinError> {
+        Ok(match self {
+            CliCommand::Help { command } => {
+                let usage = match command
+                    .as_deref()
+                    .and_then(|name| COMMANDS.iter().find(|spec| spec.name == name))
+                {
+                    Some(spec) => format!("Usage: {}", spec.usage()),
+                    None => Self::usage(),
+                };
+                Output::Usage(usage)
+            }
+            CliCommand::Send { amount, address } => Output::Send(*amount, address),
+            CliCommand::Balance => Output::Balance(wallet.map(Wallet::balance)),
+            CliCommand::Repl => {
+                return Err(invalid_argument("repl", "needs an interactive session"))
+            }
+            CliCommand::Config { config } => Output::Config(config),
+            CliCommand::NewAddress {
+                address_type,
+                network,
+                xpub,
+                path,
+                show_wif,
+            } => {
+                let (public_key, wif) = match xpub {
+                    Some(xpub) => (xpub.derive_path(path)?.public_key, None),
+                    None => {
+                        let key = PrivateKey::generate();
+                        let wif = show_wif.then(|| key.to_wif(*network));
+                        (key.public_key(), wif)
+                    }
+                };
+                let address = Address::from_public_key(&public_key, *address_type, *network)?;
+                Output::NewAddress(address, *address_type, wif)
+            }
+            CliCommand::Decode { tx } => Output::Decoded(tx),
+            CliCommand::EstimateFee { tx, fee_rate } => {
+                // Charged on the rounded-up vsize, as Core's wallet does
+                let fee = Weight::from_vb(tx.vsize())
+                    .and_then(|weight| fee_rate.fee_for_weight(weight))
+                    .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+                Output::Fee {
+                    weight: tx.weight(),
+                    vsize: tx.vsize(),
+                    fee_rate: *fee_rate,
+                    fee,
+                }
+            }
+            CliCommand::CreateTx { tx } => Output::Transaction(tx.clone()),
+            CliCommand::Sign {
+                tx,
+                input_index,
+                key,
+                prevout_script,
+            } => {
+                let mut tx = tx.clone();
+                tx.sign_input(*input_index, key, prevout_script, SigHashType::All)?;
+                Output::Transaction(tx)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+// Flags accepted anywhere on the command line, for every command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlobalOptions {
+    pub format: OutputFormat,
+    // Path of a config file to read settings from
+    pub config_file: Option<String>,
+    // Settings given as flags, which win over the config file
+    pub overrides: ConfigOverrides,
+}
+
+// Splits the global flags off, leaving the command and its arguments
+pub fn parse_global_options(args: &[String]) -> Result<(GlobalOptions, Vec<String>), BitcoinError> {
+    let mut options = GlobalOptions::default();
+    let mut rest = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--json" {
+            options.format = OutputFormat::Json;
+            continue;
+        }
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !matches!(
+            flag,
+            "--config" | "--rpc-url" | "--rpc-cookie" | "--rpc-wallet" | "--fee-rate"
+        ) {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = match inline_value {
+            Some(value) => value,
+            None => args
+                .next()
+                .map(String::as_str)
+                .ok_or_else(|| invalid_argument(flag, "missing value"))?,
+        };
+        match flag {
+            "--config" => options.config_file = Some(value.to_string()),
+            "--rpc-url" => options.overrides.rpc_url = Some(value.to_string()),
+            "--rpc-cookie" => options.overrides.rpc_cookie = Some(value.to_string()),
+            "--rpc-wallet" => options.overrides.rpc_wallet = Some(value.to_string()),
+            _ => {
+                let rate = value
+                    .parse()
+                    .map_err(|_| invalid_argument(flag, "expected a rate in sat/vB"))?;
+                options.overrides.fee_rate = Some(rate);
+            }
+        }
+    }
+    Ok((options, rest))
+}
+
+// Result of running a command, before it is rendered as text or JSON
+enum Output<'a> {
+    Usage(String),
+    Send(Amount, &'a Address),
+    // Txid of a payment made by the node
+    #[cfg(feature = "rpc")]
+    Sent(crate::Txid),
+    Balance(Option<Amount>),
+    Config(&'a Config),
+    // The WIF key is only kept when it is to be shown
+    NewAddress(Address, AddressType, Option<String>),
+    Decoded(&'a LegacyTransaction),
+    Fee {
+        weight: u64,
+        vsize: u64,
+        fee_rate: FeeRate,
+        fee: Amount,
+    },
+    // A built or signed transaction
+    Transaction(LegacyTransaction),
+}
+
+impl Output<'_> {
+    fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.to_text(),
+            OutputFormat::Json => self.to_json().to_string(),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Output::Usage(usage) => usage.clone(),
+            Output::Send(amount, address) => {
+                format!("send {} sat to {address}", amount.to_sat())
+            }
+            #[cfg(feature = "rpc")]
+            Output::Sent(txid) => txid.to_string(),
+            Output::Balance(None) => "No wallet loaded".to_string(),
+            Output::Balance(Some(balance)) => format!("{} sat", balance.to_sat()),
+            Output::Config(config) => config.to_string(),
+            Output::NewAddress(address, _, None) => address.to_string(),
+            Output::NewAddress(address, _, Some(wif)) => format!("{address}\n{wif}"),
+            Output::Decoded(tx) => describe_transaction(tx),
+            Output::Fee {
+                weight,
+                vsize,
+                fee_rate,
+                fee,
+            } => format!(
+                "weight: {weight} WU\nvsize: {vsize} vB\nfee: {} sat ({} sat/vB)",
+                fee.to_sat(),
+                fee_rate.to_sat_per_vb_floor()
+            ),
+            Output::Transaction(tx) => tx.to_hex(),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            Output::Usage(usage) => Json::object([("usage", usage.as_str().into())]),
+            Output::Send(amount, address) => Json::object([
+                ("amount", amount.to_sat().into()),
+                ("address", address.to_string().into()),
+            ]),
+            #[cfg(feature = "rpc")]
+            Output::Sent(txid) => Json::object([("txid", txid.to_string().into())]),
+            Output::Balance(balance) => Json::object([(
+                "balance",
+                balance.map_or(Json::Null, |balance| balance.to_sat().into()),
+            )]),
+            Output::Config(config) => {
+                let mut fields = vec![
+                    ("network", config.network.to_string().into()),
+                    ("rpc_url", config.rpc_url.as_str().into()),
+                ];
+                if let Some(rpc_cookie) = &config.rpc_cookie {
+                    fields.push(("rpc_cookie", rpc_cookie.as_str().into()));
+                }
+                if let Some(rpc_wallet) = &config.rpc_wallet {
+                    fields.push(("rpc_wallet", rpc_wallet.as_str().into()));
+                }
+                fields.push(("fee_rate", config.fee_rate.into()));
+                Json::object(fields)
+            }
+            Output::NewAddress(address, address_type, wif) => {
+                let mut fields = vec![
+                    ("address", address.to_string().into()),
+                    ("type", address_type.to_string().into()),
+                ];
+                if let Some(wif) = wif {
+                    fields.push(("wif", wif.as_str().into()));
+                }
+                Json::object(fields)
+            }
+            Output::Decoded(tx) => transaction_json(tx),
+            Output::Fee {
+                weight,
+                vsize,
+                fee_rate,
+                fee,
+            } => Json::object([
+                ("weight", (*weight).into()),
+                ("vsize", (*vsize).into()),
+                ("fee_rate", fee_rate.to_sat_per_vb_floor().into()),
+                ("fee", fee.to_sat().into()),
+            ]),
+            Output::Transaction(tx) => Json::object([
+                ("txid", tx.txid().to_string().into()),
+                ("hex", tx.to_hex().into()),
+            ]),
+        }
+    }
+}
+
+// Errors from parsing or running a command, in the chosen format
+pub fn format_error(error: &BitcoinError, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => format!("error: {error}"),
+        OutputFormat::Json => Json::object([("error", error.to_string().into())]).to_string(),
+    }
+}
+
+fn transaction_json(tx: &LegacyTransaction) -> Json {
+    let inputs = tx.inputs.iter().map(|input| {
+        let witness: Vec<String> = input
+            .witness
+            .items
+            .iter()
+            .map(|item| hex::encode(item))
+            .collect();
+        Json::object([
+            ("txid", input.previous_output.txid.to_string().into()),
+            ("vout", input.previous_output.vout.into()),
+            ("script_sig", script_json(&input.script_sig, false)),
+            ("sequence", input.sequence.to_consensus_u32().into()),
+            ("witness", witness.into()),
+        ])
+    });
+    let outputs = tx.outputs.iter().map(|output| {
+        Json::object([
+            ("value", output.value.to_sat().into()),
+            ("script_pubkey", script_json(&output.script_pubkey, true)),
+        ])
+    });
+    Json::object([
+        ("txid", tx.txid().to_string().into()),
+        ("version", tx.version.into()),
+        ("inputs", Json::Array(inputs.collect())),
+        ("outputs", Json::Array(outputs.collect())),
+        ("lock_time", tx.lock_time.to_consensus_u32().into()),
+    ])
+}
+
+fn script_json(script: &[u8], with_type: bool) -> Json {
+    let script = Script::from(script);
+    let mut fields = vec![
+        ("asm".to_string(), script.to_asm().into()),
+        ("hex".to_string(), hex::encode(&script).into()),
+    ];
+    if with_type {
+        fields.push((
+            "type".to_string(),
+            format!("{:?}", script.script_type()).into(),
+        ));
+    }
+    Json::Object(fields)
+}
+
+fn describe_transaction(tx: &LegacyTransaction) -> String {
+    let mut lines = vec![
+        format!("txid: {}", tx.txid()),
+        format!("version: {}", tx.version),
+        format!("inputs: {}", tx.inputs.len()),
+    ];
+    for (i, input) in tx.inputs.iter().enumerate() {
+        let outpoint = &input.previous_output;
+        lines.push(format!("  [{i}] {}:{}", outpoint.txid, outpoint.vout));
+        lines.push(format!(
+            "      script_sig: {}",
+            Script::from(&input.script_sig[..]).to_asm()
+        ));
+        lines.push(format!("      sequence: {:#010x}", input.sequence));
+        if !input.witness.is_empty() {
+            let items: Vec<String> = input
+                .witness
+                .items
+                .iter()
+                .map(|item| hex::encode(item))
+                .collect();
+            lines.push(format!("      witness: {}", items.join(" ")));
+        }
+    }
+    lines.push(format!("outputs: {}", tx.outputs.len()));
+    for (i, output) in tx.outputs.iter().enumerate() {
+        let script = Script::from(&output.script_pubkey[..]);
+        lines.push(format!("  [{i}] {} sat", output.value.to_sat()));
+        lines.push(format!(
+            "      script_pubkey: {} ({:?})",
+            script.to_asm(),
+            script.script_type()
+        ));
+    }
+    lines.push(format!("lock_time: {}", tx.lock_time));
+    lines.join("\n")
+}
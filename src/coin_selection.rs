@@ -0,0 +1,404 @@
+// Choosing which unspent outputs fund a transaction
+
+use std::cmp::Reverse;
+
+use k256::elliptic_curve::rand_core::{OsRng, RngCore};
+
+use crate::{
+    Amount, FeeRate, LegacyTransactionBuilder, OutPoint, Sequence, TxInput, TxOutput, Weight,
+};
+
+// Bitcoin Core gives up on branch and bound after this many steps
+const BNB_TOTAL_TRIES: usize = 100_000;
+
+// An unspent output the wallet could spend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub outpoint: OutPoint,
+    pub output: TxOutput,
+    // Weight of the input that would spend it, scriptSig and witness
+    // included
+    pub input_weight: Weight,
+    // Height of the block that confirmed it; None while unconfirmed
+    pub height: Option<u32>,
+}
+
+impl Candidate {
+    pub fn new(outpoint: OutPoint, output: TxOutput, input_weight: Weight) -> Self {
+        Candidate {
+            outpoint,
+            output,
+            input_weight,
+            height: None,
+        }
+    }
+
+    pub fn at_height(mut self, height: u32) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    // Value left after paying for its own input at `fee_rate`; None if
+    // spending it costs at least as much as it is worth
+    pub fn effective_value(&self, fee_rate: FeeRate) -> Option<Amount> {
+        let fee = fee_rate.fee_for_weight(self.input_weight)?;
+        self.output
+            .value
+            .checked_sub(fee)
+            .filter(|value| *value > Amount::ZERO)
+    }
+
+    // An unsigned input spending this output
+    pub fn to_input(&self) -> TxInput {
+        TxInput {
+            previous_output: self.outpoint.clone(),
+            script_sig: Vec::new(),
+            sequence: Sequence::MAX,
+            witness: Default::default(),
+        }
+    }
+}
+
+// Inputs chosen to fund a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection<'a> {
+    pub selected: Vec<&'a Candidate>,
+    // Value of the selected outputs
+    pub input_value: Amount,
+    // Fee for the selected inputs' weight
+    pub input_fee: Amount,
+}
+
+impl<'a> Selection<'a> {
+    fn new(selected: Vec<&'a Candidate>, fee_rate: FeeRate) -> Self {
+        let input_value = selected
+            .iter()
+            .map(|candidate| candidate.output.value)
+            .fold(Amount::ZERO, |sum, value| {
+                sum.checked_add(value)
+                    .expect("bounded by the candidates' total")
+            });
+        let input_fee = selected
+            .iter()
+            .filter_map(|candidate| fee_rate.fee_for_weight(candidate.input_weight))
+            .fold(Amount::ZERO, |sum, fee| {
+                sum.checked_add(fee).expect("bounded by the input value")
+            });
+        Selection {
+            selected,
+            input_value,
+            input_fee,
+        }
+    }
+
+    // Adds an input for each selected output
+    pub fn add_inputs(&self, builder: LegacyTransactionBuilder) -> LegacyTransactionBuilder {
+        self.selected.iter().fold(builder, |builder, candidate| {
+            builder.add_input(candidate.to_input())
+        })
+    }
+}
+
+// Bitcoin Core's branch and bound search for a changeless selection: the
+// candidates' effective values must add up to at least `target` and at most
+// `target + cost_of_change`, the cost of adding and later spending a change
+// output instead. `target` is what the outputs pay plus the fee for the
+// rest of the transaction. Among matches the one with the least excess
+// wins. None if there is no match, or none is found within Core's step
+// limit.
+pub fn select_coins_bnb(
+    candidates: &[Candidate],
+    target: Amount,
+    fee_rate: FeeRate,
+    cost_of_change: Amount,
+) -> Option<Selection<'_>> {
+    let mut pool = effective_values(candidates, fee_rate);
+    // Largest first, so the search overshoots early and prunes more
+    pool.sort_by_key(|(value, _)| Reverse(*value));
+
+    let target = target.to_sat();
+    let upper_bound = target.checked_add(cost_of_change.to_sat())?;
+    let mut available: u64 = pool
+        .iter()
+        .try_fold(0u64, |sum, (value, _)| sum.checked_add(*value))?;
+    if available < target {
+        return None;
+    }
+
+    // Depth first over include/exclude decisions, following Core's
+    // SelectCoinsBnB step for step
+    let mut value = 0u64;
+    let mut selection: Vec<usize> = Vec::new();
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    let mut index = 0;
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack = false;
+        if value + available < target || value > upper_bound {
+            backtrack = true;
+        } else if value >= target {
+            let excess = value - target;
+            if best
+                .as_ref()
+                .is_none_or(|(best_excess, _)| excess <= *best_excess)
+            {
+                best = Some((excess, selection.clone()));
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let Some(&last) = selection.last() else {
+                break;
+            };
+            // Give back the candidates skipped since the last inclusion,
+            // then try leaving that one out instead
+            while index - 1 > last {
+                index -= 1;
+                available += pool[index].0;
+            }
+            index -= 1;
+            value -= pool[index].0;
+            selection.pop();
+        } else {
+            let candidate_value = pool[index].0;
+            available -= candidate_value;
+            // Including a candidate just like the previous one, which was
+            // left out, repeats a branch that has been searched already
+            let duplicate = !selection.is_empty()
+                && selection.last() != Some(&(index - 1))
+                && pool[index - 1].0 == candidate_value
+                && pool[index - 1].1.input_weight == pool[index].1.input_weight;
+            if !duplicate {
+                selection.push(index);
+                value += candidate_value;
+            }
+        }
+        index += 1;
+    }
+
+    let (_, indexes) = best?;
+    let selected = indexes.into_iter().map(|i| pool[i].1).collect();
+    Some(Selection::new(selected, fee_rate))
+}
+
+// A policy for choosing inputs. Implementations pick candidates whose
+// effective values at `fee_rate` add up to at least `target`, defined as
+// for select_coins_bnb; anything over it is left for a change output.
+pub trait CoinSelector {
+    fn select<'a>(
+        &self,
+        candidates: &'a [Candidate],
+        target: Amount,
+        fee_rate: FeeRate,
+    ) -> Option<Selection<'a>>;
+}
+
+// select_coins_bnb as a CoinSelector. Only changeless selections are found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchAndBound {
+    pub cost_of_change: Amount,
+}
+
+impl CoinSelector for BranchAndBound {
+    fn select<'a>(
+        &self,
+        candidates: &'a [Candidate],
+        target: Amount,
+        fee_rate: FeeRate,
+    ) -> Option<Selection<'a>> {
+        select_coins_bnb(candidates, target, fee_rate, self.cost_of_change)
+    }
+}
+
+// Biggest outputs first: the fewest inputs, but it tends to consolidate
+// nothing and reveal the wallet's largest coins
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select<'a>(
+        &self,
+        candidates: &'a [Candidate],
+        target: Amount,
+        fee_rate: FeeRate,
+    ) -> Option<Selection<'a>> {
+        let mut pool = effective_values(candidates, fee_rate);
+        pool.sort_by_key(|(value, _)| Reverse(*value));
+        accumulate(pool, target, fee_rate)
+    }
+}
+
+// Earliest confirmed first, unconfirmed outputs last
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OldestFirst;
+
+impl CoinSelector for OldestFirst {
+    fn select<'a>(
+        &self,
+        candidates: &'a [Candidate],
+        target: Amount,
+        fee_rate: FeeRate,
+    ) -> Option<Selection<'a>> {
+        let mut pool = effective_values(candidates, fee_rate);
+        pool.sort_by_key(|(_, candidate)| candidate.height.unwrap_or(u32::MAX));
+        accumulate(pool, target, fee_rate)
+    }
+}
+
+// Bitcoin Core's fallback: candidates in random order until the target is
+// met
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SingleRandomDraw;
+
+impl CoinSelector for SingleRandomDraw {
+    fn select<'a>(
+        &self,
+        candidates: &'a [Candidate],
+        target: Amount,
+        fee_rate: FeeRate,
+    ) -> Option<Selection<'a>> {
+        let mut pool = effective_values(candidates, fee_rate);
+        shuffle(&mut pool);
+        accumulate(pool, target, fee_rate)
+    }
+}
+
+// Bitcoin Core's KnapsackSolver: an exact single match if there is one,
+// otherwise the best of many random subsets of the smaller candidates,
+// unless the smallest candidate covering the target alone comes closer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Knapsack {
+    // Rounds of random subsets to try
+    pub iterations: usize,
+}
+
+impl Default for Knapsack {
+    fn default() -> Self {
+        Knapsack { iterations: 1000 }
+    }
+}
+
+impl CoinSelector for Knapsack {
+    fn select<'a>(
+        &self,
+        candidates: &'a [Candidate],
+        target: Amount,
+        fee_rate: FeeRate,
+    ) -> Option<Selection<'a>> {
+        let target = target.to_sat();
+        let mut smaller = Vec::new();
+        let mut lowest_larger: Option<(u64, &Candidate)> = None;
+        for (value, candidate) in effective_values(candidates, fee_rate) {
+            if value == target {
+                return Some(Selection::new(vec![candidate], fee_rate));
+            } else if value < target {
+                smaller.push((value, candidate));
+            } else if lowest_larger.is_none_or(|(lowest, _)| value < lowest) {
+                lowest_larger = Some((value, candidate));
+            }
+        }
+
+        let smaller_total = smaller
+            .iter()
+            .try_fold(0u64, |sum, (value, _)| sum.checked_add(*value))?;
+        if smaller_total < target {
+            let (_, candidate) = lowest_larger?;
+            return Some(Selection::new(vec![candidate], fee_rate));
+        }
+        smaller.sort_by_key(|(value, _)| Reverse(*value));
+        let (best_total, best) = approximate_best_subset(&smaller, target, self.iterations);
+        let selected = match lowest_larger {
+            Some((value, candidate)) if best_total != target && value <= best_total => {
+                vec![candidate]
+            }
+            _ => best
+                .into_iter()
+                .zip(&smaller)
+                .filter_map(|(included, (_, candidate))| included.then_some(*candidate))
+                .collect(),
+        };
+        Some(Selection::new(selected, fee_rate))
+    }
+}
+
+// Core's ApproximateBestSubset: random passes over `pool`, sorted largest
+// first, each adding candidates with probability one half (and on the
+// second pass every candidate not yet in) until the target is met, then
+// dropping the last one added to look for something closer. Returns the
+// smallest total found that meets `target`, which the pool's total does.
+fn approximate_best_subset(
+    pool: &[(u64, &Candidate)],
+    target: u64,
+    iterations: usize,
+) -> (u64, Vec<bool>) {
+    let mut best = vec![true; pool.len()];
+    let mut best_total: u64 = pool.iter().map(|(value, _)| value).sum();
+    for _ in 0..iterations {
+        if best_total == target {
+            break;
+        }
+        let mut included = vec![false; pool.len()];
+        let mut total = 0;
+        let mut reached_target = false;
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+            for (i, (value, _)) in pool.iter().enumerate() {
+                let take = if pass == 0 {
+                    OsRng.next_u32() & 1 == 1
+                } else {
+                    !included[i]
+                };
+                if !take {
+                    continue;
+                }
+                total += value;
+                included[i] = true;
+                if total >= target {
+                    reached_target = true;
+                    if total < best_total {
+                        best_total = total;
+                        best.clone_from(&included);
+                    }
+                    total -= value;
+                    included[i] = false;
+                }
+            }
+        }
+    }
+    (best_total, best)
+}
+
+fn effective_values(candidates: &[Candidate], fee_rate: FeeRate) -> Vec<(u64, &Candidate)> {
+    candidates
+        .iter()
+        .filter_map(|candidate| Some((candidate.effective_value(fee_rate)?.to_sat(), candidate)))
+        .collect()
+}
+
+// Takes candidates in order until their effective values meet `target`
+fn accumulate<'a>(
+    pool: Vec<(u64, &'a Candidate)>,
+    target: Amount,
+    fee_rate: FeeRate,
+) -> Option<Selection<'a>> {
+    let mut total = 0u64;
+    let mut selected = Vec::new();
+    for (value, candidate) in pool {
+        if total >= target.to_sat() {
+            break;
+        }
+        total = total.checked_add(value)?;
+        selected.push(candidate);
+    }
+    (total >= target.to_sat()).then(|| Selection::new(selected, fee_rate))
+}
+
+// Fisher-Yates
+fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let j = (OsRng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
@@ -0,0 +1,92 @@
+// Coinbase transactions: the first transaction of a block, which spends
+// nothing and pays out the subsidy and fees
+
+use crate::consensus::ConsensusError;
+use crate::script::{decode_script_num, instructions, Instruction};
+use crate::{
+    BitcoinError, LegacyTransaction, LockTime, Opcode, OutPoint, ScriptBuilder, Sequence, TxInput,
+    TxOutput, Witness,
+};
+
+// Builds a coinbase whose scriptSig starts with the block height (BIP34)
+#[derive(Debug, Clone)]
+pub struct CoinbaseBuilder {
+    pub height: u32,
+    // Written after the height: extra nonce, pool tag and the like
+    pub extra_data: Vec<u8>,
+    pub outputs: Vec<TxOutput>,
+    pub version: i32,
+}
+
+impl CoinbaseBuilder {
+    pub fn new(height: u32) -> Self {
+        CoinbaseBuilder {
+            height,
+            extra_data: Vec::new(),
+            outputs: Vec::new(),
+            version: 1,
+        }
+    }
+
+    pub fn extra_data(mut self, data: &[u8]) -> Self {
+        self.extra_data = data.to_vec();
+        self
+    }
+
+    pub fn add_output(mut self, output: TxOutput) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    pub fn version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    // The height is pushed as a script number, as Core's `CScript() <<
+    // nHeight`. Low heights encode to one byte, so OP_0 pads the scriptSig
+    // to the two bytes consensus requires.
+    pub fn script_sig(&self) -> Vec<u8> {
+        let mut script_sig = ScriptBuilder::new().push_int(self.height as i64).build();
+        script_sig.extend(&self.extra_data);
+        if script_sig.len() < 2 {
+            script_sig.push(Opcode::OP_0 as u8);
+        }
+        script_sig
+    }
+
+    // Fails if the extra data makes the scriptSig longer than 100 bytes
+    pub fn build(self) -> Result<LegacyTransaction, BitcoinError> {
+        let script_sig = self.script_sig();
+        if script_sig.len() > 100 {
+            return Err(ConsensusError::CoinbaseLength.into());
+        }
+        Ok(LegacyTransaction {
+            version: self.version,
+            inputs: vec![TxInput {
+                previous_output: OutPoint::null(),
+                script_sig,
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            outputs: self.outputs,
+            lock_time: LockTime::ZERO,
+        })
+    }
+}
+
+impl LegacyTransaction {
+    // The BIP34 height at the start of a coinbase's scriptSig. Coinbases
+    // from before BIP34 activated may start with any number, or none.
+    pub fn coinbase_height(&self) -> Option<u32> {
+        if !self.is_coinbase() {
+            return None;
+        }
+        let height = match instructions(&self.inputs[0].script_sig).next()?.ok()? {
+            Instruction::Op(op) => Opcode::from_u8(op)?.small_int()? as i64,
+            Instruction::PushBytes(_, []) => 0,
+            Instruction::PushBytes(_, data) => decode_script_num(data, false, 4).ok()?,
+        };
+        u32::try_from(height).ok()
+    }
+}
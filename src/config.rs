@@ -0,0 +1,206 @@
+// CLI defaults, layered: built-in values, then the config file, then flags
+
+use std::fmt;
+
+use crate::{BitcoinError, Network, ParseError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub network: Network,
+    pub rpc_url: String,
+    // Cookie file to authenticate with, instead of credentials in the URL
+    // or the node's default cookie
+    pub rpc_cookie: Option<String>,
+    // Wallet for wallet calls, on a node with several loaded
+    pub rpc_wallet: Option<String>,
+    // Fee rate in sat/vB
+    pub fee_rate: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            network: Network::Mainnet,
+            rpc_url: "http://127.0.0.1:8332".to_string(),
+            rpc_cookie: None,
+            rpc_wallet: None,
+            fee_rate: 1,
+        }
+    }
+}
+
+impl Config {
+    // Values set in `overrides` replace ours
+    pub fn apply(mut self, overrides: &ConfigOverrides) -> Self {
+        if let Some(network) = overrides.network {
+            self.network = network;
+        }
+        if let Some(rpc_url) = &overrides.rpc_url {
+            self.rpc_url = rpc_url.clone();
+        }
+        if let Some(rpc_cookie) = &overrides.rpc_cookie {
+            self.rpc_cookie = Some(rpc_cookie.clone());
+        }
+        if let Some(rpc_wallet) = &overrides.rpc_wallet {
+            self.rpc_wallet = Some(rpc_wallet.clone());
+        }
+        if let Some(fee_rate) = overrides.fee_rate {
+            self.fee_rate = fee_rate;
+        }
+        self
+    }
+}
+
+// Written back in the config file format
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "network = \"{}\"", self.network)?;
+        writeln!(f, "rpc_url = {}", TomlString(&self.rpc_url))?;
+        if let Some(rpc_cookie) = &self.rpc_cookie {
+            writeln!(f, "rpc_cookie = {}", TomlString(rpc_cookie))?;
+        }
+        if let Some(rpc_wallet) = &self.rpc_wallet {
+            writeln!(f, "rpc_wallet = {}", TomlString(rpc_wallet))?;
+        }
+        write!(f, "fee_rate = {}", self.fee_rate)
+    }
+}
+
+// A basic string, escaped as parse_string reads it back
+struct TomlString<'a>(&'a str);
+
+impl fmt::Display for TomlString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\t' => f.write_str("\\t")?,
+                '\r' => f.write_str("\\r")?,
+                c if c.is_control() => write!(f, "\\u{:04X}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+// Settings given in a config file or on the command line; unset fields
+// fall through to the layer below
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigOverrides {
+    pub network: Option<Network>,
+    pub rpc_url: Option<String>,
+    pub rpc_cookie: Option<String>,
+    pub rpc_wallet: Option<String>,
+    pub fee_rate: Option<u64>,
+}
+
+impl ConfigOverrides {
+    // Parses a config file, e.g.
+    //
+    //     network = "testnet"
+    //     rpc_url = "http://127.0.0.1:18332"
+    //     rpc_cookie = "/var/lib/bitcoind/testnet3/.cookie"
+    //     rpc_wallet = "savings"
+    //     fee_rate = 5
+    //
+    // Only `key = value` lines, comments and blank lines are understood.
+    pub fn from_toml(s: &str) -> Result<Self, BitcoinError> {
+        let mut overrides = ConfigOverrides::default();
+        for (i, line) in s.lines().enumerate() {
+            let error = |reason: &str| {
+                BitcoinError::Parse(ParseError::Message(format!(
+                    "config line {}: {reason}",
+                    i + 1
+                )))
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected key = value"))?;
+            let value = value.trim();
+            match key.trim() {
+                "network" => {
+                    let name = parse_string(value).ok_or_else(|| error("expected a string"))?;
+                    overrides.network = Some(name.parse().map_err(|_| error("unknown network"))?);
+                }
+                "rpc_url" => {
+                    let url = parse_string(value).ok_or_else(|| error("expected a string"))?;
+                    overrides.rpc_url = Some(url);
+                }
+                "rpc_cookie" => {
+                    let path = parse_string(value).ok_or_else(|| error("expected a string"))?;
+                    overrides.rpc_cookie = Some(path);
+                }
+                "rpc_wallet" => {
+                    let name = parse_string(value).ok_or_else(|| error("expected a string"))?;
+                    overrides.rpc_wallet = Some(name);
+                }
+                "fee_rate" => {
+                    let rate = value.replace('_', "");
+                    overrides.fee_rate =
+                        Some(rate.parse().map_err(|_| error("expected an integer"))?);
+                }
+                key => return Err(error(&format!("unknown key {key:?}"))),
+            }
+        }
+        Ok(overrides)
+    }
+}
+
+// Drops a `#` comment, unless the `#` is inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// A basic string: double quotes with backslash escapes
+fn parse_string(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut s = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => s.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                '\\' => '\\',
+                '"' => '"',
+                'u' => parse_unicode_escape(&mut chars, 4)?,
+                'U' => parse_unicode_escape(&mut chars, 8)?,
+                _ => return None,
+            }),
+            '"' => return None,
+            c => s.push(c),
+        }
+    }
+    Some(s)
+}
+
+// The hex digits of a \u or \U escape, as the character they name
+fn parse_unicode_escape(chars: &mut std::str::Chars<'_>, digits: usize) -> Option<char> {
+    let hex: String = chars.by_ref().take(digits).collect();
+    if hex.len() != digits || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)
+}
@@ -0,0 +1,177 @@
+// Consensus rules that depend only on chain parameters
+
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use crate::script::{
+    count_sigops, instructions, is_p2sh, is_push_only, witness_program, Instruction,
+};
+use crate::{Amount, BitcoinError, ChainParams, LegacyTransaction, TxInput, TxOutput, UsageError};
+
+// Subsidy of the first blocks, in satoshis
+pub const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+
+// Largest weight a block may have (BIP141)
+pub const MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+
+// No amount of bitcoin can exceed the 21 million that will ever exist
+pub const MAX_MONEY: Amount = Amount::MAX_MONEY;
+
+// Largest total sigop cost of a block's transactions (BIP141)
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+
+// Rules Bitcoin Core's CheckTransaction enforces, which hold whatever the
+// rest of the chain looks like. Messages give Core's reject reasons.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsensusError {
+    #[error("bad-txns-vin-empty")]
+    NoInputs,
+    #[error("bad-txns-vout-empty")]
+    NoOutputs,
+    #[error("bad-txns-oversize")]
+    Oversize,
+    #[error("bad-txns-vout-toolarge")]
+    OutputTooLarge,
+    #[error("bad-txns-txouttotal-toolarge")]
+    OutputTotalTooLarge,
+    #[error("bad-txns-inputs-duplicate")]
+    DuplicateInput,
+    #[error("bad-cb-length")]
+    CoinbaseLength,
+    #[error("bad-txns-prevout-null")]
+    NullPrevout,
+}
+
+// Number of halvings before the block at `height`
+pub fn halving_epoch(height: u32, params: &ChainParams) -> u32 {
+    height / params.subsidy_halving_interval
+}
+
+// New coins a coinbase at `height` may claim on top of the fees it collects
+pub fn block_subsidy(height: u32, params: &ChainParams) -> u64 {
+    let halvings = halving_epoch(height, params);
+    // A shift of 64 or more is undefined; the subsidy is long zero by then
+    if halvings >= 64 {
+        return 0;
+    }
+    INITIAL_SUBSIDY >> halvings
+}
+
+impl LegacyTransaction {
+    // Checks the rules of ConsensusError, in the order Core does
+    pub fn check_consensus(&self) -> Result<(), BitcoinError> {
+        if self.inputs.is_empty() {
+            return Err(ConsensusError::NoInputs.into());
+        }
+        if self.outputs.is_empty() {
+            return Err(ConsensusError::NoOutputs.into());
+        }
+        // Witness data doesn't count, so nothing can hide in it
+        if self.base_size() as u64 * 4 > MAX_BLOCK_WEIGHT {
+            return Err(ConsensusError::Oversize.into());
+        }
+
+        let mut total = Amount::ZERO;
+        for output in &self.outputs {
+            if output.value > MAX_MONEY {
+                return Err(ConsensusError::OutputTooLarge.into());
+            }
+            total = total
+                .checked_add(output.value)
+                .ok_or(ConsensusError::OutputTotalTooLarge)?;
+        }
+
+        let mut spent = HashSet::new();
+        if self
+            .inputs
+            .iter()
+            .any(|input| !spent.insert(&input.previous_output))
+        {
+            return Err(ConsensusError::DuplicateInput.into());
+        }
+
+        if self.is_coinbase() {
+            if !(2..=100).contains(&self.inputs[0].script_sig.len()) {
+                return Err(ConsensusError::CoinbaseLength.into());
+            }
+        } else if self
+            .inputs
+            .iter()
+            .any(|input| input.previous_output.is_null())
+        {
+            return Err(ConsensusError::NullPrevout.into());
+        }
+        Ok(())
+    }
+
+    // Sigop cost as Core's GetTransactionSigOpCost: legacy and P2SH sigops
+    // weigh 4, witness sigops 1. `prevouts` are the outputs being spent, one
+    // per input in order; a coinbase spends nothing and may pass none.
+    pub fn total_sigop_cost(&self, prevouts: &[TxOutput]) -> Result<usize, BitcoinError> {
+        let legacy: usize = self
+            .inputs
+            .iter()
+            .map(|input| count_sigops(&input.script_sig, false))
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|output| count_sigops(&output.script_pubkey, false)),
+            )
+            .sum();
+        let mut cost = legacy * 4;
+        if self.is_coinbase() {
+            return Ok(cost);
+        }
+        if prevouts.len() != self.inputs.len() {
+            return Err(BitcoinError::Usage(UsageError::PrevoutCountMismatch {
+                prevouts: prevouts.len(),
+                inputs: self.inputs.len(),
+            }));
+        }
+        for (input, prevout) in self.inputs.iter().zip(prevouts) {
+            let script_pubkey = &prevout.script_pubkey;
+            if is_p2sh(script_pubkey) {
+                cost +=
+                    p2sh_redeem_script(input).map_or(0, |redeem| count_sigops(redeem, true)) * 4;
+            }
+            cost += witness_sigops(input, script_pubkey);
+        }
+        Ok(cost)
+    }
+}
+
+// The script a P2SH spend reveals: the last push of a push-only scriptSig
+fn p2sh_redeem_script(input: &TxInput) -> Option<&[u8]> {
+    if !is_push_only(&input.script_sig) {
+        return None;
+    }
+    match instructions(&input.script_sig).last()? {
+        Ok(Instruction::PushBytes(_, data)) => Some(data),
+        _ => None,
+    }
+}
+
+// Sigops of a witness spend, native or nested in P2SH. Only version 0 has
+// sigops counted; taproot spends are limited by their witness size instead.
+fn witness_sigops(input: &TxInput, script_pubkey: &[u8]) -> usize {
+    let program = match witness_program(script_pubkey) {
+        Some(program) => program,
+        None if is_p2sh(script_pubkey) => {
+            match p2sh_redeem_script(input).and_then(witness_program) {
+                Some(program) => program,
+                None => return 0,
+            }
+        }
+        None => return 0,
+    };
+    match program {
+        (0, program) if program.len() == 20 => 1,
+        (0, program) if program.len() == 32 => input
+            .witness
+            .items
+            .last()
+            .map_or(0, |witness_script| count_sigops(witness_script, true)),
+        _ => 0,
+    }
+}
@@ -0,0 +1,470 @@
+// Output descriptors: a string naming the scripts a wallet pays to, e.g.
+// wpkh([d34db33f/84'/0'/0']xpub.../0/*). Supported are pkh, wpkh,
+// sh(wpkh), sh/wsh/sh(wsh) of multi and sortedmulti, and key-path-only tr.
+// Keys are hex public keys (x-only ones too in tr) or xpubs followed by
+// unhardened steps, with a trailing * derived per index.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bip32::{DerivationPath, HARDENED};
+use crate::expression::Tree;
+use crate::script::MAX_PUBKEYS_PER_MULTISIG;
+use crate::{
+    hex, Address, BitcoinError, CryptoError, Network, ParseError, PublicKey, Script,
+    TaprootSpendInfo, UsageError, XOnlyPublicKey, Xpub,
+};
+
+// P2SH redeem scripts are limited to 520 bytes, enough for 15 compressed keys
+const MAX_P2SH_MULTISIG_KEYS: usize = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptorKey {
+    Single {
+        origin: Option<KeyOrigin>,
+        key: PublicKey,
+    },
+    // 64 hex digits, only allowed in tr()
+    XOnly {
+        origin: Option<KeyOrigin>,
+        key: XOnlyPublicKey,
+    },
+    Extended {
+        origin: Option<KeyOrigin>,
+        xpub: Xpub,
+        // Unhardened steps derived from the xpub
+        path: Vec<u32>,
+        // Whether the index is appended to the path
+        wildcard: bool,
+    },
+}
+
+// The master key fingerprint and path a key was derived with, the
+// [d34db33f/84'/0'/0'] prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOrigin {
+    pub fingerprint: [u8; 4],
+    pub path: DerivationPath,
+}
+
+impl DescriptorKey {
+    pub fn has_wildcard(&self) -> bool {
+        matches!(self, DescriptorKey::Extended { wildcard: true, .. })
+    }
+
+    // The key at `index`, which only matters for wildcard keys
+    pub fn derive_public_key(&self, index: u32) -> Result<PublicKey, BitcoinError> {
+        match self {
+            DescriptorKey::Single { key, .. } => Ok(*key),
+            // With the even y Taproot gives it
+            DescriptorKey::XOnly { key, .. } => {
+                PublicKey::from_slice(&[&[0x02], &key.serialize()[..]].concat())
+            }
+            DescriptorKey::Extended {
+                xpub,
+                path,
+                wildcard,
+                ..
+            } => {
+                let mut path = path.clone();
+                if *wildcard {
+                    path.push(index);
+                }
+                Ok(xpub.derive_path(&DerivationPath(path))?.public_key)
+            }
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        match self {
+            DescriptorKey::Single { key, .. } => key.compressed,
+            DescriptorKey::XOnly { .. } | DescriptorKey::Extended { .. } => true,
+        }
+    }
+
+    fn origin(&self) -> &Option<KeyOrigin> {
+        match self {
+            DescriptorKey::Single { origin, .. }
+            | DescriptorKey::XOnly { origin, .. }
+            | DescriptorKey::Extended { origin, .. } => origin,
+        }
+    }
+}
+
+impl FromStr for DescriptorKey {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |what: &str| {
+            BitcoinError::Parse(ParseError::Message(format!("Invalid key {s:?}: {what}")))
+        };
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unclosed origin"))?;
+                let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+                let fingerprint = hex::decode(fingerprint)?
+                    .try_into()
+                    .map_err(|_| invalid("fingerprints are 4 bytes"))?;
+                let path = if path.is_empty() {
+                    DerivationPath::default()
+                } else {
+                    format!("m/{path}").parse()?
+                };
+                (Some(KeyOrigin { fingerprint, path }), key)
+            }
+            None => (None, s),
+        };
+        if key.len() == 66 || key.len() == 130 {
+            let key = PublicKey::from_slice(&hex::decode(key)?)?;
+            return Ok(DescriptorKey::Single { origin, key });
+        }
+        if key.len() == 64 {
+            let key = XOnlyPublicKey::from_slice(&hex::decode(key)?)?;
+            return Ok(DescriptorKey::XOnly { origin, key });
+        }
+        let mut steps = key.split('/');
+        let xpub: Xpub = steps.next().unwrap_or_default().parse()?;
+        let mut path = Vec::new();
+        let mut wildcard = false;
+        for step in steps {
+            if wildcard {
+                return Err(invalid("* must be the last step"));
+            }
+            if step == "*" {
+                wildcard = true;
+                continue;
+            }
+            // Hardened steps would need the private key
+            match step.parse::<u32>() {
+                Ok(index) if index < HARDENED => path.push(index),
+                _ => return Err(invalid("only unhardened steps can follow an xpub")),
+            }
+        }
+        Ok(DescriptorKey::Extended {
+            origin,
+            xpub,
+            path,
+            wildcard,
+        })
+    }
+}
+
+impl fmt::Display for DescriptorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(origin) = self.origin() {
+            write!(f, "[{}", hex::encode(&origin.fingerprint))?;
+            // Skip DerivationPath's leading "m"
+            f.write_str(&origin.path.to_string()[1..])?;
+            f.write_str("]")?;
+        }
+        match self {
+            DescriptorKey::Single { key, .. } => f.write_str(&hex::encode(&key.serialize())),
+            DescriptorKey::XOnly { key, .. } => f.write_str(&hex::encode(&key.serialize())),
+            DescriptorKey::Extended {
+                xpub,
+                path,
+                wildcard,
+                ..
+            } => {
+                write!(f, "{xpub}")?;
+                for step in path {
+                    write!(f, "/{step}")?;
+                }
+                if *wildcard {
+                    f.write_str("/*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// multi(k,KEY,...) or sortedmulti(k,KEY,...)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Multisig {
+    pub required: usize,
+    pub keys: Vec<DescriptorKey>,
+    // Sorts the derived keys (BIP67) instead of keeping the given order
+    pub sorted: bool,
+}
+
+impl Multisig {
+    pub fn script(&self, index: u32) -> Result<Script, BitcoinError> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| key.derive_public_key(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.sorted {
+            Script::new_sorted_multisig(self.required, &keys)
+        } else {
+            Script::new_multisig(self.required, &keys)
+        }
+    }
+
+    fn from_tree(tree: &Tree) -> Result<Self, BitcoinError> {
+        let sorted = match tree.name {
+            "multi" => false,
+            "sortedmulti" => true,
+            name => {
+                return Err(BitcoinError::Parse(ParseError::Message(format!(
+                    "Expected multi or sortedmulti, got {name:?}"
+                ))))
+            }
+        };
+        let Some((required, keys)) = tree.args.split_first() else {
+            return Err(BitcoinError::Parse(ParseError::Message(format!(
+                "{} needs arguments",
+                tree.name
+            ))));
+        };
+        let required = required.terminal()?.parse().map_err(|_| {
+            BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid threshold {:?}",
+                required.name
+            )))
+        })?;
+        let keys = keys
+            .iter()
+            .map(|key| key.terminal()?.parse())
+            .collect::<Result<Vec<DescriptorKey>, _>>()?;
+        if required == 0 || required > keys.len() || keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(BitcoinError::Usage(UsageError::InvalidMultisig {
+                required,
+                keys: keys.len(),
+            }));
+        }
+        Ok(Multisig {
+            required,
+            keys,
+            sorted,
+        })
+    }
+}
+
+impl fmt::Display for Multisig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = if self.sorted { "sortedmulti" } else { "multi" };
+        write!(f, "{name}({}", self.required)?;
+        for key in &self.keys {
+            write!(f, ",{key}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Descriptor {
+    Pkh(DescriptorKey),
+    Wpkh(DescriptorKey),
+    ShWpkh(DescriptorKey),
+    Sh(Multisig),
+    Wsh(Multisig),
+    ShWsh(Multisig),
+    // Key path only; script trees aren't supported
+    Tr(DescriptorKey),
+}
+
+impl Descriptor {
+    pub fn has_wildcard(&self) -> bool {
+        self.keys().iter().any(|key| key.has_wildcard())
+    }
+
+    fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Descriptor::Pkh(key)
+            | Descriptor::Wpkh(key)
+            | Descriptor::ShWpkh(key)
+            | Descriptor::Tr(key) => vec![key],
+            Descriptor::Sh(multi) | Descriptor::Wsh(multi) | Descriptor::ShWsh(multi) => {
+                multi.keys.iter().collect()
+            }
+        }
+    }
+
+    // The output script at `index`; descriptors without a wildcard have the
+    // same script at every index
+    pub fn script_pubkey(&self, index: u32) -> Result<Script, BitcoinError> {
+        Ok(match self {
+            Descriptor::Pkh(key) => Script::new_p2pkh(&key.derive_public_key(index)?.pubkey_hash()),
+            Descriptor::Wpkh(key) => {
+                Script::new_p2wpkh(&key.derive_public_key(index)?.pubkey_hash())
+            }
+            Descriptor::ShWpkh(key) => {
+                Script::new_p2wpkh(&key.derive_public_key(index)?.pubkey_hash()).to_p2sh()
+            }
+            Descriptor::Sh(multi) => multi.script(index)?.to_p2sh(),
+            Descriptor::Wsh(multi) => multi.script(index)?.to_p2wsh(),
+            Descriptor::ShWsh(multi) => multi.script(index)?.to_p2wsh().to_p2sh(),
+            Descriptor::Tr(key) => {
+                let (internal_key, _) = key.derive_public_key(index)?.x_only_public_key();
+                TaprootSpendInfo::new_key_spend(internal_key.serialize())?
+                    .script_pubkey()
+                    .into()
+            }
+        })
+    }
+
+    pub fn address(&self, index: u32, network: Network) -> Result<Address, BitcoinError> {
+        Address::from_script(&self.script_pubkey(index)?, network)
+    }
+
+    // The form importdescriptors expects, with the #checksum suffix
+    pub fn to_string_with_checksum(&self) -> String {
+        add_checksum(&self.to_string()).expect("descriptors display in the checksum charset")
+    }
+
+    fn from_tree(tree: &Tree) -> Result<Self, BitcoinError> {
+        let inner = &tree.args_exactly(1)?[0];
+        let key = || inner.terminal()?.parse::<DescriptorKey>();
+        let descriptor = match (tree.name, inner.name) {
+            ("pkh", _) => Descriptor::Pkh(key()?),
+            ("wpkh", _) => Descriptor::Wpkh(key()?),
+            ("tr", _) => Descriptor::Tr(key()?),
+            ("sh", "wpkh") => Descriptor::ShWpkh(inner.args_exactly(1)?[0].terminal()?.parse()?),
+            ("sh", "wsh") => Descriptor::ShWsh(Multisig::from_tree(&inner.args_exactly(1)?[0])?),
+            ("sh", _) => {
+                let multi = Multisig::from_tree(inner)?;
+                if multi.keys.len() > MAX_P2SH_MULTISIG_KEYS {
+                    return Err(BitcoinError::Usage(UsageError::InvalidMultisig {
+                        required: multi.required,
+                        keys: multi.keys.len(),
+                    }));
+                }
+                Descriptor::Sh(multi)
+            }
+            ("wsh", _) => Descriptor::Wsh(Multisig::from_tree(inner)?),
+            (name, _) => {
+                return Err(BitcoinError::Parse(ParseError::Message(format!(
+                    "Unsupported descriptor {name:?}"
+                ))))
+            }
+        };
+        // Only Taproot keys are x-only
+        let taproot = matches!(descriptor, Descriptor::Tr(_));
+        let x_only = |key: &&DescriptorKey| matches!(key, DescriptorKey::XOnly { .. });
+        if !taproot && descriptor.keys().iter().any(x_only) {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        // Segwit scripts only commit to compressed keys
+        let segwit = !matches!(descriptor, Descriptor::Pkh(_) | Descriptor::Sh(_));
+        if segwit && !descriptor.keys().iter().all(|key| key.is_compressed()) {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        Ok(descriptor)
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = BitcoinError;
+
+    // The #checksum suffix is optional, but must match when present
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let descriptor = if s.contains('#') {
+            verify_checksum(s)?
+        } else {
+            s
+        };
+        Descriptor::from_tree(&Tree::parse(descriptor)?)
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Descriptor::Pkh(key) => write!(f, "pkh({key})"),
+            Descriptor::Wpkh(key) => write!(f, "wpkh({key})"),
+            Descriptor::ShWpkh(key) => write!(f, "sh(wpkh({key}))"),
+            Descriptor::Sh(multi) => write!(f, "sh({multi})"),
+            Descriptor::Wsh(multi) => write!(f, "wsh({multi})"),
+            Descriptor::ShWsh(multi) => write!(f, "sh(wsh({multi}))"),
+            Descriptor::Tr(key) => write!(f, "tr({key})"),
+        }
+    }
+}
+
+// Characters a descriptor may contain, in groups of 32 so the checksum
+// catches case errors and swaps within a group more strongly
+const INPUT_CHARSET: &[u8] =
+    b"0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LEN: usize = 8;
+
+// One step of the BCH code over GF(32) behind Bitcoin Core's descriptor
+// checksums
+fn polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let top = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if top >> i & 1 != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+fn checksum(descriptor: &str) -> Result<String, BitcoinError> {
+    let mut c = 1;
+    let mut groups = 0;
+    let mut group_count = 0;
+    for ch in descriptor.bytes() {
+        let position = INPUT_CHARSET
+            .iter()
+            .position(|&allowed| allowed == ch)
+            .ok_or_else(|| {
+                BitcoinError::Parse(ParseError::Message(format!(
+                    "Invalid descriptor character {:?}",
+                    ch as char
+                )))
+            })? as u64;
+        // The low 5 bits go in directly, the group index three at a time
+        c = polymod(c, position & 31);
+        groups = groups * 3 + (position >> 5);
+        group_count += 1;
+        if group_count == 3 {
+            c = polymod(c, groups);
+            groups = 0;
+            group_count = 0;
+        }
+    }
+    if group_count > 0 {
+        c = polymod(c, groups);
+    }
+    for _ in 0..CHECKSUM_LEN {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..CHECKSUM_LEN)
+        .map(|i| CHECKSUM_CHARSET[(c >> (5 * (CHECKSUM_LEN - 1 - i)) & 31) as usize] as char)
+        .collect())
+}
+
+// Appends "#checksum" to a descriptor string without one
+pub fn add_checksum(descriptor: &str) -> Result<String, BitcoinError> {
+    Ok(format!("{descriptor}#{}", checksum(descriptor)?))
+}
+
+// Checks the "#checksum" suffix, returning the descriptor before it
+pub fn verify_checksum(s: &str) -> Result<&str, BitcoinError> {
+    let (descriptor, given) = s.rsplit_once('#').ok_or_else(|| {
+        BitcoinError::Parse(ParseError::Message(
+            "Descriptor has no checksum".to_string(),
+        ))
+    })?;
+    let expected = checksum(descriptor)?;
+    if given != expected {
+        return Err(BitcoinError::Parse(ParseError::Message(format!(
+            "Descriptor checksum {given:?} doesn't match {expected:?}"
+        ))));
+    }
+    Ok(descriptor)
+}
@@ -0,0 +1,303 @@
+// Client for Electrum servers (electrs, ElectrumX, Fulcrum): JSON-RPC 2.0
+// requests and notifications, one per line over a TCP connection. There
+// is no TLS, so this is the plain port, 50001 on mainnet.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::json::Json;
+#[cfg(feature = "async")]
+use crate::task::{Task, Worker};
+use crate::wallet::HistoryEntry;
+use crate::{
+    BitcoinError, BitcoinSerialize, LegacyTransaction, ParseError, RemoteError, ScriptHash, Txid,
+};
+
+// The protocol version this client speaks
+pub const PROTOCOL_VERSION: &str = "1.4";
+
+// For each read and write, as for the HTTP clients
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// The longest line read, newline included. A transaction as hex is under
+// 8 MiB; the rest is headroom for long histories.
+const MAX_MESSAGE_SIZE: u64 = 32 * 1024 * 1024;
+
+// How long AsyncClient::next_notification reads before other calls get a
+// turn
+#[cfg(feature = "async")]
+const NOTIFICATION_POLL: Duration = Duration::from_millis(100);
+
+// A subscribed script's new status: a hash of its history, which changes
+// whenever a transaction touching the script arrives or confirms
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptHashStatus {
+    pub script_hash: ScriptHash,
+    // None for a script with no history
+    pub status: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+    // The part of a line read before a timeout
+    line: Vec<u8>,
+    // Arrived while waiting for a response
+    notifications: VecDeque<ScriptHashStatus>,
+}
+
+impl Client {
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self, BitcoinError> {
+        let writer = TcpStream::connect(address)?;
+        writer.set_read_timeout(Some(TIMEOUT))?;
+        writer.set_write_timeout(Some(TIMEOUT))?;
+        Ok(Client {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            next_id: 0,
+            line: Vec::new(),
+            notifications: VecDeque::new(),
+        })
+    }
+
+    // Sends one request and waits for its response, queueing any
+    // notifications that arrive first
+    fn call(&mut self, method: &str, params: Vec<Json>) -> Result<Json, BitcoinError> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut request = Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", Json::Array(params)),
+        ])
+        .to_string();
+        request.push('\n');
+        self.writer.write_all(request.as_bytes())?;
+        loop {
+            let message = self.read_message()?;
+            if message.get("id").and_then(Json::as_u64) != Some(id) {
+                self.queue_notification(&message);
+                continue;
+            }
+            match message.get("error") {
+                None | Some(Json::Null) => {}
+                // Servers send an object with a code, or just a message
+                Some(error) => {
+                    return Err(BitcoinError::Remote(RemoteError::Rpc {
+                        code: error.get("code").and_then(Json::as_i64).unwrap_or(0),
+                        message: error
+                            .get("message")
+                            .or(Some(error))
+                            .and_then(Json::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                    }))
+                }
+            }
+            return message
+                .get("result")
+                .cloned()
+                .ok_or_else(|| invalid_response(method));
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Json, BitcoinError> {
+        let remaining = MAX_MESSAGE_SIZE.saturating_sub(self.line.len() as u64);
+        (&mut self.reader)
+            .take(remaining)
+            .read_until(b'\n', &mut self.line)?;
+        if self.line.last() != Some(&b'\n') && self.line.len() as u64 >= MAX_MESSAGE_SIZE {
+            self.line.clear();
+            return Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid Electrum message: larger than {MAX_MESSAGE_SIZE} bytes"
+            ))));
+        }
+        if self.line.last() != Some(&b'\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Electrum server closed the connection",
+            )
+            .into());
+        }
+        let line = std::mem::take(&mut self.line);
+        let text = std::str::from_utf8(&line).map_err(|_| {
+            BitcoinError::Parse(ParseError::Message(
+                "Electrum message isn't UTF-8".to_string(),
+            ))
+        })?;
+        Json::parse(text.trim_end())
+    }
+
+    // Keeps script hash notifications; header notifications aren't
+    // subscribed to, so anything else is dropped
+    fn queue_notification(&mut self, message: &Json) {
+        if message.get("method").and_then(Json::as_str) != Some("blockchain.scripthash.subscribe") {
+            return;
+        }
+        let Some([script_hash, status]) = message.get("params").and_then(Json::as_array) else {
+            return;
+        };
+        if let Some(script_hash) = script_hash.as_str().and_then(|s| s.parse().ok()) {
+            self.notifications.push_back(ScriptHashStatus {
+                script_hash,
+                status: status.as_str().map(str::to_string),
+            });
+        }
+    }
+
+    // The next status change of a subscribed script, waiting for one if
+    // none has arrived. A wait past the timeout returns an I/O error, and
+    // can be retried.
+    pub fn next_notification(&mut self) -> Result<ScriptHashStatus, BitcoinError> {
+        loop {
+            if let Some(notification) = self.notifications.pop_front() {
+                return Ok(notification);
+            }
+            let message = self.read_message()?;
+            self.queue_notification(&message);
+        }
+    }
+
+    // next_notification, waiting at most `wait` (which must be nonzero)
+    // for one
+    pub fn poll_notification(
+        &mut self,
+        wait: Duration,
+    ) -> Result<Option<ScriptHashStatus>, BitcoinError> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Ok(Some(notification));
+        }
+        self.writer.set_read_timeout(Some(wait))?;
+        let message = self.read_message();
+        self.writer.set_read_timeout(Some(TIMEOUT))?;
+        match message {
+            Ok(message) => self.queue_notification(&message),
+            Err(BitcoinError::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(self.notifications.pop_front())
+    }
+
+    // Negotiates the protocol version, as servers expect first. Returns
+    // the server's software version.
+    pub fn server_version(&mut self, client_name: &str) -> Result<String, BitcoinError> {
+        let method = "server.version";
+        let result = self.call(method, vec![client_name.into(), PROTOCOL_VERSION.into()])?;
+        match result.as_array() {
+            Some([software, _protocol]) => software.as_str().map(str::to_string),
+            _ => None,
+        }
+        .ok_or_else(|| invalid_response(method))
+    }
+
+    // Subscribes to the script's status changes, returning the current
+    // status
+    pub fn script_hash_subscribe(
+        &mut self,
+        script_hash: &ScriptHash,
+    ) -> Result<Option<String>, BitcoinError> {
+        let result = self.call(
+            "blockchain.scripthash.subscribe",
+            vec![script_hash.to_string().into()],
+        )?;
+        Ok(result.as_str().map(str::to_string))
+    }
+
+    // Confirmed transactions in chain order, then the mempool's
+    pub fn script_hash_get_history(
+        &mut self,
+        script_hash: &ScriptHash,
+    ) -> Result<Vec<HistoryEntry>, BitcoinError> {
+        let method = "blockchain.scripthash.get_history";
+        let result = self.call(method, vec![script_hash.to_string().into()])?;
+        let history = result.as_array().ok_or_else(|| invalid_response(method))?;
+        history
+            .iter()
+            .map(|entry| {
+                let txid = entry
+                    .get("tx_hash")
+                    .and_then(Json::as_str)
+                    .and_then(|txid| txid.parse().ok());
+                let height = entry.get("height").and_then(Json::as_i64);
+                let (Some(txid), Some(height)) = (txid, height) else {
+                    return Err(invalid_response(method));
+                };
+                // 0 for the mempool, or -1 there with unconfirmed parents
+                Ok(HistoryEntry {
+                    txid,
+                    height: u32::try_from(height).ok().filter(|&height| height > 0),
+                })
+            })
+            .collect()
+    }
+
+    pub fn transaction_get(&mut self, txid: &Txid) -> Result<LegacyTransaction, BitcoinError> {
+        let method = "blockchain.transaction.get";
+        self.call(method, vec![txid.to_string().into()])?
+            .as_str()
+            .ok_or_else(|| invalid_response(method))?
+            .parse()
+    }
+
+    pub fn transaction_broadcast(&mut self, tx: &LegacyTransaction) -> Result<Txid, BitcoinError> {
+        let method = "blockchain.transaction.broadcast";
+        self.call(method, vec![tx.to_hex().into()])?
+            .as_str()
+            .ok_or_else(|| invalid_response(method))?
+            .parse()
+    }
+}
+
+// The client's calls as futures, for async services. The calls share the
+// one connection, so a Worker runs them in the order they're made.
+#[cfg(feature = "async")]
+pub type AsyncClient = Worker<Client>;
+
+#[cfg(feature = "async")]
+impl AsyncClient {
+    // Waits in short polls, letting calls made meanwhile run between them
+    pub fn next_notification(&self) -> Task<Result<ScriptHashStatus, BitcoinError>> {
+        self.run_until(|client| client.poll_notification(NOTIFICATION_POLL).transpose())
+    }
+
+    pub fn server_version(&self, client_name: String) -> Task<Result<String, BitcoinError>> {
+        self.run(move |client| client.server_version(&client_name))
+    }
+
+    pub fn script_hash_subscribe(
+        &self,
+        script_hash: ScriptHash,
+    ) -> Task<Result<Option<String>, BitcoinError>> {
+        self.run(move |client| client.script_hash_subscribe(&script_hash))
+    }
+
+    pub fn script_hash_get_history(
+        &self,
+        script_hash: ScriptHash,
+    ) -> Task<Result<Vec<HistoryEntry>, BitcoinError>> {
+        self.run(move |client| client.script_hash_get_history(&script_hash))
+    }
+
+    pub fn transaction_get(&self, txid: Txid) -> Task<Result<LegacyTransaction, BitcoinError>> {
+        self.run(move |client| client.transaction_get(&txid))
+    }
+
+    pub fn transaction_broadcast(&self, tx: LegacyTransaction) -> Task<Result<Txid, BitcoinError>> {
+        self.run(move |client| client.transaction_broadcast(&tx))
+    }
+}
+
+fn invalid_response(method: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(format!(
+        "Unexpected response to {method}"
+    )))
+}
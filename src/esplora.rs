@@ -0,0 +1,285 @@
+// Client for the Esplora REST API (Blockstream's and mempool.space's block
+// explorers), a chain source for the watch-only wallet that needs no node
+// of one's own. https:// URLs go over TLS through the system's OpenSSL.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use crate::http::{self, Url};
+use crate::json::Json;
+#[cfg(feature = "async")]
+use crate::task::{self, Task};
+use crate::wallet::{HistoryEntry, Wallet, WalletUtxo};
+use crate::{
+    Address, Amount, BitcoinError, BitcoinSerialize, FeeRate, LegacyTransaction, OutPoint,
+    ParseError, TxOutput, Txid,
+};
+
+// Confirmed transactions per page of an address's history
+const CHAIN_PAGE_SIZE: usize = 25;
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    base: Url,
+}
+
+impl Client {
+    // `base_url` is the API root, e.g. https://blockstream.info/api, or
+    // http://localhost:3000 for a local electrs
+    pub fn new(base_url: &str) -> Result<Self, BitcoinError> {
+        Ok(Client {
+            base: Url::parse_with_https(base_url)?,
+        })
+    }
+
+    // Trusts the certificates in a PEM file for https, besides the
+    // system's: for a server with a certificate of its own making
+    pub fn with_ca_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.base.ca_file = Some(path.into());
+        self
+    }
+
+    fn url(&self, path: &str) -> Url {
+        let mut url = self.base.clone();
+        url.path = format!("{}{path}", self.base.path.trim_end_matches('/'));
+        url
+    }
+
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        match &self.base.credentials {
+            Some((user, password)) => vec![("Authorization", http::basic_auth(user, password))],
+            None => Vec::new(),
+        }
+    }
+
+    fn get(&self, path: &str) -> Result<Vec<u8>, BitcoinError> {
+        http::request("GET", &self.url(path), &self.headers(), &[])?.ok()
+    }
+
+    fn get_text(&self, path: &str) -> Result<String, BitcoinError> {
+        String::from_utf8(self.get(path)?)
+            .map(|text| text.trim().to_string())
+            .map_err(|_| invalid_response(path))
+    }
+
+    fn get_json(&self, path: &str) -> Result<Json, BitcoinError> {
+        Json::parse(&self.get_text(path)?)
+    }
+
+    pub fn tip_height(&self) -> Result<u32, BitcoinError> {
+        let path = "/blocks/tip/height";
+        self.get_text(path)?
+            .parse()
+            .map_err(|_| invalid_response(path))
+    }
+
+    pub fn get_transaction(&self, txid: &Txid) -> Result<LegacyTransaction, BitcoinError> {
+        self.get_text(&format!("/tx/{txid}/hex"))?.parse()
+    }
+
+    // Every transaction paying to or spending from the address, newest
+    // first with the mempool's ahead of the confirmed ones
+    pub fn address_history(&self, address: &Address) -> Result<Vec<HistoryEntry>, BitcoinError> {
+        let path = format!("/address/{address}/txs");
+        // The first page has the mempool transactions and the newest
+        // confirmed ones; the rest are paged after the last txid seen
+        let mut history = parse_history(&self.get_json(&path)?, &path)?;
+        let mut page_len = history.iter().filter(|tx| tx.height.is_some()).count();
+        while page_len >= CHAIN_PAGE_SIZE {
+            let Some(last) = history.last() else { break };
+            let path = format!("/address/{address}/txs/chain/{}", last.txid);
+            let page = parse_history(&self.get_json(&path)?, &path)?;
+            page_len = page.len();
+            history.extend(page);
+        }
+        Ok(history)
+    }
+
+    pub fn address_utxos(&self, address: &Address) -> Result<Vec<WalletUtxo>, BitcoinError> {
+        let path = format!("/address/{address}/utxo");
+        let script_pubkey = address.script_pubkey().into_bytes();
+        let utxos = self.get_json(&path)?;
+        let utxos = utxos.as_array().ok_or_else(|| invalid_response(&path))?;
+        utxos
+            .iter()
+            .map(|utxo| {
+                let txid = utxo
+                    .get("txid")
+                    .and_then(Json::as_str)
+                    .and_then(|txid| txid.parse().ok());
+                let vout = utxo.get("vout").and_then(Json::as_u64);
+                let value = utxo.get("value").and_then(Json::as_u64);
+                let (Some(txid), Some(vout), Some(value)) = (txid, vout, value) else {
+                    return Err(invalid_response(&path));
+                };
+                Ok(WalletUtxo {
+                    outpoint: OutPoint::new(txid, vout as u32),
+                    output: TxOutput {
+                        value: Amount::from_sat(value),
+                        script_pubkey: script_pubkey.clone(),
+                    },
+                    height: status_height(utxo),
+                })
+            })
+            .collect()
+    }
+
+    // Fee rates to confirm within each number of blocks the server
+    // estimates for
+    pub fn fee_estimates(&self) -> Result<BTreeMap<u16, FeeRate>, BitcoinError> {
+        let path = "/fee-estimates";
+        let Json::Object(estimates) = self.get_json(path)? else {
+            return Err(invalid_response(path));
+        };
+        estimates
+            .iter()
+            .map(|(target, rate)| {
+                // In sat/vB, with a fraction
+                let rate = rate.as_f64().filter(|rate| *rate >= 0.0);
+                match (target.parse(), rate) {
+                    (Ok(target), Some(rate)) => Ok((
+                        target,
+                        FeeRate::from_sat_per_kwu((rate * 250.0).round() as u64),
+                    )),
+                    _ => Err(invalid_response(path)),
+                }
+            })
+            .collect()
+    }
+
+    pub fn broadcast(&self, tx: &LegacyTransaction) -> Result<Txid, BitcoinError> {
+        let response = http::request(
+            "POST",
+            &self.url("/tx"),
+            &self.headers(),
+            tx.to_hex().as_bytes(),
+        )?;
+        String::from_utf8_lossy(&response.ok()?).trim().parse()
+    }
+
+    // Watches the addresses and scans their histories into the wallet,
+    // then moves its tip to the server's. Returns how many transactions
+    // were scanned.
+    pub fn sync_wallet(
+        &self,
+        wallet: &mut Wallet,
+        addresses: &[Address],
+    ) -> Result<usize, BitcoinError> {
+        let mut history = Vec::new();
+        let mut seen = HashSet::new();
+        for address in addresses {
+            wallet.watch_address(address);
+            for entry in self.address_history(address)? {
+                // A payment between two of the addresses is in both
+                if seen.insert(entry.txid) {
+                    history.push(entry);
+                }
+            }
+        }
+        history.sort_by_key(|entry| entry.height.unwrap_or(u32::MAX));
+        let mut pending = Vec::with_capacity(history.len());
+        for entry in &history {
+            let tx = self.get_transaction(&entry.txid)?;
+            if tx.txid() != entry.txid {
+                return Err(invalid_response("/tx"));
+            }
+            pending.push((entry.height, tx));
+        }
+        // A spend must be scanned after the output it spends, and within a
+        // block the history doesn't say which came first, so each step
+        // takes the lowest transaction spending none of those left
+        let count = pending.len();
+        while !pending.is_empty() {
+            let next = pending
+                .iter()
+                .position(|(_, tx)| {
+                    !tx.inputs
+                        .iter()
+                        .any(|input| seen.contains(&input.previous_output.txid))
+                })
+                .unwrap_or(0);
+            let (height, tx) = pending.remove(next);
+            seen.remove(&tx.txid());
+            wallet.scan_transaction(&tx, height);
+        }
+        wallet.set_tip_height(self.tip_height()?);
+        Ok(count)
+    }
+}
+
+// The client's calls as futures, for async services. HTTP requests share
+// nothing, so calls made together run at once.
+#[cfg(feature = "async")]
+pub type AsyncClient = task::AsyncClient<Client>;
+
+#[cfg(feature = "async")]
+impl AsyncClient {
+    pub fn tip_height(&self) -> Task<Result<u32, BitcoinError>> {
+        self.run(Client::tip_height)
+    }
+
+    pub fn get_transaction(&self, txid: Txid) -> Task<Result<LegacyTransaction, BitcoinError>> {
+        self.run(move |client| client.get_transaction(&txid))
+    }
+
+    pub fn address_history(
+        &self,
+        address: Address,
+    ) -> Task<Result<Vec<HistoryEntry>, BitcoinError>> {
+        self.run(move |client| client.address_history(&address))
+    }
+
+    pub fn address_utxos(&self, address: Address) -> Task<Result<Vec<WalletUtxo>, BitcoinError>> {
+        self.run(move |client| client.address_utxos(&address))
+    }
+
+    pub fn fee_estimates(&self) -> Task<Result<BTreeMap<u16, FeeRate>, BitcoinError>> {
+        self.run(Client::fee_estimates)
+    }
+
+    pub fn broadcast(&self, tx: LegacyTransaction) -> Task<Result<Txid, BitcoinError>> {
+        self.run(move |client| client.broadcast(&tx))
+    }
+
+    // Syncs a copy of the wallet, which the future resolves to with the
+    // number of transactions scanned
+    pub fn sync_wallet(
+        &self,
+        mut wallet: Wallet,
+        addresses: Vec<Address>,
+    ) -> Task<Result<(Wallet, usize), BitcoinError>> {
+        self.run(move |client| {
+            let scanned = client.sync_wallet(&mut wallet, &addresses)?;
+            Ok((wallet, scanned))
+        })
+    }
+}
+
+fn parse_history(page: &Json, path: &str) -> Result<Vec<HistoryEntry>, BitcoinError> {
+    let page = page.as_array().ok_or_else(|| invalid_response(path))?;
+    page.iter()
+        .map(|tx| {
+            let txid = tx
+                .get("txid")
+                .and_then(Json::as_str)
+                .and_then(|txid| txid.parse().ok())
+                .ok_or_else(|| invalid_response(path))?;
+            Ok(HistoryEntry {
+                txid,
+                height: status_height(tx),
+            })
+        })
+        .collect()
+}
+
+// Unconfirmed transactions have no block_height in their status
+fn status_height(item: &Json) -> Option<u32> {
+    let height = item.get("status")?.get("block_height")?.as_u64()?;
+    u32::try_from(height).ok()
+}
+
+fn invalid_response(path: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(format!(
+        "Unexpected response from {path}"
+    )))
+}
@@ -0,0 +1,79 @@
+// The `name(arg,arg,...)` syntax shared by miniscript policies and output
+// descriptors. Terminals such as keys and numbers are nodes without
+// arguments.
+
+use crate::{BitcoinError, ParseError};
+
+// Deeper nesting is rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tree<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) args: Vec<Tree<'a>>,
+}
+
+impl<'a> Tree<'a> {
+    pub(crate) fn parse(s: &'a str) -> Result<Self, BitcoinError> {
+        let (tree, rest) = Self::parse_prefix(s, 0)?;
+        if !rest.is_empty() {
+            return Err(parse_error(format!("Unexpected {rest:?} after expression")));
+        }
+        Ok(tree)
+    }
+
+    fn parse_prefix(s: &'a str, depth: usize) -> Result<(Self, &'a str), BitcoinError> {
+        if depth > MAX_DEPTH {
+            return Err(parse_error("Expression nested too deeply".to_string()));
+        }
+        let end = s.find(['(', ',', ')']).unwrap_or(s.len());
+        let (name, mut rest) = s.split_at(end);
+        if name.is_empty() {
+            return Err(parse_error(format!("Missing name before {rest:?}")));
+        }
+        let mut args = Vec::new();
+        if let Some(inner) = rest.strip_prefix('(') {
+            rest = inner;
+            loop {
+                let (arg, after) = Self::parse_prefix(rest, depth + 1)?;
+                args.push(arg);
+                if let Some(after) = after.strip_prefix(',') {
+                    rest = after;
+                } else if let Some(after) = after.strip_prefix(')') {
+                    rest = after;
+                    break;
+                } else {
+                    return Err(parse_error(format!("Missing ')' after {name}(")));
+                }
+            }
+        }
+        Ok((Tree { name, args }, rest))
+    }
+
+    // The node's name, checking it takes no arguments
+    pub(crate) fn terminal(&self) -> Result<&'a str, BitcoinError> {
+        if !self.args.is_empty() {
+            return Err(parse_error(format!(
+                "Unexpected arguments to {}",
+                self.name
+            )));
+        }
+        Ok(self.name)
+    }
+
+    // The node's arguments, checking there are exactly `n`
+    pub(crate) fn args_exactly(&self, n: usize) -> Result<&[Tree<'a>], BitcoinError> {
+        if self.args.len() != n {
+            return Err(parse_error(format!(
+                "{} takes {n} argument(s), got {}",
+                self.name,
+                self.args.len()
+            )));
+        }
+        Ok(&self.args)
+    }
+}
+
+fn parse_error(message: String) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(message))
+}
@@ -0,0 +1,134 @@
+// Transaction weight and fee rates. Rates are kept in sat/kWU so that
+// fees can be computed from weight without losing precision.
+
+use std::fmt;
+
+use crate::script::{is_unspendable, witness_program};
+use crate::{Amount, BitcoinSerialize, TxOutput};
+
+// Bitcoin Core's default -dustrelayfee, 3 sat/vB
+pub const DUST_RELAY_FEE: FeeRate = FeeRate(750);
+
+// BIP141 weight units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Weight(u64);
+
+impl Weight {
+    pub const ZERO: Weight = Weight(0);
+    pub const WITNESS_SCALE_FACTOR: u64 = 4;
+
+    pub const fn from_wu(wu: u64) -> Self {
+        Weight(wu)
+    }
+
+    // None if the weight doesn't fit in a u64
+    pub fn from_vb(vb: u64) -> Option<Self> {
+        vb.checked_mul(Self::WITNESS_SCALE_FACTOR).map(Weight)
+    }
+
+    pub const fn to_wu(self) -> u64 {
+        self.0
+    }
+
+    // Virtual size, rounded up as in LegacyTransaction::vsize
+    pub fn to_vbytes_ceil(self) -> u64 {
+        self.0.div_ceil(Self::WITNESS_SCALE_FACTOR)
+    }
+
+    pub fn checked_add(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_add(rhs.0).map(Weight)
+    }
+
+    pub fn checked_sub(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_sub(rhs.0).map(Weight)
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} WU", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+    // Bitcoin Core's default minimum relay fee, 1 sat/vB
+    pub const MIN_RELAY: FeeRate = FeeRate(250);
+
+    pub const fn from_sat_per_kwu(sat_kwu: u64) -> Self {
+        FeeRate(sat_kwu)
+    }
+
+    // One vbyte is four weight units, so 1 sat/vB is 250 sat/kWU. None if
+    // that overflows.
+    pub fn from_sat_per_vb(sat_vb: u64) -> Option<Self> {
+        sat_vb.checked_mul(250).map(FeeRate)
+    }
+
+    pub const fn to_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+
+    pub fn to_sat_per_vb_floor(self) -> u64 {
+        self.0 / 250
+    }
+
+    pub fn to_sat_per_vb_ceil(self) -> u64 {
+        self.0.div_ceil(250)
+    }
+
+    // Rounded up to the next satoshi, so the rate paid is never below
+    // `self`. None on overflow.
+    pub fn fee_for_weight(self, weight: Weight) -> Option<Amount> {
+        let fee = self.0.checked_mul(weight.0)?.div_ceil(1000);
+        Some(Amount::from_sat(fee))
+    }
+
+    pub fn checked_add(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_add(rhs.0).map(FeeRate)
+    }
+
+    pub fn checked_sub(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_sub(rhs.0).map(FeeRate)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_mul(rhs).map(FeeRate)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} sat/kWU", self.0)
+    }
+}
+
+impl TxOutput {
+    // Bitcoin Core's GetDustThreshold: the fee, at `dust_relay_fee`, of
+    // this output plus an input spending it. Unspendable outputs are never
+    // dust.
+    pub fn dust_threshold(&self, dust_relay_fee: FeeRate) -> Amount {
+        if is_unspendable(&self.script_pubkey) {
+            return Amount::ZERO;
+        }
+        // Outpoint, sequence and an estimated signature; a segwit spend
+        // moves the signature into the discounted witness
+        let spend_size = if witness_program(&self.script_pubkey).is_some() {
+            32 + 4 + 1 + 107 / 4 + 4
+        } else {
+            32 + 4 + 1 + 107 + 4
+        };
+        let size = (BitcoinSerialize::serialized_size(self) + spend_size) as u64;
+        dust_relay_fee
+            .fee_for_weight(Weight::from_wu(size * Weight::WITNESS_SCALE_FACTOR))
+            .unwrap_or(Amount::from_sat(u64::MAX))
+    }
+
+    // Whether spending this output would cost more than it is worth
+    pub fn is_dust(&self, dust_relay_fee: FeeRate) -> bool {
+        self.value < self.dust_threshold(dust_relay_fee)
+    }
+}
@@ -0,0 +1,99 @@
+// Distinct types for the 32-byte hashes that identify chain objects, so a
+// txid can't be passed where a wtxid is expected and vice versa
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::hashes::sha256;
+use crate::{hex, BitcoinError, Hash256, ParseError};
+
+// Stored in internal byte order (as hashed and as serialized); displayed and
+// parsed byte-reversed, the way Bitcoin Core shows them
+macro_rules! hash_newtype {
+    ($name:ident, $what:literal) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+        pub struct $name([u8; 32]);
+
+        impl $name {
+            pub const fn all_zeros() -> Self {
+                $name([0; 32])
+            }
+
+            pub const fn from_byte_array(bytes: [u8; 32]) -> Self {
+                $name(bytes)
+            }
+
+            pub fn to_byte_array(self) -> [u8; 32] {
+                self.0
+            }
+
+            pub fn as_bytes(&self) -> &[u8; 32] {
+                &self.0
+            }
+        }
+
+        impl From<Hash256> for $name {
+            fn from(hash: Hash256) -> Self {
+                $name(hash.to_byte_array())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut reversed = self.0;
+                reversed.reverse();
+                f.write_str(&hex::encode(&reversed))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = BitcoinError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let mut bytes: [u8; 32] = hex::decode(s)?.try_into().map_err(|_| {
+                    BitcoinError::Parse(ParseError::Message(
+                        concat!($what, " must be 32 bytes").to_string(),
+                    ))
+                })?;
+                bytes.reverse();
+                Ok($name(bytes))
+            }
+        }
+
+        // As the byte-reversed hex string
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                crate::serde_support::deserialize_from_str(deserializer)
+            }
+        }
+    };
+}
+
+// Hash of a transaction without its witness; what outpoints refer to
+hash_newtype!(Txid, "Txid");
+// Hash of a transaction including its witness (BIP141)
+hash_newtype!(Wtxid, "Wtxid");
+// Double SHA-256 of an 80-byte block header
+hash_newtype!(BlockHash, "BlockHash");
+// Double SHA-256 of a serialized BIP158 block filter
+hash_newtype!(FilterHash, "FilterHash");
+// Commits to a block filter and, through the previous one, every filter
+// before it (BIP157)
+hash_newtype!(FilterHeader, "FilterHeader");
+// Single SHA-256 of a scriptPubKey, by which Electrum servers index the
+// transactions paying to it
+hash_newtype!(ScriptHash, "ScriptHash");
+
+impl ScriptHash {
+    pub fn from_script(script_pubkey: &[u8]) -> Self {
+        sha256(script_pubkey).into()
+    }
+}
@@ -0,0 +1,189 @@
+// Hash functions used throughout Bitcoin, with fixed-size output types
+
+use std::fmt;
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{hex, BitcoinError, ParseError};
+
+// Output of SHA-256 or double SHA-256, in the order the hash function produced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Hash256([u8; 32]);
+
+// RIPEMD-160 of SHA-256, as used in P2PKH/P2SH/P2WPKH outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Hash160([u8; 20]);
+
+macro_rules! impl_hash {
+    ($name:ident, $len:literal) => {
+        impl $name {
+            pub const fn from_byte_array(bytes: [u8; $len]) -> Self {
+                $name(bytes)
+            }
+
+            pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+                let bytes: [u8; $len] = bytes.try_into().map_err(|_| {
+                    BitcoinError::Parse(ParseError::Message(
+                        concat!("Hash must be ", $len, " bytes").to_string(),
+                    ))
+                })?;
+                Ok($name(bytes))
+            }
+
+            pub fn to_byte_array(self) -> [u8; $len] {
+                self.0
+            }
+
+            pub fn as_bytes(&self) -> &[u8; $len] {
+                &self.0
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&hex::encode(&self.0))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = BitcoinError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $name::from_slice(&hex::decode(s)?)
+            }
+        }
+    };
+}
+
+impl_hash!(Hash256, 32);
+impl_hash!(Hash160, 20);
+
+pub fn sha256(data: &[u8]) -> Hash256 {
+    Hash256(Sha256::digest(data).into())
+}
+
+// SHA-256 applied twice, used for txids, block hashes and checksums
+pub fn sha256d(data: &[u8]) -> Hash256 {
+    Hash256(Sha256::digest(Sha256::digest(data)).into())
+}
+
+pub fn hash160(data: &[u8]) -> Hash160 {
+    Hash160(Ripemd160::digest(Sha256::digest(data)).into())
+}
+
+pub fn ripemd160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(data).into()
+}
+
+// BIP340 tagged hash: SHA256(SHA256(tag) || SHA256(tag) || msg)
+pub fn tagged_hash(tag: &str, parts: &[&[u8]]) -> Hash256 {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for part in parts {
+        hasher.update(part);
+    }
+    Hash256(hasher.finalize().into())
+}
+
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+// PBKDF2 with a single 64-byte output block, as BIP39 uses it
+pub fn pbkdf2_hmac_sha512(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 64] {
+    let keyed = Hmac::<Sha512>::new_from_slice(password).expect("HMAC accepts any key length");
+    let mut mac = keyed.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut u: [u8; 64] = mac.finalize().into_bytes().into();
+    let mut output = u;
+    for _ in 1..iterations {
+        let mut mac = keyed.clone();
+        mac.update(&u);
+        u = mac.finalize().into_bytes().into();
+        for (o, b) in output.iter_mut().zip(u.iter()) {
+            *o ^= b;
+        }
+    }
+    output
+}
+
+// SipHash-2-4 keyed with (k0, k1), as BIP158 uses it to map filter items
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    // The last block holds the leftover bytes and the length's low byte
+    let mut last = [0u8; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    compress(u64::from_le_bytes(last));
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+// MurmurHash3 (x86, 32-bit), the hash BIP37 bloom filters use
+pub fn murmur3_32(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let scramble = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    let mut h = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        h ^= scramble(u32::from_le_bytes(chunk.try_into().unwrap()));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = [0u8; 4];
+        k[..tail.len()].copy_from_slice(tail);
+        h ^= scramble(u32::from_le_bytes(k));
+    }
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
@@ -0,0 +1,37 @@
+// Lowercase hex encoding and decoding
+
+use crate::{BitcoinError, ParseError};
+
+pub fn encode(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        s.push(DIGITS[(b >> 4) as usize] as char);
+        s.push(DIGITS[(b & 0x0F) as usize] as char);
+    }
+    s
+}
+
+// Accepts upper and lower case digits
+pub fn decode(s: &str) -> Result<Vec<u8>, BitcoinError> {
+    if !s.len().is_multiple_of(2) {
+        return Err(BitcoinError::Parse(ParseError::Message(
+            "Odd-length hex string".to_string(),
+        )));
+    }
+    fn digit(c: u8) -> Result<u8, BitcoinError> {
+        match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            _ => Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid hex character {:?}",
+                c as char
+            )))),
+        }
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| Ok(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
@@ -0,0 +1,241 @@
+// A minimal HTTP/1.1 client for the node clients. Plain http:// URLs work
+// everywhere; https:// takes TLS, which only the Esplora client has.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(feature = "esplora")]
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "esplora")]
+use crate::tls::TlsStream;
+use crate::{base64, BitcoinError, ParseError, RemoteError};
+
+// For each read and write
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// The most read of a response, headers included. A block as hex in a
+// JSON-RPC result is under 8 MiB; the rest is headroom for verbose results.
+const MAX_RESPONSE_SIZE: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    // Starts with '/'
+    pub(crate) path: String,
+    // From user:password@ before the host; callers add them to requests
+    pub(crate) credentials: Option<(String, String)>,
+    #[cfg(feature = "esplora")]
+    pub(crate) https: bool,
+    // A PEM file of certificates trusted for https besides the system's
+    #[cfg(feature = "esplora")]
+    pub(crate) ca_file: Option<PathBuf>,
+}
+
+impl Url {
+    pub(crate) fn parse(s: &str) -> Result<Self, BitcoinError> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid_url(s, "only http:// URLs are supported"))?;
+        Self::parse_after_scheme(s, rest, false)
+    }
+
+    // As parse, taking https:// URLs too
+    #[cfg(feature = "esplora")]
+    pub(crate) fn parse_with_https(s: &str) -> Result<Self, BitcoinError> {
+        match s.strip_prefix("https://") {
+            Some(rest) => Self::parse_after_scheme(s, rest, true),
+            None if s.starts_with("http://") => Self::parse(s),
+            None => Err(invalid_url(
+                s,
+                "only http:// and https:// URLs are supported",
+            )),
+        }
+    }
+
+    fn parse_after_scheme(s: &str, rest: &str, https: bool) -> Result<Self, BitcoinError> {
+        let invalid = |reason: &str| invalid_url(s, reason);
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (credentials, host_port) = match authority.rsplit_once('@') {
+            Some((userinfo, host_port)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_string(), password.to_string())), host_port)
+            }
+            None => (None, authority),
+        };
+        // IPv6 hosts are bracketed, so the port follows the last ':' after ']'
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid("bad port"))?)
+            }
+            _ => (host_port, if https { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            credentials,
+            #[cfg(feature = "esplora")]
+            https,
+            #[cfg(feature = "esplora")]
+            ca_file: None,
+        })
+    }
+}
+
+fn invalid_url(s: &str, reason: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(format!("Invalid URL {s:?}: {reason}")))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    // Errors for statuses other than 2xx, with the body as the message
+    pub(crate) fn ok(self) -> Result<Vec<u8>, BitcoinError> {
+        if !(200..300).contains(&self.status) {
+            return Err(BitcoinError::Remote(RemoteError::Http {
+                status: self.status,
+                message: String::from_utf8_lossy(&self.body).trim().to_string(),
+            }));
+        }
+        Ok(self.body)
+    }
+}
+
+// "Authorization" header value for HTTP basic authentication
+pub(crate) fn basic_auth(user: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64::encode(format!("{user}:{password}").as_bytes())
+    )
+}
+
+// One request on a fresh connection, which the server closes when done
+pub(crate) fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> Result<Response, BitcoinError> {
+    let stream = connect(url)?;
+    #[cfg(feature = "esplora")]
+    if url.https {
+        let stream = TlsStream::connect(stream, &url.host, url.ca_file.as_deref())?;
+        return exchange(stream, method, url, headers, body);
+    }
+    exchange(stream, method, url, headers, body)
+}
+
+fn connect(url: &Url) -> Result<TcpStream, BitcoinError> {
+    let address = (
+        url.host.trim_start_matches('[').trim_end_matches(']'),
+        url.port,
+    );
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+fn exchange(
+    mut stream: impl Read + Write,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> Result<Response, BitcoinError> {
+    let mut request = format!(
+        "{method} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        url.path,
+        url.host,
+        url.port,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    // The timeouts are per read, so a server trickling data could otherwise
+    // keep the response growing without end
+    let mut response = Vec::new();
+    stream
+        .take(MAX_RESPONSE_SIZE + 1)
+        .read_to_end(&mut response)?;
+    if response.len() as u64 > MAX_RESPONSE_SIZE {
+        return Err(BitcoinError::Parse(ParseError::Message(format!(
+            "Invalid HTTP response: larger than {MAX_RESPONSE_SIZE} bytes"
+        ))));
+    }
+    parse_response(&response)
+}
+
+fn parse_response(data: &[u8]) -> Result<Response, BitcoinError> {
+    let invalid = |reason: &str| {
+        BitcoinError::Parse(ParseError::Message(format!(
+            "Invalid HTTP response: {reason}"
+        )))
+    };
+    let end = data
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete headers"))?;
+    let head = std::str::from_utf8(&data[..end]).map_err(|_| invalid("non-UTF-8 headers"))?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid("bad status line"))?;
+    let mut chunked = false;
+    let mut length = None;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("bad header"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = Some(value.parse::<usize>().map_err(|_| invalid("bad length"))?);
+        }
+    }
+    let rest = &data[end + 4..];
+    let body = if chunked {
+        decode_chunked(rest).ok_or_else(|| invalid("bad chunked body"))?
+    } else if let Some(length) = length {
+        rest.get(..length)
+            .ok_or_else(|| invalid("body shorter than its length"))?
+            .to_vec()
+    } else {
+        rest.to_vec()
+    };
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..line_end]).ok()?;
+        // Chunk extensions follow a ';'
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
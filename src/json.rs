@@ -0,0 +1,486 @@
+// Minimal JSON values, enough to print command output and, for the node
+// clients, to read their responses
+
+use std::fmt;
+
+use crate::script::witness_program;
+use crate::{hex, Address, LegacyTransaction, Network, Script, ScriptType};
+#[cfg(feature = "rpc")]
+use crate::{Amount, Denomination};
+#[cfg(any(feature = "rpc", feature = "esplora", feature = "electrum"))]
+use crate::{BitcoinError, ParseError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    // Satoshis, written in BTC with eight decimals as Bitcoin Core does
+    Amount(u64),
+    // Numbers with a fraction or exponent, as parsed
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Keys are written in insertion order
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Self {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        Json::UInt(n)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Self {
+        Json::UInt(n as u64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Self {
+        Json::Int(n as i64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Self {
+        Json::Float(n)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(items: Vec<T>) -> Self {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+// Compact output, without whitespace
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::UInt(n) => write!(f, "{n}"),
+            Json::Int(n) => write!(f, "{n}"),
+            Json::Amount(sat) => write!(f, "{}.{:08}", sat / 100_000_000, sat % 100_000_000),
+            Json::Float(n) if n.is_finite() => write!(f, "{n}"),
+            Json::Float(_) => f.write_str("null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "rpc", feature = "esplora", feature = "electrum"))]
+impl Json {
+    // Strict RFC 8259 JSON, nested at most MAX_DEPTH deep
+    pub fn parse(s: &str) -> Result<Json, BitcoinError> {
+        let mut parser = Parser { s, pos: 0 };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            return Err(parser.error("end of input"));
+        }
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::UInt(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    #[cfg(any(feature = "rpc", feature = "electrum"))]
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Json::UInt(n) => i64::try_from(n).ok(),
+            Json::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "esplora")]
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::UInt(n) => Some(n as f64),
+            Json::Int(n) => Some(n as f64),
+            Json::Float(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    #[cfg(any(feature = "esplora", feature = "electrum"))]
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    // A BTC amount, as Core writes them, in satoshis
+    #[cfg(feature = "rpc")]
+    pub fn as_amount(&self) -> Option<Amount> {
+        let text = match *self {
+            Json::UInt(n) => n.to_string(),
+            Json::Float(n) if n >= 0.0 => format!("{n:.8}"),
+            _ => return None,
+        };
+        Amount::from_str_in(&text, Denomination::Bitcoin).ok()
+    }
+}
+
+#[cfg(any(feature = "rpc", feature = "esplora", feature = "electrum"))]
+const MAX_DEPTH: usize = 128;
+
+#[cfg(any(feature = "rpc", feature = "esplora", feature = "electrum"))]
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+#[cfg(any(feature = "rpc", feature = "esplora", feature = "electrum"))]
+impl Parser<'_> {
+    fn error(&self, expected: &str) -> BitcoinError {
+        BitcoinError::Parse(ParseError::Message(format!(
+            "Invalid JSON: expected {expected} at byte {}",
+            self.pos
+        )))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        let matched = self.s[self.pos..].starts_with(literal);
+        if matched {
+            self.pos += literal.len();
+        }
+        matched
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, BitcoinError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("less nesting"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.eat("}") {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if !self.eat(":") {
+                        return Err(self.error("':'"));
+                    }
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    if self.eat("}") {
+                        return Ok(Json::Object(fields));
+                    }
+                    if !self.eat(",") {
+                        return Err(self.error("',' or '}'"));
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.eat("]") {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    if self.eat("]") {
+                        return Ok(Json::Array(items));
+                    }
+                    if !self.eat(",") {
+                        return Err(self.error("',' or ']'"));
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ if self.eat("null") => Ok(Json::Null),
+            _ if self.eat("true") => Ok(Json::Bool(true)),
+            _ if self.eat("false") => Ok(Json::Bool(false)),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, BitcoinError> {
+        if !self.eat("\"") {
+            return Err(self.error("a string"));
+        }
+        let mut s = String::new();
+        loop {
+            let rest = &self.s[self.pos..];
+            let c = rest.chars().next().ok_or_else(|| self.error("'\"'"))?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("an escape"))?;
+                    self.pos += 1;
+                    s.push(match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("an escape")),
+                    });
+                }
+                c if (c as u32) < 0x20 => return Err(self.error("an escaped control character")),
+                c => s.push(c),
+            }
+        }
+    }
+
+    // After \u; a high surrogate must be followed by an escaped low one
+    fn unicode_escape(&mut self) -> Result<char, BitcoinError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.eat("\\u") {
+                return Err(self.error("a low surrogate"));
+            }
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("a low surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("a Unicode scalar value"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, BitcoinError> {
+        let digits = self
+            .s
+            .get(self.pos..self.pos + 4)
+            .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("four hex digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).expect("checked hex digits"))
+    }
+
+    fn number(&mut self) -> Result<Json, BitcoinError> {
+        let start = self.pos;
+        self.eat("-");
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while p.peek().is_some_and(|b| b.is_ascii_digit()) {
+                p.pos += 1;
+            }
+            p.pos > from
+        };
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else if !digits(self) {
+            return Err(self.error("a digit"));
+        }
+        let mut integer = true;
+        if self.eat(".") {
+            integer = false;
+            if !digits(self) {
+                return Err(self.error("a digit"));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            integer = false;
+            self.pos += 1;
+            if !self.eat("+") {
+                self.eat("-");
+            }
+            if !digits(self) {
+                return Err(self.error("a digit"));
+            }
+        }
+        let text = &self.s[start..self.pos];
+        if integer {
+            if let Ok(n) = text.parse() {
+                return Ok(Json::UInt(n));
+            }
+            if let Ok(n) = text.parse() {
+                return Ok(Json::Int(n));
+            }
+        }
+        // Integers too large for 64 bits too
+        Ok(Json::Float(text.parse().expect("valid JSON number")))
+    }
+}
+
+impl LegacyTransaction {
+    // The output of `bitcoin-cli decoderawtransaction` for a mainnet node,
+    // apart from the `desc` field of scriptPubKeys
+    pub fn to_json(&self) -> String {
+        self.to_json_for(Network::Mainnet)
+    }
+
+    // As to_json, with addresses for `network`
+    pub fn to_json_for(&self, network: Network) -> String {
+        let vin = self.inputs.iter().map(|input| {
+            let mut fields = Vec::new();
+            if self.is_coinbase() {
+                fields.push(("coinbase", hex::encode(&input.script_sig).into()));
+            } else {
+                let script_sig = Script::from(&input.script_sig[..]);
+                fields.push(("txid", input.previous_output.txid.to_string().into()));
+                fields.push(("vout", input.previous_output.vout.into()));
+                fields.push((
+                    "scriptSig",
+                    Json::object([
+                        ("asm", script_sig.to_asm_decode_sighash().into()),
+                        ("hex", hex::encode(&script_sig).into()),
+                    ]),
+                ));
+            }
+            if !input.witness.is_empty() {
+                let items = input.witness.items.iter().map(|item| hex::encode(item));
+                fields.push(("txinwitness", items.collect::<Vec<_>>().into()));
+            }
+            fields.push(("sequence", input.sequence.to_consensus_u32().into()));
+            Json::object(fields)
+        });
+        let vout = self.outputs.iter().enumerate().map(|(n, output)| {
+            let script = Script::from(&output.script_pubkey[..]);
+            let mut script_pubkey = vec![
+                ("asm", script.to_asm().into()),
+                ("hex", hex::encode(&script).into()),
+            ];
+            if let Ok(address) = Address::from_script(&script, network) {
+                script_pubkey.push(("address", address.to_string().into()));
+            }
+            script_pubkey.push(("type", core_script_type(&script).into()));
+            Json::object([
+                ("value", Json::Amount(output.value.to_sat())),
+                ("n", (n as u64).into()),
+                ("scriptPubKey", Json::object(script_pubkey)),
+            ])
+        });
+        Json::object([
+            ("txid", self.txid().to_string().into()),
+            ("hash", self.wtxid().to_string().into()),
+            ("version", self.version.into()),
+            ("size", (self.total_size() as u64).into()),
+            ("vsize", self.vsize().into()),
+            ("weight", self.weight().into()),
+            ("locktime", self.lock_time.to_consensus_u32().into()),
+            ("vin", Json::Array(vin.collect())),
+            ("vout", Json::Array(vout.collect())),
+        ])
+        .to_string()
+    }
+}
+
+// Core's names for output types (see GetTxnOutputType)
+fn core_script_type(script: &Script) -> &'static str {
+    match script.script_type() {
+        ScriptType::P2PK => "pubkey",
+        ScriptType::P2PKH => "pubkeyhash",
+        ScriptType::P2SH => "scripthash",
+        ScriptType::P2WPKH => "witness_v0_keyhash",
+        ScriptType::P2WSH => "witness_v0_scripthash",
+        ScriptType::P2TR => "witness_v1_taproot",
+        ScriptType::Multisig => "multisig",
+        ScriptType::OpReturn => "nulldata",
+        ScriptType::NonStandard if witness_program(script).is_some() => "witness_unknown",
+        ScriptType::NonStandard => "nonstandard",
+    }
+}
@@ -0,0 +1,169 @@
+// secp256k1 private and public keys
+
+use k256::elliptic_curve::rand_core::OsRng;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+use crate::{base58, hashes, BitcoinError, CryptoError, Hash160, Network};
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrivateKey {
+    inner: k256::SecretKey,
+    // Whether the matching public key is serialized in compressed form
+    pub compressed: bool,
+}
+
+impl PrivateKey {
+    // Fails unless 0 < key < curve order
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+        let inner = k256::SecretKey::from_slice(bytes)
+            .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+        Ok(PrivateKey {
+            inner,
+            compressed: true,
+        })
+    }
+
+    // Fresh key from the operating system's random number generator
+    pub fn generate() -> Self {
+        PrivateKey {
+            inner: k256::SecretKey::random(&mut OsRng),
+            compressed: true,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.inner.to_bytes().into()
+    }
+
+    // Wallet import format: version byte, key, and a 0x01 suffix when the
+    // public key is compressed
+    pub fn from_wif(s: &str) -> Result<Self, BitcoinError> {
+        let data = base58::decode_check(s)?;
+        let compressed = match data.len() {
+            33 => false,
+            34 if data[33] == 0x01 => true,
+            _ => return Err(BitcoinError::Crypto(CryptoError::InvalidPrivateKey)),
+        };
+        if !Network::ALL
+            .iter()
+            .any(|network| network.wif_prefix() == data[0])
+        {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPrivateKey));
+        }
+        let mut key = Self::from_slice(&data[1..33])?;
+        key.compressed = compressed;
+        Ok(key)
+    }
+
+    pub fn to_wif(&self, network: Network) -> String {
+        let mut data = Vec::with_capacity(34);
+        data.push(network.wif_prefix());
+        data.extend(self.to_bytes());
+        if self.compressed {
+            data.push(0x01);
+        }
+        base58::encode_check(&data)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            inner: self.inner.public_key(),
+            compressed: self.compressed,
+        }
+    }
+
+    pub fn x_only_public_key(&self) -> (XOnlyPublicKey, bool) {
+        self.public_key().x_only_public_key()
+    }
+
+    pub(crate) fn signing_key(&self) -> k256::ecdsa::SigningKey {
+        k256::ecdsa::SigningKey::from(&self.inner)
+    }
+
+    pub(crate) fn schnorr_signing_key(&self) -> k256::schnorr::SigningKey {
+        k256::schnorr::SigningKey::from(self.inner.to_nonzero_scalar())
+    }
+}
+
+// Never print key material
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrivateKey")
+            .field("compressed", &self.compressed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey {
+    inner: k256::PublicKey,
+    pub compressed: bool,
+}
+
+impl PublicKey {
+    // Accepts 33-byte compressed and 65-byte uncompressed SEC1 encodings
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+        let inner = k256::PublicKey::from_sec1_bytes(bytes)
+            .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+        Ok(PublicKey {
+            inner,
+            compressed: bytes.len() == 33,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.inner
+            .to_encoded_point(self.compressed)
+            .as_bytes()
+            .to_vec()
+    }
+
+    // hash160 of the serialized key, as committed to by P2PKH and P2WPKH outputs
+    pub fn pubkey_hash(&self) -> Hash160 {
+        hashes::hash160(&self.serialize())
+    }
+
+    // Drops the y coordinate, returning whether it was odd
+    pub fn x_only_public_key(&self) -> (XOnlyPublicKey, bool) {
+        let encoded = self.inner.to_encoded_point(true);
+        let bytes = encoded.as_bytes();
+        let inner = k256::schnorr::VerifyingKey::from_bytes(&bytes[1..])
+            .expect("x coordinate of a valid point");
+        (XOnlyPublicKey { inner }, bytes[0] == 0x03)
+    }
+
+    pub(crate) fn verifying_key(&self) -> k256::ecdsa::VerifyingKey {
+        k256::ecdsa::VerifyingKey::from(&self.inner)
+    }
+}
+
+// BIP340 public key: the x coordinate of a point with even y
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XOnlyPublicKey {
+    inner: k256::schnorr::VerifyingKey,
+}
+
+impl XOnlyPublicKey {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+        if bytes.len() != 32 {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        let inner = k256::schnorr::VerifyingKey::from_bytes(bytes)
+            .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+        Ok(XOnlyPublicKey { inner })
+    }
+
+    pub fn serialize(&self) -> [u8; 32] {
+        self.inner.to_bytes().into()
+    }
+
+    pub(crate) fn verifying_key(&self) -> &k256::schnorr::VerifyingKey {
+        &self.inner
+    }
+}
+
+impl From<PublicKey> for XOnlyPublicKey {
+    fn from(pubkey: PublicKey) -> Self {
+        pubkey.x_only_public_key().0
+    }
+}
@@ -1,20 +1,381 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use k256::elliptic_curve::rand_core::{OsRng, RngCore};
 use thiserror::Error;
 
-// Custom errors for Bitcoin operations
+pub mod address;
+pub mod amount;
+pub mod base58;
+pub(crate) mod base64;
+pub mod bech32;
+pub mod bip158;
+pub mod bip21;
+pub mod bip32;
+pub mod bip322;
+pub mod bip39;
+pub mod block;
+pub mod bloom;
+pub mod cli;
+pub mod coin_selection;
+pub mod coinbase;
+pub mod config;
+pub mod consensus;
+pub mod descriptor;
+#[cfg(feature = "electrum")]
+pub mod electrum;
+#[cfg(feature = "esplora")]
+pub mod esplora;
+pub(crate) mod expression;
+pub mod fee;
+pub mod hash_types;
+pub mod hashes;
+pub(crate) mod hex;
+#[cfg(any(feature = "rpc", feature = "esplora"))]
+pub(crate) mod http;
+pub(crate) mod json;
+pub mod key;
+pub mod locktime;
+pub mod merkle;
+pub mod message;
+pub mod miniscript;
+pub mod musig2;
+pub mod network;
+pub mod p2p;
+pub mod parallel;
+pub mod policy;
+pub mod pow;
+pub mod psbt;
+pub mod raw;
+pub mod repl;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod script;
+#[cfg(feature = "serde")]
+pub(crate) mod serde_support;
+pub mod sighash;
+pub mod sign;
+pub mod silent_payments;
+pub mod spv;
+pub(crate) mod stream;
+pub mod taproot;
+#[cfg(feature = "async")]
+pub mod task;
+#[cfg(feature = "esplora")]
+pub(crate) mod tls;
+pub mod utxo;
+mod verify;
+pub mod wallet;
+#[cfg(feature = "zmq")]
+pub mod zmq;
+
+pub use address::{Address, AddressType};
+pub use amount::{Amount, Denomination};
+pub use bip21::Bip21Uri;
+pub use bip32::{DerivationPath, Xpriv, Xpub};
+pub use bip39::Mnemonic;
+pub use block::{Block, BlockHeader};
+pub use bloom::BloomFilter;
+pub use cli::{
+    parse_cli_args, parse_cli_args_with_config, parse_global_options, CliCommand, GlobalOptions,
+    OutputFormat,
+};
+pub use coinbase::CoinbaseBuilder;
+pub use config::{Config, ConfigOverrides};
+pub use consensus::ConsensusError;
+pub use descriptor::Descriptor;
+pub use fee::{FeeRate, Weight};
+pub use hash_types::{BlockHash, FilterHash, FilterHeader, ScriptHash, Txid, Wtxid};
+pub use hashes::{Hash160, Hash256};
+pub use key::{PrivateKey, PublicKey, XOnlyPublicKey};
+pub use locktime::{LockTime, RelativeLockTime, Sequence};
+pub use miniscript::{Miniscript, Policy};
+pub use network::{ChainParams, Network};
+pub use policy::PolicyError;
+pub use pow::{CompactTarget, Target, Work};
+pub use psbt::Psbt;
+pub use raw::TransactionRef;
+pub use repl::Repl;
+pub use script::{
+    Interpreter, Opcode, Script, ScriptBuilder, ScriptFlags, ScriptType, SignatureChecker,
+};
+pub use sighash::{SigHashType, SighashCache};
+pub use sign::Signer;
+pub use silent_payments::{SilentPaymentAddress, SilentPaymentReceiver};
+pub use spv::{HeaderChain, HeaderError};
+pub use taproot::{TapTree, TaprootSpendInfo};
+pub use utxo::UtxoSet;
+pub use wallet::Wallet;
+
+use stream::Encoder;
+
+// Custom errors for Bitcoin operations, one variant per class of error so
+// callers can match on a whole class. New variants and classes may be
+// added, so matches need a catch-all arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum BitcoinError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("Script error: {0}")]
+    Script(#[from] ScriptError),
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+    #[error(transparent)]
+    Usage(#[from] UsageError),
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+}
+
+// Malformed encodings: transactions, addresses, keys, hex and so on
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
     #[error("Invalid transaction format")]
     InvalidTransaction,
-    #[error("Invalid script format")]
-    InvalidScript,
+    // Offsets count from the start of the data handed to the outermost parse
+    #[error("Unexpected end of data: {needed} bytes needed at offset {offset}")]
+    UnexpectedEof { needed: usize, offset: usize },
+    #[error("Invalid {field} at offset {offset}")]
+    InvalidField { field: &'static str, offset: usize },
+    #[error("{remaining} unexpected bytes after the end of the data")]
+    TrailingBytes { remaining: usize },
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("Parse error: {0}")]
+    Message(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AmountError {
     #[error("Invalid amount")]
     InvalidAmount,
-    #[error("Parse error: {0}")]
-    ParseError(String),
+    #[error("Output {0} is dust")]
+    DustOutput(usize),
+}
+
+// Keys, signatures and hashes that don't check out
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CryptoError {
+    #[error("Invalid public key")]
+    InvalidPublicKey,
+    #[error("Invalid private key")]
+    InvalidPrivateKey,
+    #[error("Key does not match the script being spent")]
+    KeyMismatch,
+    #[error("No key for the input spending {0}")]
+    MissingKey(OutPoint),
+    #[error("Invalid sighash type {0:#x}")]
+    InvalidSighashType(u32),
+    #[error("Invalid MuSig2 nonce")]
+    InvalidNonce,
+    #[error("Invalid MuSig2 partial signature")]
+    InvalidPartialSignature,
+    #[error("Block hash does not meet the proof-of-work target")]
+    BadProofOfWork,
+    #[error("Invalid merkle proof: {0}")]
+    InvalidMerkleProof(&'static str),
+}
+
+// Transactions or blocks that break the rules of the chain
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    #[error("Output {0} is not in the UTXO set")]
+    UnknownOutput(OutPoint),
+    #[error("Transaction check failed: {0}")]
+    Consensus(#[from] ConsensusError),
+    #[error("Nonstandard transaction: {0}")]
+    Policy(#[from] PolicyError),
+    #[error("Invalid block header: {0}")]
+    Header(#[from] HeaderError),
+}
+
+// Arguments that don't fit the call, such as CLI arguments or an input
+// index past the end
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UsageError {
+    #[error("Input index {0} out of range")]
+    InputIndexOutOfRange(usize),
+    #[error("Buffer too small: {needed} bytes needed, {available} available")]
+    BufferTooSmall { needed: usize, available: usize },
+    #[error("OP_RETURN data is {0} bytes; at most 80 are relayed")]
+    DataTooLarge(usize),
+    #[error("Witness script is {0} bytes; at most 3600 are relayed")]
+    WitnessScriptTooLarge(usize),
+    #[error("Invalid {required}-of-{keys} multisig")]
+    InvalidMultisig { required: usize, keys: usize },
+    #[error("Missing argument {0}")]
+    MissingArgument(String),
+    #[error("Invalid argument {argument}: {reason}")]
+    InvalidArgument { argument: String, reason: String },
+    #[error("{prevouts} prevouts given for {inputs} inputs")]
+    PrevoutCountMismatch { prevouts: usize, inputs: usize },
+}
+
+// Failures reported by a node or server
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RemoteError {
+    #[error("HTTP error {status}: {message}")]
+    Http { status: u16, message: String },
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+}
+
+// The chain's rule errors convert straight to a BitcoinError, as they did
+// before the classes were nested
+macro_rules! impl_from_validation_error {
+    ($($error:ident),*) => {
+        $(
+            impl From<$error> for BitcoinError {
+                fn from(e: $error) -> Self {
+                    BitcoinError::Validation(e.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_validation_error!(ConsensusError, PolicyError, HeaderError);
+
+// Broad classes of BitcoinError
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Parse,
+    Script,
+    Amount,
+    Io,
+    Crypto,
+    Validation,
+    Usage,
+    Remote,
+}
+
+impl BitcoinError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            BitcoinError::Parse(_) => ErrorKind::Parse,
+            BitcoinError::Script(_) => ErrorKind::Script,
+            BitcoinError::Amount(_) => ErrorKind::Amount,
+            BitcoinError::Io(_) => ErrorKind::Io,
+            BitcoinError::Crypto(_) => ErrorKind::Crypto,
+            BitcoinError::Validation(_) => ErrorKind::Validation,
+            BitcoinError::Usage(_) => ErrorKind::Usage,
+            BitcoinError::Remote(_) => ErrorKind::Remote,
+        }
+    }
+}
+
+// Reasons script execution can fail, following Bitcoin Core's ScriptError
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    #[error("Script evaluated without error but finished with a false/empty top stack element")]
+    EvalFalse,
+    #[error("OP_RETURN was encountered")]
+    OpReturn,
+    #[error("Script is too big")]
+    ScriptSize,
+    #[error("Push value size limit exceeded")]
+    PushSize,
+    #[error("Operation limit exceeded")]
+    OpCount,
+    #[error("Stack size limit exceeded")]
+    StackSize,
+    #[error("Signature count negative or greater than pubkey count")]
+    SigCount,
+    #[error("Pubkey count negative or limit exceeded")]
+    PubkeyCount,
+    #[error("Script failed an OP_VERIFY operation")]
+    Verify,
+    #[error("Script failed an OP_EQUALVERIFY operation")]
+    EqualVerify,
+    #[error("Script failed an OP_CHECKMULTISIGVERIFY operation")]
+    CheckMultisigVerify,
+    #[error("Script failed an OP_CHECKSIGVERIFY operation")]
+    CheckSigVerify,
+    #[error("Script failed an OP_NUMEQUALVERIFY operation")]
+    NumEqualVerify,
+    #[error("Opcode missing or not understood")]
+    BadOpcode,
+    #[error("Attempted to use a disabled opcode")]
+    DisabledOpcode,
+    #[error("Operation not valid with the current stack size")]
+    InvalidStackOperation,
+    #[error("Operation not valid with the current altstack size")]
+    InvalidAltstackOperation,
+    #[error("Invalid OP_IF construction")]
+    UnbalancedConditional,
+    #[error("Script number overflow or non-minimal encoding")]
+    InvalidNumber,
+    #[error("Negative locktime")]
+    NegativeLockTime,
+    #[error("Locktime requirement not satisfied")]
+    UnsatisfiedLockTime,
+    #[error("Signature hash type missing or not understood")]
+    SigHashType,
+    #[error("Non-canonical DER signature")]
+    SigDer,
+    #[error("Data push larger than necessary")]
+    MinimalData,
+    #[error("Only push operators allowed in signatures")]
+    SigPushOnly,
+    #[error("Non-canonical signature: S value is unnecessarily high")]
+    SigHighS,
+    #[error("Dummy CHECKMULTISIG argument must be zero")]
+    SigNullDummy,
+    #[error("Public key is neither compressed or uncompressed")]
+    PubkeyType,
+    #[error("Stack size must be exactly one after execution")]
+    CleanStack,
+    #[error("Signature must be zero for failed CHECK(MULTI)SIG operation")]
+    NullFail,
+    #[error("NOPx reserved for soft-fork upgrades")]
+    DiscourageUpgradableNops,
+    #[error("Witness program has incorrect length")]
+    WitnessProgramWrongLength,
+    #[error("Witness program was passed an empty witness")]
+    WitnessProgramWitnessEmpty,
+    #[error("Witness program hash mismatch")]
+    WitnessProgramMismatch,
+    #[error("Witness requires empty scriptSig")]
+    WitnessMalleated,
+    #[error("Witness requires only-redeemscript scriptSig")]
+    WitnessMalleatedP2sh,
+    #[error("Witness provided for non-witness script")]
+    WitnessUnexpected,
+    #[error("Invalid Schnorr signature size")]
+    SchnorrSigSize,
+    #[error("Invalid Schnorr signature hash type")]
+    SchnorrSigHashType,
+    #[error("Invalid Schnorr signature")]
+    SchnorrSig,
+    #[error("Invalid Taproot control block size")]
+    TaprootWrongControlSize,
+    #[error("Too much signature validation relative to witness weight")]
+    TapscriptValidationWeight,
+    #[error("OP_CHECKMULTISIG(VERIFY) is not available in tapscript")]
+    TapscriptCheckMultisig,
+    #[error("OP_IF/NOTIF argument must be minimal in tapscript")]
+    TapscriptMinimalIf,
+    #[error("Empty public key in tapscript")]
+    TapscriptEmptyPubkey,
 }
 
 // Generic Point struct for Bitcoin addresses or coordinates
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Point<T> {
     pub x: T,
     pub y: T,
@@ -29,29 +390,189 @@ impl<T> Point<T> {
 // Custom serialization for Bitcoin transaction
 pub trait BitcoinSerialize {
     fn serialize(&self) -> Vec<u8>;
+
+    // Lowercase hex of the serialization, the form raw data is usually
+    // passed around in
+    fn to_hex(&self) -> String {
+        hex::encode(&self.serialize())
+    }
+
+    // Writes the serialization to `w`, returning the number of bytes
+    // written. The default goes through `serialize`; the types in this
+    // crate write field by field without building the whole buffer.
+    fn serialize_to<W: io::Write>(&self, w: &mut W) -> io::Result<usize> {
+        let bytes = self.serialize();
+        w.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    // Length of the serialization, counted without building it
+    fn serialized_size(&self) -> usize {
+        self.serialize_to(&mut io::sink())
+            .expect("io::Sink never fails")
+    }
+
+    // Writes the serialization to the start of `buf`, returning its length,
+    // so callers can serialize into storage they have allocated once
+    fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, BitcoinError> {
+        let needed = self.serialized_size();
+        let available = buf.len();
+        let mut buf =
+            buf.get_mut(..needed)
+                .ok_or(BitcoinError::Usage(UsageError::BufferTooSmall {
+                    needed,
+                    available,
+                }))?;
+        self.serialize_to(&mut buf)?;
+        Ok(needed)
+    }
 }
 
+// Components with an inherent serialize method get to_hex too, and stream
+// through the matching Encoder method
+macro_rules! impl_bitcoin_serialize {
+    ($($ty:ty => $encode:ident),*) => {
+        $(
+            impl BitcoinSerialize for $ty {
+                fn serialize(&self) -> Vec<u8> {
+                    <$ty>::serialize(self)
+                }
+
+                fn serialize_to<W: io::Write>(&self, w: &mut W) -> io::Result<usize> {
+                    let mut encoder = Encoder::new(w);
+                    encoder.$encode(self)?;
+                    Ok(encoder.written())
+                }
+            }
+        )*
+    };
+}
+
+impl_bitcoin_serialize!(
+    TxInput => input,
+    Witness => witness,
+    TxOutput => output,
+    OutPoint => outpoint,
+    BlockHeader => header,
+    Block => block
+);
+
 // Legacy Bitcoin transaction
-#[derive(Debug, Clone)]
+// Inputs may carry witness data, in which case the BIP141 format is used on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LegacyTransaction {
     pub version: i32,
     pub inputs: Vec<TxInput>,
     pub outputs: Vec<TxOutput>,
-    pub lock_time: u32,
+    pub lock_time: LockTime,
 }
 
+// Transactions with and without witness data share one representation
+pub type Transaction = LegacyTransaction;
+
 impl LegacyTransaction {
     pub fn builder() -> LegacyTransactionBuilder {
         LegacyTransactionBuilder::default()
     }
+
+    // Double SHA-256 of the serialization without witness data
+    pub fn txid(&self) -> Txid {
+        hashes::sha256d(&self.serialize_without_witness()).into()
+    }
+
+    // Double SHA-256 of the full serialization, witness included. Equal to
+    // the txid for transactions without witness data.
+    pub fn wtxid(&self) -> Wtxid {
+        hashes::sha256d(&self.serialize()).into()
+    }
+
+    // The first transaction of a block, which spends no previous output
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_output.is_null()
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    // Size in bytes without witness data
+    pub fn base_size(&self) -> usize {
+        if !self.has_witness() {
+            return self.total_size();
+        }
+        let witness_size: usize = self
+            .inputs
+            .iter()
+            .map(|input| input.witness.serialized_size())
+            .sum();
+        // The marker and flag count as witness data too
+        self.total_size() - 2 - witness_size
+    }
+
+    // Size in bytes as sent over the network, witness included
+    pub fn total_size(&self) -> usize {
+        self.serialized_size()
+    }
+
+    // BIP141 weight: non-witness bytes count four times, witness bytes once
+    pub fn weight(&self) -> u64 {
+        (self.base_size() * 3 + self.total_size()) as u64
+    }
+
+    // Fee paid: what the inputs spend minus what the outputs create.
+    // `prevouts` are the outputs being spent, one per input in order.
+    pub fn fee(&self, prevouts: &[TxOutput]) -> Result<Amount, BitcoinError> {
+        if prevouts.len() != self.inputs.len() {
+            return Err(BitcoinError::Usage(UsageError::PrevoutCountMismatch {
+                prevouts: prevouts.len(),
+                inputs: self.inputs.len(),
+            }));
+        }
+        let sum = |outputs: &[TxOutput]| {
+            Amount::checked_sum(outputs.iter().map(|output| output.value))
+                .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))
+        };
+        sum(prevouts)?
+            .checked_sub(sum(&self.outputs)?)
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))
+    }
+
+    // Virtual size in vbytes, the unit fee rates are quoted in
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+
+    // Pre-BIP141 serialization, with all witness data stripped
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        let mut v = Vec::new();
+        v.extend(&self.version.to_le_bytes());
+        self.serialize_inputs_outputs(&mut v);
+        v.extend(&self.lock_time.to_consensus_u32().to_le_bytes());
+        v
+    }
+
+    fn serialize_inputs_outputs(&self, v: &mut Vec<u8>) {
+        v.extend(CompactSize(self.inputs.len() as u64).encode());
+        for input in &self.inputs {
+            v.extend(input.serialize());
+        }
+        v.extend(CompactSize(self.outputs.len() as u64).encode());
+        for output in &self.outputs {
+            v.extend(output.serialize());
+        }
+    }
 }
 
 // Transaction builder
+#[derive(Debug, Clone)]
 pub struct LegacyTransactionBuilder {
     pub version: i32,
     pub inputs: Vec<TxInput>,
     pub outputs: Vec<TxOutput>,
-    pub lock_time: u32,
+    pub lock_time: LockTime,
+    // Checked by try_build
+    pub dust_relay_fee: Option<FeeRate>,
 }
 
 impl Default for LegacyTransactionBuilder {
@@ -60,7 +581,8 @@ impl Default for LegacyTransactionBuilder {
             version: 1,
             inputs: Vec::new(),
             outputs: Vec::new(),
-            lock_time: 0,
+            lock_time: LockTime::ZERO,
+            dust_relay_fee: None,
         }
     }
 }
@@ -85,11 +607,44 @@ impl LegacyTransactionBuilder {
         self
     }
 
-    pub fn lock_time(mut self, lock_time: u32) -> Self {
+    pub fn lock_time(mut self, lock_time: LockTime) -> Self {
         self.lock_time = lock_time;
         self
     }
 
+    // Locks the transaction to the next block, as Core's wallet does, so a
+    // miner can't profit from reorganising the chain to include it earlier.
+    // One time in ten the lock goes up to 99 blocks back, so transactions
+    // that were delayed before broadcast don't stand out. The lock time only
+    // binds if an input's sequence enables it (see Sequence).
+    pub fn anti_fee_sniping(mut self, current_height: u32) -> Self {
+        let mut height = current_height;
+        if OsRng.next_u32().is_multiple_of(10) {
+            height = height.saturating_sub(OsRng.next_u32() % 100);
+        }
+        if let Some(lock_time) = LockTime::from_height(height) {
+            self.lock_time = lock_time;
+        }
+        self
+    }
+
+    // Makes try_build fail on outputs that are dust at this rate, such as
+    // fee::DUST_RELAY_FEE
+    pub fn reject_dust(mut self, dust_relay_fee: FeeRate) -> Self {
+        self.dust_relay_fee = Some(dust_relay_fee);
+        self
+    }
+
+    // Like build, but applies the checks asked for
+    pub fn try_build(self) -> Result<LegacyTransaction, BitcoinError> {
+        if let Some(fee_rate) = self.dust_relay_fee {
+            if let Some(index) = self.outputs.iter().position(|o| o.is_dust(fee_rate)) {
+                return Err(BitcoinError::Amount(AmountError::DustOutput(index)));
+            }
+        }
+        Ok(self.build())
+    }
+
     pub fn build(self) -> LegacyTransaction {
         LegacyTransaction {
             version: self.version,
@@ -101,86 +656,157 @@ impl LegacyTransactionBuilder {
 }
 
 // Transaction components
-#[derive(Debug, Clone)]
+// The witness is not part of the input's own serialization; it is written
+// after all outputs when the transaction is serialized in BIP141 format
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TxInput {
     pub previous_output: OutPoint,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::hex_bytes"))]
     pub script_sig: Vec<u8>,
-    pub sequence: u32,
+    pub sequence: Sequence,
+    pub witness: Witness,
 }
 
 impl TxInput {
     pub fn serialize(&self) -> Vec<u8> {
         let mut v = Vec::new();
         v.extend(&self.previous_output.serialize());
-        v.extend(&(self.script_sig.len() as u32).to_le_bytes());
+        v.extend(CompactSize(self.script_sig.len() as u64).encode());
         v.extend(&self.script_sig);
-        v.extend(&self.sequence.to_le_bytes());
+        v.extend(&self.sequence.to_consensus_u32().to_le_bytes());
         v
     }
     pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
-        if data.len() < 36 + 4 {
-            // OutPoint + script_len
-            return Err(BitcoinError::InvalidTransaction);
-        }
-        let (outpoint, outpoint_len) = OutPoint::parse(data)?;
-        let script_len_start = outpoint_len;
-        let script_len = u32::from_le_bytes([
-            data[script_len_start],
-            data[script_len_start + 1],
-            data[script_len_start + 2],
-            data[script_len_start + 3],
-        ]) as usize;
-        let script_start = script_len_start + 4;
-        let script_end = script_start + script_len;
-        if data.len() < script_end + 4 {
-            return Err(BitcoinError::InvalidTransaction);
-        }
-        let script_sig = data[script_start..script_end].to_vec();
-        let sequence = u32::from_le_bytes([
-            data[script_end],
-            data[script_end + 1],
-            data[script_end + 2],
-            data[script_end + 3],
-        ]);
+        let (outpoint, mut offset) = OutPoint::parse(data)?;
+        let (script_len, used) =
+            CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        // script_len comes from untrusted input, so check it against what is left
+        let script_sig = read_bytes(data, offset, script_len.0)?.to_vec();
+        offset += script_sig.len();
+        let sequence = Sequence::from_consensus(u32::from_le_bytes(read_array(data, offset)?));
         Ok((
             TxInput {
                 previous_output: outpoint,
                 script_sig,
                 sequence,
+                witness: Witness::new(),
             },
-            script_end + 4,
+            offset + 4,
         ))
     }
 }
 
-#[derive(Debug, Clone)]
+// Witness stack for a single input (BIP141)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Witness {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::hex_byte_vecs"))]
+    pub items: Vec<Vec<u8>>,
+}
+
+impl Witness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, item: Vec<u8>) {
+        self.items.push(item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = Vec::new();
+        v.extend(CompactSize(self.items.len() as u64).encode());
+        for item in &self.items {
+            v.extend(CompactSize(item.len() as u64).encode());
+            v.extend(item);
+        }
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (count, mut offset) = CompactSize::decode(data)?;
+        // Every item takes at least one byte for its length prefix
+        let mut items = Vec::with_capacity((count.0 as usize).min(data.len()));
+        for _ in 0..count.0 {
+            let (item_len, used) =
+                CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+            offset += used;
+            let item = read_bytes(data, offset, item_len.0)?;
+            items.push(item.to_vec());
+            offset += item.len();
+        }
+        Ok((Witness { items }, offset))
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Witness {
+    fn from(items: Vec<Vec<u8>>) -> Self {
+        Witness { items }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TxOutput {
-    pub value: u64, // in satoshis
+    pub value: Amount,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::hex_bytes"))]
     pub script_pubkey: Vec<u8>,
 }
 
 impl TxOutput {
+    // Zero-value data carrier. Data past policy::MAX_OP_RETURN_DATA would
+    // make the transaction nonstandard, so it is refused.
+    pub fn new_op_return(data: &[u8]) -> Result<Self, BitcoinError> {
+        if data.len() > policy::MAX_OP_RETURN_DATA {
+            return Err(BitcoinError::Usage(UsageError::DataTooLarge(data.len())));
+        }
+        Ok(TxOutput {
+            value: Amount::ZERO,
+            script_pubkey: Script::new_op_return(data).into_bytes(),
+        })
+    }
+
+    // The data an OP_RETURN output carries, its pushes joined together. None
+    // for other outputs, or if anything but pushes follows the OP_RETURN.
+    pub fn op_return_data(&self) -> Option<Vec<u8>> {
+        let (&first, rest) = self.script_pubkey.split_first()?;
+        if first != Opcode::OP_RETURN as u8 {
+            return None;
+        }
+        let mut data = Vec::new();
+        for instruction in script::instructions(rest) {
+            match instruction.ok()? {
+                script::Instruction::PushBytes(_, bytes) => data.extend(bytes),
+                script::Instruction::Op(_) => return None,
+            }
+        }
+        Some(data)
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut v = Vec::new();
-        v.extend(&self.value.to_le_bytes());
-        v.extend(&(self.script_pubkey.len() as u32).to_le_bytes());
+        v.extend(&self.value.to_sat().to_le_bytes());
+        v.extend(CompactSize(self.script_pubkey.len() as u64).encode());
         v.extend(&self.script_pubkey);
         v
     }
     pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
-        if data.len() < 8 + 4 {
-            return Err(BitcoinError::InvalidTransaction);
-        }
-        let value = u64::from_le_bytes([
-            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-        ]);
-        let script_len = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
-        let script_start = 12;
-        let script_end = script_start + script_len;
-        if data.len() < script_end {
-            return Err(BitcoinError::InvalidTransaction);
-        }
-        let script_pubkey = data[script_start..script_end].to_vec();
+        let value = Amount::from_sat(u64::from_le_bytes(read_array(data, 0)?));
+        let (script_len, used) = CompactSize::decode(&data[8..]).map_err(|e| e.offset_by(8))?;
+        let script_start = 8 + used;
+        let script_pubkey = read_bytes(data, script_start, script_len.0)?.to_vec();
+        let script_end = script_start + script_pubkey.len();
         Ok((
             TxOutput {
                 value,
@@ -191,108 +817,261 @@ impl TxOutput {
     }
 }
 
-#[derive(Debug, Clone)]
+// Outputs are always referenced by txid, never by wtxid
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OutPoint {
-    pub txid: [u8; 32],
+    pub txid: Txid,
     pub vout: u32,
 }
 
 impl OutPoint {
+    pub fn new(txid: Txid, vout: u32) -> Self {
+        OutPoint { txid, vout }
+    }
+
+    // The outpoint of a coinbase input
+    pub fn null() -> Self {
+        OutPoint::new(Txid::all_zeros(), u32::MAX)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.txid == Txid::all_zeros() && self.vout == u32::MAX
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut v = Vec::new();
-        v.extend(&self.txid);
+        v.extend(self.txid.as_bytes());
         v.extend(&self.vout.to_le_bytes());
         v
     }
     pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
-        if data.len() < 32 + 4 {
-            return Err(BitcoinError::InvalidTransaction);
-        }
+        let bytes: [u8; 36] = read_array(data, 0)?;
         let mut txid = [0u8; 32];
-        txid.copy_from_slice(&data[0..32]);
-        let vout = u32::from_le_bytes([data[32], data[33], data[34], data[35]]);
-        Ok((OutPoint { txid, vout }, 36))
+        txid.copy_from_slice(&bytes[..32]);
+        Ok((
+            OutPoint {
+                txid: Txid::from_byte_array(txid),
+                vout: u32::from_le_bytes([bytes[32], bytes[33], bytes[34], bytes[35]]),
+            },
+            36,
+        ))
     }
 }
 
-// Simple CLI argument parser
-// Simple CLI argument parser
-pub fn parse_cli_args(args: &[String]) -> Result<CliCommand, BitcoinError> {
-    if args.is_empty() {
-        return Err(BitcoinError::ParseError("No command provided".to_string()));
+// As `txid:vout`, the form the CLI accepts
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.txid, self.vout)
     }
-    match args[0].as_str() {
-        "send" => {
-            if args.len() < 3 {
-                return Err(BitcoinError::ParseError(
-                    "Missing arguments for send".to_string(),
-                ));
+}
+
+// Variable-length integer used for counts and lengths on the wire (CompactSize)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactSize(pub u64);
+
+impl CompactSize {
+    pub fn encode(&self) -> Vec<u8> {
+        match self.0 {
+            0..=0xFC => vec![self.0 as u8],
+            0xFD..=0xFFFF => {
+                let mut v = vec![0xFD];
+                v.extend(&(self.0 as u16).to_le_bytes());
+                v
+            }
+            0x1_0000..=0xFFFF_FFFF => {
+                let mut v = vec![0xFE];
+                v.extend(&(self.0 as u32).to_le_bytes());
+                v
+            }
+            _ => {
+                let mut v = vec![0xFF];
+                v.extend(&self.0.to_le_bytes());
+                v
             }
-            let amount = args[1]
-                .parse::<u64>()
-                .map_err(|_| BitcoinError::InvalidAmount)?;
-            let address = args[2].clone();
-            Ok(CliCommand::Send { amount, address })
         }
-        "balance" => Ok(CliCommand::Balance),
-        _ => Err(BitcoinError::ParseError("Unknown command".to_string())),
+    }
+
+    // Returns the value and the number of bytes it occupied.
+    // Non-minimal encodings are rejected, as Bitcoin Core does.
+    pub fn decode(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let [prefix] = read_array(data, 0)?;
+        let (value, len, min) = match prefix {
+            0xFD => (u16::from_le_bytes(read_array(data, 1)?) as u64, 3, 0xFD),
+            0xFE => (u32::from_le_bytes(read_array(data, 1)?) as u64, 5, 0x1_0000),
+            0xFF => (u64::from_le_bytes(read_array(data, 1)?), 9, 0x1_0000_0000),
+            _ => return Ok((CompactSize(prefix as u64), 1)),
+        };
+        if value < min {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "compact size",
+                offset: 0,
+            }));
+        }
+        Ok((CompactSize(value), len))
     }
 }
 
-pub enum CliCommand {
-    Send { amount: u64, address: String },
-    Balance,
+impl BitcoinError {
+    // Moves the offset of a decoding error from a sub-slice starting at
+    // `base` to the enclosing data
+    pub(crate) fn offset_by(self, base: usize) -> Self {
+        match self {
+            BitcoinError::Parse(ParseError::UnexpectedEof { needed, offset }) => {
+                BitcoinError::Parse(ParseError::UnexpectedEof {
+                    needed,
+                    offset: base + offset,
+                })
+            }
+            BitcoinError::Parse(ParseError::InvalidField { field, offset }) => {
+                BitcoinError::Parse(ParseError::InvalidField {
+                    field,
+                    offset: base + offset,
+                })
+            }
+            e => e,
+        }
+    }
+}
+
+// `len` bytes starting at `offset`, where `len` may come from untrusted input
+pub(crate) fn read_bytes(data: &[u8], offset: usize, len: u64) -> Result<&[u8], BitcoinError> {
+    let available = data.len().saturating_sub(offset);
+    if len > available as u64 {
+        return Err(BitcoinError::Parse(ParseError::UnexpectedEof {
+            needed: usize::try_from(len).unwrap_or(usize::MAX),
+            offset,
+        }));
+    }
+    Ok(&data[offset..offset + len as usize])
+}
+
+pub(crate) fn read_array<const N: usize>(
+    data: &[u8],
+    offset: usize,
+) -> Result<[u8; N], BitcoinError> {
+    let bytes = read_bytes(data, offset, N as u64)?;
+    Ok(bytes.try_into().expect("read_bytes returns N bytes"))
 }
 
 // Decoding legacy transaction
+// Both the legacy format and the BIP141 format (marker 0x00, flag 0x01) are accepted
 impl TryFrom<&[u8]> for LegacyTransaction {
     type Error = BitcoinError;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        // Minimum length: 16 bytes (4 version + 4 inputs count + 4 outputs count + 4 lock_time)
-        if data.len() < 16 {
-            return Err(BitcoinError::InvalidTransaction);
+        Self::parse(data).map(|(tx, _)| tx)
+    }
+}
+
+impl LegacyTransaction {
+    // Returns the transaction and the number of bytes it occupied, so
+    // transactions can be read back to back (as in a block)
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let version = i32::from_le_bytes(read_array(data, 0)?);
+        let mut offset = 4;
+
+        // An empty input list followed by a non-zero byte can only be the
+        // segwit marker and flag, mirroring Bitcoin Core's interpretation
+        let segwit = matches!(data.get(4..6), Some([0x00, flag]) if *flag != 0x00);
+        if segwit {
+            if data[5] != 0x01 {
+                return Err(BitcoinError::Parse(ParseError::InvalidField {
+                    field: "segwit flag",
+                    offset: 5,
+                }));
+            }
+            offset += 2;
         }
-        let version = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-        let inputs_count = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
-        let outputs_count = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
-        let lock_time = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
-        let mut offset = 16;
-        let mut inputs = Vec::with_capacity(inputs_count);
-        for _ in 0..inputs_count {
-            let (input, used) = TxInput::parse(&data[offset..])?;
+
+        let (inputs_count, used) =
+            CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        // Counts are untrusted; an input takes at least 41 bytes, so don't
+        // reserve more than the remaining data could possibly hold
+        let mut inputs = Vec::with_capacity((inputs_count.0 as usize).min(data.len() / 41));
+        for _ in 0..inputs_count.0 {
+            let (input, used) = TxInput::parse(&data[offset..]).map_err(|e| e.offset_by(offset))?;
             inputs.push(input);
             offset += used;
         }
-        let mut outputs = Vec::with_capacity(outputs_count);
-        for _ in 0..outputs_count {
-            let (output, used) = TxOutput::parse(&data[offset..])?;
+
+        let (outputs_count, used) =
+            CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        let mut outputs = Vec::with_capacity((outputs_count.0 as usize).min(data.len() / 9));
+        for _ in 0..outputs_count.0 {
+            let (output, used) =
+                TxOutput::parse(&data[offset..]).map_err(|e| e.offset_by(offset))?;
             outputs.push(output);
             offset += used;
         }
-        Ok(LegacyTransaction {
-            version,
-            inputs,
-            outputs,
-            lock_time,
-        })
+
+        if segwit {
+            for input in inputs.iter_mut() {
+                let (witness, used) =
+                    Witness::parse(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+                input.witness = witness;
+                offset += used;
+            }
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(BitcoinError::Parse(ParseError::Message(
+                    "Superfluous witness record".to_string(),
+                )));
+            }
+        }
+
+        let lock_time = LockTime::from_consensus(u32::from_le_bytes(read_array(data, offset)?));
+        Ok((
+            LegacyTransaction {
+                version,
+                inputs,
+                outputs,
+                lock_time,
+            },
+            offset + 4,
+        ))
+    }
+}
+
+impl LegacyTransaction {
+    // Like TryFrom, but `data` must hold exactly one transaction. Use parse
+    // to read a transaction embedded in something larger.
+    pub fn parse_exact(data: &[u8]) -> Result<Self, BitcoinError> {
+        let (tx, used) = Self::parse(data)?;
+        if used != data.len() {
+            return Err(BitcoinError::Parse(ParseError::TrailingBytes {
+                remaining: data.len() - used,
+            }));
+        }
+        Ok(tx)
+    }
+}
+
+// Hex of a whole transaction, as accepted by `bitcoin-cli decoderawtransaction`.
+// Unlike TryFrom, bytes after the transaction are an error.
+impl FromStr for LegacyTransaction {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_exact(&hex::decode(s)?)
     }
 }
 
 // Custom serialization for transaction
+// Uses the BIP141 format whenever any input carries witness data
 impl BitcoinSerialize for LegacyTransaction {
+    // Sized up front so the buffer is allocated once
     fn serialize(&self) -> Vec<u8> {
-        let mut v = Vec::new();
-        v.extend(&self.version.to_le_bytes());
-        v.extend(&(self.inputs.len() as u32).to_le_bytes());
-        v.extend(&(self.outputs.len() as u32).to_le_bytes());
-        v.extend(&self.lock_time.to_le_bytes());
-        for input in &self.inputs {
-            v.extend(input.serialize());
-        }
-        for output in &self.outputs {
-            v.extend(output.serialize());
-        }
+        let mut v = Vec::with_capacity(self.serialized_size());
+        self.serialize_to(&mut v)
+            .expect("writing to a Vec never fails");
         v
     }
+
+    fn serialize_to<W: io::Write>(&self, w: &mut W) -> io::Result<usize> {
+        let mut encoder = Encoder::new(w);
+        encoder.transaction(self)?;
+        Ok(encoder.written())
+    }
 }
@@ -0,0 +1,216 @@
+// Timelocks: a transaction's nLockTime, and the nSequence field of inputs,
+// which signals replaceability and carries BIP68 relative lock times
+
+use std::fmt;
+
+use crate::LegacyTransaction;
+
+// Lock times below this are block heights, the rest Unix timestamps
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+// A transaction's nLockTime: the earliest block it may be included in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTime {
+    Blocks(u32),
+    Seconds(u32),
+}
+
+impl LockTime {
+    // No lock; the usual value
+    pub const ZERO: LockTime = LockTime::Blocks(0);
+
+    pub const fn from_consensus(n: u32) -> Self {
+        if n < LOCK_TIME_THRESHOLD {
+            LockTime::Blocks(n)
+        } else {
+            LockTime::Seconds(n)
+        }
+    }
+
+    pub const fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Blocks(n) | LockTime::Seconds(n) => n,
+        }
+    }
+
+    // None for heights that would be read as timestamps
+    pub fn from_height(height: u32) -> Option<Self> {
+        (height < LOCK_TIME_THRESHOLD).then_some(LockTime::Blocks(height))
+    }
+
+    // None for timestamps that would be read as heights
+    pub fn from_time(time: u32) -> Option<Self> {
+        (time >= LOCK_TIME_THRESHOLD).then_some(LockTime::Seconds(time))
+    }
+
+    pub fn is_block_height(self) -> bool {
+        matches!(self, LockTime::Blocks(_))
+    }
+
+    pub fn is_block_time(self) -> bool {
+        matches!(self, LockTime::Seconds(_))
+    }
+
+    // Whether the next block may include the transaction, given the chain
+    // tip's height and median time past (BIP113). As in Core's IsFinalTx, a
+    // height lock is met at the block after it and a time lock once the
+    // median time has passed it.
+    pub fn is_satisfied_by(self, height: u32, median_time_past: u32) -> bool {
+        match self {
+            LockTime::Blocks(n) => n <= height,
+            LockTime::Seconds(n) => n < median_time_past,
+        }
+    }
+}
+
+impl LegacyTransaction {
+    // Core's IsFinalTx: whether the next block may include the transaction.
+    // A lock time only binds if some input's sequence enables it.
+    pub fn is_final(&self, height: u32, median_time_past: u32) -> bool {
+        self.lock_time == LockTime::ZERO
+            || self.lock_time.is_satisfied_by(height, median_time_past)
+            || !self
+                .inputs
+                .iter()
+                .any(|input| input.sequence.enables_locktime())
+    }
+}
+
+impl Default for LockTime {
+    fn default() -> Self {
+        LockTime::ZERO
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_consensus_u32())
+    }
+}
+
+// As the consensus number, which says which kind it is
+#[cfg(feature = "serde")]
+impl serde::Serialize for LockTime {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.to_consensus_u32())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for LockTime {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer).map(LockTime::from_consensus)
+    }
+}
+
+// An input's nSequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Sequence(u32);
+
+// A BIP68 relative lock time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelativeLockTime {
+    Blocks(u16),
+    // In units of 512 seconds
+    Time(u16),
+}
+
+impl Sequence {
+    // Final: no RBF signal, and the transaction's lock time is ignored
+    pub const MAX: Sequence = Sequence(0xFFFFFFFF);
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xFFFFFFFE);
+    // The lowest value that signals RBF without a relative lock time
+    pub const ENABLE_RBF_NO_LOCKTIME: Sequence = Sequence(0xFFFFFFFD);
+    pub const ZERO: Sequence = Sequence(0);
+
+    // BIP68 fields
+    pub const LOCK_TIME_DISABLE_FLAG: u32 = 1 << 31;
+    pub const LOCK_TIME_TYPE_FLAG: u32 = 1 << 22;
+    pub const LOCK_TIME_MASK: u32 = 0x0000FFFF;
+    // Time-based lock times count in units of 2^9 seconds
+    pub const LOCK_TIME_GRANULARITY: u32 = 9;
+
+    pub const fn from_consensus(n: u32) -> Self {
+        Sequence(n)
+    }
+
+    pub const fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    // Relative lock of `blocks` blocks
+    pub const fn from_height(blocks: u16) -> Self {
+        Sequence(blocks as u32)
+    }
+
+    pub const fn from_512_second_intervals(intervals: u16) -> Self {
+        Sequence(Self::LOCK_TIME_TYPE_FLAG | intervals as u32)
+    }
+
+    // Relative lock of at least `seconds`, rounded up to whole intervals.
+    // None if that is more than u16::MAX intervals (about 388 days).
+    pub fn from_seconds_ceil(seconds: u32) -> Option<Self> {
+        let intervals = seconds.div_ceil(1 << Self::LOCK_TIME_GRANULARITY);
+        u16::try_from(intervals)
+            .ok()
+            .map(Self::from_512_second_intervals)
+    }
+
+    // BIP125: any input below ENABLE_LOCKTIME_NO_RBF makes the transaction
+    // replaceable
+    pub fn is_rbf(self) -> bool {
+        self < Self::ENABLE_LOCKTIME_NO_RBF
+    }
+
+    // The transaction's lock time only applies if some input isn't final
+    pub fn enables_locktime(self) -> bool {
+        self != Self::MAX
+    }
+
+    // Whether BIP68 gives this sequence a meaning, for transactions of
+    // version 2 and above
+    pub fn is_relative_lock_time(self) -> bool {
+        self.0 & Self::LOCK_TIME_DISABLE_FLAG == 0
+    }
+
+    // Bits outside the type flag and mask are ignored, as in BIP68
+    pub fn to_relative_lock_time(self) -> Option<RelativeLockTime> {
+        if !self.is_relative_lock_time() {
+            return None;
+        }
+        let value = (self.0 & Self::LOCK_TIME_MASK) as u16;
+        Some(match self.0 & Self::LOCK_TIME_TYPE_FLAG {
+            0 => RelativeLockTime::Blocks(value),
+            _ => RelativeLockTime::Time(value),
+        })
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence::MAX
+    }
+}
+
+impl From<RelativeLockTime> for Sequence {
+    fn from(lock_time: RelativeLockTime) -> Self {
+        match lock_time {
+            RelativeLockTime::Blocks(blocks) => Sequence::from_height(blocks),
+            RelativeLockTime::Time(intervals) => Sequence::from_512_second_intervals(intervals),
+        }
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::LowerHex for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+use rust_week_4_exercises::cli::format_error;
+#[cfg(feature = "rpc")]
+use rust_week_4_exercises::rpc;
+use rust_week_4_exercises::{
+    parse_cli_args_with_config, parse_global_options, BitcoinError, CliCommand, Config,
+    ConfigOverrides, OutputFormat, Repl,
+};
+
+fn fail(error: &BitcoinError, format: OutputFormat) -> ! {
+    eprintln!("{}", format_error(error, format));
+    process::exit(1);
+}
+
+// Built-in defaults, then the config file, then flags
+fn load_config(path: Option<&str>, flags: &ConfigOverrides) -> Result<Config, BitcoinError> {
+    let mut config = Config::default();
+    if let Some(path) = path {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read {path}: {e}")))?;
+        config = config.apply(&ConfigOverrides::from_toml(&contents)?);
+    }
+    Ok(config.apply(flags))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (options, args) = parse_global_options(&args).unwrap_or_else(|e| {
+        let json = args.iter().any(|arg| arg == "--json");
+        fail(
+            &e,
+            if json {
+                OutputFormat::Json
+            } else {
+                OutputFormat::Text
+            },
+        )
+    });
+    let format = options.format;
+    let config = load_config(options.config_file.as_deref(), &options.overrides)
+        .unwrap_or_else(|e| fail(&e, format));
+    let command = parse_cli_args_with_config(&args, &config).unwrap_or_else(|e| fail(&e, format));
+    if let CliCommand::Repl = command {
+        let mut repl = Repl::new(format).with_config(config);
+        if let Err(e) = repl.run(io::stdin().lock(), io::stdout().lock()) {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+    // With a node to talk to, payments and balances are real
+    #[cfg(feature = "rpc")]
+    let result = match command {
+        CliCommand::Send { .. } | CliCommand::Balance => rpc::Client::from_config(&config)
+            .and_then(|client| command.run_with_node(format, &client)),
+        _ => command.run_as(format),
+    };
+    #[cfg(not(feature = "rpc"))]
+    let result = command.run_as(format);
+    match result {
+        Ok(output) => println!("{output}"),
+        Err(e) => fail(&e, format),
+    }
+}
@@ -0,0 +1,390 @@
+// Merkle trees over transaction ids, as committed to in block headers
+
+use crate::bloom::BloomFilter;
+use crate::consensus::MAX_BLOCK_WEIGHT;
+use crate::{
+    hashes, read_array, BitcoinError, Block, BlockHeader, CompactSize, CryptoError, Hash256,
+    ParseError, Txid,
+};
+
+// Weight of the smallest possible transaction, bounding how many a block
+// can hold
+const MIN_TRANSACTION_WEIGHT: u64 = 4 * 60;
+
+// Root of the tree whose leaves are `txids` in block order. A level with an
+// odd number of nodes pairs its last node with itself. An empty list gives
+// the all-zero hash.
+pub fn compute_root(txids: &[Txid]) -> Hash256 {
+    compute_root_mutated(txids).0
+}
+
+// compute_root, and whether two siblings of the tree are identical. Such a
+// list has the root of a shorter one (CVE-2012-2459): repeating the last
+// transactions of a block that pairs its last node with itself keeps the
+// root, so a valid root says nothing about a list that is mutated.
+pub fn compute_root_mutated(txids: &[Txid]) -> (Hash256, bool) {
+    let mut level: Vec<[u8; 32]> = txids.iter().map(|txid| txid.to_byte_array()).collect();
+    if level.is_empty() {
+        return (Hash256::default(), false);
+    }
+    let mut mutated = false;
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    mutated |= left == right;
+                    parent(left, right)
+                }
+                _ => parent(&pair[0], &pair[0]),
+            })
+            .collect();
+    }
+    (Hash256::from_byte_array(level[0]), mutated)
+}
+
+pub(crate) fn parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left);
+    data[32..].copy_from_slice(right);
+    hashes::sha256d(&data).to_byte_array()
+}
+
+// Path from a transaction to the merkle root: the sibling at each level,
+// leaf first. `index` is the transaction's position in the block, whose bits
+// say on which side each sibling goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub txid: Txid,
+    pub index: u32,
+    pub branch: Vec<Hash256>,
+}
+
+impl MerkleProof {
+    // None if `target` is not among `txids`
+    pub fn generate(txids: &[Txid], target: Txid) -> Option<Self> {
+        let position = txids.iter().position(|txid| *txid == target)?;
+        let mut level: Vec<[u8; 32]> = txids.iter().map(|txid| txid.to_byte_array()).collect();
+        let mut index = position;
+        let mut branch = Vec::new();
+        while level.len() > 1 {
+            let sibling = level.get(index ^ 1).unwrap_or(&level[index]);
+            branch.push(Hash256::from_byte_array(*sibling));
+            level = level
+                .chunks(2)
+                .map(|pair| parent(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                .collect();
+            index /= 2;
+        }
+        Some(MerkleProof {
+            txid: target,
+            index: position as u32,
+            branch,
+        })
+    }
+
+    pub fn compute_root(&self) -> Hash256 {
+        let mut node = self.txid.to_byte_array();
+        for (level, sibling) in self.branch.iter().enumerate() {
+            node = if (self.index >> level) & 1 == 1 {
+                parent(sibling.as_bytes(), &node)
+            } else {
+                parent(&node, sibling.as_bytes())
+            };
+        }
+        Hash256::from_byte_array(node)
+    }
+
+    // Whether the proof links the transaction to `root`
+    pub fn verify(&self, root: &Hash256) -> bool {
+        // Index bits beyond the tree height would be ignored, letting one
+        // proof claim many positions
+        let index_fits = self.branch.len() >= 32 || self.index >> self.branch.len() == 0;
+        index_fits && self.compute_root() == *root
+    }
+}
+
+// The part of a block's merkle tree proving that some of its transactions
+// are included (Core's CPartialMerkleTree). Nodes are visited depth first;
+// a flag bit per visited node says whether a matched transaction lies
+// beneath it, and nodes with none beneath, or matched leaves, carry their
+// hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub num_transactions: u32,
+    pub bits: Vec<bool>,
+    pub hashes: Vec<Hash256>,
+}
+
+impl PartialMerkleTree {
+    // `matches[i]` says whether `txids[i]` is to be proven; missing flags
+    // count as false
+    pub fn from_txids(txids: &[Txid], matches: &[bool]) -> Self {
+        let mut matches = matches.to_vec();
+        matches.resize(txids.len(), false);
+        let mut tree = PartialMerkleTree {
+            num_transactions: txids.len() as u32,
+            bits: Vec::new(),
+            hashes: Vec::new(),
+        };
+        if txids.is_empty() {
+            return tree;
+        }
+        let leaves: Vec<[u8; 32]> = txids.iter().map(|txid| txid.to_byte_array()).collect();
+        tree.build(tree.height(), 0, &leaves, &matches);
+        tree
+    }
+
+    // Nodes at `height` above the leaves
+    fn width(&self, height: u32) -> u32 {
+        ((self.num_transactions as u64 + (1 << height) - 1) >> height) as u32
+    }
+
+    fn height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        height
+    }
+
+    fn hash_at(&self, height: u32, position: u32, leaves: &[[u8; 32]]) -> [u8; 32] {
+        if height == 0 {
+            return leaves[position as usize];
+        }
+        let left = self.hash_at(height - 1, position * 2, leaves);
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            self.hash_at(height - 1, position * 2 + 1, leaves)
+        } else {
+            left
+        };
+        parent(&left, &right)
+    }
+
+    fn build(&mut self, height: u32, position: u32, leaves: &[[u8; 32]], matches: &[bool]) {
+        let start = (position as usize) << height;
+        let end = ((position as usize + 1) << height).min(leaves.len());
+        let parent_of_match = matches[start..end].iter().any(|matched| *matched);
+        self.bits.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            let hash = self.hash_at(height, position, leaves);
+            self.hashes.push(Hash256::from_byte_array(hash));
+        } else {
+            self.build(height - 1, position * 2, leaves, matches);
+            if position * 2 + 1 < self.width(height - 1) {
+                self.build(height - 1, position * 2 + 1, leaves, matches);
+            }
+        }
+    }
+
+    // Recomputes the merkle root, returning it with the matched
+    // transactions and their positions in the block. The caller checks the
+    // root against the block header.
+    pub fn extract_matches(&self) -> Result<(Hash256, Vec<(u32, Txid)>), BitcoinError> {
+        if self.num_transactions == 0 {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "no transactions",
+            )));
+        }
+        if self.num_transactions as u64 > MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "more transactions than fit a block",
+            )));
+        }
+        if self.hashes.len() > self.num_transactions as usize {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "more hashes than transactions",
+            )));
+        }
+        if self.bits.len() < self.hashes.len() {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "fewer flag bits than hashes",
+            )));
+        }
+        let mut cursor = Cursor::default();
+        let mut matches = Vec::new();
+        let root = self.extract(self.height(), 0, &mut cursor, &mut matches)?;
+        // Every hash must be used, and every flag bit but the padding
+        if cursor.bits.div_ceil(8) != self.bits.len().div_ceil(8) {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "unused flag bits",
+            )));
+        }
+        if cursor.hashes != self.hashes.len() {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "unused hashes",
+            )));
+        }
+        Ok((Hash256::from_byte_array(root), matches))
+    }
+
+    fn extract(
+        &self,
+        height: u32,
+        position: u32,
+        cursor: &mut Cursor,
+        matches: &mut Vec<(u32, Txid)>,
+    ) -> Result<[u8; 32], BitcoinError> {
+        let parent_of_match = *self.bits.get(cursor.bits).ok_or(BitcoinError::Crypto(
+            CryptoError::InvalidMerkleProof("ran out of flag bits"),
+        ))?;
+        cursor.bits += 1;
+        if height == 0 || !parent_of_match {
+            let hash = self
+                .hashes
+                .get(cursor.hashes)
+                .ok_or(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                    "ran out of hashes",
+                )))?
+                .to_byte_array();
+            cursor.hashes += 1;
+            if height == 0 && parent_of_match {
+                matches.push((position, Txid::from_byte_array(hash)));
+            }
+            return Ok(hash);
+        }
+        let left = self.extract(height - 1, position * 2, cursor, matches)?;
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1, cursor, matches)?;
+            // Identical siblings would let a duplicated transaction list
+            // prove the same root (CVE-2012-2459)
+            if right == left {
+                return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                    "identical sibling hashes",
+                )));
+            }
+            right
+        } else {
+            left
+        };
+        Ok(parent(&left, &right))
+    }
+
+    // Transaction count, hashes, then the flag bits packed least
+    // significant bit first
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.num_transactions.to_le_bytes().to_vec();
+        v.extend(CompactSize(self.hashes.len() as u64).encode());
+        for hash in &self.hashes {
+            v.extend(hash.as_bytes());
+        }
+        let mut flags = vec![0u8; self.bits.len().div_ceil(8)];
+        for (i, bit) in self.bits.iter().enumerate() {
+            flags[i / 8] |= (*bit as u8) << (i % 8);
+        }
+        v.extend(CompactSize(flags.len() as u64).encode());
+        v.extend(flags);
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let num_transactions = u32::from_le_bytes(read_array(data, 0)?);
+        let (CompactSize(count), used) =
+            CompactSize::decode(&data[4..]).map_err(|e| e.offset_by(4))?;
+        let mut offset = 4 + used;
+        // Checked against the data left so a huge count can't allocate
+        if count > (data.len() - offset) as u64 / 32 {
+            return Err(BitcoinError::Parse(ParseError::UnexpectedEof {
+                needed: 32,
+                offset: data.len(),
+            }));
+        }
+        let mut hashes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            hashes.push(Hash256::from_byte_array(read_array(data, offset)?));
+            offset += 32;
+        }
+        let (CompactSize(flag_bytes), used) =
+            CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        let flags = usize::try_from(flag_bytes)
+            .ok()
+            .and_then(|len| data.get(offset..offset.checked_add(len)?))
+            .ok_or(BitcoinError::Parse(ParseError::UnexpectedEof {
+                needed: flag_bytes as usize,
+                offset,
+            }))?;
+        offset += flags.len();
+        let bits = (0..flags.len() * 8)
+            .map(|i| flags[i / 8] >> (i % 8) & 1 == 1)
+            .collect();
+        Ok((
+            PartialMerkleTree {
+                num_transactions,
+                bits,
+                hashes,
+            },
+            offset,
+        ))
+    }
+}
+
+// Flag bits and hashes consumed so far while extracting
+#[derive(Default)]
+struct Cursor {
+    bits: usize,
+    hashes: usize,
+}
+
+// A block header with a partial merkle tree proving some of the block's
+// transactions, as sent in the merkleblock message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub txn: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    // Proves the block's transactions whose txids are in `txids`
+    pub fn from_block(block: &Block, txids: &[Txid]) -> Self {
+        let block_txids: Vec<Txid> = block.txdata.iter().map(|tx| tx.txid()).collect();
+        let matches: Vec<bool> = block_txids
+            .iter()
+            .map(|txid| txids.contains(txid))
+            .collect();
+        MerkleBlock {
+            header: block.header,
+            txn: PartialMerkleTree::from_txids(&block_txids, &matches),
+        }
+    }
+
+    // Proves the transactions matching a peer's bloom filter, updating the
+    // filter as matching does
+    pub fn from_block_with_filter(block: &Block, filter: &mut BloomFilter) -> Self {
+        let block_txids: Vec<Txid> = block.txdata.iter().map(|tx| tx.txid()).collect();
+        let matches: Vec<bool> = block
+            .txdata
+            .iter()
+            .map(|tx| tx.matches_filter(filter))
+            .collect();
+        MerkleBlock {
+            header: block.header,
+            txn: PartialMerkleTree::from_txids(&block_txids, &matches),
+        }
+    }
+
+    // The proven transactions and their positions, once the tree is checked
+    // against the header's merkle root
+    pub fn extract_matches(&self) -> Result<Vec<(u32, Txid)>, BitcoinError> {
+        let (root, matches) = self.txn.extract_matches()?;
+        if root != self.header.merkle_root {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "merkle root doesn't match the header",
+            )));
+        }
+        Ok(matches)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.header.serialize();
+        v.extend(self.txn.serialize());
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (header, offset) = BlockHeader::parse(data)?;
+        let (txn, used) =
+            PartialMerkleTree::parse(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        Ok((MerkleBlock { header, txn }, offset + used))
+    }
+}
@@ -0,0 +1,86 @@
+// The message signatures of Bitcoin Core's signmessage and verifymessage:
+// a recoverable ECDSA signature over the message with a magic prefix, as
+// base64. The key is recovered from the signature and checked against a
+// P2PKH address.
+
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+use crate::{
+    base64, hashes, Address, BitcoinError, CompactSize, CryptoError, Hash256, ParseError,
+    PrivateKey, PublicKey,
+};
+
+const MESSAGE_PREFIX: &[u8] = b"Bitcoin Signed Message:\n";
+
+// Header byte of a signature by an uncompressed key; one by the compressed
+// key adds 4, and the recovery id is added on top
+const HEADER_BASE: u8 = 27;
+
+// The digest signed: the prefix and the message, each with its length
+pub fn signed_message_hash(message: &[u8]) -> Hash256 {
+    let mut data = CompactSize(MESSAGE_PREFIX.len() as u64).encode();
+    data.extend(MESSAGE_PREFIX);
+    data.extend(CompactSize(message.len() as u64).encode());
+    data.extend(message);
+    hashes::sha256d(&data)
+}
+
+// Signs as signmessage does, for the P2PKH address of the key in its own
+// compressed or uncompressed form
+pub fn sign_message(key: &PrivateKey, message: &[u8]) -> Result<String, BitcoinError> {
+    let digest = signed_message_hash(message);
+    let (signature, recovery_id) = key
+        .signing_key()
+        .sign_prehash_recoverable(digest.as_bytes())
+        .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+    // Negating s for a low-S signature flips the recovered key's y
+    let (signature, recovery_id) = match signature.normalize_s() {
+        Some(normalized) => (
+            normalized,
+            RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced()),
+        ),
+        None => (signature, recovery_id),
+    };
+    let mut bytes = vec![HEADER_BASE + recovery_id.to_byte() + if key.compressed { 4 } else { 0 }];
+    bytes.extend(signature.to_bytes());
+    Ok(base64::encode(&bytes))
+}
+
+// The key that made the signature, compressed or not as the header says
+pub fn recover_public_key(signature: &str, message: &[u8]) -> Result<PublicKey, BitcoinError> {
+    let invalid =
+        || BitcoinError::Parse(ParseError::Message("Invalid message signature".to_string()));
+    let bytes = base64::decode(signature)?;
+    let Some((&header, compact)) = bytes.split_first() else {
+        return Err(invalid());
+    };
+    if compact.len() != 64 || !(HEADER_BASE..HEADER_BASE + 8).contains(&header) {
+        return Err(invalid());
+    }
+    let compressed = header >= HEADER_BASE + 4;
+    let recovery_id = RecoveryId::from_byte((header - HEADER_BASE) % 4).ok_or_else(invalid)?;
+    let signature = Signature::from_slice(compact).map_err(|_| invalid())?;
+    let digest = signed_message_hash(message);
+    let key = VerifyingKey::recover_from_prehash(digest.as_bytes(), &signature, recovery_id)
+        .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+    PublicKey::from_slice(key.to_encoded_point(compressed).as_bytes())
+}
+
+// Checks the signature as verifymessage does: only P2PKH addresses have a
+// single key to recover, and a valid signature by another key fails with
+// KeyMismatch
+pub fn verify_message(
+    address: &Address,
+    signature: &str,
+    message: &[u8],
+) -> Result<(), BitcoinError> {
+    let Address::P2pkh { pubkey_hash, .. } = address else {
+        return Err(BitcoinError::Parse(ParseError::InvalidAddress(format!(
+            "{address} does not refer to a key"
+        ))));
+    };
+    if recover_public_key(signature, message)?.pubkey_hash() != *pubkey_hash {
+        return Err(BitcoinError::Crypto(CryptoError::KeyMismatch));
+    }
+    Ok(())
+}
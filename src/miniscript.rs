@@ -0,0 +1,487 @@
+// Spending policies compiled to miniscript, for P2WSH witness scripts.
+//
+// The policy language is pk(KEY), after(N), older(N), sha256(H),
+// hash256(H), ripemd160(H), hash160(H), and(X,Y), or(X,Y) and
+// thresh(k,X,Y,...), with keys and hashes in hex. Or-branch weights
+// (`9@X`) are not supported: both branches are assumed equally likely.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::expression::Tree;
+use crate::script::{Opcode, Script, ScriptBuilder, MAX_PUBKEYS_PER_MULTISIG};
+use crate::{hex, policy, BitcoinError, ParseError, PublicKey, UsageError};
+
+// Timelocks must be nonzero and fit in 31 bits: the top bit disables
+// OP_CHECKSEQUENCEVERIFY and would make the number negative
+const MAX_TIMELOCK: u32 = 0x8000_0000;
+
+// Witness element sizes, each including its one-byte length prefix
+const SIGNATURE_SIZE: usize = 1 + 73;
+const EMPTY_SIZE: usize = 1;
+const ONE_SIZE: usize = 2;
+const PREIMAGE_SIZE: usize = 1 + 32;
+
+// A hash preimage condition, shared by policies and miniscript
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashLock {
+    Sha256([u8; 32]),
+    Hash256([u8; 32]),
+    Ripemd160([u8; 20]),
+    Hash160([u8; 20]),
+}
+
+impl HashLock {
+    fn name(&self) -> &'static str {
+        match self {
+            HashLock::Sha256(_) => "sha256",
+            HashLock::Hash256(_) => "hash256",
+            HashLock::Ripemd160(_) => "ripemd160",
+            HashLock::Hash160(_) => "hash160",
+        }
+    }
+
+    fn opcode(&self) -> Opcode {
+        match self {
+            HashLock::Sha256(_) => Opcode::OP_SHA256,
+            HashLock::Hash256(_) => Opcode::OP_HASH256,
+            HashLock::Ripemd160(_) => Opcode::OP_RIPEMD160,
+            HashLock::Hash160(_) => Opcode::OP_HASH160,
+        }
+    }
+
+    fn hash(&self) -> &[u8] {
+        match self {
+            HashLock::Sha256(h) | HashLock::Hash256(h) => h,
+            HashLock::Ripemd160(h) | HashLock::Hash160(h) => h,
+        }
+    }
+
+    fn parse(name: &str, arg: &str) -> Result<Self, BitcoinError> {
+        let bytes = hex::decode(arg)?;
+        let invalid =
+            || BitcoinError::Parse(ParseError::Message(format!("Invalid {name} hash {arg:?}")));
+        Ok(match name {
+            "sha256" => HashLock::Sha256(bytes.try_into().map_err(|_| invalid())?),
+            "hash256" => HashLock::Hash256(bytes.try_into().map_err(|_| invalid())?),
+            "ripemd160" => HashLock::Ripemd160(bytes.try_into().map_err(|_| invalid())?),
+            _ => HashLock::Hash160(bytes.try_into().map_err(|_| invalid())?),
+        })
+    }
+}
+
+impl fmt::Display for HashLock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.name(), hex::encode(self.hash()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    Key(PublicKey),
+    // Absolute lock time (OP_CHECKLOCKTIMEVERIFY)
+    After(u32),
+    // Relative lock time (OP_CHECKSEQUENCEVERIFY)
+    Older(u32),
+    Hash(HashLock),
+    And(Box<Policy>, Box<Policy>),
+    Or(Box<Policy>, Box<Policy>),
+    // k of the sub-policies
+    Thresh(usize, Vec<Policy>),
+}
+
+impl Policy {
+    fn from_tree(tree: &Tree) -> Result<Self, BitcoinError> {
+        match tree.name {
+            "pk" => {
+                let key = tree.args_exactly(1)?[0].terminal()?;
+                Ok(Policy::Key(PublicKey::from_slice(&hex::decode(key)?)?))
+            }
+            "after" => Ok(Policy::After(parse_timelock(tree)?)),
+            "older" => Ok(Policy::Older(parse_timelock(tree)?)),
+            "sha256" | "hash256" | "ripemd160" | "hash160" => {
+                let hash = tree.args_exactly(1)?[0].terminal()?;
+                Ok(Policy::Hash(HashLock::parse(tree.name, hash)?))
+            }
+            "and" | "or" => {
+                let args = tree.args_exactly(2)?;
+                let left = Box::new(Policy::from_tree(&args[0])?);
+                let right = Box::new(Policy::from_tree(&args[1])?);
+                Ok(if tree.name == "and" {
+                    Policy::And(left, right)
+                } else {
+                    Policy::Or(left, right)
+                })
+            }
+            "thresh" => {
+                let Some((k, subs)) = tree.args.split_first() else {
+                    return Err(BitcoinError::Parse(ParseError::Message(
+                        "thresh needs arguments".to_string(),
+                    )));
+                };
+                let k: usize = k.terminal()?.parse().map_err(|_| {
+                    BitcoinError::Parse(ParseError::Message(format!(
+                        "Invalid threshold {:?}",
+                        k.name
+                    )))
+                })?;
+                if k == 0 || k > subs.len() {
+                    return Err(BitcoinError::Parse(ParseError::Message(format!(
+                        "Threshold {k} out of range for {} sub-policies",
+                        subs.len()
+                    ))));
+                }
+                let subs = subs
+                    .iter()
+                    .map(Policy::from_tree)
+                    .collect::<Result<_, _>>()?;
+                Ok(Policy::Thresh(k, subs))
+            }
+            name if name.contains('@') => Err(BitcoinError::Parse(ParseError::Message(
+                "Or-branch weights are not supported".to_string(),
+            ))),
+            name => Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Unknown policy fragment {name:?}"
+            )))),
+        }
+    }
+
+    // Fails if the script is too large to relay as a P2WSH witness script
+    pub fn compile(&self) -> Result<Miniscript, BitcoinError> {
+        let miniscript = self.compile_node();
+        let size = miniscript.script_size();
+        if size > policy::MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Err(BitcoinError::Usage(UsageError::WitnessScriptTooLarge(size)));
+        }
+        Ok(miniscript)
+    }
+
+    // Picks a miniscript fragment for each policy node, favouring small
+    // scripts: thresholds of keys become CHECKMULTISIG, k-of-k and 1-of-n
+    // thresholds become and/or chains
+    fn compile_node(&self) -> Miniscript {
+        match self {
+            Policy::Key(key) => Miniscript::Pk(*key),
+            Policy::After(n) => Miniscript::After(*n),
+            Policy::Older(n) => Miniscript::Older(*n),
+            Policy::Hash(lock) => Miniscript::Hash(*lock),
+            Policy::And(left, right) => Miniscript::AndV(
+                Box::new(left.compile_node()),
+                Box::new(right.compile_node()),
+            ),
+            Policy::Or(left, right) => Miniscript::OrI(
+                Box::new(left.compile_node()),
+                Box::new(right.compile_node()),
+            ),
+            Policy::Thresh(k, subs) => {
+                let keys: Vec<PublicKey> = subs
+                    .iter()
+                    .filter_map(|sub| match sub {
+                        Policy::Key(key) => Some(*key),
+                        _ => None,
+                    })
+                    .collect();
+                if keys.len() == subs.len() && keys.len() <= MAX_PUBKEYS_PER_MULTISIG {
+                    return Miniscript::Multi(*k, keys);
+                }
+                let mut compiled: Vec<Miniscript> = subs.iter().map(Policy::compile_node).collect();
+                if *k == subs.len() || *k == 1 {
+                    let last = compiled.pop().expect("thresh has sub-policies");
+                    return compiled.into_iter().rev().fold(last, |acc, sub| {
+                        if *k == 1 {
+                            Miniscript::OrI(Box::new(sub), Box::new(acc))
+                        } else {
+                            Miniscript::AndV(Box::new(sub), Box::new(acc))
+                        }
+                    });
+                }
+                let compiled = compiled
+                    .into_iter()
+                    .map(|sub| {
+                        if sub.dissatisfaction_size().is_some() {
+                            sub
+                        } else {
+                            Miniscript::Dissatisfiable(Box::new(sub))
+                        }
+                    })
+                    .collect();
+                Miniscript::Thresh(*k, compiled)
+            }
+        }
+    }
+}
+
+fn parse_timelock(tree: &Tree) -> Result<u32, BitcoinError> {
+    let arg = tree.args_exactly(1)?[0].terminal()?;
+    match arg.parse::<u32>() {
+        Ok(n) if n > 0 && n < MAX_TIMELOCK => Ok(n),
+        _ => Err(BitcoinError::Parse(ParseError::Message(format!(
+            "Invalid {} timelock {arg:?}",
+            tree.name
+        )))),
+    }
+}
+
+impl FromStr for Policy {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Policy::from_tree(&Tree::parse(s)?)
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Policy::Key(key) => write!(f, "pk({})", hex::encode(&key.serialize())),
+            Policy::After(n) => write!(f, "after({n})"),
+            Policy::Older(n) => write!(f, "older({n})"),
+            Policy::Hash(lock) => write!(f, "{lock}"),
+            Policy::And(left, right) => write!(f, "and({left},{right})"),
+            Policy::Or(left, right) => write!(f, "or({left},{right})"),
+            Policy::Thresh(k, subs) => {
+                write!(f, "thresh({k}")?;
+                for sub in subs {
+                    write!(f, ",{sub}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+// The subset of miniscript the policy compiler produces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Miniscript {
+    // pk(KEY): <key> OP_CHECKSIG
+    Pk(PublicKey),
+    // <n> OP_CHECKLOCKTIMEVERIFY
+    After(u32),
+    // <n> OP_CHECKSEQUENCEVERIFY
+    Older(u32),
+    // OP_SIZE <32> OP_EQUALVERIFY <hash op> <hash> OP_EQUAL
+    Hash(HashLock),
+    // <k> <keys> <n> OP_CHECKMULTISIG
+    Multi(usize, Vec<PublicKey>),
+    // and_v(v:X,Y): [X] verified, then [Y]
+    AndV(Box<Miniscript>, Box<Miniscript>),
+    // OP_IF [X] OP_ELSE [Y] OP_ENDIF, the witness choosing the branch
+    OrI(Box<Miniscript>, Box<Miniscript>),
+    // [X1] then each of the others run on the witness below the running
+    // count, added to it, then <k> OP_EQUAL
+    Thresh(usize, Vec<Miniscript>),
+    // ln:X, OP_IF 0 OP_ELSE [X] OP_0NOTEQUAL OP_ENDIF: lets thresh skip a
+    // fragment that can't otherwise be dissatisfied, such as a timelock
+    Dissatisfiable(Box<Miniscript>),
+}
+
+impl Miniscript {
+    pub fn encode(&self) -> Script {
+        self.push_to(ScriptBuilder::new(), false).into_script()
+    }
+
+    // The P2WSH output paying to the encoded script
+    pub fn to_p2wsh(&self) -> Script {
+        self.encode().to_p2wsh()
+    }
+
+    pub fn script_size(&self) -> usize {
+        self.encode().len()
+    }
+
+    // Largest witness satisfying the script, not counting the witness
+    // script itself, with signatures at their maximum DER size
+    pub fn max_satisfaction_size(&self) -> usize {
+        match self {
+            Miniscript::Pk(_) => SIGNATURE_SIZE,
+            Miniscript::After(_) | Miniscript::Older(_) => 0,
+            Miniscript::Hash(_) => PREIMAGE_SIZE,
+            // The extra element is the dummy CHECKMULTISIG pops
+            Miniscript::Multi(k, _) => EMPTY_SIZE + k * SIGNATURE_SIZE,
+            Miniscript::AndV(left, right) => {
+                left.max_satisfaction_size() + right.max_satisfaction_size()
+            }
+            Miniscript::OrI(left, right) => (left.max_satisfaction_size() + ONE_SIZE)
+                .max(right.max_satisfaction_size() + EMPTY_SIZE),
+            Miniscript::Thresh(k, subs) => {
+                // Dissatisfy every fragment, then satisfy the k that cost
+                // the most more to satisfy
+                let dissatisfied: Vec<usize> = subs.iter().map(Self::thresh_dissat).collect();
+                let mut extra: Vec<isize> = subs
+                    .iter()
+                    .zip(&dissatisfied)
+                    .map(|(sub, dissat)| sub.max_satisfaction_size() as isize - *dissat as isize)
+                    .collect();
+                extra.sort_unstable_by(|a, b| b.cmp(a));
+                let total = dissatisfied.iter().sum::<usize>() as isize
+                    + extra.iter().take(*k).sum::<isize>();
+                total as usize
+            }
+            Miniscript::Dissatisfiable(inner) => inner.max_satisfaction_size() + EMPTY_SIZE,
+        }
+    }
+
+    // Witness that makes the fragment push 0 without failing, or None if
+    // there isn't one
+    fn dissatisfaction_size(&self) -> Option<usize> {
+        match self {
+            Miniscript::Pk(_) => Some(EMPTY_SIZE),
+            Miniscript::Hash(_) => Some(PREIMAGE_SIZE),
+            Miniscript::Multi(k, _) => Some((k + 1) * EMPTY_SIZE),
+            Miniscript::Thresh(_, subs) => Some(subs.iter().map(Self::thresh_dissat).sum()),
+            Miniscript::Dissatisfiable(_) => Some(ONE_SIZE),
+            Miniscript::After(_)
+            | Miniscript::Older(_)
+            | Miniscript::AndV(..)
+            | Miniscript::OrI(..) => None,
+        }
+    }
+
+    fn thresh_dissat(&self) -> usize {
+        self.dissatisfaction_size()
+            .expect("thresh fragments are dissatisfiable")
+    }
+
+    // `verify` asks for the fragment's result to be checked rather than
+    // left on the stack, fusing into the VERIFY form of the final opcode
+    // where there is one
+    fn push_to(&self, builder: ScriptBuilder, verify: bool) -> ScriptBuilder {
+        match self {
+            Miniscript::Pk(key) => builder.push_bytes(&key.serialize()).push_opcode(if verify {
+                Opcode::OP_CHECKSIGVERIFY
+            } else {
+                Opcode::OP_CHECKSIG
+            }),
+            Miniscript::After(n) => verify_if(
+                builder
+                    .push_int(*n as i64)
+                    .push_opcode(Opcode::OP_CHECKLOCKTIMEVERIFY),
+                verify,
+            ),
+            Miniscript::Older(n) => verify_if(
+                builder
+                    .push_int(*n as i64)
+                    .push_opcode(Opcode::OP_CHECKSEQUENCEVERIFY),
+                verify,
+            ),
+            Miniscript::Hash(lock) => builder
+                .push_opcode(Opcode::OP_SIZE)
+                .push_int(32)
+                .push_opcode(Opcode::OP_EQUALVERIFY)
+                .push_opcode(lock.opcode())
+                .push_bytes(lock.hash())
+                .push_opcode(equal(verify)),
+            Miniscript::Multi(k, keys) => {
+                let builder = keys
+                    .iter()
+                    .fold(builder.push_int(*k as i64), |builder, key| {
+                        builder.push_bytes(&key.serialize())
+                    });
+                builder.push_int(keys.len() as i64).push_opcode(if verify {
+                    Opcode::OP_CHECKMULTISIGVERIFY
+                } else {
+                    Opcode::OP_CHECKMULTISIG
+                })
+            }
+            Miniscript::AndV(left, right) => right.push_to(left.push_to(builder, true), verify),
+            Miniscript::OrI(left, right) => {
+                let builder = left.push_to(builder.push_opcode(Opcode::OP_IF), false);
+                let builder = right
+                    .push_to(builder.push_opcode(Opcode::OP_ELSE), false)
+                    .push_opcode(Opcode::OP_ENDIF);
+                verify_if(builder, verify)
+            }
+            Miniscript::Thresh(k, subs) => {
+                let mut builder = subs[0].push_to(builder, false);
+                for sub in &subs[1..] {
+                    // s: for a fragment taking one witness element, a:
+                    // (via the altstack) otherwise
+                    builder = if let Miniscript::Pk(_) = sub {
+                        sub.push_to(builder.push_opcode(Opcode::OP_SWAP), false)
+                    } else {
+                        sub.push_to(builder.push_opcode(Opcode::OP_TOALTSTACK), false)
+                            .push_opcode(Opcode::OP_FROMALTSTACK)
+                    };
+                    builder = builder.push_opcode(Opcode::OP_ADD);
+                }
+                builder.push_int(*k as i64).push_opcode(equal(verify))
+            }
+            Miniscript::Dissatisfiable(inner) => {
+                let builder = builder
+                    .push_opcode(Opcode::OP_IF)
+                    .push_int(0)
+                    .push_opcode(Opcode::OP_ELSE);
+                let builder = inner
+                    .push_to(builder, false)
+                    .push_opcode(Opcode::OP_0NOTEQUAL)
+                    .push_opcode(Opcode::OP_ENDIF);
+                verify_if(builder, verify)
+            }
+        }
+    }
+
+    fn fmt_wrapped(&self, f: &mut fmt::Formatter, wrappers: &str) -> fmt::Result {
+        match self {
+            Miniscript::Dissatisfiable(inner) => inner.fmt_wrapped(f, &format!("{wrappers}ln")),
+            _ if wrappers.is_empty() => write!(f, "{self}"),
+            _ => write!(f, "{wrappers}:{self}"),
+        }
+    }
+}
+
+fn verify_if(builder: ScriptBuilder, verify: bool) -> ScriptBuilder {
+    if verify {
+        builder.push_opcode(Opcode::OP_VERIFY)
+    } else {
+        builder
+    }
+}
+
+fn equal(verify: bool) -> Opcode {
+    if verify {
+        Opcode::OP_EQUALVERIFY
+    } else {
+        Opcode::OP_EQUAL
+    }
+}
+
+impl fmt::Display for Miniscript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Miniscript::Pk(key) => write!(f, "pk({})", hex::encode(&key.serialize())),
+            Miniscript::After(n) => write!(f, "after({n})"),
+            Miniscript::Older(n) => write!(f, "older({n})"),
+            Miniscript::Hash(lock) => write!(f, "{lock}"),
+            Miniscript::Multi(k, keys) => {
+                write!(f, "multi({k}")?;
+                for key in keys {
+                    write!(f, ",{}", hex::encode(&key.serialize()))?;
+                }
+                write!(f, ")")
+            }
+            Miniscript::AndV(left, right) => {
+                write!(f, "and_v(")?;
+                left.fmt_wrapped(f, "v")?;
+                write!(f, ",{right})")
+            }
+            Miniscript::OrI(left, right) => write!(f, "or_i({left},{right})"),
+            Miniscript::Thresh(k, subs) => {
+                write!(f, "thresh({k},")?;
+                subs[0].fmt_wrapped(f, "")?;
+                for sub in &subs[1..] {
+                    write!(f, ",")?;
+                    sub.fmt_wrapped(
+                        f,
+                        if let Miniscript::Pk(_) = sub {
+                            "s"
+                        } else {
+                            "a"
+                        },
+                    )?;
+                }
+                write!(f, ")")
+            }
+            Miniscript::Dissatisfiable(_) => self.fmt_wrapped(f, ""),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+// MuSig2 (BIP327): several signers produce one BIP340 signature for the
+// aggregate of their keys, in two rounds: exchanging nonces, then partial
+// signatures. With the Taproot tweak applied the aggregate is an output key,
+// and the signature spends it by key path like any single signer's.
+
+use k256::elliptic_curve::ops::Reduce;
+use k256::elliptic_curve::rand_core::{OsRng, RngCore};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::elliptic_curve::PrimeField;
+use k256::{ProjectivePoint, Scalar, U256};
+
+use crate::hashes::tagged_hash;
+use crate::{BitcoinError, CryptoError, Hash256, PrivateKey, PublicKey, XOnlyPublicKey};
+
+// The signers' keys combined into one, plus any tweaks applied on top
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyAggContext {
+    // Compressed, in aggregation order
+    pubkeys: Vec<[u8; 33]>,
+    list_hash: Hash256,
+    // The first key differing from the first, whose coefficient is 1
+    second_key: Option<[u8; 33]>,
+    q: ProjectivePoint,
+    // Accumulated sign flips and tweaks (BIP327's gacc and tacc)
+    gacc: Scalar,
+    tacc: Scalar,
+}
+
+impl KeyAggContext {
+    // The order of `pubkeys` changes the aggregate; sort them with
+    // key_sort for one that doesn't depend on it
+    pub fn new(pubkeys: &[PublicKey]) -> Result<Self, BitcoinError> {
+        let pubkeys: Vec<[u8; 33]> = pubkeys.iter().map(compressed_key).collect();
+        let first = pubkeys
+            .first()
+            .ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+        let second_key = pubkeys.iter().find(|pk| *pk != first).copied();
+        let list_hash = tagged_hash("KeyAgg list", &[&pubkeys.concat()]);
+        let mut context = KeyAggContext {
+            pubkeys,
+            list_hash,
+            second_key,
+            q: ProjectivePoint::IDENTITY,
+            gacc: Scalar::ONE,
+            tacc: Scalar::ZERO,
+        };
+        for pk in &context.pubkeys {
+            let point =
+                parse_point(pk).ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+            context.q += point * context.coefficient(pk);
+        }
+        if context.q == ProjectivePoint::IDENTITY {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        Ok(context)
+    }
+
+    fn coefficient(&self, pk: &[u8; 33]) -> Scalar {
+        if self.second_key.as_ref() == Some(pk) {
+            return Scalar::ONE;
+        }
+        reduce(tagged_hash(
+            "KeyAgg coefficient",
+            &[self.list_hash.as_bytes(), pk],
+        ))
+    }
+
+    // The key the final signature verifies under
+    pub fn aggregated_key(&self) -> XOnlyPublicKey {
+        XOnlyPublicKey::from_slice(&x_bytes(&self.q)).expect("aggregate is never infinity")
+    }
+
+    // With its y parity, for BIP32 derivation or a further tweak elsewhere
+    pub fn aggregated_public_key(&self) -> PublicKey {
+        PublicKey::from_slice(self.q.to_affine().to_encoded_point(true).as_bytes())
+            .expect("aggregate is never infinity")
+    }
+
+    // Adds `tweak` times G to the aggregate. An x-only tweak first negates
+    // the aggregate if its y is odd, as Taproot tweaks do (BIP327
+    // ApplyTweak).
+    pub fn tweak(mut self, tweak: &[u8; 32], x_only: bool) -> Result<Self, BitcoinError> {
+        let t = Option::<Scalar>::from(Scalar::from_repr((*tweak).into()))
+            .ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+        let g = if x_only && !has_even_y(&self.q) {
+            -Scalar::ONE
+        } else {
+            Scalar::ONE
+        };
+        self.q = self.q * g + ProjectivePoint::GENERATOR * t;
+        if self.q == ProjectivePoint::IDENTITY {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+        }
+        self.gacc *= g;
+        self.tacc = t + g * self.tacc;
+        Ok(self)
+    }
+
+    // The BIP341 tweak, making the aggregate the output key of a Taproot
+    // output with this internal key and script tree
+    pub fn taproot_tweak(self, merkle_root: Option<[u8; 32]>) -> Result<Self, BitcoinError> {
+        let internal_key = x_bytes(&self.q);
+        let tweak = match merkle_root {
+            Some(root) => tagged_hash("TapTweak", &[&internal_key, &root]),
+            None => tagged_hash("TapTweak", &[&internal_key]),
+        };
+        self.tweak(tweak.as_bytes(), true)
+    }
+}
+
+// Orders keys by their compressed encoding (BIP327 KeySort)
+pub fn key_sort(pubkeys: &mut [PublicKey]) {
+    pubkeys.sort_by_key(compressed_key);
+}
+
+// A signer's two secret nonces for one session. It isn't Clone, and
+// signing consumes it: a second signature with the same nonce would reveal
+// the key.
+pub struct SecretNonce {
+    k1: Scalar,
+    k2: Scalar,
+    pubkey: [u8; 33],
+}
+
+impl std::fmt::Debug for SecretNonce {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretNonce").finish_non_exhaustive()
+    }
+}
+
+// What a signer sends the others in the first round
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicNonce {
+    r1: ProjectivePoint,
+    r2: ProjectivePoint,
+}
+
+impl PublicNonce {
+    // Two compressed points
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0; 66];
+        bytes[..33].copy_from_slice(&point_bytes(&self.r1));
+        bytes[33..].copy_from_slice(&point_bytes(&self.r2));
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+        let (r1, r2) = match bytes.split_at_checked(33) {
+            Some((r1, r2)) if r2.len() == 33 => (parse_point(r1), parse_point(r2)),
+            _ => return Err(BitcoinError::Crypto(CryptoError::InvalidNonce)),
+        };
+        match (r1, r2) {
+            (Some(r1), Some(r2)) => Ok(PublicNonce { r1, r2 }),
+            _ => Err(BitcoinError::Crypto(CryptoError::InvalidNonce)),
+        }
+    }
+}
+
+// The sum of every signer's public nonce, which any of them, or a
+// coordinator, can compute and hand out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AggregatedNonce {
+    r1: ProjectivePoint,
+    r2: ProjectivePoint,
+}
+
+impl AggregatedNonce {
+    // Like PublicNonce's, with 33 zero bytes for a point at infinity
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0; 66];
+        bytes[..33].copy_from_slice(&point_bytes(&self.r1));
+        bytes[33..].copy_from_slice(&point_bytes(&self.r2));
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+        let parse = |bytes: &[u8]| {
+            if bytes.iter().all(|b| *b == 0) {
+                Some(ProjectivePoint::IDENTITY)
+            } else {
+                parse_point(bytes)
+            }
+        };
+        let (r1, r2) = match bytes.split_at_checked(33) {
+            Some((r1, r2)) if r2.len() == 33 => (parse(r1), parse(r2)),
+            _ => return Err(BitcoinError::Crypto(CryptoError::InvalidNonce)),
+        };
+        match (r1, r2) {
+            (Some(r1), Some(r2)) => Ok(AggregatedNonce { r1, r2 }),
+            _ => Err(BitcoinError::Crypto(CryptoError::InvalidNonce)),
+        }
+    }
+}
+
+pub fn aggregate_nonces(nonces: &[PublicNonce]) -> AggregatedNonce {
+    nonces.iter().fold(
+        AggregatedNonce {
+            r1: ProjectivePoint::IDENTITY,
+            r2: ProjectivePoint::IDENTITY,
+        },
+        |sum, nonce| AggregatedNonce {
+            r1: sum.r1 + nonce.r1,
+            r2: sum.r2 + nonce.r2,
+        },
+    )
+}
+
+// BIP327 NonceGen. `rand` must be fresh randomness for every session; the
+// optional inputs only guard against a weak source of it.
+pub fn nonce_gen(
+    rand: [u8; 32],
+    key: Option<&PrivateKey>,
+    pubkey: &PublicKey,
+    aggregated_key: Option<&XOnlyPublicKey>,
+    msg: Option<&[u8]>,
+    extra_in: &[u8],
+) -> Result<(SecretNonce, PublicNonce), BitcoinError> {
+    let mut rand = rand;
+    if let Some(key) = key {
+        let aux = tagged_hash("MuSig/aux", &[&rand]);
+        rand = std::array::from_fn(|i| key.to_bytes()[i] ^ aux.as_bytes()[i]);
+    }
+    let pubkey = compressed_key(pubkey);
+    let aggregated_key = aggregated_key.map(XOnlyPublicKey::serialize);
+    let aggregated_key: &[u8] = aggregated_key.as_ref().map_or(&[], |key| key);
+    let mut msg_prefixed = Vec::new();
+    match msg {
+        Some(msg) => {
+            msg_prefixed.push(1);
+            msg_prefixed.extend((msg.len() as u64).to_be_bytes());
+            msg_prefixed.extend(msg);
+        }
+        None => msg_prefixed.push(0),
+    }
+    let nonce = |i: u8| {
+        reduce(tagged_hash(
+            "MuSig/nonce",
+            &[
+                &rand,
+                &[pubkey.len() as u8],
+                &pubkey,
+                &[aggregated_key.len() as u8],
+                aggregated_key,
+                &msg_prefixed,
+                &(extra_in.len() as u32).to_be_bytes(),
+                extra_in,
+                &[i],
+            ],
+        ))
+    };
+    let (k1, k2) = (nonce(0), nonce(1));
+    if k1 == Scalar::ZERO || k2 == Scalar::ZERO {
+        return Err(BitcoinError::Crypto(CryptoError::InvalidNonce));
+    }
+    let public = PublicNonce {
+        r1: ProjectivePoint::GENERATOR * k1,
+        r2: ProjectivePoint::GENERATOR * k2,
+    };
+    Ok((SecretNonce { k1, k2, pubkey }, public))
+}
+
+// Nonces for signing with `key` under `key_agg`, from the operating
+// system's random number generator
+pub fn generate_nonce(
+    key: &PrivateKey,
+    key_agg: &KeyAggContext,
+    msg: Option<&[u8]>,
+) -> Result<(SecretNonce, PublicNonce), BitcoinError> {
+    let mut rand = [0; 32];
+    OsRng.fill_bytes(&mut rand);
+    nonce_gen(
+        rand,
+        Some(key),
+        &key.public_key(),
+        Some(&key_agg.aggregated_key()),
+        msg,
+        &[],
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialSignature(Scalar);
+
+impl PartialSignature {
+    pub fn serialize(&self) -> [u8; 32] {
+        self.0.to_repr().into()
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, BitcoinError> {
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPartialSignature))?;
+        Option::<Scalar>::from(Scalar::from_repr(bytes.into()))
+            .map(PartialSignature)
+            .ok_or(BitcoinError::Crypto(CryptoError::InvalidPartialSignature))
+    }
+}
+
+// The second round for one message, once the nonces are aggregated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    key_agg: KeyAggContext,
+    // Nonce coefficient, final nonce and challenge
+    b: Scalar,
+    r: ProjectivePoint,
+    e: Scalar,
+}
+
+impl Session {
+    pub fn new(key_agg: &KeyAggContext, aggregated_nonce: &AggregatedNonce, msg: &[u8]) -> Self {
+        let q = x_bytes(&key_agg.q);
+        let b = reduce(tagged_hash(
+            "MuSig/noncecoef",
+            &[&aggregated_nonce.serialize(), &q, msg],
+        ));
+        // An infinite nonce can only come from dishonest signers, who
+        // can't gain from it; G keeps the protocol going
+        let mut r = aggregated_nonce.r1 + aggregated_nonce.r2 * b;
+        if r == ProjectivePoint::IDENTITY {
+            r = ProjectivePoint::GENERATOR;
+        }
+        let e = reduce(tagged_hash("BIP0340/challenge", &[&x_bytes(&r), &q, msg]));
+        Session {
+            key_agg: key_agg.clone(),
+            b,
+            r,
+            e,
+        }
+    }
+
+    // The key's share of the signature, using up its nonce
+    pub fn sign(
+        &self,
+        nonce: SecretNonce,
+        key: &PrivateKey,
+    ) -> Result<PartialSignature, BitcoinError> {
+        let pubkey = compressed_key(&key.public_key());
+        if pubkey != nonce.pubkey || !self.key_agg.pubkeys.contains(&pubkey) {
+            return Err(BitcoinError::Crypto(CryptoError::KeyMismatch));
+        }
+        let d = Option::<Scalar>::from(Scalar::from_repr(key.to_bytes().into()))
+            .ok_or(BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+        let (k1, k2) = if has_even_y(&self.r) {
+            (nonce.k1, nonce.k2)
+        } else {
+            (-nonce.k1, -nonce.k2)
+        };
+        let d = self.key_sign() * self.key_agg.gacc * d;
+        let a = self.key_agg.coefficient(&pubkey);
+        Ok(PartialSignature(k1 + self.b * k2 + self.e * a * d))
+    }
+
+    // Whether `signature` is a valid share from the signer of `pubkey` and
+    // `nonce`, to find which signer spoiled an invalid final signature
+    pub fn verify_partial(
+        &self,
+        signature: &PartialSignature,
+        nonce: &PublicNonce,
+        pubkey: &PublicKey,
+    ) -> bool {
+        let pk = compressed_key(pubkey);
+        let Some(point) = parse_point(&pk).filter(|_| self.key_agg.pubkeys.contains(&pk)) else {
+            return false;
+        };
+        let r = nonce.r1 + nonce.r2 * self.b;
+        let r = if has_even_y(&self.r) { r } else { -r };
+        let g = self.key_sign() * self.key_agg.gacc;
+        let a = self.key_agg.coefficient(&pk);
+        ProjectivePoint::GENERATOR * signature.0 == r + point * (self.e * a * g)
+    }
+
+    // The BIP340 signature for the aggregated key, from every signer's
+    // partial signature
+    pub fn aggregate(&self, signatures: &[PartialSignature]) -> [u8; 64] {
+        let s = signatures
+            .iter()
+            .fold(self.e * self.key_sign() * self.key_agg.tacc, |s, sig| {
+                s + sig.0
+            });
+        let mut signature = [0; 64];
+        signature[..32].copy_from_slice(&x_bytes(&self.r));
+        signature[32..].copy_from_slice(&s.to_repr());
+        signature
+    }
+
+    // Negates the keys when the aggregate has odd y, as BIP340 requires
+    fn key_sign(&self) -> Scalar {
+        if has_even_y(&self.key_agg.q) {
+            Scalar::ONE
+        } else {
+            -Scalar::ONE
+        }
+    }
+}
+
+fn compressed_key(pubkey: &PublicKey) -> [u8; 33] {
+    let mut pubkey = *pubkey;
+    pubkey.compressed = true;
+    let mut bytes = [0; 33];
+    bytes.copy_from_slice(&pubkey.serialize());
+    bytes
+}
+
+// A compressed point; anything else is rejected
+fn parse_point(bytes: &[u8]) -> Option<ProjectivePoint> {
+    if bytes.len() != 33 {
+        return None;
+    }
+    k256::PublicKey::from_sec1_bytes(bytes)
+        .ok()
+        .map(|pk| pk.to_projective())
+}
+
+// Compressed, or 33 zero bytes for infinity
+fn point_bytes(point: &ProjectivePoint) -> [u8; 33] {
+    let encoded = point.to_affine().to_encoded_point(true);
+    let mut bytes = [0; 33];
+    if let Ok(compressed) = <[u8; 33]>::try_from(encoded.as_bytes()) {
+        bytes = compressed;
+    }
+    bytes
+}
+
+fn x_bytes(point: &ProjectivePoint) -> [u8; 32] {
+    let mut x = [0; 32];
+    x.copy_from_slice(&point_bytes(point)[1..]);
+    x
+}
+
+fn has_even_y(point: &ProjectivePoint) -> bool {
+    point_bytes(point)[0] == 0x02
+}
+
+fn reduce(hash: Hash256) -> Scalar {
+    <Scalar as Reduce<U256>>::reduce_bytes(&hash.to_byte_array().into())
+}
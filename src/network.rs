@@ -0,0 +1,232 @@
+// Bitcoin networks and the parameters that differ between them
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{
+    consensus, hex, Amount, BitcoinError, Block, BlockHash, BlockHeader, Hash256,
+    LegacyTransaction, LockTime, OutPoint, ParseError, Sequence, Target, TxInput, TxOutput,
+    Witness,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    // Ordered so that testnet wins when prefixes are shared
+    pub const ALL: [Network; 4] = [
+        Network::Mainnet,
+        Network::Testnet,
+        Network::Signet,
+        Network::Regtest,
+    ];
+
+    // Base58 version byte for pay-to-pubkey-hash addresses
+    pub fn p2pkh_prefix(self) -> u8 {
+        match self {
+            Network::Mainnet => 0x00,
+            _ => 0x6F,
+        }
+    }
+
+    // Base58 version byte for pay-to-script-hash addresses
+    pub fn p2sh_prefix(self) -> u8 {
+        match self {
+            Network::Mainnet => 0x05,
+            _ => 0xC4,
+        }
+    }
+
+    // Base58 version byte for WIF-encoded private keys
+    pub fn wif_prefix(self) -> u8 {
+        match self {
+            Network::Mainnet => 0x80,
+            _ => 0xEF,
+        }
+    }
+
+    // Human-readable part of bech32 segwit addresses
+    pub fn bech32_hrp(self) -> &'static str {
+        match self {
+            Network::Mainnet => "bc",
+            Network::Testnet | Network::Signet => "tb",
+            Network::Regtest => "bcrt",
+        }
+    }
+
+    // Start of every P2P message on this network
+    pub fn magic(self) -> [u8; 4] {
+        match self {
+            Network::Mainnet => [0xF9, 0xBE, 0xB4, 0xD9],
+            Network::Testnet => [0x0B, 0x11, 0x09, 0x07],
+            Network::Signet => [0x0A, 0x03, 0xCF, 0x40],
+            Network::Regtest => [0xFA, 0xBF, 0xB5, 0xDA],
+        }
+    }
+
+    pub fn from_magic(magic: [u8; 4]) -> Option<Network> {
+        Network::ALL
+            .into_iter()
+            .find(|network| network.magic() == magic)
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        })
+    }
+}
+
+// Accepts Bitcoin Core's chain names (main, test, ...) as well
+impl FromStr for Network {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" | "main" | "bitcoin" => Ok(Network::Mainnet),
+            "testnet" | "test" => Ok(Network::Testnet),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Unknown network {s:?}"
+            )))),
+        }
+    }
+}
+
+// Consensus parameters of a network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    pub network: Network,
+    pub p2pkh_prefix: u8,
+    pub p2sh_prefix: u8,
+    pub wif_prefix: u8,
+    pub bech32_hrp: &'static str,
+    pub magic: [u8; 4],
+    pub genesis_hash: BlockHash,
+    // Blocks between halvings of the block subsidy
+    pub subsidy_halving_interval: u32,
+    // Easiest target a block may have
+    pub pow_limit: Target,
+    // Time the blocks of one retarget period should take, in seconds
+    pub pow_target_timespan: u64,
+    pub pow_target_spacing: u64,
+    // The testnet rule allowing a difficulty 1 block after 20 minutes
+    pub pow_allow_min_difficulty_blocks: bool,
+    pub pow_no_retargeting: bool,
+}
+
+impl ChainParams {
+    pub fn new(network: Network) -> Self {
+        let mut pow_limit = [0xFF; 32];
+        match network {
+            Network::Mainnet | Network::Testnet => pow_limit[..4].fill(0),
+            Network::Signet => {
+                pow_limit[..5].copy_from_slice(&[0x00, 0x00, 0x03, 0x77, 0xAE]);
+                pow_limit[5..].fill(0);
+            }
+            Network::Regtest => {
+                pow_limit[0] = 0x7F;
+                pow_limit[3..].fill(0);
+            }
+        }
+        let genesis_hash = match network {
+            Network::Mainnet => "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            Network::Testnet => "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            Network::Signet => "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            Network::Regtest => "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+        };
+        ChainParams {
+            network,
+            p2pkh_prefix: network.p2pkh_prefix(),
+            p2sh_prefix: network.p2sh_prefix(),
+            wif_prefix: network.wif_prefix(),
+            bech32_hrp: network.bech32_hrp(),
+            magic: network.magic(),
+            genesis_hash: genesis_hash.parse().expect("valid genesis hash"),
+            subsidy_halving_interval: match network {
+                Network::Regtest => 150,
+                _ => 210_000,
+            },
+            pow_limit: Target::from_be_bytes(pow_limit),
+            pow_target_timespan: 14 * 24 * 60 * 60,
+            pow_target_spacing: 10 * 60,
+            pow_allow_min_difficulty_blocks: matches!(network, Network::Testnet | Network::Regtest),
+            pow_no_retargeting: network == Network::Regtest,
+        }
+    }
+
+    // Block 0. Every network shares the same coinbase transaction; only
+    // the header's time, bits and nonce differ.
+    pub fn genesis_block(&self) -> Block {
+        let (time, bits, nonce) = match self.network {
+            Network::Mainnet => (1231006505, 0x1d00ffff, 2083236893),
+            Network::Testnet => (1296688602, 0x1d00ffff, 414098458),
+            Network::Signet => (1598918400, 0x1e0377ae, 52613770),
+            Network::Regtest => (1296688602, 0x207fffff, 2),
+        };
+        let coinbase = genesis_coinbase();
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: Hash256::from_byte_array(coinbase.txid().to_byte_array()),
+                time,
+                bits,
+                nonce,
+            },
+            txdata: vec![coinbase],
+        }
+    }
+
+    // Number of blocks between retargets (2016)
+    pub fn difficulty_adjustment_interval(&self) -> u64 {
+        self.pow_target_timespan / self.pow_target_spacing
+    }
+}
+
+impl Network {
+    pub fn params(self) -> ChainParams {
+        ChainParams::new(self)
+    }
+}
+
+fn genesis_coinbase() -> LegacyTransaction {
+    // nBits 0x1d00ffff, the number 4, and the headline of The Times
+    let script_sig = hex::decode(concat!(
+        "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e",
+        "206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73"
+    ))
+    .expect("valid hex");
+    // Pay-to-pubkey output that can never be spent
+    let script_pubkey = hex::decode(concat!(
+        "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f3",
+        "5504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac"
+    ))
+    .expect("valid hex");
+    LegacyTransaction {
+        version: 1,
+        inputs: vec![TxInput {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        outputs: vec![TxOutput {
+            value: Amount::from_sat(consensus::INITIAL_SUBSIDY),
+            script_pubkey,
+        }],
+        lock_time: LockTime::ZERO,
+    }
+}
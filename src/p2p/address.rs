@@ -0,0 +1,191 @@
+// Peer addresses: the fixed-size IPv6-based form of version and addr
+// messages, and the BIP155 form of addrv2 messages, which also carries
+// Tor v3 addresses
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::{read_var_bytes, write_var_bytes, ServiceFlags};
+use crate::{read_array, BitcoinError, CompactSize, ParseError};
+
+// Most entries a single addr or addrv2 message may carry
+pub const MAX_ADDR_TO_SEND: usize = 1_000;
+
+// Longest address an addrv2 entry may carry, whatever its network
+pub const MAX_ADDRV2_SIZE: usize = 512;
+
+// Services, then the address as 16 IPv6 bytes (IPv4 addresses are mapped)
+// and the port in network byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetAddress {
+    pub services: ServiceFlags,
+    pub addr: SocketAddr,
+}
+
+impl NetAddress {
+    pub const SIZE: usize = 26;
+
+    pub fn new(addr: SocketAddr, services: ServiceFlags) -> Self {
+        NetAddress { services, addr }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.services.0.to_le_bytes().to_vec();
+        let ip = match self.addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        v.extend(ip.octets());
+        v.extend(self.addr.port().to_be_bytes());
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let services = ServiceFlags(u64::from_le_bytes(read_array(data, 0)?));
+        let ip = Ipv6Addr::from(read_array::<16>(data, 8)?);
+        let port = u16::from_be_bytes(read_array(data, 24)?);
+        let ip = match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        };
+        Ok((
+            NetAddress {
+                services,
+                addr: SocketAddr::new(ip, port),
+            },
+            Self::SIZE,
+        ))
+    }
+}
+
+impl Default for NetAddress {
+    // 0.0.0.0:0, which nodes send when they don't know the address
+    fn default() -> Self {
+        NetAddress::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            ServiceFlags::NONE,
+        )
+    }
+}
+
+// An address in the BIP155 encoding, identified by network
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetworkAddress {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    // The onion service's ed25519 public key
+    TorV3([u8; 32]),
+    // Networks this crate doesn't interpret (Tor v2, I2P, CJDNS and later
+    // additions), kept so they can be relayed
+    Unknown { network_id: u8, addr: Vec<u8> },
+}
+
+impl NetworkAddress {
+    pub fn network_id(&self) -> u8 {
+        match self {
+            NetworkAddress::Ipv4(_) => 1,
+            NetworkAddress::Ipv6(_) => 2,
+            NetworkAddress::TorV3(_) => 4,
+            NetworkAddress::Unknown { network_id, .. } => *network_id,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            NetworkAddress::Ipv4(ip) => ip.octets().to_vec(),
+            NetworkAddress::Ipv6(ip) => ip.octets().to_vec(),
+            NetworkAddress::TorV3(key) => key.to_vec(),
+            NetworkAddress::Unknown { addr, .. } => addr.clone(),
+        }
+    }
+
+    // Addresses of known networks must have that network's length
+    pub fn from_bytes(network_id: u8, addr: &[u8]) -> Result<Self, BitcoinError> {
+        let invalid = || {
+            BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid address of {} bytes for network {network_id}",
+                addr.len()
+            )))
+        };
+        Ok(match network_id {
+            1 => NetworkAddress::Ipv4(<[u8; 4]>::try_from(addr).map_err(|_| invalid())?.into()),
+            2 => NetworkAddress::Ipv6(<[u8; 16]>::try_from(addr).map_err(|_| invalid())?.into()),
+            4 => NetworkAddress::TorV3(addr.try_into().map_err(|_| invalid())?),
+            _ => NetworkAddress::Unknown {
+                network_id,
+                addr: addr.to_vec(),
+            },
+        })
+    }
+
+    // With `port`, for the addresses a plain TCP connection can reach
+    pub fn socket_addr(&self, port: u16) -> Option<SocketAddr> {
+        match self {
+            NetworkAddress::Ipv4(ip) => Some(SocketAddr::new(IpAddr::V4(*ip), port)),
+            NetworkAddress::Ipv6(ip) => Some(SocketAddr::new(IpAddr::V6(*ip), port)),
+            _ => None,
+        }
+    }
+}
+
+impl From<IpAddr> for NetworkAddress {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => NetworkAddress::Ipv4(ip),
+            IpAddr::V6(ip) => NetworkAddress::Ipv6(ip),
+        }
+    }
+}
+
+// An addrv2 entry: when the node was last seen, its services and where to
+// reach it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AddrV2Entry {
+    // Unix time in seconds
+    pub time: u32,
+    pub services: ServiceFlags,
+    pub addr: NetworkAddress,
+    pub port: u16,
+}
+
+impl AddrV2Entry {
+    // Services are a CompactSize here, unlike in NetAddress
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.time.to_le_bytes().to_vec();
+        v.extend(CompactSize(self.services.0).encode());
+        v.push(self.addr.network_id());
+        write_var_bytes(&mut v, &self.addr.to_bytes());
+        v.extend(self.port.to_be_bytes());
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let time = u32::from_le_bytes(read_array(data, 0)?);
+        let (CompactSize(services), used) =
+            CompactSize::decode(&data[4..]).map_err(|e| e.offset_by(4))?;
+        let offset = 4 + used;
+        let [network_id] = read_array(data, offset)?;
+        let (addr, end) = read_var_bytes(data, offset + 1)?;
+        if addr.len() > MAX_ADDRV2_SIZE {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "addrv2 address length",
+                offset: offset + 1,
+            }));
+        }
+        let addr = NetworkAddress::from_bytes(network_id, addr).map_err(|_| {
+            BitcoinError::Parse(ParseError::InvalidField {
+                field: "addrv2 address",
+                offset: offset + 1,
+            })
+        })?;
+        let port = u16::from_be_bytes(read_array(data, end)?);
+        Ok((
+            AddrV2Entry {
+                time,
+                services: ServiceFlags(services),
+                addr,
+                port,
+            },
+            end + 2,
+        ))
+    }
+}
@@ -0,0 +1,335 @@
+// Compact block relay (BIP152): a block announced as its header and 6-byte
+// short ids of its transactions, which the receiver matches against its
+// mempool, requesting only the transactions it doesn't have
+
+use std::collections::HashMap;
+
+use super::{parse_list, serialize_list};
+use crate::hashes::{sha256, siphash24};
+use crate::{
+    read_array, BitcoinError, BitcoinSerialize, Block, BlockHash, BlockHeader, CompactSize,
+    CryptoError, LegacyTransaction, ParseError, Wtxid,
+};
+
+// The sendcmpct version whose short ids are computed from wtxids, the only
+// one segwit nodes use
+pub const COMPACT_BLOCK_VERSION: u64 = 2;
+
+// Transaction indexes are limited to 16 bits, as in Bitcoin Core
+const MAX_INDEX: u64 = u16::MAX as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShortId(pub [u8; 6]);
+
+impl ShortId {
+    pub const SIZE: usize = 6;
+
+    // SipHash-2-4 of the wtxid, truncated to 48 bits
+    pub fn new(keys: (u64, u64), wtxid: &Wtxid) -> Self {
+        let hash = siphash24(keys.0, keys.1, wtxid.as_bytes());
+        ShortId(read_array(&hash.to_le_bytes(), 0).expect("u64 is 8 bytes"))
+    }
+}
+
+// A transaction sent in full, such as the coinbase, which the receiver
+// can't have in its mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefilledTransaction {
+    // Position in the block
+    pub index: usize,
+    pub tx: LegacyTransaction,
+}
+
+// The cmpctblock message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderAndShortIds {
+    pub header: BlockHeader,
+    // Salts the short ids, so collisions differ between announcements
+    pub nonce: u64,
+    // For the transactions not prefilled, in block order
+    pub short_ids: Vec<ShortId>,
+    // In increasing index order
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+impl HeaderAndShortIds {
+    // Prefills the coinbase and the transactions at `prefill`, which the
+    // receiver is unlikely to have
+    pub fn from_block(block: &Block, nonce: u64, prefill: &[usize]) -> Self {
+        let mut compact = HeaderAndShortIds {
+            header: block.header,
+            nonce,
+            short_ids: Vec::new(),
+            prefilled_txs: Vec::new(),
+        };
+        let keys = compact.short_id_keys();
+        for (index, tx) in block.txdata.iter().enumerate() {
+            if index == 0 || prefill.contains(&index) {
+                compact.prefilled_txs.push(PrefilledTransaction {
+                    index,
+                    tx: tx.clone(),
+                });
+            } else {
+                compact.short_ids.push(ShortId::new(keys, &tx.wtxid()));
+            }
+        }
+        compact
+    }
+
+    // The SipHash keys: the first two little-endian words of the SHA-256 of
+    // the header and nonce
+    pub fn short_id_keys(&self) -> (u64, u64) {
+        let mut data = self.header.serialize();
+        data.extend(self.nonce.to_le_bytes());
+        let hash = sha256(&data);
+        let bytes = hash.as_bytes();
+        (
+            u64::from_le_bytes(read_array(bytes, 0).expect("hash is 32 bytes")),
+            u64::from_le_bytes(read_array(bytes, 8).expect("hash is 32 bytes")),
+        )
+    }
+
+    pub fn short_id(&self, wtxid: &Wtxid) -> ShortId {
+        ShortId::new(self.short_id_keys(), wtxid)
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled_txs.len()
+    }
+
+    // The block's transactions as far as the prefilled ones and `mempool`
+    // provide them; request the missing ones with
+    // BlockTransactionsRequest::missing. A short id matching more than one
+    // mempool transaction is left missing, since either may be wrong.
+    pub fn reconstruct(
+        &self,
+        mempool: &[LegacyTransaction],
+    ) -> Result<Vec<Option<LegacyTransaction>>, BitcoinError> {
+        let invalid = |reason: &str| {
+            BitcoinError::Parse(ParseError::Message(format!(
+                "Invalid compact block: {reason}"
+            )))
+        };
+        let mut slots = vec![None; self.transaction_count()];
+        for prefilled in &self.prefilled_txs {
+            let slot = slots
+                .get_mut(prefilled.index)
+                .ok_or_else(|| invalid("prefilled index out of range"))?;
+            *slot = Some(prefilled.tx.clone());
+        }
+        // Slots for the short ids, in order, skipping the prefilled ones
+        let keys = self.short_id_keys();
+        let mut by_short_id = HashMap::with_capacity(self.short_ids.len());
+        let mut empty = (0..slots.len()).filter(|&i| slots[i].is_none());
+        for short_id in &self.short_ids {
+            let index = empty.next().ok_or_else(|| invalid("too many short ids"))?;
+            if by_short_id.insert(*short_id, index).is_some() {
+                return Err(invalid("duplicate short id"));
+            }
+        }
+        let mut collided = Vec::new();
+        for tx in mempool {
+            if let Some(&index) = by_short_id.get(&ShortId::new(keys, &tx.wtxid())) {
+                if slots[index].replace(tx.clone()).is_some() {
+                    collided.push(index);
+                }
+            }
+        }
+        for index in collided {
+            slots[index] = None;
+        }
+        Ok(slots)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.header.serialize();
+        v.extend(self.nonce.to_le_bytes());
+        v.extend(serialize_list(&self.short_ids, |id| id.0.to_vec()));
+        v.extend(CompactSize(self.prefilled_txs.len() as u64).encode());
+        // Each index is encoded as its distance past the previous one
+        let mut next = 0;
+        for prefilled in &self.prefilled_txs {
+            v.extend(CompactSize((prefilled.index - next) as u64).encode());
+            v.extend(prefilled.tx.serialize());
+            next = prefilled.index + 1;
+        }
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (header, mut offset) = BlockHeader::parse(data)?;
+        let nonce = u64::from_le_bytes(read_array(data, offset)?);
+        offset += 8;
+        let (short_ids, used) =
+            parse_list(&data[offset..], u16::MAX as usize, "short id count", |d| {
+                Ok((ShortId(read_array(d, 0)?), ShortId::SIZE))
+            })
+            .map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        let mut next = 0;
+        let (prefilled_txs, used) =
+            parse_list(&data[offset..], u16::MAX as usize, "prefilled count", |d| {
+                let (index, used) = read_index(d, &mut next)?;
+                let (tx, tx_used) =
+                    LegacyTransaction::parse(&d[used..]).map_err(|e| e.offset_by(used))?;
+                Ok((PrefilledTransaction { index, tx }, used + tx_used))
+            })
+            .map_err(|e| e.offset_by(offset))?;
+        offset += used;
+        Ok((
+            HeaderAndShortIds {
+                header,
+                nonce,
+                short_ids,
+                prefilled_txs,
+            },
+            offset,
+        ))
+    }
+}
+
+// The getblocktxn message, asking for the transactions a compact block
+// couldn't be reconstructed without
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTransactionsRequest {
+    pub block_hash: BlockHash,
+    // In increasing order
+    pub indexes: Vec<usize>,
+}
+
+impl BlockTransactionsRequest {
+    // For the empty slots left by HeaderAndShortIds::reconstruct
+    pub fn missing(block_hash: BlockHash, slots: &[Option<LegacyTransaction>]) -> Self {
+        BlockTransactionsRequest {
+            block_hash,
+            indexes: (0..slots.len()).filter(|&i| slots[i].is_none()).collect(),
+        }
+    }
+
+    // The blocktxn answer from the peer's copy of the block
+    pub fn answer(&self, block: &Block) -> Result<BlockTransactions, BitcoinError> {
+        let transactions = self
+            .indexes
+            .iter()
+            .map(|&index| {
+                block.txdata.get(index).cloned().ok_or_else(|| {
+                    BitcoinError::Parse(ParseError::Message(format!(
+                        "Block has no transaction {index}"
+                    )))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BlockTransactions {
+            block_hash: self.block_hash,
+            transactions,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.block_hash.as_bytes().to_vec();
+        let mut next = 0;
+        v.extend(serialize_list(&self.indexes, |&index| {
+            let encoded = CompactSize((index - next) as u64).encode();
+            next = index + 1;
+            encoded
+        }));
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let block_hash = BlockHash::from_byte_array(read_array(data, 0)?);
+        let mut next = 0;
+        let (indexes, used) = parse_list(&data[32..], u16::MAX as usize, "index count", |d| {
+            read_index(d, &mut next)
+        })
+        .map_err(|e| e.offset_by(32))?;
+        Ok((
+            BlockTransactionsRequest {
+                block_hash,
+                indexes,
+            },
+            32 + used,
+        ))
+    }
+}
+
+// The blocktxn message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTransactions {
+    pub block_hash: BlockHash,
+    // In the order requested
+    pub transactions: Vec<LegacyTransaction>,
+}
+
+impl BlockTransactions {
+    // Fills the empty slots left by HeaderAndShortIds::reconstruct, in
+    // order, and checks the result against the header's merkle root, which
+    // catches transactions wrongly matched by short id
+    pub fn complete(
+        &self,
+        compact: &HeaderAndShortIds,
+        mut slots: Vec<Option<LegacyTransaction>>,
+    ) -> Result<Block, BitcoinError> {
+        let mut transactions = self.transactions.iter();
+        for slot in slots.iter_mut().filter(|slot| slot.is_none()) {
+            *slot = transactions.next().cloned();
+        }
+        let txdata: Option<Vec<_>> = slots.into_iter().collect();
+        let (Some(txdata), None) = (txdata, transactions.next()) else {
+            return Err(BitcoinError::Parse(ParseError::Message(
+                "Block transactions don't fill the compact block".to_string(),
+            )));
+        };
+        let block = Block {
+            header: compact.header,
+            txdata,
+        };
+        if block.compute_merkle_root() != block.header.merkle_root {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidMerkleProof(
+                "reconstructed block doesn't match its merkle root",
+            )));
+        }
+        Ok(block)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.block_hash.as_bytes().to_vec();
+        v.extend(serialize_list(
+            &self.transactions,
+            BitcoinSerialize::serialize,
+        ));
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let block_hash = BlockHash::from_byte_array(read_array(data, 0)?);
+        let (transactions, used) = parse_list(
+            &data[32..],
+            u16::MAX as usize,
+            "transaction count",
+            LegacyTransaction::parse,
+        )
+        .map_err(|e| e.offset_by(32))?;
+        Ok((
+            BlockTransactions {
+                block_hash,
+                transactions,
+            },
+            32 + used,
+        ))
+    }
+}
+
+// A differentially encoded index, given the one after the previous index
+fn read_index(data: &[u8], next: &mut u64) -> Result<(usize, usize), BitcoinError> {
+    let (CompactSize(delta), used) = CompactSize::decode(data)?;
+    let index = next.saturating_add(delta);
+    if index > MAX_INDEX {
+        return Err(BitcoinError::Parse(ParseError::InvalidField {
+            field: "transaction index",
+            offset: 0,
+        }));
+    }
+    *next = index + 1;
+    Ok((index as usize, used))
+}
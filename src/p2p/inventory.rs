@@ -0,0 +1,88 @@
+// Inventory vectors: the type and hash of an object a peer announces (inv),
+// requests (getdata) or doesn't have (notfound)
+
+use crate::{read_array, BitcoinError, BlockHash, Txid, Wtxid};
+
+// Most items a single inv, getdata or notfound message may carry
+pub const MAX_INV_SIZE: usize = 50_000;
+
+// Requests for the witness variants are answered with the BIP144
+// serialization, including witness data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Inventory {
+    Error,
+    Transaction(Txid),
+    Block(BlockHash),
+    // The block as a merkleblock message, matched against the loaded filter
+    FilteredBlock(BlockHash),
+    // The block as a cmpctblock message (BIP152)
+    CompactBlock(BlockHash),
+    // A transaction announced by wtxid (BIP339)
+    WTx(Wtxid),
+    WitnessTransaction(Txid),
+    WitnessBlock(BlockHash),
+    Unknown { inv_type: u32, hash: [u8; 32] },
+}
+
+const MSG_WITNESS_FLAG: u32 = 1 << 30;
+
+impl Inventory {
+    pub const SIZE: usize = 36;
+
+    pub fn inv_type(&self) -> u32 {
+        match self {
+            Inventory::Error => 0,
+            Inventory::Transaction(_) => 1,
+            Inventory::Block(_) => 2,
+            Inventory::FilteredBlock(_) => 3,
+            Inventory::CompactBlock(_) => 4,
+            Inventory::WTx(_) => 5,
+            Inventory::WitnessTransaction(_) => 1 | MSG_WITNESS_FLAG,
+            Inventory::WitnessBlock(_) => 2 | MSG_WITNESS_FLAG,
+            Inventory::Unknown { inv_type, .. } => *inv_type,
+        }
+    }
+
+    // The hash in internal byte order; all zeros for Error
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            Inventory::Error => [0; 32],
+            Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                txid.to_byte_array()
+            }
+            Inventory::Block(hash)
+            | Inventory::FilteredBlock(hash)
+            | Inventory::CompactBlock(hash)
+            | Inventory::WitnessBlock(hash) => hash.to_byte_array(),
+            Inventory::WTx(wtxid) => wtxid.to_byte_array(),
+            Inventory::Unknown { hash, .. } => *hash,
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.inv_type().to_le_bytes().to_vec();
+        v.extend(self.hash());
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let inv_type = u32::from_le_bytes(read_array(data, 0)?);
+        let hash: [u8; 32] = read_array(data, 4)?;
+        let inventory = match inv_type {
+            0 => Inventory::Error,
+            1 => Inventory::Transaction(Txid::from_byte_array(hash)),
+            2 => Inventory::Block(BlockHash::from_byte_array(hash)),
+            3 => Inventory::FilteredBlock(BlockHash::from_byte_array(hash)),
+            4 => Inventory::CompactBlock(BlockHash::from_byte_array(hash)),
+            5 => Inventory::WTx(Wtxid::from_byte_array(hash)),
+            t if t == 1 | MSG_WITNESS_FLAG => {
+                Inventory::WitnessTransaction(Txid::from_byte_array(hash))
+            }
+            t if t == 2 | MSG_WITNESS_FLAG => {
+                Inventory::WitnessBlock(BlockHash::from_byte_array(hash))
+            }
+            _ => Inventory::Unknown { inv_type, hash },
+        };
+        Ok((inventory, Self::SIZE))
+    }
+}
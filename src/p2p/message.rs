@@ -0,0 +1,521 @@
+// Message payloads, and the envelope framing them on the wire: a header
+// with the network magic, the command naming the payload's type, its length
+// and a checksum
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use k256::elliptic_curve::rand_core::{OsRng, RngCore};
+
+use super::address::{AddrV2Entry, NetAddress, MAX_ADDR_TO_SEND};
+use super::compact_block::{BlockTransactions, BlockTransactionsRequest, HeaderAndShortIds};
+use super::inventory::{Inventory, MAX_INV_SIZE};
+use super::{
+    parse_list, read_var_bytes, serialize_list, write_var_bytes, ServiceFlags, PROTOCOL_VERSION,
+};
+use crate::hashes::sha256d;
+use crate::{
+    read_array, read_bytes, BitcoinError, BitcoinSerialize, Block, BlockHash, BlockHeader,
+    CompactSize, LegacyTransaction, Network, ParseError,
+};
+
+// Longest user agent a node accepts
+pub const MAX_USER_AGENT_LENGTH: usize = 256;
+
+// Most headers a single headers message may carry
+pub const MAX_HEADERS_RESULTS: usize = 2_000;
+
+// Most hashes a getheaders block locator may carry
+pub const MAX_LOCATOR_SIZE: usize = 101;
+
+// Largest payload a node accepts (Core's MAX_PROTOCOL_MESSAGE_LENGTH)
+pub const MAX_MESSAGE_SIZE: usize = 4_000_000;
+
+const COMMAND_SIZE: usize = 12;
+
+// The first message on a connection, announcing the sender's version and
+// services. The peer answers with its own version and a verack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionMessage {
+    pub version: i32,
+    pub services: ServiceFlags,
+    // Unix time in seconds
+    pub timestamp: i64,
+    pub receiver: NetAddress,
+    pub sender: NetAddress,
+    // Random, so a node can detect connecting to itself
+    pub nonce: u64,
+    pub user_agent: String,
+    // Height of the sender's best chain
+    pub start_height: i32,
+    // Whether the peer should announce transactions before a filter is loaded
+    pub relay: bool,
+}
+
+impl VersionMessage {
+    // Our version message to the peer at `receiver`, with the current time
+    // and a fresh nonce; set the public fields to change the rest
+    pub fn new(receiver: SocketAddr, start_height: i32) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: ServiceFlags::NONE,
+            timestamp,
+            receiver: NetAddress::new(receiver, ServiceFlags::NONE),
+            sender: NetAddress::default(),
+            nonce: OsRng.next_u64(),
+            user_agent: format!("/{}:{}/", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            start_height,
+            relay: true,
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.version.to_le_bytes().to_vec();
+        v.extend(self.services.0.to_le_bytes());
+        v.extend(self.timestamp.to_le_bytes());
+        v.extend(self.receiver.serialize());
+        v.extend(self.sender.serialize());
+        v.extend(self.nonce.to_le_bytes());
+        write_var_bytes(&mut v, self.user_agent.as_bytes());
+        v.extend(self.start_height.to_le_bytes());
+        v.push(self.relay as u8);
+        v
+    }
+
+    // The relay flag is optional (BIP37) and defaults to true
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let version = i32::from_le_bytes(read_array(data, 0)?);
+        let services = ServiceFlags(u64::from_le_bytes(read_array(data, 4)?));
+        let timestamp = i64::from_le_bytes(read_array(data, 12)?);
+        let (receiver, _) = NetAddress::parse(&data[20..]).map_err(|e| e.offset_by(20))?;
+        let (sender, _) = NetAddress::parse(&data[46..]).map_err(|e| e.offset_by(46))?;
+        let nonce = u64::from_le_bytes(read_array(data, 72)?);
+        let (user_agent, mut offset) = read_var_bytes(data, 80)?;
+        if user_agent.len() > MAX_USER_AGENT_LENGTH {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "user agent length",
+                offset: 80,
+            }));
+        }
+        let user_agent = String::from_utf8(user_agent.to_vec()).map_err(|_| {
+            BitcoinError::Parse(ParseError::InvalidField {
+                field: "user agent",
+                offset: 80,
+            })
+        })?;
+        let start_height = i32::from_le_bytes(read_array(data, offset)?);
+        offset += 4;
+        let relay = match data.get(offset) {
+            Some(&flag) => {
+                offset += 1;
+                flag != 0
+            }
+            None => true,
+        };
+        Ok((
+            VersionMessage {
+                version,
+                services,
+                timestamp,
+                receiver,
+                sender,
+                nonce,
+                user_agent,
+                start_height,
+                relay,
+            },
+            offset,
+        ))
+    }
+}
+
+// Asks for the headers following the first locator hash the peer has on
+// its best chain, up to `stop_hash` or MAX_HEADERS_RESULTS of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetHeadersMessage {
+    pub version: u32,
+    // From the tip back, increasingly sparse (see HeaderChain::locator)
+    pub locator_hashes: Vec<BlockHash>,
+    // All zeros for as many as the peer will send
+    pub stop_hash: BlockHash,
+}
+
+impl GetHeadersMessage {
+    pub fn new(locator_hashes: Vec<BlockHash>, stop_hash: BlockHash) -> Self {
+        GetHeadersMessage {
+            version: PROTOCOL_VERSION as u32,
+            locator_hashes,
+            stop_hash,
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.version.to_le_bytes().to_vec();
+        v.extend(serialize_list(&self.locator_hashes, |hash| {
+            hash.as_bytes().to_vec()
+        }));
+        v.extend(self.stop_hash.as_bytes());
+        v
+    }
+
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let version = u32::from_le_bytes(read_array(data, 0)?);
+        let (locator_hashes, used) =
+            parse_list(&data[4..], MAX_LOCATOR_SIZE, "locator size", |d| {
+                Ok((BlockHash::from_byte_array(read_array(d, 0)?), 32))
+            })
+            .map_err(|e| e.offset_by(4))?;
+        let offset = 4 + used;
+        let stop_hash = BlockHash::from_byte_array(read_array(data, offset)?);
+        Ok((
+            GetHeadersMessage {
+                version,
+                locator_hashes,
+                stop_hash,
+            },
+            offset + 32,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkMessage {
+    Version(VersionMessage),
+    // Acknowledges the peer's version; the handshake is done once both
+    // sides have sent one
+    Verack,
+    // Objects the sender has, announced by hash
+    Inv(Vec<Inventory>),
+    // Requests the objects, answered with tx, block or merkleblock
+    // messages and a notfound for the rest
+    GetData(Vec<Inventory>),
+    NotFound(Vec<Inventory>),
+    Tx(LegacyTransaction),
+    Block(Block),
+    // Addresses of other nodes, each with when it was last seen
+    Addr(Vec<(u32, NetAddress)>),
+    // Sent during the handshake to ask for addrv2 rather than addr (BIP155)
+    SendAddrV2,
+    AddrV2(Vec<AddrV2Entry>),
+    // Whether the peer should announce new blocks with cmpctblock rather
+    // than inv or headers, and the compact block version spoken (BIP152)
+    SendCmpct { announce: bool, version: u64 },
+    CmpctBlock(HeaderAndShortIds),
+    GetBlockTxn(BlockTransactionsRequest),
+    BlockTxn(BlockTransactions),
+    GetHeaders(GetHeadersMessage),
+    Headers(Vec<BlockHeader>),
+    // A command this crate doesn't interpret, kept so it can be skipped
+    Unknown { command: String, payload: Vec<u8> },
+}
+
+impl NetworkMessage {
+    // The command name identifying the message on the wire
+    pub fn command(&self) -> &str {
+        match self {
+            NetworkMessage::Version(_) => "version",
+            NetworkMessage::Verack => "verack",
+            NetworkMessage::Inv(_) => "inv",
+            NetworkMessage::GetData(_) => "getdata",
+            NetworkMessage::NotFound(_) => "notfound",
+            NetworkMessage::Tx(_) => "tx",
+            NetworkMessage::Block(_) => "block",
+            NetworkMessage::Addr(_) => "addr",
+            NetworkMessage::SendAddrV2 => "sendaddrv2",
+            NetworkMessage::AddrV2(_) => "addrv2",
+            NetworkMessage::SendCmpct { .. } => "sendcmpct",
+            NetworkMessage::CmpctBlock(_) => "cmpctblock",
+            NetworkMessage::GetBlockTxn(_) => "getblocktxn",
+            NetworkMessage::BlockTxn(_) => "blocktxn",
+            NetworkMessage::GetHeaders(_) => "getheaders",
+            NetworkMessage::Headers(_) => "headers",
+            NetworkMessage::Unknown { command, .. } => command,
+        }
+    }
+
+    pub fn serialize_payload(&self) -> Vec<u8> {
+        match self {
+            NetworkMessage::Version(version) => version.serialize(),
+            NetworkMessage::Verack | NetworkMessage::SendAddrV2 => Vec::new(),
+            NetworkMessage::Inv(items)
+            | NetworkMessage::GetData(items)
+            | NetworkMessage::NotFound(items) => serialize_list(items, Inventory::serialize),
+            NetworkMessage::Tx(tx) => tx.serialize(),
+            NetworkMessage::Block(block) => block.serialize(),
+            NetworkMessage::Addr(addrs) => serialize_list(addrs, |(time, addr)| {
+                let mut v = time.to_le_bytes().to_vec();
+                v.extend(addr.serialize());
+                v
+            }),
+            NetworkMessage::AddrV2(entries) => serialize_list(entries, AddrV2Entry::serialize),
+            NetworkMessage::SendCmpct { announce, version } => {
+                let mut v = vec![*announce as u8];
+                v.extend(version.to_le_bytes());
+                v
+            }
+            NetworkMessage::CmpctBlock(compact) => compact.serialize(),
+            NetworkMessage::GetBlockTxn(request) => request.serialize(),
+            NetworkMessage::BlockTxn(transactions) => transactions.serialize(),
+            NetworkMessage::GetHeaders(request) => request.serialize(),
+            // Each header is followed by an empty transaction count
+            NetworkMessage::Headers(headers) => serialize_list(headers, |header| {
+                let mut v = header.serialize();
+                v.push(0);
+                v
+            }),
+            NetworkMessage::Unknown { payload, .. } => payload.clone(),
+        }
+    }
+
+    // The whole payload must be consumed, except that a version message may
+    // carry fields added by later protocol versions
+    pub fn parse_payload(command: &str, payload: &[u8]) -> Result<Self, BitcoinError> {
+        let (message, used) = match command {
+            "version" => {
+                let (version, _) = VersionMessage::parse(payload)?;
+                (NetworkMessage::Version(version), payload.len())
+            }
+            "verack" => (NetworkMessage::Verack, 0),
+            "inv" => wrap(parse_inventory(payload)?, NetworkMessage::Inv),
+            "getdata" => wrap(parse_inventory(payload)?, NetworkMessage::GetData),
+            "notfound" => wrap(parse_inventory(payload)?, NetworkMessage::NotFound),
+            "tx" => wrap(LegacyTransaction::parse(payload)?, NetworkMessage::Tx),
+            "block" => wrap(Block::parse(payload)?, NetworkMessage::Block),
+            "addr" => wrap(parse_addr(payload)?, NetworkMessage::Addr),
+            "sendaddrv2" => (NetworkMessage::SendAddrV2, 0),
+            "addrv2" => wrap(
+                parse_list(
+                    payload,
+                    MAX_ADDR_TO_SEND,
+                    "address count",
+                    AddrV2Entry::parse,
+                )?,
+                NetworkMessage::AddrV2,
+            ),
+            "sendcmpct" => {
+                let [announce] = read_array(payload, 0)?;
+                let version = u64::from_le_bytes(read_array(payload, 1)?);
+                (
+                    NetworkMessage::SendCmpct {
+                        announce: announce != 0,
+                        version,
+                    },
+                    9,
+                )
+            }
+            "cmpctblock" => wrap(
+                HeaderAndShortIds::parse(payload)?,
+                NetworkMessage::CmpctBlock,
+            ),
+            "getblocktxn" => wrap(
+                BlockTransactionsRequest::parse(payload)?,
+                NetworkMessage::GetBlockTxn,
+            ),
+            "blocktxn" => wrap(BlockTransactions::parse(payload)?, NetworkMessage::BlockTxn),
+            "getheaders" => wrap(
+                GetHeadersMessage::parse(payload)?,
+                NetworkMessage::GetHeaders,
+            ),
+            "headers" => wrap(parse_headers(payload)?, NetworkMessage::Headers),
+            _ => (
+                NetworkMessage::Unknown {
+                    command: command.to_string(),
+                    payload: payload.to_vec(),
+                },
+                payload.len(),
+            ),
+        };
+        if used != payload.len() {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "message payload length",
+                offset: used,
+            }));
+        }
+        Ok(message)
+    }
+}
+
+fn parse_inventory(payload: &[u8]) -> Result<(Vec<Inventory>, usize), BitcoinError> {
+    parse_list(payload, MAX_INV_SIZE, "inventory count", Inventory::parse)
+}
+
+fn parse_addr(payload: &[u8]) -> Result<(Vec<(u32, NetAddress)>, usize), BitcoinError> {
+    parse_list(payload, MAX_ADDR_TO_SEND, "address count", |data| {
+        let time = u32::from_le_bytes(read_array(data, 0)?);
+        let (addr, used) = NetAddress::parse(&data[4..]).map_err(|e| e.offset_by(4))?;
+        Ok(((time, addr), 4 + used))
+    })
+}
+
+fn parse_headers(payload: &[u8]) -> Result<(Vec<BlockHeader>, usize), BitcoinError> {
+    parse_list(payload, MAX_HEADERS_RESULTS, "header count", |data| {
+        let (header, used) = BlockHeader::parse(data)?;
+        let (CompactSize(count), count_used) =
+            CompactSize::decode(&data[used..]).map_err(|e| e.offset_by(used))?;
+        if count != 0 {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "headers transaction count",
+                offset: used,
+            }));
+        }
+        Ok((header, used + count_used))
+    })
+}
+
+// A parsed payload as the message carrying it
+fn wrap<T>(
+    (value, used): (T, usize),
+    f: impl FnOnce(T) -> NetworkMessage,
+) -> (NetworkMessage, usize) {
+    (f(value), used)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub magic: [u8; 4],
+    pub command: String,
+    pub length: u32,
+    // First four bytes of the payload's double SHA-256
+    pub checksum: [u8; 4],
+}
+
+impl MessageHeader {
+    pub const SIZE: usize = 24;
+
+    pub fn new(magic: [u8; 4], command: &str, payload: &[u8]) -> Self {
+        MessageHeader {
+            magic,
+            command: command.to_string(),
+            length: payload.len() as u32,
+            checksum: checksum(payload),
+        }
+    }
+
+    // The command is NUL-padded to 12 bytes; longer ones are truncated
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut v = self.magic.to_vec();
+        let mut command = [0; COMMAND_SIZE];
+        let len = self.command.len().min(COMMAND_SIZE);
+        command[..len].copy_from_slice(&self.command.as_bytes()[..len]);
+        v.extend(command);
+        v.extend(self.length.to_le_bytes());
+        v.extend(self.checksum);
+        v
+    }
+
+    // Rejects commands that aren't printable ASCII followed only by NUL
+    // padding, and payload lengths over MAX_MESSAGE_SIZE
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let magic = read_array(data, 0)?;
+        let command: [u8; COMMAND_SIZE] = read_array(data, 4)?;
+        let end = command.iter().position(|&b| b == 0).unwrap_or(COMMAND_SIZE);
+        if !command[..end].iter().all(|b| b.is_ascii_graphic())
+            || command[end..].iter().any(|&b| b != 0)
+        {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "message command",
+                offset: 4,
+            }));
+        }
+        let length = u32::from_le_bytes(read_array(data, 16)?);
+        if length as usize > MAX_MESSAGE_SIZE {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "message length",
+                offset: 16,
+            }));
+        }
+        Ok((
+            MessageHeader {
+                magic,
+                command: String::from_utf8(command[..end].to_vec()).expect("checked ASCII"),
+                length,
+                checksum: read_array(data, 20)?,
+            },
+            Self::SIZE,
+        ))
+    }
+
+    // Parses `payload` as the message this header announces, checking its
+    // checksum
+    fn parse_payload(&self, payload: &[u8]) -> Result<NetworkMessage, BitcoinError> {
+        if checksum(payload) != self.checksum {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "message checksum",
+                offset: 20,
+            }));
+        }
+        NetworkMessage::parse_payload(&self.command, payload).map_err(|e| e.offset_by(Self::SIZE))
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    read_array(sha256d(payload).as_bytes(), 0).expect("hash is 32 bytes")
+}
+
+// A message with its envelope, as sent on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawNetworkMessage {
+    pub magic: [u8; 4],
+    pub payload: NetworkMessage,
+}
+
+impl RawNetworkMessage {
+    pub fn new(network: Network, payload: NetworkMessage) -> Self {
+        RawNetworkMessage {
+            magic: network.params().magic,
+            payload,
+        }
+    }
+
+    // None for a magic none of the known networks use
+    pub fn network(&self) -> Option<Network> {
+        Network::from_magic(self.magic)
+    }
+
+    pub fn command(&self) -> &str {
+        self.payload.command()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let payload = self.payload.serialize_payload();
+        let mut v = MessageHeader::new(self.magic, self.command(), &payload).serialize();
+        v.extend(payload);
+        v
+    }
+
+    // The magic isn't checked; compare it with the expected network's
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (header, offset) = MessageHeader::parse(data)?;
+        let payload = read_bytes(data, offset, header.length as u64)?;
+        Ok((
+            RawNetworkMessage {
+                magic: header.magic,
+                payload: header.parse_payload(payload)?,
+            },
+            offset + payload.len(),
+        ))
+    }
+
+    // Reads exactly one message, such as the next one from a peer's stream
+    pub fn consensus_decode<R: Read>(r: &mut R) -> Result<Self, BitcoinError> {
+        let mut header = [0; MessageHeader::SIZE];
+        r.read_exact(&mut header)?;
+        let (header, _) = MessageHeader::parse(&header)?;
+        let mut payload = vec![0; header.length as usize];
+        r.read_exact(&mut payload)?;
+        Ok(RawNetworkMessage {
+            magic: header.magic,
+            payload: header.parse_payload(&payload)?,
+        })
+    }
+}
+
+impl BitcoinSerialize for RawNetworkMessage {
+    fn serialize(&self) -> Vec<u8> {
+        RawNetworkMessage::serialize(self)
+    }
+}
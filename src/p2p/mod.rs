@@ -0,0 +1,90 @@
+// Peer-to-peer network protocol: the messages nodes exchange and their wire
+// encoding
+
+pub mod address;
+pub mod compact_block;
+pub mod inventory;
+pub mod message;
+pub mod peer;
+
+use std::ops::BitOr;
+
+use crate::{read_bytes, BitcoinError, CompactSize, ParseError};
+
+// Protocol version announced in our version message (BIP339 wtxid relay)
+pub const PROTOCOL_VERSION: i32 = 70016;
+
+// Services a node offers, as announced in version and addr messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ServiceFlags(pub u64);
+
+impl ServiceFlags {
+    pub const NONE: ServiceFlags = ServiceFlags(0);
+    // Serves the full block chain
+    pub const NETWORK: ServiceFlags = ServiceFlags(1);
+    // BIP37 bloom filters
+    pub const BLOOM: ServiceFlags = ServiceFlags(1 << 2);
+    // BIP144 segregated witness data
+    pub const WITNESS: ServiceFlags = ServiceFlags(1 << 3);
+    // BIP157 compact block filters
+    pub const COMPACT_FILTERS: ServiceFlags = ServiceFlags(1 << 6);
+    // Serves only the last 288 blocks (BIP159)
+    pub const NETWORK_LIMITED: ServiceFlags = ServiceFlags(1 << 10);
+
+    pub fn has(self, flags: ServiceFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl BitOr for ServiceFlags {
+    type Output = ServiceFlags;
+
+    fn bitor(self, rhs: ServiceFlags) -> ServiceFlags {
+        ServiceFlags(self.0 | rhs.0)
+    }
+}
+
+// Length-prefixed byte string at `offset`, and the offset after it
+pub(crate) fn read_var_bytes(data: &[u8], offset: usize) -> Result<(&[u8], usize), BitcoinError> {
+    let (CompactSize(len), used) =
+        CompactSize::decode(&data[offset.min(data.len())..]).map_err(|e| e.offset_by(offset))?;
+    let bytes = read_bytes(data, offset + used, len)?;
+    Ok((bytes, offset + used + bytes.len()))
+}
+
+pub(crate) fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend(CompactSize(bytes.len() as u64).encode());
+    out.extend(bytes);
+}
+
+// A CompactSize count of items, each parsed by `parse`, rejecting counts
+// over `max`
+pub(crate) fn parse_list<T>(
+    data: &[u8],
+    max: usize,
+    field: &'static str,
+    mut parse: impl FnMut(&[u8]) -> Result<(T, usize), BitcoinError>,
+) -> Result<(Vec<T>, usize), BitcoinError> {
+    let (CompactSize(count), mut offset) = CompactSize::decode(data)?;
+    if count > max as u64 {
+        return Err(BitcoinError::Parse(ParseError::InvalidField {
+            field,
+            offset: 0,
+        }));
+    }
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (item, used) = parse(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+        items.push(item);
+        offset += used;
+    }
+    Ok((items, offset))
+}
+
+pub(crate) fn serialize_list<T>(items: &[T], mut serialize: impl FnMut(&T) -> Vec<u8>) -> Vec<u8> {
+    let mut v = CompactSize(items.len() as u64).encode();
+    for item in items {
+        v.extend(serialize(item));
+    }
+    v
+}
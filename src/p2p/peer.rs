@@ -0,0 +1,152 @@
+// A connection to a node over plain TCP: the version handshake, then
+// messages in either direction
+
+use std::io::{BufReader, Write};
+#[cfg(feature = "async")]
+use std::net::Shutdown;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::message::{NetworkMessage, RawNetworkMessage, VersionMessage};
+#[cfg(feature = "async")]
+use crate::task::{Task, Worker};
+use crate::{BitcoinError, Network, ParseError};
+
+// For the handshake and each write; once connected, reads wait as long as
+// the peer is quiet
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct Peer {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    network: Network,
+    version: VersionMessage,
+}
+
+impl Peer {
+    // Connects and completes the handshake, announcing `start_height` as
+    // our best height
+    pub fn connect(
+        address: SocketAddr,
+        network: Network,
+        start_height: i32,
+    ) -> Result<Self, BitcoinError> {
+        let writer = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        writer.set_read_timeout(Some(TIMEOUT))?;
+        writer.set_write_timeout(Some(TIMEOUT))?;
+        let mut peer = Peer {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            network,
+            version: VersionMessage::new(address, start_height),
+        };
+        peer.send(NetworkMessage::Version(peer.version.clone()))?;
+        let (mut version, mut verack) = (None, false);
+        while version.is_none() || !verack {
+            match peer.receive()? {
+                NetworkMessage::Version(theirs) if version.is_none() => {
+                    peer.send(NetworkMessage::Verack)?;
+                    version = Some(theirs);
+                }
+                NetworkMessage::Verack if version.is_some() => verack = true,
+                // Feature negotiation (sendaddrv2, wtxidrelay) is optional
+                _ if version.is_some() => {}
+                message => {
+                    return Err(BitcoinError::Parse(ParseError::Message(format!(
+                        "Unexpected {} message during the handshake",
+                        message.command()
+                    ))))
+                }
+            }
+        }
+        peer.version = version.expect("set before the loop ends");
+        peer.writer.set_read_timeout(None)?;
+        Ok(peer)
+    }
+
+    // The version message the peer sent
+    pub fn version(&self) -> &VersionMessage {
+        &self.version
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn send(&mut self, message: NetworkMessage) -> Result<(), BitcoinError> {
+        let raw = RawNetworkMessage::new(self.network, message);
+        self.writer.write_all(&raw.serialize())?;
+        Ok(())
+    }
+
+    // The next message from the peer, waiting for one. Pings come through
+    // as Unknown messages; answer each with a pong carrying its payload or
+    // the peer disconnects.
+    pub fn receive(&mut self) -> Result<NetworkMessage, BitcoinError> {
+        let raw = RawNetworkMessage::consensus_decode(&mut self.reader)?;
+        if raw.network() != Some(self.network) {
+            return Err(BitcoinError::Parse(ParseError::Message(format!(
+                "Peer sent a message for another network (magic {:02x?})",
+                raw.magic
+            ))));
+        }
+        Ok(raw.payload)
+    }
+
+    // For sending only: bytes already buffered for reading stay with self
+    #[cfg(feature = "async")]
+    fn try_clone(&self) -> Result<Self, BitcoinError> {
+        Ok(Peer {
+            reader: BufReader::new(self.writer.try_clone()?),
+            writer: self.writer.try_clone()?,
+            network: self.network,
+            version: self.version.clone(),
+        })
+    }
+}
+
+// The peer's sends and receives as futures, for async services. Each
+// direction has a worker thread of its own, so a pending receive doesn't
+// hold up sends; within a direction, calls run in the order they're made.
+// Dropping the AsyncPeer closes the connection.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncPeer {
+    sender: Worker<Peer>,
+    receiver: Worker<Peer>,
+    stream: TcpStream,
+    version: VersionMessage,
+}
+
+#[cfg(feature = "async")]
+impl AsyncPeer {
+    pub fn new(peer: Peer) -> Result<Self, BitcoinError> {
+        Ok(AsyncPeer {
+            sender: Worker::new(peer.try_clone()?),
+            stream: peer.writer.try_clone()?,
+            version: peer.version.clone(),
+            receiver: Worker::new(peer),
+        })
+    }
+
+    pub fn version(&self) -> &VersionMessage {
+        &self.version
+    }
+
+    pub fn send(&self, message: NetworkMessage) -> Task<Result<(), BitcoinError>> {
+        self.sender.run(move |peer| peer.send(message))
+    }
+
+    pub fn receive(&self) -> Task<Result<NetworkMessage, BitcoinError>> {
+        self.receiver.run(Peer::receive)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for AsyncPeer {
+    // Ends a receive still waiting, so the workers can exit
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
@@ -0,0 +1,64 @@
+// Transaction decoding spread over threads, for indexing many transactions
+// at once. Work is split into one contiguous chunk per available core with
+// the parallel feature; without it the same calls run on the caller's
+// thread.
+
+use std::ops::Range;
+#[cfg(feature = "parallel")]
+use std::panic;
+#[cfg(feature = "parallel")]
+use std::thread;
+
+use crate::{BitcoinError, Block, LegacyTransaction, TransactionRef};
+
+// Results are in the same order as the inputs
+pub fn parse_batch(txs: &[&[u8]]) -> Vec<Result<LegacyTransaction, BitcoinError>> {
+    map_chunks(txs, |data| LegacyTransaction::try_from(*data))
+}
+
+impl Block {
+    // Same result as parse. Transaction boundaries are found in one pass
+    // over the block, then the transactions are decoded in parallel.
+    pub fn parse_parallel(data: &[u8]) -> Result<(Self, usize), BitcoinError> {
+        let (header, transactions) = Block::stream(data)?;
+        let mut offset = transactions.position();
+        let mut ranges: Vec<Range<usize>> = Vec::with_capacity(transactions.remaining());
+        for _ in 0..transactions.remaining() {
+            let (_, used) =
+                TransactionRef::parse(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+            ranges.push(offset..offset + used);
+            offset += used;
+        }
+        let txdata = map_chunks(&ranges, |range| {
+            LegacyTransaction::parse(&data[range.clone()])
+                .expect("checked by TransactionRef::parse")
+                .0
+        });
+        Ok((Block { header, txdata }, offset))
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn map_chunks<T: Sync, U: Send>(items: &[T], f: impl Fn(&T) -> U + Sync) -> Vec<U> {
+    items.iter().map(f).collect()
+}
+
+#[cfg(feature = "parallel")]
+fn map_chunks<T: Sync, U: Send>(items: &[T], f: impl Fn(&T) -> U + Sync) -> Vec<U> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 || items.len() < 2 {
+        return items.iter().map(f).collect();
+    }
+    let chunk_len = items.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_len)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<U>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })
+}
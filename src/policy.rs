@@ -0,0 +1,109 @@
+// Bitcoin Core's standardness rules: what nodes relay and mine by default.
+// Nonstandard transactions can still be valid in a block.
+
+use thiserror::Error;
+
+use crate::consensus::MAX_BLOCK_SIGOPS_COST;
+use crate::fee::DUST_RELAY_FEE;
+use crate::script::{bare_multisig_counts, is_push_only, witness_program};
+use crate::{BitcoinError, LegacyTransaction, ScriptType};
+
+// Versions 1 and 2, and 3 for TRUC transactions (BIP431)
+pub const MAX_STANDARD_VERSION: i32 = 3;
+pub const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+// Below this, a transaction could be mistaken for a 64-byte merkle node.
+// Core enforces it when accepting to the mempool rather than in
+// IsStandardTx, but with the same effect on relay.
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+// Enough for a 15-of-15 multisig redeem script with its signatures
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+// Most keys in a relayed bare multisig output
+pub const MAX_STANDARD_BARE_MULTISIG_KEYS: usize = 3;
+// Largest witness script relayed in a P2WSH spend
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+// Data an OP_RETURN output may carry
+pub const MAX_OP_RETURN_DATA: usize = 80;
+// OP_RETURN plus that data and its push opcodes
+pub const MAX_OP_RETURN_RELAY: usize = MAX_OP_RETURN_DATA + 3;
+// Highest sigop cost a relayed transaction may have, a fifth of a block's
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = MAX_BLOCK_SIGOPS_COST / 5;
+
+// Messages give Core's reject reasons
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyError {
+    #[error("version")]
+    Version,
+    #[error("tx-size")]
+    TxSize,
+    #[error("tx-size-small")]
+    TxSizeSmall,
+    #[error("scriptsig-size")]
+    ScriptSigSize,
+    #[error("scriptsig-not-pushonly")]
+    ScriptSigNotPushOnly,
+    #[error("scriptpubkey")]
+    ScriptPubKey,
+    #[error("multi-op-return")]
+    MultiOpReturn,
+    #[error("dust")]
+    Dust,
+}
+
+// Whether nodes with default settings would relay `tx`
+pub fn is_standard(tx: &LegacyTransaction) -> bool {
+    check_standard(tx).is_ok()
+}
+
+// is_standard, saying which rule failed. Checks run in Core's order, with
+// the mempool's tx-size-small check among IsStandardTx's.
+pub fn check_standard(tx: &LegacyTransaction) -> Result<(), BitcoinError> {
+    if !(1..=MAX_STANDARD_VERSION).contains(&tx.version) {
+        return Err(PolicyError::Version.into());
+    }
+    if tx.weight() > MAX_STANDARD_TX_WEIGHT {
+        return Err(PolicyError::TxSize.into());
+    }
+    if tx.base_size() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err(PolicyError::TxSizeSmall.into());
+    }
+    for input in &tx.inputs {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(PolicyError::ScriptSigSize.into());
+        }
+        if !is_push_only(&input.script_sig) {
+            return Err(PolicyError::ScriptSigNotPushOnly.into());
+        }
+    }
+
+    let mut data_outputs = 0;
+    for output in &tx.outputs {
+        let script = &output.script_pubkey;
+        match ScriptType::classify(script) {
+            ScriptType::OpReturn if script.len() > MAX_OP_RETURN_RELAY => {
+                return Err(PolicyError::ScriptPubKey.into());
+            }
+            ScriptType::OpReturn => data_outputs += 1,
+            // Core relays bare multisig up to 3 keys (-permitbaremultisig)
+            ScriptType::Multisig
+                if bare_multisig_counts(script)
+                    .is_none_or(|(_, keys)| keys > MAX_STANDARD_BARE_MULTISIG_KEYS) =>
+            {
+                return Err(PolicyError::ScriptPubKey.into());
+            }
+            // Unknown witness versions are left for future soft forks
+            ScriptType::NonStandard if !is_future_witness_program(script) => {
+                return Err(PolicyError::ScriptPubKey.into());
+            }
+            _ if output.is_dust(DUST_RELAY_FEE) => return Err(PolicyError::Dust.into()),
+            _ => {}
+        }
+    }
+    if data_outputs > 1 {
+        return Err(PolicyError::MultiOpReturn.into());
+    }
+    Ok(())
+}
+
+fn is_future_witness_program(script: &[u8]) -> bool {
+    matches!(witness_program(script), Some((version, _)) if version != 0)
+}
@@ -0,0 +1,157 @@
+// Proof-of-work targets and their compact (nBits) encoding
+
+use std::ops::Add;
+
+use k256::elliptic_curve::bigint::{Encoding, U256};
+
+use crate::{BlockHash, BlockHeader, ChainParams};
+
+// A 256-bit proof-of-work target. Block hashes, read as little-endian
+// numbers, must not exceed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target(U256);
+
+impl Target {
+    pub const ZERO: Target = Target(U256::ZERO);
+
+    // The difficulty 1 target, 0x00000000FFFF << 208
+    pub const MAX: Target = Target(U256::from_u64(0xFFFF).shl_vartime(208));
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        Target(U256::from_be_bytes(bytes))
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        self.0.to_be_bytes()
+    }
+
+    // Block hashes are compared as little-endian 256-bit numbers
+    pub fn is_met_by(&self, hash: BlockHash) -> bool {
+        U256::from_le_bytes(hash.to_byte_array()) <= self.0
+    }
+
+    // How many times harder this target is to meet than the difficulty 1
+    // target. Infinite for the zero target.
+    pub fn difficulty(&self) -> f64 {
+        to_f64(&Target::MAX.0) / to_f64(&self.0)
+    }
+
+    // Expected hashes to find a block meeting the target: 2^256 / (target + 1)
+    pub fn to_work(&self) -> Work {
+        if self.0 == U256::MAX {
+            return Work(U256::ONE);
+        }
+        // Computed as !target / (target + 1) + 1, since 2^256 doesn't fit
+        let work = self.0.not().wrapping_div(&self.0.wrapping_add(&U256::ONE));
+        Work(work.wrapping_add(&U256::ONE))
+    }
+}
+
+// Chains are compared by their total work, not their length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Work(U256);
+
+impl Work {
+    pub const ZERO: Work = Work(U256::ZERO);
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl Add for Work {
+    type Output = Work;
+
+    fn add(self, rhs: Work) -> Work {
+        Work(self.0.saturating_add(&rhs.0))
+    }
+}
+
+fn to_f64(n: &U256) -> f64 {
+    n.to_be_bytes()
+        .iter()
+        .fold(0.0, |acc, byte| acc * 256.0 + *byte as f64)
+}
+
+// The 32-bit floating point encoding of a target used in block headers: one
+// exponent byte (the target's length in bytes) and a 23-bit mantissa, with
+// the top mantissa bit as a sign bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompactTarget(pub u32);
+
+impl CompactTarget {
+    // None for negative or overflowing encodings, which Bitcoin Core treats
+    // as invalid
+    pub fn to_target(self) -> Option<Target> {
+        let size = (self.0 >> 24) as usize;
+        let mut mantissa = self.0 & 0x007F_FFFF;
+        let negative = self.0 & 0x0080_0000 != 0;
+        if mantissa != 0 && negative {
+            return None;
+        }
+        if size <= 3 {
+            mantissa >>= 8 * (3 - size);
+            return Some(Target(U256::from_u32(mantissa)));
+        }
+        let significant_bytes = match mantissa {
+            0 => 0,
+            1..=0xFF => 1,
+            0x100..=0xFFFF => 2,
+            _ => 3,
+        };
+        if mantissa != 0 && size - 3 + significant_bytes > 32 {
+            return None;
+        }
+        Some(Target(U256::from_u32(mantissa).shl_vartime(8 * (size - 3))))
+    }
+
+    // Rounds the target down to 3 significant bytes, so the round trip back
+    // through `to_target` is lossy
+    pub fn from_target(target: Target) -> Self {
+        let mut size = target.0.bits_vartime().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (target.0.as_words()[0] << (8 * (3 - size))) as u32
+        } else {
+            target.0.shr_vartime(8 * (size - 3)).as_words()[0] as u32
+        };
+        // Keep the mantissa's sign bit clear
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        CompactTarget(compact | ((size as u32) << 24))
+    }
+
+    // Difficulty of the encoded target, or None if the encoding is invalid
+    pub fn difficulty(self) -> Option<f64> {
+        self.to_target().map(|target| target.difficulty())
+    }
+}
+
+// Target for the block after `last_header`, which must end a retarget
+// period. As in Bitcoin Core, `first_header` is the first block of that
+// period, so the timespan covers 2015 block intervals rather than 2016.
+pub fn calculate_next_work_required(
+    first_header: &BlockHeader,
+    last_header: &BlockHeader,
+    params: &ChainParams,
+) -> CompactTarget {
+    if params.pow_no_retargeting {
+        return CompactTarget(last_header.bits);
+    }
+    let timespan = params.pow_target_timespan as i64;
+    // Limit the adjustment to a factor of 4 either way
+    let actual_timespan =
+        (last_header.time as i64 - first_header.time as i64).clamp(timespan / 4, timespan * 4);
+
+    // Invalid encodings can't appear in a valid chain; treat them as the limit
+    let last_target = CompactTarget(last_header.bits)
+        .to_target()
+        .unwrap_or(params.pow_limit);
+    // Saturation only happens for targets far above any network's limit
+    let new_target = last_target
+        .0
+        .saturating_mul(&U256::from_u64(actual_timespan as u64))
+        .wrapping_div(&U256::from_u64(timespan as u64));
+    CompactTarget::from_target(Target(new_target).min(params.pow_limit))
+}
@@ -0,0 +1,836 @@
+// Partially signed bitcoin transactions (BIP174 version 0 and BIP370 version 2)
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{
+    base64, Amount, BitcoinError, BitcoinSerialize, CompactSize, LegacyTransaction, LockTime,
+    OutPoint, ParseError, PublicKey, Sequence, TxInput, TxOutput, Txid, Witness,
+};
+
+pub const PSBT_MAGIC: [u8; 5] = *b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u64 = 0x00;
+const PSBT_GLOBAL_XPUB: u64 = 0x01;
+const PSBT_GLOBAL_TX_VERSION: u64 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u64 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u64 = 0x06;
+const PSBT_GLOBAL_VERSION: u64 = 0xFB;
+
+const PSBT_IN_NON_WITNESS_UTXO: u64 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u64 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u64 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u64 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u64 = 0x04;
+const PSBT_IN_WITNESS_SCRIPT: u64 = 0x05;
+const PSBT_IN_BIP32_DERIVATION: u64 = 0x06;
+const PSBT_IN_FINAL_SCRIPTSIG: u64 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u64 = 0x08;
+const PSBT_IN_PREVIOUS_TXID: u64 = 0x0E;
+const PSBT_IN_OUTPUT_INDEX: u64 = 0x0F;
+const PSBT_IN_SEQUENCE: u64 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
+
+const PSBT_OUT_REDEEM_SCRIPT: u64 = 0x00;
+const PSBT_OUT_WITNESS_SCRIPT: u64 = 0x01;
+const PSBT_OUT_BIP32_DERIVATION: u64 = 0x02;
+const PSBT_OUT_AMOUNT: u64 = 0x03;
+const PSBT_OUT_SCRIPT: u64 = 0x04;
+
+// PSBT_GLOBAL_TX_MODIFIABLE flags
+pub const TX_MODIFIABLE_INPUTS: u8 = 0x01;
+pub const TX_MODIFIABLE_OUTPUTS: u8 = 0x02;
+pub const TX_MODIFIABLE_SIGHASH_SINGLE: u8 = 0x04;
+
+// Master key fingerprint and derivation path of a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySource {
+    pub fingerprint: [u8; 4],
+    pub path: Vec<u32>,
+}
+
+impl KeySource {
+    fn serialize(&self) -> Vec<u8> {
+        let mut v = self.fingerprint.to_vec();
+        for index in &self.path {
+            v.extend(&index.to_le_bytes());
+        }
+        v
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, BitcoinError> {
+        if data.len() < 4 || !(data.len() - 4).is_multiple_of(4) {
+            return Err(psbt_error("Invalid key origin length"));
+        }
+        let path = data[4..]
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        Ok(KeySource {
+            fingerprint: [data[0], data[1], data[2], data[3]],
+            path,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PsbtInput {
+    pub non_witness_utxo: Option<LegacyTransaction>,
+    pub witness_utxo: Option<TxOutput>,
+    // Serialized public key -> signature with sighash byte
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub final_script_sig: Option<Vec<u8>>,
+    pub final_script_witness: Option<Witness>,
+    // Version 2 only: lock time this input needs, as a timestamp or a height
+    pub required_time_lock_time: Option<u32>,
+    pub required_height_lock_time: Option<u32>,
+    // Full key (type and key data) -> value, for fields we don't interpret
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PsbtOutput {
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+// Both versions keep the transaction data in `unsigned_tx`. Version 2 puts it
+// on the wire as per-input and per-output fields instead, with the lock time
+// given by `lock_time()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Psbt {
+    pub unsigned_tx: LegacyTransaction,
+    pub version: u32,
+    // Serialized extended public key -> its origin
+    pub xpubs: BTreeMap<Vec<u8>, KeySource>,
+    // Version 2 only
+    pub fallback_lock_time: Option<u32>,
+    pub tx_modifiable: Option<u8>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>,
+}
+
+fn psbt_error(msg: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(format!("PSBT: {msg}")))
+}
+
+// Transaction fields carried in version 2 maps
+#[derive(Default)]
+struct InputTxFields {
+    previous_txid: Option<Txid>,
+    output_index: Option<u32>,
+    sequence: Option<Sequence>,
+}
+
+#[derive(Default)]
+struct OutputTxFields {
+    amount: Option<Amount>,
+    script: Option<Vec<u8>>,
+}
+
+impl Psbt {
+    // The transaction must not carry any scriptSigs or witnesses yet
+    pub fn from_unsigned_tx(tx: LegacyTransaction) -> Result<Self, BitcoinError> {
+        check_unsigned(&tx)?;
+        Ok(Psbt {
+            inputs: vec![PsbtInput::default(); tx.inputs.len()],
+            outputs: vec![PsbtOutput::default(); tx.outputs.len()],
+            unsigned_tx: tx,
+            version: 0,
+            xpubs: BTreeMap::new(),
+            fallback_lock_time: None,
+            tx_modifiable: None,
+            unknown: BTreeMap::new(),
+        })
+    }
+
+    // Empty version 2 PSBT that inputs and outputs can be added to
+    pub fn new_v2(tx_version: i32, fallback_lock_time: Option<u32>) -> Self {
+        let tx = LegacyTransaction::builder().version(tx_version).build();
+        Psbt {
+            version: 2,
+            fallback_lock_time,
+            tx_modifiable: Some(TX_MODIFIABLE_INPUTS | TX_MODIFIABLE_OUTPUTS),
+            ..Self::from_unsigned_tx(tx).expect("empty transaction")
+        }
+    }
+
+    pub fn add_input(
+        &mut self,
+        previous_output: OutPoint,
+        sequence: Sequence,
+        input: PsbtInput,
+    ) -> Result<(), BitcoinError> {
+        self.check_modifiable(TX_MODIFIABLE_INPUTS)?;
+        self.unsigned_tx.inputs.push(TxInput {
+            previous_output,
+            script_sig: Vec::new(),
+            sequence,
+            witness: Witness::new(),
+        });
+        self.inputs.push(input);
+        // An input whose lock time conflicts with the others' isn't added
+        match self.lock_time() {
+            Ok(lock_time) => {
+                self.unsigned_tx.lock_time = lock_time;
+                Ok(())
+            }
+            Err(e) => {
+                self.unsigned_tx.inputs.pop();
+                self.inputs.pop();
+                Err(e)
+            }
+        }
+    }
+
+    pub fn add_output(
+        &mut self,
+        tx_output: TxOutput,
+        output: PsbtOutput,
+    ) -> Result<(), BitcoinError> {
+        self.check_modifiable(TX_MODIFIABLE_OUTPUTS)?;
+        self.unsigned_tx.outputs.push(tx_output);
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    fn check_modifiable(&self, flag: u8) -> Result<(), BitcoinError> {
+        if self.version != 2 || self.tx_modifiable.unwrap_or(0) & flag == 0 {
+            return Err(psbt_error("Transaction is not modifiable"));
+        }
+        Ok(())
+    }
+
+    // BIP370 lock time: the maximum required lock time of the kind every
+    // constrained input supports (heights preferred), or the fallback if no
+    // input has a requirement. Version 0 PSBTs use the unsigned tx's.
+    pub fn lock_time(&self) -> Result<LockTime, BitcoinError> {
+        if self.version < 2 {
+            return Ok(self.unsigned_tx.lock_time);
+        }
+        let constrained: Vec<_> = self
+            .inputs
+            .iter()
+            .filter(|i| {
+                i.required_time_lock_time.is_some() || i.required_height_lock_time.is_some()
+            })
+            .collect();
+        if constrained.is_empty() {
+            return Ok(LockTime::from_consensus(
+                self.fallback_lock_time.unwrap_or(0),
+            ));
+        }
+        if constrained
+            .iter()
+            .all(|i| i.required_height_lock_time.is_some())
+        {
+            let height = constrained
+                .iter()
+                .filter_map(|i| i.required_height_lock_time)
+                .max()
+                .unwrap_or(0);
+            return Ok(LockTime::from_consensus(height));
+        }
+        if constrained
+            .iter()
+            .all(|i| i.required_time_lock_time.is_some())
+        {
+            let time = constrained
+                .iter()
+                .filter_map(|i| i.required_time_lock_time)
+                .max()
+                .unwrap_or(0);
+            return Ok(LockTime::from_consensus(time));
+        }
+        Err(psbt_error(
+            "Inputs have incompatible lock time requirements",
+        ))
+    }
+
+    pub fn to_v2(mut self) -> Self {
+        if self.version < 2 {
+            self.version = 2;
+            self.fallback_lock_time = Some(self.unsigned_tx.lock_time.to_consensus_u32());
+        }
+        self
+    }
+
+    // Required lock times have no version 0 equivalent; they are folded into
+    // the unsigned transaction's lock time
+    pub fn to_v0(mut self) -> Result<Self, BitcoinError> {
+        self.unsigned_tx.lock_time = self.lock_time()?;
+        self.version = 0;
+        self.fallback_lock_time = None;
+        self.tx_modifiable = None;
+        for input in &mut self.inputs {
+            input.required_time_lock_time = None;
+            input.required_height_lock_time = None;
+        }
+        Ok(self)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let v2 = self.version >= 2;
+        let mut v = PSBT_MAGIC.to_vec();
+        if !v2 {
+            write_pair(
+                &mut v,
+                PSBT_GLOBAL_UNSIGNED_TX,
+                &[],
+                &self.unsigned_tx.serialize_without_witness(),
+            );
+        }
+        for (xpub, source) in &self.xpubs {
+            write_pair(&mut v, PSBT_GLOBAL_XPUB, xpub, &source.serialize());
+        }
+        if v2 {
+            let tx = &self.unsigned_tx;
+            write_pair(
+                &mut v,
+                PSBT_GLOBAL_TX_VERSION,
+                &[],
+                &tx.version.to_le_bytes(),
+            );
+            if let Some(lock_time) = self.fallback_lock_time {
+                write_pair(
+                    &mut v,
+                    PSBT_GLOBAL_FALLBACK_LOCKTIME,
+                    &[],
+                    &lock_time.to_le_bytes(),
+                );
+            }
+            let input_count = CompactSize(tx.inputs.len() as u64).encode();
+            write_pair(&mut v, PSBT_GLOBAL_INPUT_COUNT, &[], &input_count);
+            let output_count = CompactSize(tx.outputs.len() as u64).encode();
+            write_pair(&mut v, PSBT_GLOBAL_OUTPUT_COUNT, &[], &output_count);
+            if let Some(flags) = self.tx_modifiable {
+                write_pair(&mut v, PSBT_GLOBAL_TX_MODIFIABLE, &[], &[flags]);
+            }
+        }
+        if self.version > 0 {
+            write_pair(
+                &mut v,
+                PSBT_GLOBAL_VERSION,
+                &[],
+                &self.version.to_le_bytes(),
+            );
+        }
+        write_unknown(&mut v, &self.unknown);
+        v.push(0x00);
+
+        for (input, tx_input) in self.inputs.iter().zip(&self.unsigned_tx.inputs) {
+            input.serialize_into(&mut v, v2.then_some(tx_input));
+        }
+        for (output, tx_output) in self.outputs.iter().zip(&self.unsigned_tx.outputs) {
+            output.serialize_into(&mut v, v2.then_some(tx_output));
+        }
+        v
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, BitcoinError> {
+        if !data.starts_with(&PSBT_MAGIC) {
+            return Err(psbt_error("Invalid magic"));
+        }
+        let mut offset = PSBT_MAGIC.len();
+
+        let mut unsigned_tx = None;
+        let mut version = 0;
+        let mut xpubs = BTreeMap::new();
+        let mut tx_version = None;
+        let mut fallback_lock_time = None;
+        let mut input_count = None;
+        let mut output_count = None;
+        let mut tx_modifiable = None;
+        let mut unknown = BTreeMap::new();
+        for (key_type, key, value) in parse_map(data, &mut offset)? {
+            match key_type {
+                PSBT_GLOBAL_UNSIGNED_TX => {
+                    expect_no_key_data(&key)?;
+                    let tx = parse_tx(&value)?;
+                    check_unsigned(&tx)?;
+                    unsigned_tx = Some(tx);
+                }
+                PSBT_GLOBAL_XPUB => {
+                    xpubs.insert(key[1..].to_vec(), KeySource::parse(&value)?);
+                }
+                PSBT_GLOBAL_TX_VERSION => {
+                    expect_no_key_data(&key)?;
+                    tx_version = Some(parse_u32(&value)? as i32);
+                }
+                PSBT_GLOBAL_FALLBACK_LOCKTIME => {
+                    expect_no_key_data(&key)?;
+                    fallback_lock_time = Some(parse_u32(&value)?);
+                }
+                PSBT_GLOBAL_INPUT_COUNT => {
+                    expect_no_key_data(&key)?;
+                    input_count = Some(parse_count(&value)?);
+                }
+                PSBT_GLOBAL_OUTPUT_COUNT => {
+                    expect_no_key_data(&key)?;
+                    output_count = Some(parse_count(&value)?);
+                }
+                PSBT_GLOBAL_TX_MODIFIABLE => {
+                    expect_no_key_data(&key)?;
+                    let [flags] = value[..] else {
+                        return Err(psbt_error("Invalid modifiable flags"));
+                    };
+                    tx_modifiable = Some(flags);
+                }
+                PSBT_GLOBAL_VERSION => {
+                    expect_no_key_data(&key)?;
+                    version = parse_u32(&value)?;
+                }
+                _ => {
+                    unknown.insert(key, value);
+                }
+            }
+        }
+
+        let v2_globals = tx_version.is_some()
+            || fallback_lock_time.is_some()
+            || input_count.is_some()
+            || output_count.is_some()
+            || tx_modifiable.is_some();
+        let (mut unsigned_tx, input_count, output_count) = match version {
+            0 => {
+                if v2_globals {
+                    return Err(psbt_error("Version 2 fields in a version 0 PSBT"));
+                }
+                let tx = unsigned_tx.ok_or_else(|| psbt_error("Missing unsigned transaction"))?;
+                let counts = (tx.inputs.len(), tx.outputs.len());
+                (tx, counts.0, counts.1)
+            }
+            2 => {
+                if unsigned_tx.is_some() {
+                    return Err(psbt_error("Unsigned transaction in a version 2 PSBT"));
+                }
+                let missing = || psbt_error("Missing required version 2 field");
+                let tx_version = tx_version.ok_or_else(missing)?;
+                let tx = LegacyTransaction::builder().version(tx_version).build();
+                (
+                    tx,
+                    input_count.ok_or_else(missing)?,
+                    output_count.ok_or_else(missing)?,
+                )
+            }
+            _ => return Err(psbt_error("Unsupported version")),
+        };
+        let v2 = version == 2;
+
+        let mut inputs = Vec::with_capacity(input_count.min(data.len()));
+        for i in 0..input_count {
+            let (input, fields) = PsbtInput::parse(data, &mut offset)?;
+            match (v2, fields.previous_txid, fields.output_index) {
+                (true, Some(txid), Some(vout)) => unsigned_tx.inputs.push(TxInput {
+                    previous_output: OutPoint::new(txid, vout),
+                    script_sig: Vec::new(),
+                    sequence: fields.sequence.unwrap_or_default(),
+                    witness: Witness::new(),
+                }),
+                (true, ..) => return Err(psbt_error("Input missing previous output")),
+                (false, None, None) if fields.sequence.is_none() => {}
+                (false, ..) => return Err(psbt_error("Version 2 fields in a version 0 PSBT")),
+            }
+            if !v2
+                && (input.required_time_lock_time.is_some()
+                    || input.required_height_lock_time.is_some())
+            {
+                return Err(psbt_error("Version 2 fields in a version 0 PSBT"));
+            }
+            if input
+                .required_time_lock_time
+                .is_some_and(|t| LockTime::from_time(t).is_none())
+                || input
+                    .required_height_lock_time
+                    .is_some_and(|h| h == 0 || LockTime::from_height(h).is_none())
+            {
+                return Err(psbt_error(&format!(
+                    "Invalid required lock time on input {i}"
+                )));
+            }
+            // The full previous transaction must be the one the input spends
+            let previous_output = &unsigned_tx.inputs[i].previous_output;
+            if input.non_witness_utxo.as_ref().is_some_and(|tx| {
+                tx.txid() != previous_output.txid
+                    || tx.outputs.len() <= previous_output.vout as usize
+            }) {
+                return Err(psbt_error(&format!(
+                    "Non-witness UTXO doesn't match the output spent by input {i}"
+                )));
+            }
+            inputs.push(input);
+        }
+
+        let mut outputs = Vec::with_capacity(output_count.min(data.len()));
+        for _ in 0..output_count {
+            let (output, fields) = PsbtOutput::parse(data, &mut offset)?;
+            match (v2, fields.amount, fields.script) {
+                (true, Some(value), Some(script_pubkey)) => unsigned_tx.outputs.push(TxOutput {
+                    value,
+                    script_pubkey,
+                }),
+                (true, ..) => return Err(psbt_error("Output missing amount or script")),
+                (false, None, None) => {}
+                (false, ..) => return Err(psbt_error("Version 2 fields in a version 0 PSBT")),
+            }
+            outputs.push(output);
+        }
+        if offset != data.len() {
+            return Err(psbt_error("Trailing data"));
+        }
+
+        let mut psbt = Psbt {
+            unsigned_tx,
+            version,
+            xpubs,
+            fallback_lock_time,
+            tx_modifiable,
+            unknown,
+            inputs,
+            outputs,
+        };
+        psbt.unsigned_tx.lock_time = psbt.lock_time()?;
+        Ok(psbt)
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.serialize())
+    }
+
+    pub fn from_base64(s: &str) -> Result<Self, BitcoinError> {
+        Self::deserialize(&base64::decode(s)?)
+    }
+}
+
+// PSBTs are exchanged as base64 text
+impl fmt::Display for Psbt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_base64())
+    }
+}
+
+impl FromStr for Psbt {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_base64(s)
+    }
+}
+
+impl PsbtInput {
+    // `tx_input` is given for version 2, which carries the outpoint and sequence
+    fn serialize_into(&self, v: &mut Vec<u8>, tx_input: Option<&TxInput>) {
+        if let Some(tx) = &self.non_witness_utxo {
+            write_pair(v, PSBT_IN_NON_WITNESS_UTXO, &[], &tx.serialize());
+        }
+        if let Some(output) = &self.witness_utxo {
+            write_pair(v, PSBT_IN_WITNESS_UTXO, &[], &output.serialize());
+        }
+        for (pubkey, signature) in &self.partial_sigs {
+            write_pair(v, PSBT_IN_PARTIAL_SIG, pubkey, signature);
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            write_pair(v, PSBT_IN_SIGHASH_TYPE, &[], &sighash_type.to_le_bytes());
+        }
+        if let Some(script) = &self.redeem_script {
+            write_pair(v, PSBT_IN_REDEEM_SCRIPT, &[], script);
+        }
+        if let Some(script) = &self.witness_script {
+            write_pair(v, PSBT_IN_WITNESS_SCRIPT, &[], script);
+        }
+        for (pubkey, source) in &self.bip32_derivation {
+            write_pair(v, PSBT_IN_BIP32_DERIVATION, pubkey, &source.serialize());
+        }
+        if let Some(script) = &self.final_script_sig {
+            write_pair(v, PSBT_IN_FINAL_SCRIPTSIG, &[], script);
+        }
+        if let Some(witness) = &self.final_script_witness {
+            write_pair(v, PSBT_IN_FINAL_SCRIPTWITNESS, &[], &witness.serialize());
+        }
+        if let Some(tx_input) = tx_input {
+            let outpoint = &tx_input.previous_output;
+            write_pair(v, PSBT_IN_PREVIOUS_TXID, &[], outpoint.txid.as_bytes());
+            write_pair(v, PSBT_IN_OUTPUT_INDEX, &[], &outpoint.vout.to_le_bytes());
+            if tx_input.sequence != Sequence::MAX {
+                let sequence = tx_input.sequence.to_consensus_u32();
+                write_pair(v, PSBT_IN_SEQUENCE, &[], &sequence.to_le_bytes());
+            }
+            if let Some(lock_time) = self.required_time_lock_time {
+                write_pair(
+                    v,
+                    PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                    &[],
+                    &lock_time.to_le_bytes(),
+                );
+            }
+            if let Some(lock_time) = self.required_height_lock_time {
+                write_pair(
+                    v,
+                    PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                    &[],
+                    &lock_time.to_le_bytes(),
+                );
+            }
+        }
+        write_unknown(v, &self.unknown);
+        v.push(0x00);
+    }
+
+    fn parse(data: &[u8], offset: &mut usize) -> Result<(Self, InputTxFields), BitcoinError> {
+        let mut input = PsbtInput::default();
+        let mut fields = InputTxFields::default();
+        for (key_type, key, value) in parse_map(data, offset)? {
+            match key_type {
+                PSBT_IN_NON_WITNESS_UTXO => {
+                    expect_no_key_data(&key)?;
+                    input.non_witness_utxo = Some(parse_tx(&value)?);
+                }
+                PSBT_IN_WITNESS_UTXO => {
+                    expect_no_key_data(&key)?;
+                    let (output, used) = TxOutput::parse(&value)?;
+                    if used != value.len() {
+                        return Err(psbt_error("Invalid witness UTXO"));
+                    }
+                    input.witness_utxo = Some(output);
+                }
+                PSBT_IN_PARTIAL_SIG => {
+                    input.partial_sigs.insert(parse_pubkey(&key[1..])?, value);
+                }
+                PSBT_IN_SIGHASH_TYPE => {
+                    expect_no_key_data(&key)?;
+                    input.sighash_type = Some(parse_u32(&value)?);
+                }
+                PSBT_IN_REDEEM_SCRIPT => {
+                    expect_no_key_data(&key)?;
+                    input.redeem_script = Some(value);
+                }
+                PSBT_IN_WITNESS_SCRIPT => {
+                    expect_no_key_data(&key)?;
+                    input.witness_script = Some(value);
+                }
+                PSBT_IN_BIP32_DERIVATION => {
+                    input
+                        .bip32_derivation
+                        .insert(parse_pubkey(&key[1..])?, KeySource::parse(&value)?);
+                }
+                PSBT_IN_FINAL_SCRIPTSIG => {
+                    expect_no_key_data(&key)?;
+                    input.final_script_sig = Some(value);
+                }
+                PSBT_IN_FINAL_SCRIPTWITNESS => {
+                    expect_no_key_data(&key)?;
+                    let (witness, used) = Witness::parse(&value)?;
+                    if used != value.len() {
+                        return Err(psbt_error("Invalid final script witness"));
+                    }
+                    input.final_script_witness = Some(witness);
+                }
+                PSBT_IN_PREVIOUS_TXID => {
+                    expect_no_key_data(&key)?;
+                    let txid: [u8; 32] = value[..]
+                        .try_into()
+                        .map_err(|_| psbt_error("Invalid previous txid"))?;
+                    fields.previous_txid = Some(Txid::from_byte_array(txid));
+                }
+                PSBT_IN_OUTPUT_INDEX => {
+                    expect_no_key_data(&key)?;
+                    fields.output_index = Some(parse_u32(&value)?);
+                }
+                PSBT_IN_SEQUENCE => {
+                    expect_no_key_data(&key)?;
+                    fields.sequence = Some(Sequence::from_consensus(parse_u32(&value)?));
+                }
+                PSBT_IN_REQUIRED_TIME_LOCKTIME => {
+                    expect_no_key_data(&key)?;
+                    input.required_time_lock_time = Some(parse_u32(&value)?);
+                }
+                PSBT_IN_REQUIRED_HEIGHT_LOCKTIME => {
+                    expect_no_key_data(&key)?;
+                    input.required_height_lock_time = Some(parse_u32(&value)?);
+                }
+                _ => {
+                    input.unknown.insert(key, value);
+                }
+            }
+        }
+        Ok((input, fields))
+    }
+}
+
+impl PsbtOutput {
+    // `tx_output` is given for version 2, which carries the amount and script
+    fn serialize_into(&self, v: &mut Vec<u8>, tx_output: Option<&TxOutput>) {
+        if let Some(script) = &self.redeem_script {
+            write_pair(v, PSBT_OUT_REDEEM_SCRIPT, &[], script);
+        }
+        if let Some(script) = &self.witness_script {
+            write_pair(v, PSBT_OUT_WITNESS_SCRIPT, &[], script);
+        }
+        for (pubkey, source) in &self.bip32_derivation {
+            write_pair(v, PSBT_OUT_BIP32_DERIVATION, pubkey, &source.serialize());
+        }
+        if let Some(tx_output) = tx_output {
+            write_pair(
+                v,
+                PSBT_OUT_AMOUNT,
+                &[],
+                &tx_output.value.to_sat().to_le_bytes(),
+            );
+            write_pair(v, PSBT_OUT_SCRIPT, &[], &tx_output.script_pubkey);
+        }
+        write_unknown(v, &self.unknown);
+        v.push(0x00);
+    }
+
+    fn parse(data: &[u8], offset: &mut usize) -> Result<(Self, OutputTxFields), BitcoinError> {
+        let mut output = PsbtOutput::default();
+        let mut fields = OutputTxFields::default();
+        for (key_type, key, value) in parse_map(data, offset)? {
+            match key_type {
+                PSBT_OUT_REDEEM_SCRIPT => {
+                    expect_no_key_data(&key)?;
+                    output.redeem_script = Some(value);
+                }
+                PSBT_OUT_WITNESS_SCRIPT => {
+                    expect_no_key_data(&key)?;
+                    output.witness_script = Some(value);
+                }
+                PSBT_OUT_BIP32_DERIVATION => {
+                    output
+                        .bip32_derivation
+                        .insert(parse_pubkey(&key[1..])?, KeySource::parse(&value)?);
+                }
+                PSBT_OUT_AMOUNT => {
+                    expect_no_key_data(&key)?;
+                    let amount: [u8; 8] = value[..]
+                        .try_into()
+                        .map_err(|_| psbt_error("Invalid amount"))?;
+                    fields.amount = Some(Amount::from_sat(u64::from_le_bytes(amount)));
+                }
+                PSBT_OUT_SCRIPT => {
+                    expect_no_key_data(&key)?;
+                    fields.script = Some(value);
+                }
+                _ => {
+                    output.unknown.insert(key, value);
+                }
+            }
+        }
+        Ok((output, fields))
+    }
+}
+
+fn check_unsigned(tx: &LegacyTransaction) -> Result<(), BitcoinError> {
+    if tx
+        .inputs
+        .iter()
+        .any(|input| !input.script_sig.is_empty() || !input.witness.is_empty())
+    {
+        return Err(psbt_error(
+            "Unsigned transaction has scriptSigs or witnesses",
+        ));
+    }
+    Ok(())
+}
+
+fn write_pair(v: &mut Vec<u8>, key_type: u64, key_data: &[u8], value: &[u8]) {
+    let key_type = CompactSize(key_type).encode();
+    v.extend(CompactSize((key_type.len() + key_data.len()) as u64).encode());
+    v.extend(key_type);
+    v.extend(key_data);
+    v.extend(CompactSize(value.len() as u64).encode());
+    v.extend(value);
+}
+
+fn write_unknown(v: &mut Vec<u8>, unknown: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for (key, value) in unknown {
+        v.extend(CompactSize(key.len() as u64).encode());
+        v.extend(key);
+        v.extend(CompactSize(value.len() as u64).encode());
+        v.extend(value);
+    }
+}
+
+// (key type, full key, value)
+type Pair = (u64, Vec<u8>, Vec<u8>);
+
+// Reads key-value pairs up to the 0x00 separator. Every key type interpreted
+// here fits in one byte, so key[1..] is the key data.
+fn parse_map(data: &[u8], offset: &mut usize) -> Result<Vec<Pair>, BitcoinError> {
+    let mut pairs = Vec::new();
+    let mut seen = std::collections::BTreeSet::new();
+    loop {
+        let key = read_bytes(data, offset)?;
+        if key.is_empty() {
+            return Ok(pairs);
+        }
+        let (key_type, _) = CompactSize::decode(&key)?;
+        let value = read_bytes(data, offset)?;
+        if !seen.insert(key.clone()) {
+            return Err(psbt_error("Duplicate key"));
+        }
+        pairs.push((key_type.0, key, value));
+    }
+}
+
+fn read_bytes(data: &[u8], offset: &mut usize) -> Result<Vec<u8>, BitcoinError> {
+    let rest = data
+        .get(*offset..)
+        .filter(|rest| !rest.is_empty())
+        .ok_or_else(|| psbt_error("Unexpected end of data"))?;
+    let (len, used) = CompactSize::decode(rest)?;
+    if len.0 > (rest.len() - used) as u64 {
+        return Err(psbt_error("Unexpected end of data"));
+    }
+    let start = *offset + used;
+    let end = start + len.0 as usize;
+    *offset = end;
+    Ok(data[start..end].to_vec())
+}
+
+fn expect_no_key_data(key: &[u8]) -> Result<(), BitcoinError> {
+    if key.len() != 1 {
+        return Err(psbt_error("Unexpected key data"));
+    }
+    Ok(())
+}
+
+fn parse_u32(value: &[u8]) -> Result<u32, BitcoinError> {
+    let bytes: [u8; 4] = value
+        .try_into()
+        .map_err(|_| psbt_error("Invalid 32-bit value"))?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn parse_count(value: &[u8]) -> Result<usize, BitcoinError> {
+    let (count, used) = CompactSize::decode(value)?;
+    if used != value.len() {
+        return Err(psbt_error("Invalid count"));
+    }
+    Ok(count.0 as usize)
+}
+
+fn parse_pubkey(key_data: &[u8]) -> Result<Vec<u8>, BitcoinError> {
+    PublicKey::from_slice(key_data)?;
+    Ok(key_data.to_vec())
+}
+
+// Transactions must fill the value exactly
+fn parse_tx(value: &[u8]) -> Result<LegacyTransaction, BitcoinError> {
+    let tx = LegacyTransaction::try_from(value)?;
+    if tx.serialize().len() != value.len() {
+        return Err(psbt_error("Invalid transaction"));
+    }
+    Ok(tx)
+}
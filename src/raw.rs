@@ -0,0 +1,488 @@
+// Borrowed views of serialized transactions. Parsing checks the whole
+// encoding up front but copies nothing; fields are decoded as they are read.
+
+use sha2::{Digest, Sha256};
+
+use crate::hashes::Hash256;
+use crate::{
+    read_array, read_bytes, Amount, BitcoinError, CompactSize, LegacyTransaction, LockTime,
+    OutPoint, ParseError, Sequence, TxInput, TxOutput, Txid, Witness, Wtxid,
+};
+
+// Input and output counts are recorded along with where each section starts,
+// so iterating never needs to re-check the encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionRef<'a> {
+    // Exactly the bytes of this transaction
+    data: &'a [u8],
+    // Offset of the input count, after the segwit marker and flag if any
+    counts_at: usize,
+    input_count: usize,
+    inputs_at: usize,
+    output_count: usize,
+    outputs_at: usize,
+    // End of the outputs: the witnesses start here, then the lock time
+    outputs_end: usize,
+    segwit: bool,
+}
+
+impl<'a> TransactionRef<'a> {
+    // Accepts exactly what LegacyTransaction::parse does, with the same
+    // errors, and likewise returns the number of bytes used
+    pub fn parse(data: &'a [u8]) -> Result<(Self, usize), BitcoinError> {
+        let (segwit, counts_at) = prefix(data)?;
+        let (input_count, inputs_at) = compact_size(data, counts_at)?;
+        let mut offset = inputs_at;
+        for _ in 0..input_count {
+            offset = skip_input(data, offset)?;
+        }
+        let (output_count, outputs_at) = compact_size(data, offset)?;
+        offset = outputs_at;
+        for _ in 0..output_count {
+            offset = skip_output(data, offset)?;
+        }
+        let outputs_end = offset;
+
+        if segwit {
+            let mut any_items = false;
+            for _ in 0..input_count {
+                let (items, end) = skip_witness(data, offset)?;
+                any_items |= items > 0;
+                offset = end;
+            }
+            if !any_items {
+                return Err(BitcoinError::Parse(ParseError::Message(
+                    "Superfluous witness record".to_string(),
+                )));
+            }
+        }
+        read_array::<4>(data, offset)?;
+        let size = offset + 4;
+
+        Ok((
+            TransactionRef {
+                data: &data[..size],
+                counts_at,
+                // Each input and output took at least one byte, so the
+                // counts fit in usize
+                input_count: input_count as usize,
+                inputs_at,
+                output_count: output_count as usize,
+                outputs_at,
+                outputs_end,
+                segwit,
+            },
+            size,
+        ))
+    }
+
+    // The serialized transaction
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn version(&self) -> i32 {
+        i32::from_le_bytes(self.data[..4].try_into().unwrap())
+    }
+
+    pub fn lock_time(&self) -> LockTime {
+        let at = self.data.len() - 4;
+        LockTime::from_consensus(u32::from_le_bytes(self.data[at..].try_into().unwrap()))
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.segwit
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.input_count == 1
+            && self
+                .inputs()
+                .next()
+                .is_some_and(|input| input.previous_output().is_null())
+    }
+
+    pub fn inputs(&self) -> Inputs<'a> {
+        Inputs {
+            data: self.data,
+            offset: self.inputs_at,
+            witness_offset: self.segwit.then_some(self.outputs_end),
+            remaining: self.input_count,
+        }
+    }
+
+    pub fn outputs(&self) -> Outputs<'a> {
+        Outputs {
+            data: self.data,
+            offset: self.outputs_at,
+            remaining: self.output_count,
+        }
+    }
+
+    // Hashes the non-witness parts in place rather than re-serializing
+    pub fn txid(&self) -> Txid {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.data[..4]);
+        hasher.update(&self.data[self.counts_at..self.outputs_end]);
+        hasher.update(&self.data[self.data.len() - 4..]);
+        Hash256::from_byte_array(Sha256::digest(hasher.finalize()).into()).into()
+    }
+
+    pub fn wtxid(&self) -> Wtxid {
+        crate::hashes::sha256d(self.data).into()
+    }
+
+    pub fn base_size(&self) -> usize {
+        4 + (self.outputs_end - self.counts_at) + 4
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn weight(&self) -> u64 {
+        (self.base_size() * 3 + self.total_size()) as u64
+    }
+
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+}
+
+impl From<TransactionRef<'_>> for LegacyTransaction {
+    fn from(tx: TransactionRef<'_>) -> Self {
+        LegacyTransaction {
+            version: tx.version(),
+            inputs: tx.inputs().map(TxInput::from).collect(),
+            outputs: tx.outputs().map(TxOutput::from).collect(),
+            lock_time: tx.lock_time(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxInputRef<'a> {
+    // Outpoint, script length, scriptSig and sequence
+    data: &'a [u8],
+    script_at: usize,
+    witness: WitnessRef<'a>,
+}
+
+impl<'a> TxInputRef<'a> {
+    pub fn previous_output(&self) -> OutPoint {
+        OutPoint::parse(self.data)
+            .expect("checked by TransactionRef::parse")
+            .0
+    }
+
+    pub fn script_sig(&self) -> &'a [u8] {
+        &self.data[self.script_at..self.data.len() - 4]
+    }
+
+    pub fn sequence(&self) -> Sequence {
+        let at = self.data.len() - 4;
+        Sequence::from_consensus(u32::from_le_bytes(self.data[at..].try_into().unwrap()))
+    }
+
+    // Empty for inputs of a transaction without witness data
+    pub fn witness(&self) -> WitnessRef<'a> {
+        self.witness
+    }
+}
+
+impl From<TxInputRef<'_>> for TxInput {
+    fn from(input: TxInputRef<'_>) -> Self {
+        TxInput {
+            previous_output: input.previous_output(),
+            script_sig: input.script_sig().to_vec(),
+            sequence: input.sequence(),
+            witness: input.witness().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxOutputRef<'a> {
+    // Value, script length and scriptPubKey
+    data: &'a [u8],
+    script_at: usize,
+}
+
+impl<'a> TxOutputRef<'a> {
+    // In satoshis
+    pub fn value(&self) -> Amount {
+        Amount::from_sat(u64::from_le_bytes(self.data[..8].try_into().unwrap()))
+    }
+
+    pub fn script_pubkey(&self) -> &'a [u8] {
+        &self.data[self.script_at..]
+    }
+}
+
+impl From<TxOutputRef<'_>> for TxOutput {
+    fn from(output: TxOutputRef<'_>) -> Self {
+        TxOutput {
+            value: output.value(),
+            script_pubkey: output.script_pubkey().to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WitnessRef<'a> {
+    // The items after the item count
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> WitnessRef<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> WitnessItems<'a> {
+        WitnessItems {
+            data: self.data,
+            remaining: self.len,
+        }
+    }
+}
+
+impl From<WitnessRef<'_>> for Witness {
+    fn from(witness: WitnessRef<'_>) -> Self {
+        Witness {
+            items: witness.iter().map(<[u8]>::to_vec).collect(),
+        }
+    }
+}
+
+pub struct Inputs<'a> {
+    data: &'a [u8],
+    offset: usize,
+    witness_offset: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Inputs<'a> {
+    type Item = TxInputRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let start = self.offset;
+        self.offset = skip_input(self.data, start).expect("checked by TransactionRef::parse");
+        let (_, script_at) =
+            compact_size(self.data, start + 36).expect("checked by TransactionRef::parse");
+        let witness = match self.witness_offset {
+            Some(offset) => {
+                let (len, items_at) =
+                    compact_size(self.data, offset).expect("checked by TransactionRef::parse");
+                let (_, end) =
+                    skip_witness(self.data, offset).expect("checked by TransactionRef::parse");
+                self.witness_offset = Some(end);
+                WitnessRef {
+                    data: &self.data[items_at..end],
+                    len: len as usize,
+                }
+            }
+            None => WitnessRef::default(),
+        };
+        Some(TxInputRef {
+            data: &self.data[start..self.offset],
+            script_at: script_at - start,
+            witness,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Inputs<'_> {}
+
+pub struct Outputs<'a> {
+    data: &'a [u8],
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Outputs<'a> {
+    type Item = TxOutputRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let start = self.offset;
+        self.offset = skip_output(self.data, start).expect("checked by TransactionRef::parse");
+        let (_, script_at) =
+            compact_size(self.data, start + 8).expect("checked by TransactionRef::parse");
+        Some(TxOutputRef {
+            data: &self.data[start..self.offset],
+            script_at: script_at - start,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Outputs<'_> {}
+
+pub struct WitnessItems<'a> {
+    data: &'a [u8],
+    remaining: usize,
+}
+
+impl<'a> Iterator for WitnessItems<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let (len, at) = compact_size(self.data, 0).expect("checked by TransactionRef::parse");
+        let (item, rest) = self.data[at..].split_at(len as usize);
+        self.data = rest;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for WitnessItems<'_> {}
+
+// Where each input or output of a serialized transaction lies, found by
+// following the length prefixes without decoding anything. Yields
+// `(offset, len)` pairs and stops after the first error.
+pub struct Regions<'a> {
+    data: &'a [u8],
+    offset: usize,
+    remaining: u64,
+    skip: fn(&[u8], usize) -> Result<usize, BitcoinError>,
+    // Found while locating the first region
+    error: Option<BitcoinError>,
+}
+
+impl<'a> Regions<'a> {
+    fn new(
+        data: &'a [u8],
+        start: Result<(u64, usize), BitcoinError>,
+        skip: fn(&[u8], usize) -> Result<usize, BitcoinError>,
+    ) -> Self {
+        let (remaining, offset, error) = match start {
+            Ok((count, offset)) => (count, offset, None),
+            Err(e) => (0, 0, Some(e)),
+        };
+        Regions {
+            data,
+            offset,
+            remaining,
+            skip,
+            error,
+        }
+    }
+}
+
+impl Iterator for Regions<'_> {
+    type Item = Result<(usize, usize), BitcoinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        if self.remaining == 0 {
+            return None;
+        }
+        match (self.skip)(self.data, self.offset) {
+            Ok(end) => {
+                let start = self.offset;
+                self.offset = end;
+                self.remaining -= 1;
+                Some(Ok((start, end - start)))
+            }
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Regions of the inputs of the transaction at the start of `data`
+pub fn iter_inputs(data: &[u8]) -> Regions<'_> {
+    let start = prefix(data).and_then(|(_, counts_at)| compact_size(data, counts_at));
+    Regions::new(data, start, skip_input)
+}
+
+// Regions of the outputs. The inputs are skipped over but not decoded.
+pub fn iter_outputs(data: &[u8]) -> Regions<'_> {
+    let start = prefix(data).and_then(|(_, counts_at)| {
+        let (input_count, mut offset) = compact_size(data, counts_at)?;
+        for _ in 0..input_count {
+            offset = skip_input(data, offset)?;
+        }
+        compact_size(data, offset)
+    });
+    Regions::new(data, start, skip_output)
+}
+
+// Whether the transaction uses the BIP141 format, and the offset of its
+// input count
+fn prefix(data: &[u8]) -> Result<(bool, usize), BitcoinError> {
+    read_array::<4>(data, 0)?;
+    let segwit = matches!(data.get(4..6), Some([0x00, flag]) if *flag != 0x00);
+    if segwit && data[5] != 0x01 {
+        return Err(BitcoinError::Parse(ParseError::InvalidField {
+            field: "segwit flag",
+            offset: 5,
+        }));
+    }
+    Ok((segwit, if segwit { 6 } else { 4 }))
+}
+
+// The value and the offset just after it. Errors carry offsets into `data`.
+fn compact_size(data: &[u8], offset: usize) -> Result<(u64, usize), BitcoinError> {
+    let (value, used) = CompactSize::decode(&data[offset..]).map_err(|e| e.offset_by(offset))?;
+    Ok((value.0, offset + used))
+}
+
+// The following return the offset just past what they skipped
+
+fn skip_input(data: &[u8], offset: usize) -> Result<usize, BitcoinError> {
+    read_bytes(data, offset, 36)?;
+    let (script_len, script_at) = compact_size(data, offset + 36)?;
+    let script = read_bytes(data, script_at, script_len)?;
+    let sequence_at = script_at + script.len();
+    read_array::<4>(data, sequence_at)?;
+    Ok(sequence_at + 4)
+}
+
+fn skip_output(data: &[u8], offset: usize) -> Result<usize, BitcoinError> {
+    read_array::<8>(data, offset)?;
+    let (script_len, script_at) = compact_size(data, offset + 8)?;
+    let script = read_bytes(data, script_at, script_len)?;
+    Ok(script_at + script.len())
+}
+
+// Also returns the number of items
+fn skip_witness(data: &[u8], offset: usize) -> Result<(u64, usize), BitcoinError> {
+    let (count, mut offset) = compact_size(data, offset)?;
+    for _ in 0..count {
+        let (len, at) = compact_size(data, offset)?;
+        offset = at + read_bytes(data, at, len)?.len();
+    }
+    Ok((count, offset))
+}
@@ -0,0 +1,310 @@
+// Interactive mode: one command per line, sharing keys and a draft
+// transaction between lines
+
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use crate::cli::{
+    format_error, invalid_argument, is_help, option, parse_cli_input, parse_cli_output,
+    CommandSpec, OptionKind, ParsedArgs,
+};
+use crate::json::Json;
+use crate::{
+    hex, parse_cli_args_with_config, parse_global_options, Address, BitcoinError, CliCommand,
+    Config, LegacyTransaction, LegacyTransactionBuilder, OutputFormat, PrivateKey, Sequence,
+    SigHashType, Wallet,
+};
+
+// Commands that only make sense within a session. Anything else is
+// handed to `parse_cli_args_with_config`.
+const SESSION_COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "load-key",
+        summary: "Keep a WIF private key for signing the draft",
+        positionals: &["wif"],
+        options: &[],
+    },
+    CommandSpec {
+        name: "add-input",
+        summary: "Add an input to the draft transaction",
+        positionals: &["txid:vout"],
+        options: &[],
+    },
+    CommandSpec {
+        name: "add-output",
+        summary: "Add an output to the draft transaction",
+        positionals: &["address:amount"],
+        options: &[],
+    },
+    CommandSpec {
+        name: "draft",
+        summary: "Show the draft transaction",
+        positionals: &[],
+        options: &[],
+    },
+    CommandSpec {
+        name: "sign-draft",
+        summary: "Sign a draft input with a loaded key",
+        positionals: &[],
+        options: &[
+            option("prevout-script", OptionKind::Required, "hex"),
+            option("input", OptionKind::Optional, "index"),
+            option("key", OptionKind::Optional, "index"),
+        ],
+    },
+    CommandSpec {
+        name: "watch",
+        summary: "Track the outputs paying to an address; balance reports them",
+        positionals: &["address"],
+        options: &[],
+    },
+    CommandSpec {
+        name: "scan",
+        summary: "Look through a transaction for watched outputs",
+        positionals: &["tx_hex"],
+        options: &[option("height", OptionKind::Optional, "height")],
+    },
+    CommandSpec {
+        name: "clear",
+        summary: "Start a new draft transaction",
+        positionals: &[],
+        options: &[],
+    },
+    CommandSpec {
+        name: "exit",
+        summary: "End the session; quit works too",
+        positionals: &[],
+        options: &[],
+    },
+];
+
+pub struct Repl {
+    // Used for lines without their own --json flag
+    format: OutputFormat,
+    config: Config,
+    keys: Vec<PrivateKey>,
+    draft: LegacyTransactionBuilder,
+    wallet: Wallet,
+}
+
+impl Repl {
+    pub fn new(format: OutputFormat) -> Self {
+        Repl {
+            format,
+            config: Config::default(),
+            keys: Vec::new(),
+            draft: LegacyTransactionBuilder::new(),
+            wallet: Wallet::new(),
+        }
+    }
+
+    // Settings for every line of the session
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn keys(&self) -> &[PrivateKey] {
+        &self.keys
+    }
+
+    pub fn draft(&self) -> &LegacyTransactionBuilder {
+        &self.draft
+    }
+
+    pub fn wallet(&self) -> &Wallet {
+        &self.wallet
+    }
+
+    // Usage text for the CLI commands and the session commands
+    pub fn usage() -> String {
+        let commands: Vec<String> = SESSION_COMMANDS
+            .iter()
+            .map(|spec| format!("  {}", spec.usage()))
+            .collect();
+        format!(
+            "{}\n\nSession commands:\n{}",
+            CliCommand::usage(),
+            commands.join("\n")
+        )
+    }
+
+    // Reads lines until end of input or `exit`. Errors are printed and the
+    // session carries on.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                break;
+            };
+            let format = self.line_format(&line);
+            match self.eval_line(&line) {
+                Ok(Some(text)) if text.is_empty() => {}
+                Ok(Some(text)) => writeln!(output, "{text}")?,
+                Ok(None) => break,
+                Err(e) => writeln!(output, "{}", format_error(&e, format))?,
+            }
+        }
+        Ok(())
+    }
+
+    // Output of one line, or None once the session is over
+    pub fn eval_line(&mut self, line: &str) -> Result<Option<String>, BitcoinError> {
+        // Global flags on the line apply to it alone
+        let (options, args) = parse_global_options(&split_line(line))?;
+        let format = match options.format {
+            OutputFormat::Json => OutputFormat::Json,
+            OutputFormat::Text => self.format,
+        };
+        let config = self.config.clone().apply(&options.overrides);
+        self.eval(&args, format, &config)
+    }
+
+    // Format for the result of `line`, even if it has invalid flags
+    fn line_format(&self, line: &str) -> OutputFormat {
+        if split_line(line).iter().any(|arg| arg == "--json") {
+            OutputFormat::Json
+        } else {
+            self.format
+        }
+    }
+
+    fn eval(
+        &mut self,
+        args: &[String],
+        format: OutputFormat,
+        config: &Config,
+    ) -> Result<Option<String>, BitcoinError> {
+        let Some(name) = args.first() else {
+            return Ok(Some(String::new()));
+        };
+        if name == "help" || is_help(name) {
+            return Ok(Some(match format {
+                OutputFormat::Text => Self::usage(),
+                OutputFormat::Json => Json::object([("usage", Self::usage().into())]).to_string(),
+            }));
+        }
+        if name == "quit" {
+            return Ok(None);
+        }
+        let Some(spec) = SESSION_COMMANDS.iter().find(|spec| spec.name == *name) else {
+            if name == "repl" {
+                return Err(invalid_argument("repl", "already in a session"));
+            }
+            return parse_cli_args_with_config(args, config)?
+                .run_with_wallet(format, Some(&self.wallet))
+                .map(Some);
+        };
+        let parsed = ParsedArgs::parse(spec, &args[1..])?;
+        let text = match spec.name {
+            "load-key" => {
+                let key = PrivateKey::from_wif(parsed.positional(0))?;
+                let public_key = hex::encode(&key.public_key().serialize());
+                self.keys.push(key);
+                let index = self.keys.len() - 1;
+                match format {
+                    OutputFormat::Text => format!("key {index}: {public_key}"),
+                    OutputFormat::Json => Json::object([
+                        ("index", (index as u64).into()),
+                        ("public_key", public_key.into()),
+                    ])
+                    .to_string(),
+                }
+            }
+            "add-input" => {
+                let input = parse_cli_input(parsed.positional(0), Sequence::MAX)?;
+                self.draft = mem::take(&mut self.draft).add_input(input);
+                self.draft_summary(format)
+            }
+            "add-output" => {
+                let output = parse_cli_output(parsed.positional(0))?;
+                self.draft = mem::take(&mut self.draft).add_output(output);
+                self.draft_summary(format)
+            }
+            "draft" => CliCommand::Decode {
+                tx: self.draft.clone().build(),
+            }
+            .run_as(format)?,
+            "sign-draft" => {
+                let input_index = parse_index(&parsed, "input")?;
+                let key_index = parse_index(&parsed, "key")?;
+                let key = self
+                    .keys
+                    .get(key_index)
+                    .ok_or_else(|| invalid_argument("--key", "no key loaded at that index"))?;
+                let prevout_script =
+                    hex::decode(parsed.value("prevout-script").unwrap_or_default())?;
+                let mut tx = self.draft.clone().build();
+                tx.sign_input(input_index, key, &prevout_script, SigHashType::All)?;
+                // Later inputs are signed on top of this one
+                self.draft.inputs = tx.inputs.clone();
+                CliCommand::CreateTx { tx }.run_as(format)?
+            }
+            "watch" => {
+                let address: Address = parsed.positional(0).parse()?;
+                self.wallet.watch_address(&address);
+                let watched = self.wallet.watched_scripts() as u64;
+                match format {
+                    OutputFormat::Text => format!("watching {watched} scripts"),
+                    OutputFormat::Json => Json::object([("watching", watched.into())]).to_string(),
+                }
+            }
+            "scan" => {
+                let tx: LegacyTransaction = parsed.positional(0).parse()?;
+                let height =
+                    match parsed.value("height") {
+                        Some(height) => Some(height.parse().map_err(|_| {
+                            invalid_argument("--height", "expected a block height")
+                        })?),
+                        None => None,
+                    };
+                let relevant = self.wallet.scan_transaction(&tx, height);
+                let unspent = self.wallet.list_unspent().len() as u64;
+                match format {
+                    OutputFormat::Text if relevant => format!("{unspent} unspent outputs"),
+                    OutputFormat::Text => "no watched outputs".to_string(),
+                    OutputFormat::Json => {
+                        Json::object([("relevant", relevant.into()), ("unspent", unspent.into())])
+                            .to_string()
+                    }
+                }
+            }
+            "clear" => {
+                self.draft = LegacyTransactionBuilder::new();
+                self.draft_summary(format)
+            }
+            "exit" => return Ok(None),
+            _ => unreachable!("every command in SESSION_COMMANDS is handled"),
+        };
+        Ok(Some(text))
+    }
+
+    fn draft_summary(&self, format: OutputFormat) -> String {
+        let (inputs, outputs) = (self.draft.inputs.len(), self.draft.outputs.len());
+        match format {
+            OutputFormat::Text => format!("draft: {inputs} inputs, {outputs} outputs"),
+            OutputFormat::Json => Json::object([
+                ("inputs", (inputs as u64).into()),
+                ("outputs", (outputs as u64).into()),
+            ])
+            .to_string(),
+        }
+    }
+}
+
+// Arguments are separated by whitespace; there is no quoting
+fn split_line(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_string).collect()
+}
+
+// Optional index option, defaulting to the first item
+fn parse_index(parsed: &ParsedArgs, name: &str) -> Result<usize, BitcoinError> {
+    match parsed.value(name) {
+        Some(index) => index
+            .parse()
+            .map_err(|_| invalid_argument(&format!("--{name}"), "expected an index")),
+        None => Ok(0),
+    }
+}
@@ -0,0 +1,1206 @@
+use std::ops::BitOr;
+
+use sha1::{Digest, Sha1};
+
+use super::{
+    encode_script_num, instructions, is_p2sh, is_push_only, witness_program, Instruction, Opcode,
+    Script, ScriptBuilder,
+};
+use crate::sighash::{TapScriptSpend, TAPROOT_SIGHASH_DEFAULT};
+use crate::taproot::{tap_branch_hash, tap_leaf_hash, tweak_public_key, TAPSCRIPT_LEAF_VERSION};
+use crate::{hashes, read_array, BitcoinError, Hash160, ScriptError, Sequence, Witness};
+
+// Consensus limits on script execution
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_STACK_SIZE: usize = 1000;
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+
+// Disables the relative lock check in OP_CHECKSEQUENCEVERIFY (BIP112)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = Sequence::LOCK_TIME_DISABLE_FLAG as i64;
+
+// BIP341 control blocks: the leaf version and internal key, then up to 128
+// merkle path hashes
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_MAX_NODES: usize = 128;
+const ANNEX_TAG: u8 = 0x50;
+
+// BIP342 signature budget: each signature checked costs 50, and a script
+// gets 50 plus its witness size
+const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+// Verification flags, named after Bitcoin Core's SCRIPT_VERIFY_* constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScriptFlags(u32);
+
+impl ScriptFlags {
+    pub const NONE: ScriptFlags = ScriptFlags(0);
+    pub const P2SH: ScriptFlags = ScriptFlags(1 << 0);
+    pub const STRICTENC: ScriptFlags = ScriptFlags(1 << 1);
+    pub const DERSIG: ScriptFlags = ScriptFlags(1 << 2);
+    pub const LOW_S: ScriptFlags = ScriptFlags(1 << 3);
+    pub const NULLDUMMY: ScriptFlags = ScriptFlags(1 << 4);
+    pub const SIGPUSHONLY: ScriptFlags = ScriptFlags(1 << 5);
+    pub const MINIMALDATA: ScriptFlags = ScriptFlags(1 << 6);
+    pub const DISCOURAGE_UPGRADABLE_NOPS: ScriptFlags = ScriptFlags(1 << 7);
+    pub const CLEANSTACK: ScriptFlags = ScriptFlags(1 << 8);
+    pub const CHECKLOCKTIMEVERIFY: ScriptFlags = ScriptFlags(1 << 9);
+    pub const CHECKSEQUENCEVERIFY: ScriptFlags = ScriptFlags(1 << 10);
+    pub const WITNESS: ScriptFlags = ScriptFlags(1 << 11);
+    pub const NULLFAIL: ScriptFlags = ScriptFlags(1 << 14);
+    pub const TAPROOT: ScriptFlags = ScriptFlags(1 << 17);
+
+    // Soft-forked rules that every block must follow
+    pub const CONSENSUS: ScriptFlags = ScriptFlags(
+        Self::P2SH.0
+            | Self::DERSIG.0
+            | Self::NULLDUMMY.0
+            | Self::CHECKLOCKTIMEVERIFY.0
+            | Self::CHECKSEQUENCEVERIFY.0
+            | Self::WITNESS.0
+            | Self::TAPROOT.0,
+    );
+
+    // Rules nodes apply before relaying a transaction
+    pub const STANDARD: ScriptFlags = ScriptFlags(
+        Self::CONSENSUS.0
+            | Self::STRICTENC.0
+            | Self::LOW_S.0
+            | Self::MINIMALDATA.0
+            | Self::DISCOURAGE_UPGRADABLE_NOPS.0
+            | Self::CLEANSTACK.0
+            | Self::NULLFAIL.0,
+    );
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: ScriptFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for ScriptFlags {
+    type Output = ScriptFlags;
+
+    fn bitor(self, rhs: ScriptFlags) -> ScriptFlags {
+        ScriptFlags(self.0 | rhs.0)
+    }
+}
+
+// Supplies the transaction context the interpreter can't see on its own.
+// `script_code` is the part of the script being signed, with signatures removed.
+pub trait SignatureChecker {
+    fn check_signature(&self, signature: &[u8], pubkey: &[u8], script_code: &[u8]) -> bool;
+
+    // The BIP143 form of check_signature, for segwit v0 scripts
+    fn check_witness_v0_signature(
+        &self,
+        _signature: &[u8],
+        _pubkey: &[u8],
+        _script_code: &[u8],
+    ) -> bool {
+        false
+    }
+
+    // A BIP340 signature, with its hash type byte unless SIGHASH_DEFAULT.
+    // Key-path spends pass no `script_path`; `annex` includes its 0x50 prefix.
+    fn check_schnorr_signature(
+        &self,
+        _signature: &[u8],
+        _pubkey: &[u8; 32],
+        _annex: Option<&[u8]>,
+        _script_path: Option<&TapScriptSpend>,
+    ) -> bool {
+        false
+    }
+
+    fn check_lock_time(&self, _lock_time: i64) -> bool {
+        false
+    }
+
+    fn check_sequence(&self, _sequence: i64) -> bool {
+        false
+    }
+}
+
+// Which rules a script runs under, after Bitcoin Core's SigVersion
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+    Tapscript(Tapscript),
+}
+
+// What a tapscript's signatures commit to, and the budget they share
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tapscript {
+    leaf_hash: [u8; 32],
+    annex: Option<Vec<u8>>,
+    validation_weight: i64,
+}
+
+// Executes scripts: legacy ones, and with the WITNESS and TAPROOT flags
+// segwit v0 and tapscript ones
+pub struct Interpreter<'a> {
+    flags: ScriptFlags,
+    checker: &'a dyn SignatureChecker,
+    sig_version: SigVersion,
+}
+
+type Stack = Vec<Vec<u8>>;
+
+impl<'a> Interpreter<'a> {
+    pub fn new(flags: ScriptFlags, checker: &'a dyn SignatureChecker) -> Self {
+        Interpreter {
+            flags,
+            checker,
+            sig_version: SigVersion::Base,
+        }
+    }
+
+    // Runs scriptSig then scriptPubKey, and the redeem script for P2SH spends
+    pub fn verify(&self, script_sig: &[u8], script_pubkey: &[u8]) -> Result<(), BitcoinError> {
+        self.verify_with_witness(script_sig, script_pubkey, &Witness::new())
+    }
+
+    // verify for an input with a witness, which runs after the scripts when
+    // scriptPubKey or the redeem script is a witness program
+    pub fn verify_with_witness(
+        &self,
+        script_sig: &[u8],
+        script_pubkey: &[u8],
+        witness: &Witness,
+    ) -> Result<(), BitcoinError> {
+        if self.flags.contains(ScriptFlags::SIGPUSHONLY) && !is_push_only(script_sig) {
+            return Err(ScriptError::SigPushOnly.into());
+        }
+
+        let mut stack = Stack::new();
+        self.eval(script_sig, &mut stack)?;
+        let stack_copy = self
+            .flags
+            .contains(ScriptFlags::P2SH)
+            .then(|| stack.clone());
+        self.eval(script_pubkey, &mut stack)?;
+        if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+            return Err(ScriptError::EvalFalse.into());
+        }
+
+        let segwit = self.flags.contains(ScriptFlags::WITNESS);
+        let mut had_witness = false;
+        if let Some((version, program)) = witness_program(script_pubkey).filter(|_| segwit) {
+            had_witness = true;
+            if !script_sig.is_empty() {
+                return Err(ScriptError::WitnessMalleated.into());
+            }
+            self.verify_witness_program(witness, version, program, false)?;
+            // A single element stands in for the witness's for CLEANSTACK
+            stack.truncate(1);
+        }
+
+        if let Some(mut stack_copy) = stack_copy.filter(|_| is_p2sh(script_pubkey)) {
+            if !is_push_only(script_sig) {
+                return Err(ScriptError::SigPushOnly.into());
+            }
+            // scriptPubKey succeeded, so scriptSig must have pushed the redeem script
+            let redeem_script = stack_copy.pop().ok_or(ScriptError::EvalFalse)?;
+            self.eval(&redeem_script, &mut stack_copy)?;
+            if !stack_copy.last().is_some_and(|top| cast_to_bool(top)) {
+                return Err(ScriptError::EvalFalse.into());
+            }
+            stack = stack_copy;
+            if let Some((version, program)) = witness_program(&redeem_script).filter(|_| segwit) {
+                had_witness = true;
+                // Anything besides the redeem script would be malleable
+                if script_sig != ScriptBuilder::new().push_bytes(&redeem_script).build() {
+                    return Err(ScriptError::WitnessMalleatedP2sh.into());
+                }
+                self.verify_witness_program(witness, version, program, true)?;
+                stack.truncate(1);
+            }
+        }
+
+        // CLEANSTACK is only meaningful together with P2SH
+        if self.flags.contains(ScriptFlags::CLEANSTACK)
+            && self.flags.contains(ScriptFlags::P2SH)
+            && stack.len() != 1
+        {
+            return Err(ScriptError::CleanStack.into());
+        }
+        if segwit && !had_witness && !witness.is_empty() {
+            return Err(ScriptError::WitnessUnexpected.into());
+        }
+        Ok(())
+    }
+
+    fn verify_witness_program(
+        &self,
+        witness: &Witness,
+        version: u8,
+        program: &[u8],
+        is_p2sh: bool,
+    ) -> Result<(), BitcoinError> {
+        let mut stack = witness.items.clone();
+        match (version, program.len()) {
+            // P2WSH: the script comes last, after its arguments
+            (0, 32) => {
+                let script = stack.pop().ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+                if hashes::sha256(&script).as_bytes()[..] != *program {
+                    return Err(ScriptError::WitnessProgramMismatch.into());
+                }
+                self.execute_witness_script(SigVersion::WitnessV0, &script, stack)
+            }
+            // P2WPKH: a signature and pubkey for the P2PKH script of the hash
+            (0, 20) => {
+                if stack.len() != 2 {
+                    return Err(ScriptError::WitnessProgramMismatch.into());
+                }
+                let script = Script::new_p2pkh(&Hash160::from_byte_array(read_array(program, 0)?));
+                self.execute_witness_script(SigVersion::WitnessV0, script.as_bytes(), stack)
+            }
+            (0, _) => Err(ScriptError::WitnessProgramWrongLength.into()),
+            (1, 32) if !is_p2sh && self.flags.contains(ScriptFlags::TAPROOT) => {
+                self.verify_taproot(witness, &read_array(program, 0)?)
+            }
+            // Other versions and sizes are left for future soft forks
+            _ => Ok(()),
+        }
+    }
+
+    fn verify_taproot(&self, witness: &Witness, output_key: &[u8; 32]) -> Result<(), BitcoinError> {
+        let mut stack = witness.items.clone();
+        if stack.is_empty() {
+            return Err(ScriptError::WitnessProgramWitnessEmpty.into());
+        }
+        let annex = match stack.last() {
+            Some(last) if stack.len() >= 2 && last.first() == Some(&ANNEX_TAG) => stack.pop(),
+            _ => None,
+        };
+        if let [signature] = stack.as_slice() {
+            return self.check_schnorr_signature(signature, output_key, annex.as_deref(), None);
+        }
+
+        // Script path: the arguments, the leaf script and the control block
+        let (Some(control), Some(script)) = (stack.pop(), stack.pop()) else {
+            return Err(ScriptError::WitnessProgramWitnessEmpty.into());
+        };
+        let path_len = control.len().wrapping_sub(TAPROOT_CONTROL_BASE_SIZE);
+        if control.len() < TAPROOT_CONTROL_BASE_SIZE
+            || !path_len.is_multiple_of(32)
+            || path_len / 32 > TAPROOT_CONTROL_MAX_NODES
+        {
+            return Err(ScriptError::TaprootWrongControlSize.into());
+        }
+        let leaf_version = control[0] & 0xFE;
+        let leaf_hash = tap_leaf_hash(&script, leaf_version);
+        let mut node = leaf_hash;
+        for offset in (TAPROOT_CONTROL_BASE_SIZE..control.len()).step_by(32) {
+            node = tap_branch_hash(&node, &read_array(&control, offset)?);
+        }
+        let internal_key = read_array(&control, 1)?;
+        let committed = tweak_public_key(&internal_key, Some(node))
+            .is_ok_and(|(key, odd)| key == *output_key && odd == (control[0] & 1 == 1));
+        if !committed {
+            return Err(ScriptError::WitnessProgramMismatch.into());
+        }
+        // Unknown leaf versions are left for future soft forks
+        if leaf_version != TAPSCRIPT_LEAF_VERSION {
+            return Ok(());
+        }
+        let tapscript = Tapscript {
+            leaf_hash,
+            annex,
+            validation_weight: VALIDATION_WEIGHT_OFFSET + witness.serialize().len() as i64,
+        };
+        self.execute_witness_script(SigVersion::Tapscript(tapscript), &script, stack)
+    }
+
+    // Witness scripts must leave exactly one element, and a true one
+    fn execute_witness_script(
+        &self,
+        sig_version: SigVersion,
+        script: &[u8],
+        mut stack: Stack,
+    ) -> Result<(), BitcoinError> {
+        if let SigVersion::Tapscript(_) = sig_version {
+            // OP_SUCCESSx makes a tapscript succeed unexecuted, so that soft
+            // forks can give those opcodes meaning
+            for instruction in instructions(script) {
+                match instruction.map_err(|_| ScriptError::BadOpcode)? {
+                    Instruction::Op(opcode) if is_op_success(opcode) => return Ok(()),
+                    _ => {}
+                }
+            }
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::StackSize.into());
+            }
+        }
+        if stack
+            .iter()
+            .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+        {
+            return Err(ScriptError::PushSize.into());
+        }
+        let interpreter = Interpreter {
+            flags: self.flags,
+            checker: self.checker,
+            sig_version,
+        };
+        interpreter.eval(script, &mut stack)?;
+        if stack.len() != 1 {
+            return Err(ScriptError::CleanStack.into());
+        }
+        if !cast_to_bool(&stack[0]) {
+            return Err(ScriptError::EvalFalse.into());
+        }
+        Ok(())
+    }
+
+    // Executes a single script against the given stack
+    pub fn eval(&self, script: &[u8], stack: &mut Stack) -> Result<(), BitcoinError> {
+        let tapscript = match &self.sig_version {
+            SigVersion::Tapscript(tapscript) => Some(tapscript),
+            _ => None,
+        };
+        // Tapscript drops the size and opcode limits for the signature budget
+        if script.len() > MAX_SCRIPT_SIZE && tapscript.is_none() {
+            return Err(ScriptError::ScriptSize.into());
+        }
+        let require_minimal = self.flags.contains(ScriptFlags::MINIMALDATA);
+        let mut exec_stack: Vec<bool> = Vec::new();
+        let mut altstack = Stack::new();
+        let mut op_count = 0;
+        let mut code_start = 0;
+        let mut validation_weight = tapscript.map_or(0, |tapscript| tapscript.validation_weight);
+        // Tapscript signatures commit to the last OP_CODESEPARATOR by its
+        // index among the script's opcodes
+        let mut opcode_position = 0;
+        let mut code_separator_position = u32::MAX;
+
+        let mut iter = instructions(script);
+        while let Some(instruction) = iter.next() {
+            let position = opcode_position;
+            opcode_position += 1;
+            let executing = exec_stack.iter().all(|e| *e);
+            let instruction = instruction.map_err(|_| ScriptError::BadOpcode)?;
+
+            let opcode = match instruction {
+                Instruction::PushBytes(opcode, data) => {
+                    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(ScriptError::PushSize.into());
+                    }
+                    if executing {
+                        if require_minimal && !is_minimal_push(opcode, data) {
+                            return Err(ScriptError::MinimalData.into());
+                        }
+                        stack.push(data.to_vec());
+                    }
+                    check_stack_size(stack, &altstack)?;
+                    continue;
+                }
+                Instruction::Op(opcode) => opcode,
+            };
+
+            if opcode > Opcode::OP_16 as u8 && tapscript.is_none() {
+                op_count += 1;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ScriptError::OpCount.into());
+                }
+            }
+            let op = Opcode::from_u8(opcode);
+            if op.is_some_and(is_disabled) {
+                return Err(ScriptError::DisabledOpcode.into());
+            }
+
+            let conditional = (Opcode::OP_IF as u8..=Opcode::OP_ENDIF as u8).contains(&opcode);
+            if !executing && !conditional {
+                continue;
+            }
+            let op = op.ok_or(ScriptError::BadOpcode)?;
+
+            match op {
+                Opcode::OP_1NEGATE
+                | Opcode::OP_1
+                | Opcode::OP_2
+                | Opcode::OP_3
+                | Opcode::OP_4
+                | Opcode::OP_5
+                | Opcode::OP_6
+                | Opcode::OP_7
+                | Opcode::OP_8
+                | Opcode::OP_9
+                | Opcode::OP_10
+                | Opcode::OP_11
+                | Opcode::OP_12
+                | Opcode::OP_13
+                | Opcode::OP_14
+                | Opcode::OP_15
+                | Opcode::OP_16 => {
+                    stack.push(encode_script_num(opcode as i64 - 0x50));
+                }
+
+                Opcode::OP_NOP => {}
+
+                Opcode::OP_CHECKLOCKTIMEVERIFY => {
+                    if !self.flags.contains(ScriptFlags::CHECKLOCKTIMEVERIFY) {
+                        self.upgradable_nop()?;
+                    } else {
+                        let lock_time = decode_script_num(top(stack, 1)?, require_minimal, 5)?;
+                        if lock_time < 0 {
+                            return Err(ScriptError::NegativeLockTime.into());
+                        }
+                        if !self.checker.check_lock_time(lock_time) {
+                            return Err(ScriptError::UnsatisfiedLockTime.into());
+                        }
+                    }
+                }
+
+                Opcode::OP_CHECKSEQUENCEVERIFY => {
+                    if !self.flags.contains(ScriptFlags::CHECKSEQUENCEVERIFY) {
+                        self.upgradable_nop()?;
+                    } else {
+                        let sequence = decode_script_num(top(stack, 1)?, require_minimal, 5)?;
+                        if sequence < 0 {
+                            return Err(ScriptError::NegativeLockTime.into());
+                        }
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                            && !self.checker.check_sequence(sequence)
+                        {
+                            return Err(ScriptError::UnsatisfiedLockTime.into());
+                        }
+                    }
+                }
+
+                Opcode::OP_NOP1
+                | Opcode::OP_NOP4
+                | Opcode::OP_NOP5
+                | Opcode::OP_NOP6
+                | Opcode::OP_NOP7
+                | Opcode::OP_NOP8
+                | Opcode::OP_NOP9
+                | Opcode::OP_NOP10 => self.upgradable_nop()?,
+
+                Opcode::OP_IF | Opcode::OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        let condition = stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                        if tapscript.is_some() && !(condition.is_empty() || condition == [1]) {
+                            return Err(ScriptError::TapscriptMinimalIf.into());
+                        }
+                        value = cast_to_bool(&condition);
+                        if op == Opcode::OP_NOTIF {
+                            value = !value;
+                        }
+                    }
+                    exec_stack.push(value);
+                }
+
+                Opcode::OP_ELSE => {
+                    let last = exec_stack
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *last = !*last;
+                }
+
+                Opcode::OP_ENDIF => {
+                    exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+
+                Opcode::OP_VERIFY => {
+                    if !cast_to_bool(top(stack, 1)?) {
+                        return Err(ScriptError::Verify.into());
+                    }
+                    stack.pop();
+                }
+
+                Opcode::OP_RETURN => return Err(ScriptError::OpReturn.into()),
+
+                // Stack operations
+                Opcode::OP_TOALTSTACK => {
+                    altstack.push(pop(stack)?);
+                }
+
+                Opcode::OP_FROMALTSTACK => {
+                    let item = altstack
+                        .pop()
+                        .ok_or(ScriptError::InvalidAltstackOperation)?;
+                    stack.push(item);
+                }
+
+                Opcode::OP_2DROP => {
+                    require(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                }
+
+                Opcode::OP_2DUP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.extend_from_within(len - 2..);
+                }
+
+                Opcode::OP_3DUP => {
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    stack.extend_from_within(len - 3..);
+                }
+
+                Opcode::OP_2OVER => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.extend_from_within(len - 4..len - 2);
+                }
+
+                Opcode::OP_2ROT => {
+                    require(stack, 6)?;
+                    let len = stack.len();
+                    let moved: Vec<_> = stack.drain(len - 6..len - 4).collect();
+                    stack.extend(moved);
+                }
+
+                Opcode::OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                }
+
+                Opcode::OP_IFDUP => {
+                    let item = top(stack, 1)?.clone();
+                    if cast_to_bool(&item) {
+                        stack.push(item);
+                    }
+                }
+
+                Opcode::OP_DEPTH => {
+                    stack.push(encode_script_num(stack.len() as i64));
+                }
+
+                Opcode::OP_DROP => {
+                    pop(stack)?;
+                }
+
+                Opcode::OP_DUP => {
+                    let item = top(stack, 1)?.clone();
+                    stack.push(item);
+                }
+
+                Opcode::OP_NIP => {
+                    require(stack, 2)?;
+                    stack.remove(stack.len() - 2);
+                }
+
+                Opcode::OP_OVER => {
+                    let item = top(stack, 2)?.clone();
+                    stack.push(item);
+                }
+
+                Opcode::OP_PICK | Opcode::OP_ROLL => {
+                    let n = decode_script_num(&pop(stack)?, require_minimal, 4)?;
+                    if n < 0 || n as usize >= stack.len() {
+                        return Err(ScriptError::InvalidStackOperation.into());
+                    }
+                    let index = stack.len() - 1 - n as usize;
+                    let item = if op == Opcode::OP_ROLL {
+                        stack.remove(index)
+                    } else {
+                        stack[index].clone()
+                    };
+                    stack.push(item);
+                }
+
+                Opcode::OP_ROT => {
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    stack.swap(len - 3, len - 2);
+                    stack.swap(len - 2, len - 1);
+                }
+
+                Opcode::OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+
+                Opcode::OP_TUCK => {
+                    require(stack, 2)?;
+                    let item = top(stack, 1)?.clone();
+                    stack.insert(stack.len() - 2, item);
+                }
+
+                Opcode::OP_SIZE => {
+                    let size = top(stack, 1)?.len();
+                    stack.push(encode_script_num(size as i64));
+                }
+
+                // Bitwise logic
+                Opcode::OP_EQUAL | Opcode::OP_EQUALVERIFY => {
+                    let b = pop2(stack)?;
+                    let equal = b.0 == b.1;
+                    if op == Opcode::OP_EQUALVERIFY {
+                        if !equal {
+                            return Err(ScriptError::EqualVerify.into());
+                        }
+                    } else {
+                        stack.push(bool_bytes(equal));
+                    }
+                }
+
+                // Numeric
+                Opcode::OP_1ADD
+                | Opcode::OP_1SUB
+                | Opcode::OP_NEGATE
+                | Opcode::OP_ABS
+                | Opcode::OP_NOT
+                | Opcode::OP_0NOTEQUAL => {
+                    let n = decode_script_num(&pop(stack)?, require_minimal, 4)?;
+                    let result = match op {
+                        Opcode::OP_1ADD => n + 1,
+                        Opcode::OP_1SUB => n - 1,
+                        Opcode::OP_NEGATE => -n,
+                        Opcode::OP_ABS => n.abs(),
+                        Opcode::OP_NOT => (n == 0) as i64,
+                        _ => (n != 0) as i64,
+                    };
+                    stack.push(encode_script_num(result));
+                }
+
+                Opcode::OP_ADD
+                | Opcode::OP_SUB
+                | Opcode::OP_BOOLAND
+                | Opcode::OP_BOOLOR
+                | Opcode::OP_NUMEQUAL
+                | Opcode::OP_NUMEQUALVERIFY
+                | Opcode::OP_NUMNOTEQUAL
+                | Opcode::OP_LESSTHAN
+                | Opcode::OP_GREATERTHAN
+                | Opcode::OP_LESSTHANOREQUAL
+                | Opcode::OP_GREATERTHANOREQUAL
+                | Opcode::OP_MIN
+                | Opcode::OP_MAX => {
+                    let (a, b) = pop2(stack)?;
+                    let a = decode_script_num(&a, require_minimal, 4)?;
+                    let b = decode_script_num(&b, require_minimal, 4)?;
+                    let result = match op {
+                        Opcode::OP_ADD => a + b,
+                        Opcode::OP_SUB => a - b,
+                        Opcode::OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        Opcode::OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        Opcode::OP_NUMEQUAL | Opcode::OP_NUMEQUALVERIFY => (a == b) as i64,
+                        Opcode::OP_NUMNOTEQUAL => (a != b) as i64,
+                        Opcode::OP_LESSTHAN => (a < b) as i64,
+                        Opcode::OP_GREATERTHAN => (a > b) as i64,
+                        Opcode::OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        Opcode::OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        Opcode::OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    };
+                    if op == Opcode::OP_NUMEQUALVERIFY {
+                        if result == 0 {
+                            return Err(ScriptError::NumEqualVerify.into());
+                        }
+                    } else {
+                        stack.push(encode_script_num(result));
+                    }
+                }
+
+                Opcode::OP_WITHIN => {
+                    require(stack, 3)?;
+                    let max = decode_script_num(&pop(stack)?, require_minimal, 4)?;
+                    let min = decode_script_num(&pop(stack)?, require_minimal, 4)?;
+                    let x = decode_script_num(&pop(stack)?, require_minimal, 4)?;
+                    stack.push(bool_bytes(min <= x && x < max));
+                }
+
+                // Crypto
+                Opcode::OP_RIPEMD160
+                | Opcode::OP_SHA1
+                | Opcode::OP_SHA256
+                | Opcode::OP_HASH160
+                | Opcode::OP_HASH256 => {
+                    let item = pop(stack)?;
+                    let hash = match op {
+                        Opcode::OP_RIPEMD160 => hashes::ripemd160(&item).to_vec(),
+                        Opcode::OP_SHA1 => Sha1::digest(&item).to_vec(),
+                        Opcode::OP_SHA256 => hashes::sha256(&item).as_bytes().to_vec(),
+                        Opcode::OP_HASH160 => hashes::hash160(&item).as_bytes().to_vec(),
+                        _ => hashes::sha256d(&item).as_bytes().to_vec(),
+                    };
+                    stack.push(hash);
+                }
+
+                Opcode::OP_CODESEPARATOR => {
+                    code_start = iter.position();
+                    code_separator_position = position;
+                }
+
+                Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY => {
+                    require(stack, 2)?;
+                    let pubkey = &stack[stack.len() - 1];
+                    let signature = &stack[stack.len() - 2];
+                    let success = match tapscript {
+                        Some(tapscript) => self.check_tapscript_signature(
+                            signature,
+                            pubkey,
+                            tapscript,
+                            code_separator_position,
+                            &mut validation_weight,
+                        )?,
+                        None => {
+                            let script_code = self.script_code(&script[code_start..], signature);
+                            self.check_signature_encoding(signature)?;
+                            self.check_pubkey_encoding(pubkey)?;
+                            let success =
+                                self.check_ecdsa_signature(signature, pubkey, &script_code);
+                            if !success
+                                && self.flags.contains(ScriptFlags::NULLFAIL)
+                                && !signature.is_empty()
+                            {
+                                return Err(ScriptError::NullFail.into());
+                            }
+                            success
+                        }
+                    };
+                    stack.truncate(stack.len() - 2);
+                    if op == Opcode::OP_CHECKSIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckSigVerify.into());
+                        }
+                    } else {
+                        stack.push(bool_bytes(success));
+                    }
+                }
+
+                // BIP342's batchable replacement for CHECKMULTISIG:
+                // <sig> <n> <pubkey> becomes n + 1 if the signature is valid
+                Opcode::OP_CHECKSIGADD => {
+                    let Some(tapscript) = tapscript else {
+                        return Err(ScriptError::BadOpcode.into());
+                    };
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    let n = decode_script_num(&stack[len - 2], require_minimal, 4)?;
+                    let success = self.check_tapscript_signature(
+                        &stack[len - 3],
+                        &stack[len - 1],
+                        tapscript,
+                        code_separator_position,
+                        &mut validation_weight,
+                    )?;
+                    stack.truncate(len - 3);
+                    stack.push(encode_script_num(n + i64::from(success)));
+                }
+
+                Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY => {
+                    if tapscript.is_some() {
+                        return Err(ScriptError::TapscriptCheckMultisig.into());
+                    }
+                    let success =
+                        self.check_multisig(stack, &script[code_start..], &mut op_count)?;
+                    if op == Opcode::OP_CHECKMULTISIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckMultisigVerify.into());
+                        }
+                    } else {
+                        stack.push(bool_bytes(success));
+                    }
+                }
+
+                _ => return Err(ScriptError::BadOpcode.into()),
+            }
+
+            check_stack_size(stack, &altstack)?;
+        }
+
+        if !exec_stack.is_empty() {
+            return Err(ScriptError::UnbalancedConditional.into());
+        }
+        Ok(())
+    }
+
+    // Stack layout, top last: <dummy> <sig>... <m> <pubkey>... <n>
+    fn check_multisig(
+        &self,
+        stack: &mut Stack,
+        script_code: &[u8],
+        op_count: &mut usize,
+    ) -> Result<bool, BitcoinError> {
+        let require_minimal = self.flags.contains(ScriptFlags::MINIMALDATA);
+        let key_count = decode_script_num(top(stack, 1)?, require_minimal, 4)?;
+        if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&key_count) {
+            return Err(ScriptError::PubkeyCount.into());
+        }
+        let key_count = key_count as usize;
+        *op_count += key_count;
+        if *op_count > MAX_OPS_PER_SCRIPT {
+            return Err(ScriptError::OpCount.into());
+        }
+        let sig_count = decode_script_num(top(stack, key_count + 2)?, require_minimal, 4)?;
+        if sig_count < 0 || sig_count as usize > key_count {
+            return Err(ScriptError::SigCount.into());
+        }
+        let sig_count = sig_count as usize;
+        // Pubkeys and signatures plus both counts and the dummy element
+        let total = key_count + sig_count + 3;
+        require(stack, total)?;
+
+        let len = stack.len();
+        let keys: Vec<&Vec<u8>> = (0..key_count).map(|i| &stack[len - 2 - i]).collect();
+        let sigs: Vec<&Vec<u8>> = (0..sig_count)
+            .map(|i| &stack[len - key_count - 3 - i])
+            .collect();
+
+        let mut script_code = script_code.to_vec();
+        for sig in &sigs {
+            script_code = self.script_code(&script_code, sig);
+        }
+
+        // Signatures must appear in the same order as their pubkeys
+        let mut success = true;
+        let (mut isig, mut ikey) = (0, 0);
+        while success && isig < sig_count {
+            let (sig, key) = (sigs[isig], keys[ikey]);
+            self.check_signature_encoding(sig)?;
+            self.check_pubkey_encoding(key)?;
+            if self.check_ecdsa_signature(sig, key, &script_code) {
+                isig += 1;
+            }
+            ikey += 1;
+            if sig_count - isig > key_count - ikey {
+                success = false;
+            }
+        }
+
+        if !success
+            && self.flags.contains(ScriptFlags::NULLFAIL)
+            && sigs.iter().any(|sig| !sig.is_empty())
+        {
+            return Err(ScriptError::NullFail.into());
+        }
+
+        // The extra element consumed by the historical off-by-one bug
+        let dummy = &stack[len - total];
+        if self.flags.contains(ScriptFlags::NULLDUMMY) && !dummy.is_empty() {
+            return Err(ScriptError::SigNullDummy.into());
+        }
+        stack.truncate(len - total);
+        Ok(success)
+    }
+
+    // Legacy signatures can't sign themselves, so they are removed from
+    // the script they sign; BIP143 dropped that
+    fn script_code(&self, script: &[u8], signature: &[u8]) -> Vec<u8> {
+        match self.sig_version {
+            SigVersion::Base => find_and_delete(script, signature),
+            _ => script.to_vec(),
+        }
+    }
+
+    fn check_ecdsa_signature(&self, signature: &[u8], pubkey: &[u8], script_code: &[u8]) -> bool {
+        match self.sig_version {
+            SigVersion::Base => self.checker.check_signature(signature, pubkey, script_code),
+            _ => self
+                .checker
+                .check_witness_v0_signature(signature, pubkey, script_code),
+        }
+    }
+
+    // An empty signature fails the check; unlike in CHECKSIG elsewhere, any
+    // other invalid one fails the script
+    fn check_tapscript_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        tapscript: &Tapscript,
+        code_separator_position: u32,
+        validation_weight: &mut i64,
+    ) -> Result<bool, BitcoinError> {
+        if pubkey.is_empty() {
+            return Err(ScriptError::TapscriptEmptyPubkey.into());
+        }
+        if signature.is_empty() {
+            return Ok(false);
+        }
+        *validation_weight -= VALIDATION_WEIGHT_PER_SIGOP;
+        if *validation_weight < 0 {
+            return Err(ScriptError::TapscriptValidationWeight.into());
+        }
+        // Other sizes are key types for future soft forks, valid for now
+        let Ok(pubkey) = <&[u8; 32]>::try_from(pubkey) else {
+            return Ok(true);
+        };
+        let script_path = TapScriptSpend {
+            leaf_hash: tapscript.leaf_hash,
+            code_separator_position,
+        };
+        self.check_schnorr_signature(
+            signature,
+            pubkey,
+            tapscript.annex.as_deref(),
+            Some(&script_path),
+        )?;
+        Ok(true)
+    }
+
+    fn check_schnorr_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8; 32],
+        annex: Option<&[u8]>,
+        script_path: Option<&TapScriptSpend>,
+    ) -> Result<(), BitcoinError> {
+        match signature.len() {
+            64 => {}
+            // SIGHASH_DEFAULT is only ever implicit
+            65 if signature[64] != TAPROOT_SIGHASH_DEFAULT => {}
+            65 => return Err(ScriptError::SchnorrSigHashType.into()),
+            _ => return Err(ScriptError::SchnorrSigSize.into()),
+        }
+        if !self
+            .checker
+            .check_schnorr_signature(signature, pubkey, annex, script_path)
+        {
+            return Err(ScriptError::SchnorrSig.into());
+        }
+        Ok(())
+    }
+
+    fn upgradable_nop(&self) -> Result<(), BitcoinError> {
+        if self.flags.contains(ScriptFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+            return Err(ScriptError::DiscourageUpgradableNops.into());
+        }
+        Ok(())
+    }
+
+    // Empty signatures are always allowed as a compact way to fail a check
+    fn check_signature_encoding(&self, signature: &[u8]) -> Result<(), BitcoinError> {
+        if signature.is_empty() {
+            return Ok(());
+        }
+        let strict = ScriptFlags::DERSIG | ScriptFlags::LOW_S | ScriptFlags::STRICTENC;
+        if self.flags.0 & strict.0 != 0 && !is_valid_signature_encoding(signature) {
+            return Err(ScriptError::SigDer.into());
+        }
+        if self.flags.contains(ScriptFlags::LOW_S) && !is_low_s(signature) {
+            return Err(ScriptError::SigHighS.into());
+        }
+        if self.flags.contains(ScriptFlags::STRICTENC) {
+            let hash_type = signature[signature.len() - 1] & !0x80;
+            if !(0x01..=0x03).contains(&hash_type) {
+                return Err(ScriptError::SigHashType.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_pubkey_encoding(&self, pubkey: &[u8]) -> Result<(), BitcoinError> {
+        if self.flags.contains(ScriptFlags::STRICTENC) {
+            let valid = match pubkey.first() {
+                Some(0x02) | Some(0x03) => pubkey.len() == 33,
+                Some(0x04) => pubkey.len() == 65,
+                _ => false,
+            };
+            if !valid {
+                return Err(ScriptError::PubkeyType.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn require(stack: &Stack, n: usize) -> Result<(), ScriptError> {
+    if stack.len() < n {
+        return Err(ScriptError::InvalidStackOperation);
+    }
+    Ok(())
+}
+
+// The n-th element from the top, starting at 1
+fn top(stack: &Stack, n: usize) -> Result<&Vec<u8>, ScriptError> {
+    require(stack, n)?;
+    Ok(&stack[stack.len() - n])
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+// Pops the top two elements, returned in stack order (deeper one first)
+fn pop2(stack: &mut Stack) -> Result<(Vec<u8>, Vec<u8>), ScriptError> {
+    require(stack, 2)?;
+    let b = pop(stack)?;
+    let a = pop(stack)?;
+    Ok((a, b))
+}
+
+fn check_stack_size(stack: &Stack, altstack: &Stack) -> Result<(), ScriptError> {
+    if stack.len() + altstack.len() > MAX_STACK_SIZE {
+        return Err(ScriptError::StackSize);
+    }
+    Ok(())
+}
+
+fn bool_bytes(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        Vec::new()
+    }
+}
+
+// Opcodes BIP342 reserves for upgrades in tapscript
+fn is_op_success(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254
+    )
+}
+
+fn is_disabled(op: Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::OP_CAT
+            | Opcode::OP_SUBSTR
+            | Opcode::OP_LEFT
+            | Opcode::OP_RIGHT
+            | Opcode::OP_INVERT
+            | Opcode::OP_AND
+            | Opcode::OP_OR
+            | Opcode::OP_XOR
+            | Opcode::OP_2MUL
+            | Opcode::OP_2DIV
+            | Opcode::OP_MUL
+            | Opcode::OP_DIV
+            | Opcode::OP_MOD
+            | Opcode::OP_LSHIFT
+            | Opcode::OP_RSHIFT
+    )
+}
+
+// Any non-zero byte makes the value true, except a lone sign bit (negative zero)
+pub fn cast_to_bool(data: &[u8]) -> bool {
+    for (i, byte) in data.iter().enumerate() {
+        if *byte != 0 {
+            return !(i == data.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+pub fn decode_script_num(
+    data: &[u8],
+    require_minimal: bool,
+    max_len: usize,
+) -> Result<i64, ScriptError> {
+    if data.len() > max_len {
+        return Err(ScriptError::InvalidNumber);
+    }
+    if require_minimal {
+        if let Some(last) = data.last() {
+            // The last byte may only be 0x00/0x80 if it is needed for the sign bit
+            if last & 0x7F == 0 && (data.len() == 1 || data[data.len() - 2] & 0x80 == 0) {
+                return Err(ScriptError::InvalidNumber);
+            }
+        }
+    }
+    let Some(last) = data.last() else {
+        return Ok(0);
+    };
+    let mut result: i64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        result |= (*byte as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        let sign_bit = 0x80i64 << (8 * (data.len() - 1));
+        return Ok(-(result & !sign_bit));
+    }
+    Ok(result)
+}
+
+fn is_minimal_push(opcode: u8, data: &[u8]) -> bool {
+    match data.len() {
+        0 => opcode == Opcode::OP_0 as u8,
+        1 if (1..=16).contains(&data[0]) => false,
+        1 if data[0] == 0x81 => false,
+        len if len <= 75 => opcode as usize == len,
+        len if len <= 0xFF => opcode == Opcode::OP_PUSHDATA1 as u8,
+        len if len <= 0xFFFF => opcode == Opcode::OP_PUSHDATA2 as u8,
+        _ => true,
+    }
+}
+
+// Removes every push of `signature` from the script code, as legacy signing
+// can't commit to the signature itself
+fn find_and_delete(script: &[u8], signature: &[u8]) -> Vec<u8> {
+    if signature.is_empty() {
+        return script.to_vec();
+    }
+    let pattern = super::ScriptBuilder::new().push_bytes(signature).build();
+    let mut result = Vec::with_capacity(script.len());
+    let mut iter = instructions(script);
+    let mut start = 0;
+    while let Some(instruction) = iter.next() {
+        let end = iter.position();
+        if instruction.is_err() || script[start..end] != pattern[..] {
+            result.extend(&script[start..end]);
+        }
+        start = end;
+    }
+    result
+}
+
+// Strict DER check from BIP66; the trailing byte is the sighash type
+pub fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+// Half the curve order; S values above it are malleable
+const HALF_ORDER: [u8; 32] = [
+    0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0x5D, 0x57, 0x6E, 0x73, 0x57, 0xA4, 0x50, 0x1D, 0xDF, 0xE9, 0x2F, 0x46, 0x68, 0x1B, 0x20, 0xA0,
+];
+
+fn is_low_s(sig: &[u8]) -> bool {
+    if !is_valid_signature_encoding(sig) {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    let len_s = sig[5 + len_r] as usize;
+    let s = &sig[6 + len_r..6 + len_r + len_s];
+    let s = &s[s.iter().take_while(|b| **b == 0).count()..];
+    if s.len() > 32 {
+        return false;
+    }
+    let mut padded = [0u8; 32];
+    padded[32 - s.len()..].copy_from_slice(s);
+    padded <= HALF_ORDER
+}
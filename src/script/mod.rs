@@ -0,0 +1,542 @@
+// Helpers for building and inspecting scripts
+
+mod interpreter;
+mod opcodes;
+
+pub use interpreter::{
+    is_valid_signature_encoding, Interpreter, ScriptFlags, SignatureChecker, MAX_SCRIPT_SIZE,
+};
+pub use opcodes::Opcode;
+
+pub(crate) use interpreter::decode_script_num;
+
+use std::ops::Deref;
+
+use crate::{hashes, hex, BitcoinError, Hash160, Hash256, PublicKey, ScriptError, UsageError};
+
+// Owned script bytes, e.g. a scriptPubKey built from one of the standard templates
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Script(Vec<u8>);
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // OP_DUP OP_HASH160 <pubkey_hash> OP_EQUALVERIFY OP_CHECKSIG
+    pub fn new_p2pkh(pubkey_hash: &Hash160) -> Self {
+        ScriptBuilder::new()
+            .push_opcode(Opcode::OP_DUP)
+            .push_opcode(Opcode::OP_HASH160)
+            .push_bytes(pubkey_hash.as_bytes())
+            .push_opcode(Opcode::OP_EQUALVERIFY)
+            .push_opcode(Opcode::OP_CHECKSIG)
+            .into_script()
+    }
+
+    // OP_HASH160 <script_hash> OP_EQUAL
+    pub fn new_p2sh(script_hash: &Hash160) -> Self {
+        ScriptBuilder::new()
+            .push_opcode(Opcode::OP_HASH160)
+            .push_bytes(script_hash.as_bytes())
+            .push_opcode(Opcode::OP_EQUAL)
+            .into_script()
+    }
+
+    // OP_0 <20-byte pubkey hash>
+    pub fn new_p2wpkh(pubkey_hash: &Hash160) -> Self {
+        Self::new_witness_program(0, pubkey_hash.as_bytes())
+    }
+
+    // OP_0 <32-byte sha256 of the witness script>
+    pub fn new_p2wsh(script_hash: &Hash256) -> Self {
+        Self::new_witness_program(0, script_hash.as_bytes())
+    }
+
+    // OP_m <keys> OP_n OP_CHECKMULTISIG, keys in the order given. Needs
+    // 1 <= m <= n <= 20.
+    pub fn new_multisig(required: usize, keys: &[PublicKey]) -> Result<Self, BitcoinError> {
+        if required == 0 || required > keys.len() || keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(BitcoinError::Usage(UsageError::InvalidMultisig {
+                required,
+                keys: keys.len(),
+            }));
+        }
+        let builder = ScriptBuilder::new().push_int(required as i64);
+        Ok(keys
+            .iter()
+            .fold(builder, |builder, key| builder.push_bytes(&key.serialize()))
+            .push_int(keys.len() as i64)
+            .push_opcode(Opcode::OP_CHECKMULTISIG)
+            .into_script())
+    }
+
+    // new_multisig with the keys sorted by their encoding (BIP67), so every
+    // party derives the same script from the same set of keys
+    pub fn new_sorted_multisig(required: usize, keys: &[PublicKey]) -> Result<Self, BitcoinError> {
+        let mut keys = keys.to_vec();
+        keys.sort_by_key(PublicKey::serialize);
+        Self::new_multisig(required, &keys)
+    }
+
+    // P2SH output paying to this script as the redeem script
+    pub fn to_p2sh(&self) -> Self {
+        Self::new_p2sh(&hashes::hash160(&self.0))
+    }
+
+    // P2WSH output paying to this script as the witness script
+    pub fn to_p2wsh(&self) -> Self {
+        Self::new_p2wsh(&hashes::sha256(&self.0))
+    }
+
+    // OP_RETURN <data>, a provably unspendable output
+    pub fn new_op_return(data: &[u8]) -> Self {
+        ScriptBuilder::new()
+            .push_opcode(Opcode::OP_RETURN)
+            .push_bytes(data)
+            .into_script()
+    }
+
+    // Version 0 uses OP_0, versions 1-16 use OP_1..OP_16
+    pub fn new_witness_program(version: u8, program: &[u8]) -> Self {
+        ScriptBuilder::new()
+            .push_int(version as i64)
+            .push_bytes(program)
+            .into_script()
+    }
+
+    // Human-readable form matching the `asm` field of Bitcoin Core's RPCs
+    pub fn to_asm(&self) -> String {
+        script_to_asm(&self.0, false)
+    }
+
+    // As to_asm, but signatures are shown with their sighash type, e.g. "[ALL]",
+    // the way Core renders scriptSigs
+    pub fn to_asm_decode_sighash(&self) -> String {
+        script_to_asm(&self.0, true)
+    }
+
+    // See the count_sigops function
+    pub fn count_sigops(&self, accurate: bool) -> usize {
+        count_sigops(&self.0, accurate)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl Deref for Script {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(bytes: Vec<u8>) -> Self {
+        Script(bytes)
+    }
+}
+
+impl From<&[u8]> for Script {
+    fn from(bytes: &[u8]) -> Self {
+        Script(bytes.to_vec())
+    }
+}
+
+impl From<Script> for Vec<u8> {
+    fn from(script: Script) -> Self {
+        script.0
+    }
+}
+
+// A single parsed script element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    // Data push together with the opcode byte that introduced it
+    PushBytes(u8, &'a [u8]),
+    Op(u8),
+}
+
+// Walks a script one instruction at a time; a truncated push yields an
+// error and ends the iteration
+pub struct Instructions<'a> {
+    script: &'a [u8],
+    pos: usize,
+}
+
+pub fn instructions(script: &[u8]) -> Instructions<'_> {
+    Instructions { script, pos: 0 }
+}
+
+impl<'a> Instructions<'a> {
+    // Offset of the next instruction within the script
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn fail(&mut self) -> Result<Instruction<'a>, BitcoinError> {
+        self.pos = self.script.len();
+        Err(BitcoinError::Script(ScriptError::BadOpcode))
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, BitcoinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let opcode = *self.script.get(self.pos)?;
+        let rest = &self.script[self.pos + 1..];
+        let (prefix_len, data_len) = match opcode {
+            0x00..=0x4B => (0, opcode as usize),
+            0x4C => match rest.first() {
+                Some(len) => (1, *len as usize),
+                None => return Some(self.fail()),
+            },
+            0x4D => match rest.get(..2) {
+                Some(len) => (2, u16::from_le_bytes([len[0], len[1]]) as usize),
+                None => return Some(self.fail()),
+            },
+            0x4E => match rest.get(..4) {
+                Some(len) => (
+                    4,
+                    u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize,
+                ),
+                None => return Some(self.fail()),
+            },
+            _ => {
+                self.pos += 1;
+                return Some(Ok(Instruction::Op(opcode)));
+            }
+        };
+        match rest.get(prefix_len..prefix_len + data_len) {
+            Some(data) => {
+                self.pos += 1 + prefix_len + data_len;
+                Some(Ok(Instruction::PushBytes(opcode, data)))
+            }
+            None => Some(self.fail()),
+        }
+    }
+}
+
+fn script_to_asm(script: &[u8], decode_sighash: bool) -> String {
+    let unspendable = is_unspendable(script);
+    let mut parts = Vec::new();
+    for instruction in instructions(script) {
+        match instruction {
+            // Short pushes are shown as numbers, as Core does
+            Ok(Instruction::PushBytes(_, data)) if data.len() <= 4 => {
+                let n = interpreter::decode_script_num(data, false, 4).unwrap_or_default();
+                parts.push(n.to_string());
+            }
+            Ok(Instruction::PushBytes(_, data)) => {
+                let sighash = decode_sighash && !unspendable && is_valid_signature_encoding(data);
+                let name = sighash
+                    .then(|| sighash_type_name(data[data.len() - 1]))
+                    .flatten();
+                match name {
+                    Some(name) => parts.push(format!(
+                        "{}[{}]",
+                        hex::encode(&data[..data.len() - 1]),
+                        name
+                    )),
+                    None => parts.push(hex::encode(data)),
+                }
+            }
+            Ok(Instruction::Op(op)) => parts.push(opcode_asm_name(op)),
+            Err(_) => {
+                parts.push("[error]".to_string());
+                break;
+            }
+        }
+    }
+    parts.join(" ")
+}
+
+fn opcode_asm_name(op: u8) -> String {
+    match Opcode::from_u8(op) {
+        Some(Opcode::OP_1NEGATE) => "-1".to_string(),
+        Some(op) => match op.small_int() {
+            Some(n) => n.to_string(),
+            None => op.name().to_string(),
+        },
+        None => "OP_UNKNOWN".to_string(),
+    }
+}
+
+fn sighash_type_name(hash_type: u8) -> Option<&'static str> {
+    match hash_type {
+        0x01 => Some("ALL"),
+        0x02 => Some("NONE"),
+        0x03 => Some("SINGLE"),
+        0x81 => Some("ALL|ANYONECANPAY"),
+        0x82 => Some("NONE|ANYONECANPAY"),
+        0x83 => Some("SINGLE|ANYONECANPAY"),
+        _ => None,
+    }
+}
+
+// Output script templates recognised by Bitcoin Core
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    P2PK,
+    P2PKH,
+    P2SH,
+    P2WPKH,
+    P2WSH,
+    P2TR,
+    // Bare m-of-n multisig
+    Multisig,
+    OpReturn,
+    NonStandard,
+}
+
+impl ScriptType {
+    pub fn classify(script: &[u8]) -> ScriptType {
+        if let Some((version, program)) = witness_program(script) {
+            return match (version, program.len()) {
+                (0, 20) => ScriptType::P2WPKH,
+                (0, 32) => ScriptType::P2WSH,
+                (1, 32) => ScriptType::P2TR,
+                _ => ScriptType::NonStandard,
+            };
+        }
+        if is_p2sh(script) {
+            ScriptType::P2SH
+        } else if is_p2pkh(script) {
+            ScriptType::P2PKH
+        } else if p2pk_pubkey(script).is_some() {
+            ScriptType::P2PK
+        } else if is_bare_multisig(script) {
+            ScriptType::Multisig
+        } else if script.first() == Some(&(Opcode::OP_RETURN as u8)) && is_push_only(&script[1..]) {
+            ScriptType::OpReturn
+        } else {
+            ScriptType::NonStandard
+        }
+    }
+}
+
+impl Script {
+    pub fn script_type(&self) -> ScriptType {
+        ScriptType::classify(&self.0)
+    }
+}
+
+// Version and program of a segwit output: <OP_0..OP_16> <2-40 byte push>
+pub fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 || script[1] as usize != script.len() - 2 {
+        return None;
+    }
+    let version = match script[0] {
+        0x00 => 0,
+        op @ 0x51..=0x60 => op - 0x50,
+        _ => return None,
+    };
+    Some((version, &script[2..]))
+}
+
+// OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+pub fn is_p2pkh(script: &[u8]) -> bool {
+    script.len() == 25
+        && script[..3] == [0x76, 0xA9, 0x14]
+        && script[23] == 0x88
+        && script[24] == 0xAC
+}
+
+// <pubkey> OP_CHECKSIG, returning the pubkey
+pub fn p2pk_pubkey(script: &[u8]) -> Option<&[u8]> {
+    let (last, rest) = script.split_last()?;
+    if *last != Opcode::OP_CHECKSIG as u8 {
+        return None;
+    }
+    let (len, pubkey) = rest.split_first()?;
+    let valid = match (*len, pubkey.first()) {
+        (33, Some(0x02 | 0x03)) => pubkey.len() == 33,
+        (65, Some(0x04 | 0x06 | 0x07)) => pubkey.len() == 65,
+        _ => false,
+    };
+    valid.then_some(pubkey)
+}
+
+// OP_m <pubkeys> OP_n OP_CHECKMULTISIG, with 1 <= m <= n
+pub fn is_bare_multisig(script: &[u8]) -> bool {
+    bare_multisig_counts(script).is_some()
+}
+
+// The required signatures and key count of a bare multisig script, (m, n)
+pub fn bare_multisig_counts(script: &[u8]) -> Option<(usize, usize)> {
+    let small_int = |ins: Option<Result<Instruction, BitcoinError>>| match ins {
+        Some(Ok(Instruction::Op(op))) => Opcode::from_u8(op)
+            .and_then(Opcode::small_int)
+            .map(usize::from),
+        _ => None,
+    };
+    let (&last, rest) = script.split_last()?;
+    if last != Opcode::OP_CHECKMULTISIG as u8 {
+        return None;
+    }
+    let mut iter = instructions(rest);
+    let required = small_int(iter.next())?;
+    let mut keys = 0;
+    loop {
+        match iter.next() {
+            Some(Ok(Instruction::PushBytes(_, key))) if matches!(key.len(), 33 | 65) => keys += 1,
+            next => {
+                let valid = small_int(next) == Some(keys)
+                    && (1..=keys).contains(&required)
+                    && iter.next().is_none();
+                return valid.then_some((required, keys));
+            }
+        }
+    }
+}
+
+// True if the script only pushes data (OP_RESERVED counts, as in Core)
+pub fn is_push_only(script: &[u8]) -> bool {
+    instructions(script).all(|ins| match ins {
+        Ok(Instruction::PushBytes(..)) => true,
+        Ok(Instruction::Op(op)) => op <= Opcode::OP_16 as u8,
+        Err(_) => false,
+    })
+}
+
+// Outputs no script can ever satisfy: OP_RETURN data carriers and scripts
+// over the size limit
+pub fn is_unspendable(script: &[u8]) -> bool {
+    script.first() == Some(&(Opcode::OP_RETURN as u8)) || script.len() > MAX_SCRIPT_SIZE
+}
+
+// Most keys an OP_CHECKMULTISIG can take, and what one counts as when the
+// number of keys isn't known: the interpreter's limit, as a count
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = interpreter::MAX_PUBKEYS_PER_MULTISIG as usize;
+
+// Signature operations in the script, as Core's GetSigOpCount. Inaccurate
+// counting charges every multisig the maximum; accurate counting reads the
+// key count from the preceding OP_1..OP_16. Counting stops at a malformed
+// push.
+pub fn count_sigops(script: &[u8], accurate: bool) -> usize {
+    let mut count = 0;
+    let mut last_op = None;
+    for instruction in instructions(script) {
+        let Ok(instruction) = instruction else {
+            break;
+        };
+        if let Instruction::Op(op) = instruction {
+            match Opcode::from_u8(op) {
+                Some(Opcode::OP_CHECKSIG | Opcode::OP_CHECKSIGVERIFY) => count += 1,
+                Some(Opcode::OP_CHECKMULTISIG | Opcode::OP_CHECKMULTISIGVERIFY) => {
+                    count += match last_op.and_then(Opcode::small_int) {
+                        Some(keys) if accurate => keys as usize,
+                        // Without a key count, charge the most keys allowed
+                        _ => MAX_PUBKEYS_PER_MULTISIG,
+                    }
+                }
+                _ => {}
+            }
+        }
+        last_op = match instruction {
+            Instruction::Op(op) => Opcode::from_u8(op),
+            Instruction::PushBytes(..) => None,
+        };
+    }
+    count
+}
+
+// OP_HASH160 <20 bytes> OP_EQUAL
+pub fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23 && script[0] == 0xA9 && script[1] == 0x14 && script[22] == 0x87
+}
+
+// Witness v1 output: OP_1 <32-byte x-only output key>
+pub fn p2tr(output_key: &[u8; 32]) -> Vec<u8> {
+    ScriptBuilder::new()
+        .push_opcode(Opcode::OP_1)
+        .push_bytes(output_key)
+        .build()
+}
+
+// Minimal little-endian sign-magnitude encoding used for script numbers
+pub fn encode_script_num(n: i64) -> Vec<u8> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let negative = n < 0;
+    let mut abs = n.unsigned_abs();
+    let mut v = Vec::new();
+    while abs > 0 {
+        v.push((abs & 0xFF) as u8);
+        abs >>= 8;
+    }
+    // The top bit carries the sign, so add a byte if it's already taken
+    if v[v.len() - 1] & 0x80 != 0 {
+        v.push(if negative { 0x80 } else { 0x00 });
+    } else if negative {
+        let last = v.len() - 1;
+        v[last] |= 0x80;
+    }
+    v
+}
+
+// Builds scripts with correctly encoded pushes
+#[derive(Debug, Clone, Default)]
+pub struct ScriptBuilder {
+    bytes: Vec<u8>,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_opcode(mut self, opcode: Opcode) -> Self {
+        self.bytes.push(opcode as u8);
+        self
+    }
+
+    // Uses the smallest push prefix for the data length, as Bitcoin Core does
+    pub fn push_bytes(mut self, data: &[u8]) -> Self {
+        let len = data.len();
+        if len < Opcode::OP_PUSHDATA1 as usize {
+            self.bytes.push(len as u8);
+        } else if len <= 0xFF {
+            self.bytes.push(Opcode::OP_PUSHDATA1 as u8);
+            self.bytes.push(len as u8);
+        } else if len <= 0xFFFF {
+            self.bytes.push(Opcode::OP_PUSHDATA2 as u8);
+            self.bytes.extend(&(len as u16).to_le_bytes());
+        } else {
+            self.bytes.push(Opcode::OP_PUSHDATA4 as u8);
+            self.bytes.extend(&(len as u32).to_le_bytes());
+        }
+        self.bytes.extend(data);
+        self
+    }
+
+    // Small integers use OP_1NEGATE/OP_0..OP_16, everything else a script number push
+    pub fn push_int(mut self, n: i64) -> Self {
+        match n {
+            -1 => self.push_opcode(Opcode::OP_1NEGATE),
+            0 => self.push_opcode(Opcode::OP_0),
+            1..=16 => {
+                self.bytes.push(Opcode::OP_1 as u8 + (n as u8 - 1));
+                self
+            }
+            _ => self.push_bytes(&encode_script_num(n)),
+        }
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn into_script(self) -> Script {
+        Script(self.bytes)
+    }
+}
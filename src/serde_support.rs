@@ -0,0 +1,71 @@
+// Helpers for the serde feature: byte fields are written as hex strings
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer};
+
+// Deserializes a string through the type's FromStr impl
+pub(crate) fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let s: std::borrow::Cow<'de, str> = de::Deserialize::deserialize(deserializer)?;
+    s.parse().map_err(de::Error::custom)
+}
+
+// For `#[serde(with = ...)]` on a Vec<u8>
+pub(crate) mod hex_bytes {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::Serializer;
+
+    use crate::hex;
+
+    pub(crate) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let s = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+        hex::decode(&s).map_err(de::Error::custom)
+    }
+}
+
+// For `#[serde(with = ...)]` on a Vec<Vec<u8>>, as a list of hex strings
+pub(crate) mod hex_byte_vecs {
+    use serde::de::{Deserialize, Deserializer};
+    use serde::ser::{SerializeSeq, Serializer};
+
+    use super::HexBytes;
+    use crate::hex;
+
+    pub(crate) fn serialize<S: Serializer>(
+        items: &[Vec<u8>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(items.len()))?;
+        for item in items {
+            seq.serialize_element(&hex::encode(item))?;
+        }
+        seq.end()
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        let items = Vec::<HexBytes>::deserialize(deserializer)?;
+        Ok(items.into_iter().map(|item| item.0).collect())
+    }
+}
+
+struct HexBytes(Vec<u8>);
+
+impl<'de> de::Deserialize<'de> for HexBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        hex_bytes::deserialize(deserializer).map(HexBytes)
+    }
+}
@@ -0,0 +1,508 @@
+// Signature hash types and the digests that signatures commit to
+
+use std::cell::OnceCell;
+
+use crate::script::{instructions, Instruction};
+use crate::taproot::{tap_leaf_hash, TAPSCRIPT_LEAF_VERSION};
+use crate::{
+    hashes, Amount, BitcoinError, CompactSize, CryptoError, Hash256, LegacyTransaction, Opcode,
+    ParseError, TxOutput, UsageError,
+};
+
+// Which parts of the transaction a signature commits to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SigHashType {
+    All = 0x01,
+    None = 0x02,
+    Single = 0x03,
+    AllPlusAnyoneCanPay = 0x81,
+    NonePlusAnyoneCanPay = 0x82,
+    SinglePlusAnyoneCanPay = 0x83,
+}
+
+impl SigHashType {
+    pub fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0x01 => Some(SigHashType::All),
+            0x02 => Some(SigHashType::None),
+            0x03 => Some(SigHashType::Single),
+            0x81 => Some(SigHashType::AllPlusAnyoneCanPay),
+            0x82 => Some(SigHashType::NonePlusAnyoneCanPay),
+            0x83 => Some(SigHashType::SinglePlusAnyoneCanPay),
+            _ => None,
+        }
+    }
+}
+
+const SIGHASH_ALL: u32 = 0x01;
+const SIGHASH_NONE: u32 = 0x02;
+const SIGHASH_SINGLE: u32 = 0x03;
+const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+impl LegacyTransaction {
+    // Digest a pre-segwit signature on `input_index` commits to. `sighash_type` is
+    // the raw byte appended to the signature, so non-standard values hash the
+    // same way Bitcoin Core hashes them.
+    pub fn signature_hash(
+        &self,
+        input_index: usize,
+        script_code: &[u8],
+        sighash_type: u32,
+    ) -> Result<Hash256, BitcoinError> {
+        legacy(self, input_index, script_code, sighash_type)
+    }
+}
+
+// Returned for SIGHASH_SINGLE without a matching output. Consensus treats this
+// as the message being signed rather than an error.
+fn one() -> Hash256 {
+    let mut bytes = [0u8; 32];
+    bytes[0] = 1;
+    Hash256::from_byte_array(bytes)
+}
+
+// OP_CODESEPARATORs are never part of the signed script code. Anything after
+// an unparseable push is kept as-is.
+fn strip_code_separators(script: &[u8]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(script.len());
+    let mut iter = instructions(script);
+    let mut start = 0;
+    while let Some(instruction) = iter.next() {
+        match instruction {
+            Ok(Instruction::Op(op)) if op == Opcode::OP_CODESEPARATOR as u8 => {}
+            Ok(_) => v.extend(&script[start..iter.position()]),
+            Err(_) => {
+                v.extend(&script[start..]);
+                break;
+            }
+        }
+        start = iter.position();
+    }
+    v
+}
+
+// Pre-segwit digest: the transaction with every other scriptSig emptied, the
+// signed input's scriptSig replaced by `script_code`, followed by the hash type
+pub(crate) fn legacy(
+    tx: &LegacyTransaction,
+    input_index: usize,
+    script_code: &[u8],
+    hash_type: u32,
+) -> Result<Hash256, BitcoinError> {
+    SighashCache::new(tx).legacy_signature_hash(input_index, script_code, hash_type)
+}
+
+// BIP143 hashPrevouts/hashSequence/hashOutputs. They only depend on the
+// transaction, so compute them once and reuse them for every input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegwitV0Midstates {
+    pub hash_prevouts: Hash256,
+    pub hash_sequence: Hash256,
+    pub hash_outputs: Hash256,
+}
+
+impl SegwitV0Midstates {
+    pub fn new(tx: &LegacyTransaction) -> Self {
+        let mut prevouts = Vec::with_capacity(tx.inputs.len() * 36);
+        let mut sequences = Vec::with_capacity(tx.inputs.len() * 4);
+        for input in &tx.inputs {
+            prevouts.extend(input.previous_output.serialize());
+            sequences.extend(&input.sequence.to_consensus_u32().to_le_bytes());
+        }
+        let mut outputs = Vec::new();
+        for output in &tx.outputs {
+            outputs.extend(output.serialize());
+        }
+        SegwitV0Midstates {
+            hash_prevouts: hashes::sha256d(&prevouts),
+            hash_sequence: hashes::sha256d(&sequences),
+            hash_outputs: hashes::sha256d(&outputs),
+        }
+    }
+
+    // BIP143 digest for `input_index` spending an output worth `value`.
+    // `script_code` is given without its length prefix.
+    pub fn signature_hash(
+        &self,
+        tx: &LegacyTransaction,
+        input_index: usize,
+        script_code: &[u8],
+        value: Amount,
+        sighash_type: u32,
+    ) -> Result<Hash256, BitcoinError> {
+        let input = tx.inputs.get(input_index).ok_or(BitcoinError::Usage(
+            UsageError::InputIndexOutOfRange(input_index),
+        ))?;
+        let base_type = sighash_type & 0x1F;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let zero = Hash256::from_byte_array([0; 32]);
+
+        let hash_prevouts = if anyone_can_pay {
+            zero
+        } else {
+            self.hash_prevouts
+        };
+        let hash_sequence =
+            if anyone_can_pay || base_type == SIGHASH_SINGLE || base_type == SIGHASH_NONE {
+                zero
+            } else {
+                self.hash_sequence
+            };
+        let hash_outputs = if base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+            self.hash_outputs
+        } else if base_type == SIGHASH_SINGLE && input_index < tx.outputs.len() {
+            hashes::sha256d(&tx.outputs[input_index].serialize())
+        } else {
+            zero
+        };
+
+        let mut v = Vec::with_capacity(156 + script_code.len());
+        v.extend(&tx.version.to_le_bytes());
+        v.extend(hash_prevouts.as_bytes());
+        v.extend(hash_sequence.as_bytes());
+        v.extend(input.previous_output.serialize());
+        v.extend(CompactSize(script_code.len() as u64).encode());
+        v.extend(script_code);
+        v.extend(&value.to_sat().to_le_bytes());
+        v.extend(&input.sequence.to_consensus_u32().to_le_bytes());
+        v.extend(hash_outputs.as_bytes());
+        v.extend(&tx.lock_time.to_consensus_u32().to_le_bytes());
+        v.extend(&sighash_type.to_le_bytes());
+        Ok(hashes::sha256d(&v))
+    }
+}
+
+// BIP143 digest for a single input. Use SegwitV0Midstates directly when
+// signing several inputs of the same transaction.
+pub fn segwit_v0(
+    tx: &LegacyTransaction,
+    input_index: usize,
+    script_code: &[u8],
+    value: Amount,
+    sighash_type: u32,
+) -> Result<Hash256, BitcoinError> {
+    SegwitV0Midstates::new(tx).signature_hash(tx, input_index, script_code, value, sighash_type)
+}
+
+// Taproot-only hash type committing to all inputs and outputs, like ALL
+pub const TAPROOT_SIGHASH_DEFAULT: u8 = 0x00;
+const ANNEX_TAG: u8 = 0x50;
+
+// Script-path context for a Taproot signature (ext_flag = 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapScriptSpend {
+    pub leaf_hash: [u8; 32],
+    // Opcode position of the last executed OP_CODESEPARATOR, 0xFFFFFFFF if none
+    pub code_separator_position: u32,
+}
+
+impl TapScriptSpend {
+    pub fn new(script: &[u8]) -> Self {
+        TapScriptSpend {
+            leaf_hash: tap_leaf_hash(script, TAPSCRIPT_LEAF_VERSION),
+            code_separator_position: 0xFFFFFFFF,
+        }
+    }
+}
+
+// BIP341 sha_prevouts/sha_amounts/sha_scriptpubkeys/sha_sequences/sha_outputs.
+// Unlike BIP143 these are single SHA256s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaprootMidstates {
+    pub sha_prevouts: Hash256,
+    pub sha_amounts: Hash256,
+    pub sha_script_pubkeys: Hash256,
+    pub sha_sequences: Hash256,
+    pub sha_outputs: Hash256,
+}
+
+impl TaprootMidstates {
+    // `prevouts` are the outputs being spent, one per input in order
+    pub fn new(tx: &LegacyTransaction, prevouts: &[TxOutput]) -> Result<Self, BitcoinError> {
+        if prevouts.len() != tx.inputs.len() {
+            return Err(BitcoinError::Usage(UsageError::PrevoutCountMismatch {
+                prevouts: prevouts.len(),
+                inputs: tx.inputs.len(),
+            }));
+        }
+        let mut outpoints = Vec::with_capacity(tx.inputs.len() * 36);
+        let mut sequences = Vec::with_capacity(tx.inputs.len() * 4);
+        for input in &tx.inputs {
+            outpoints.extend(input.previous_output.serialize());
+            sequences.extend(&input.sequence.to_consensus_u32().to_le_bytes());
+        }
+        let mut amounts = Vec::with_capacity(prevouts.len() * 8);
+        let mut script_pubkeys = Vec::new();
+        for prevout in prevouts {
+            amounts.extend(&prevout.value.to_sat().to_le_bytes());
+            script_pubkeys.extend(CompactSize(prevout.script_pubkey.len() as u64).encode());
+            script_pubkeys.extend(&prevout.script_pubkey);
+        }
+        let mut outputs = Vec::new();
+        for output in &tx.outputs {
+            outputs.extend(output.serialize());
+        }
+        Ok(TaprootMidstates {
+            sha_prevouts: hashes::sha256(&outpoints),
+            sha_amounts: hashes::sha256(&amounts),
+            sha_script_pubkeys: hashes::sha256(&script_pubkeys),
+            sha_sequences: hashes::sha256(&sequences),
+            sha_outputs: hashes::sha256(&outputs),
+        })
+    }
+
+    // BIP341 signature message hashed with the "TapSighash" tag. Key-path
+    // spends pass no `script_path`; `annex` includes its 0x50 prefix.
+    pub fn signature_hash(
+        &self,
+        tx: &LegacyTransaction,
+        input_index: usize,
+        prevouts: &[TxOutput],
+        annex: Option<&[u8]>,
+        script_path: Option<&TapScriptSpend>,
+        hash_type: u8,
+    ) -> Result<Hash256, BitcoinError> {
+        if !matches!(hash_type, 0x00..=0x03 | 0x81..=0x83) {
+            return Err(BitcoinError::Crypto(CryptoError::InvalidSighashType(
+                hash_type as u32,
+            )));
+        }
+        let input = tx.inputs.get(input_index).ok_or(BitcoinError::Usage(
+            UsageError::InputIndexOutOfRange(input_index),
+        ))?;
+        let prevout = prevouts.get(input_index).ok_or(BitcoinError::Usage(
+            UsageError::PrevoutCountMismatch {
+                prevouts: prevouts.len(),
+                inputs: tx.inputs.len(),
+            },
+        ))?;
+        if annex.is_some_and(|annex| annex.first() != Some(&ANNEX_TAG)) {
+            return Err(BitcoinError::Parse(ParseError::InvalidTransaction));
+        }
+        let output_type = if hash_type == TAPROOT_SIGHASH_DEFAULT {
+            SIGHASH_ALL
+        } else {
+            hash_type as u32 & 0x03
+        };
+        let anyone_can_pay = hash_type as u32 & SIGHASH_ANYONECANPAY != 0;
+
+        // Epoch 0
+        let mut v = vec![0x00, hash_type];
+        v.extend(&tx.version.to_le_bytes());
+        v.extend(&tx.lock_time.to_consensus_u32().to_le_bytes());
+        if !anyone_can_pay {
+            v.extend(self.sha_prevouts.as_bytes());
+            v.extend(self.sha_amounts.as_bytes());
+            v.extend(self.sha_script_pubkeys.as_bytes());
+            v.extend(self.sha_sequences.as_bytes());
+        }
+        if output_type == SIGHASH_ALL {
+            v.extend(self.sha_outputs.as_bytes());
+        }
+
+        let ext_flag = u8::from(script_path.is_some());
+        v.push(ext_flag * 2 + u8::from(annex.is_some()));
+        if anyone_can_pay {
+            v.extend(input.previous_output.serialize());
+            v.extend(prevout.serialize());
+            v.extend(&input.sequence.to_consensus_u32().to_le_bytes());
+        } else {
+            v.extend(&(input_index as u32).to_le_bytes());
+        }
+        if let Some(annex) = annex {
+            let mut serialized = CompactSize(annex.len() as u64).encode();
+            serialized.extend(annex);
+            v.extend(hashes::sha256(&serialized).as_bytes());
+        }
+
+        if output_type == SIGHASH_SINGLE {
+            let output = tx.outputs.get(input_index).ok_or(BitcoinError::Crypto(
+                CryptoError::InvalidSighashType(hash_type as u32),
+            ))?;
+            v.extend(hashes::sha256(&output.serialize()).as_bytes());
+        }
+
+        if let Some(spend) = script_path {
+            v.extend(&spend.leaf_hash);
+            // key_version 0 is the only one defined (BIP342)
+            v.push(0x00);
+            v.extend(&spend.code_separator_position.to_le_bytes());
+        }
+        Ok(hashes::tagged_hash("TapSighash", &[&v]))
+    }
+}
+
+// BIP341 digest for a single input. Use TaprootMidstates directly when
+// signing several inputs of the same transaction.
+pub fn taproot(
+    tx: &LegacyTransaction,
+    input_index: usize,
+    prevouts: &[TxOutput],
+    annex: Option<&[u8]>,
+    script_path: Option<&TapScriptSpend>,
+    hash_type: u8,
+) -> Result<Hash256, BitcoinError> {
+    TaprootMidstates::new(tx, prevouts)?.signature_hash(
+        tx,
+        input_index,
+        prevouts,
+        annex,
+        script_path,
+        hash_type,
+    )
+}
+
+// Digests for any input of one transaction, computing the parts every input
+// shares only once: the BIP143 and BIP341 midstates, and the outputs as
+// legacy SIGHASH_ALL digests serialize them. Signing or verifying all inputs
+// of a large transaction then serializes and hashes those once, not once per
+// input. Each part is computed on first use.
+#[derive(Debug, Clone)]
+pub struct SighashCache<'a> {
+    tx: &'a LegacyTransaction,
+    prevouts: Option<&'a [TxOutput]>,
+    legacy_outputs: OnceCell<Vec<u8>>,
+    segwit_v0: OnceCell<SegwitV0Midstates>,
+    taproot: OnceCell<TaprootMidstates>,
+}
+
+impl<'a> SighashCache<'a> {
+    pub fn new(tx: &'a LegacyTransaction) -> Self {
+        SighashCache {
+            tx,
+            prevouts: None,
+            legacy_outputs: OnceCell::new(),
+            segwit_v0: OnceCell::new(),
+            taproot: OnceCell::new(),
+        }
+    }
+
+    // The outputs being spent, one per input in order, which Taproot
+    // digests commit to
+    pub fn prevouts(mut self, prevouts: &'a [TxOutput]) -> Self {
+        self.prevouts = Some(prevouts);
+        self.taproot = OnceCell::new();
+        self
+    }
+
+    pub fn transaction(&self) -> &'a LegacyTransaction {
+        self.tx
+    }
+
+    // Same as LegacyTransaction::signature_hash
+    pub fn legacy_signature_hash(
+        &self,
+        input_index: usize,
+        script_code: &[u8],
+        hash_type: u32,
+    ) -> Result<Hash256, BitcoinError> {
+        if input_index >= self.tx.inputs.len() {
+            return Err(BitcoinError::Usage(UsageError::InputIndexOutOfRange(
+                input_index,
+            )));
+        }
+        let base_type = hash_type & 0x1F;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        if base_type == SIGHASH_SINGLE && input_index >= self.tx.outputs.len() {
+            return Ok(one());
+        }
+        let script_code = strip_code_separators(script_code);
+
+        let mut v = Vec::new();
+        v.extend(&self.tx.version.to_le_bytes());
+
+        let inputs: Vec<usize> = if anyone_can_pay {
+            vec![input_index]
+        } else {
+            (0..self.tx.inputs.len()).collect()
+        };
+        v.extend(CompactSize(inputs.len() as u64).encode());
+        for i in inputs {
+            let input = &self.tx.inputs[i];
+            v.extend(input.previous_output.serialize());
+            if i == input_index {
+                v.extend(CompactSize(script_code.len() as u64).encode());
+                v.extend(&script_code);
+            } else {
+                v.push(0x00);
+            }
+            // Other inputs' sequences aren't committed to with NONE and SINGLE
+            let sequence =
+                if i != input_index && (base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE) {
+                    0
+                } else {
+                    input.sequence.to_consensus_u32()
+                };
+            v.extend(&sequence.to_le_bytes());
+        }
+
+        match base_type {
+            SIGHASH_NONE => v.push(0x00),
+            SIGHASH_SINGLE => {
+                // Outputs before the signed one are blanked to value -1 and an empty script
+                v.extend(CompactSize(input_index as u64 + 1).encode());
+                for _ in 0..input_index {
+                    v.extend(&u64::MAX.to_le_bytes());
+                    v.push(0x00);
+                }
+                v.extend(self.tx.outputs[input_index].serialize());
+            }
+            _ => v.extend(self.legacy_outputs.get_or_init(|| {
+                let mut outputs = CompactSize(self.tx.outputs.len() as u64).encode();
+                for output in &self.tx.outputs {
+                    outputs.extend(output.serialize());
+                }
+                outputs
+            })),
+        }
+
+        v.extend(&self.tx.lock_time.to_consensus_u32().to_le_bytes());
+        v.extend(&hash_type.to_le_bytes());
+        Ok(hashes::sha256d(&v))
+    }
+
+    // BIP143 digest for `input_index` spending an output worth `value`
+    pub fn segwit_v0_signature_hash(
+        &self,
+        input_index: usize,
+        script_code: &[u8],
+        value: Amount,
+        sighash_type: u32,
+    ) -> Result<Hash256, BitcoinError> {
+        self.segwit_v0
+            .get_or_init(|| SegwitV0Midstates::new(self.tx))
+            .signature_hash(self.tx, input_index, script_code, value, sighash_type)
+    }
+
+    // BIP341 digest, which needs the prevouts set
+    pub fn taproot_signature_hash(
+        &self,
+        input_index: usize,
+        annex: Option<&[u8]>,
+        script_path: Option<&TapScriptSpend>,
+        hash_type: u8,
+    ) -> Result<Hash256, BitcoinError> {
+        let prevouts = self.prevouts.ok_or_else(|| {
+            BitcoinError::Usage(UsageError::MissingArgument("prevouts".to_string()))
+        })?;
+        let midstates = match self.taproot.get() {
+            Some(midstates) => midstates,
+            None => {
+                let midstates = TaprootMidstates::new(self.tx, prevouts)?;
+                self.taproot.get_or_init(|| midstates)
+            }
+        };
+        midstates.signature_hash(
+            self.tx,
+            input_index,
+            prevouts,
+            annex,
+            script_path,
+            hash_type,
+        )
+    }
+}
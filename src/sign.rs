@@ -0,0 +1,342 @@
+// ECDSA and Schnorr signing of transaction inputs
+
+use std::collections::HashMap;
+
+use k256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use k256::ecdsa::Signature;
+
+use crate::script::{is_p2pkh, is_p2sh, p2pk_pubkey, p2tr, witness_program};
+use crate::sighash::{SighashCache, TapScriptSpend, TAPROOT_SIGHASH_DEFAULT};
+use crate::{
+    hashes, sighash, taproot, BitcoinError, CryptoError, Hash256, LegacyTransaction, OutPoint,
+    ParseError, PrivateKey, PublicKey, Script, ScriptBuilder, SigHashType, TxOutput, UsageError,
+    ValidationError, Witness, XOnlyPublicKey,
+};
+
+// DER-encoded, low-S ECDSA signature over a 32-byte digest (RFC6979 nonces)
+pub fn sign_ecdsa(digest: &Hash256, key: &PrivateKey) -> Result<Vec<u8>, BitcoinError> {
+    let signature: Signature = key
+        .signing_key()
+        .sign_prehash(digest.as_bytes())
+        .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+    Ok(signature.to_der().as_bytes().to_vec())
+}
+
+// Checks a DER signature (without sighash byte). High-S signatures are valid
+// by consensus, so they are normalized before verification.
+pub fn verify_ecdsa(digest: &Hash256, der_signature: &[u8], pubkey: &PublicKey) -> bool {
+    let Ok(signature) = Signature::from_der(der_signature) else {
+        return false;
+    };
+    let signature = signature.normalize_s().unwrap_or(signature);
+    pubkey
+        .verifying_key()
+        .verify_prehash(digest.as_bytes(), &signature)
+        .is_ok()
+}
+
+// BIP340 signature with all-zero auxiliary randomness, so signing the same
+// digest with the same key always gives the same signature
+pub fn sign_schnorr(digest: &Hash256, key: &PrivateKey) -> Result<[u8; 64], BitcoinError> {
+    sign_schnorr_with_aux_rand(digest, key, &[0; 32])
+}
+
+pub fn sign_schnorr_with_aux_rand(
+    digest: &Hash256,
+    key: &PrivateKey,
+    aux_rand: &[u8; 32],
+) -> Result<[u8; 64], BitcoinError> {
+    let signature = key
+        .schnorr_signing_key()
+        .sign_prehash_with_aux_rand(digest.as_bytes(), aux_rand)
+        .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+    Ok(signature.to_bytes())
+}
+
+// Checks a 64-byte BIP340 signature (without sighash byte)
+pub fn verify_schnorr(digest: &Hash256, signature: &[u8], pubkey: &XOnlyPublicKey) -> bool {
+    let Ok(signature) = k256::schnorr::Signature::try_from(signature) else {
+        return false;
+    };
+    pubkey
+        .verifying_key()
+        .verify_raw(digest.as_bytes(), &signature)
+        .is_ok()
+}
+
+// BIP341 signatures omit the hash type byte for SIGHASH_DEFAULT
+fn taproot_signature(signature: [u8; 64], hash_type: u8) -> Vec<u8> {
+    let mut v = signature.to_vec();
+    if hash_type != TAPROOT_SIGHASH_DEFAULT {
+        v.push(hash_type);
+    }
+    v
+}
+
+impl LegacyTransaction {
+    // Signs a P2PKH or P2PK input spending an output locked by `prev_script` and
+    // sets its scriptSig
+    pub fn sign_input(
+        &mut self,
+        input_index: usize,
+        key: &PrivateKey,
+        prev_script: &[u8],
+        sighash_type: SigHashType,
+    ) -> Result<(), BitcoinError> {
+        let script_sig = legacy_script_sig(
+            &SighashCache::new(self),
+            input_index,
+            key,
+            prev_script,
+            sighash_type,
+        )?;
+        self.inputs[input_index].script_sig = script_sig;
+        Ok(())
+    }
+}
+
+// The scriptSig of sign_input, with the digest from `cache`
+fn legacy_script_sig(
+    cache: &SighashCache,
+    input_index: usize,
+    key: &PrivateKey,
+    prev_script: &[u8],
+    sighash_type: SigHashType,
+) -> Result<Vec<u8>, BitcoinError> {
+    let tx = cache.transaction();
+    if input_index >= tx.inputs.len() {
+        return Err(BitcoinError::Usage(UsageError::InputIndexOutOfRange(
+            input_index,
+        )));
+    }
+    let pubkey = key.public_key();
+    let serialized_pubkey = pubkey.serialize();
+    let is_p2pk = match p2pk_pubkey(prev_script) {
+        Some(expected) if expected == serialized_pubkey => true,
+        None if is_p2pkh(prev_script)
+            && prev_script[3..23] == pubkey.pubkey_hash().as_bytes()[..] =>
+        {
+            false
+        }
+        _ => return Err(BitcoinError::Crypto(CryptoError::KeyMismatch)),
+    };
+
+    // Never sign the SIGHASH_SINGLE placeholder digest; such a signature
+    // would be valid for any transaction spending this output
+    if sighash_type.to_u32() & 0x1F == SigHashType::Single.to_u32()
+        && input_index >= tx.outputs.len()
+    {
+        return Err(BitcoinError::Parse(ParseError::InvalidTransaction));
+    }
+    let digest = cache.legacy_signature_hash(input_index, prev_script, sighash_type.to_u32())?;
+    let mut signature = sign_ecdsa(&digest, key)?;
+    signature.push(sighash_type.to_u32() as u8);
+
+    let mut builder = ScriptBuilder::new().push_bytes(&signature);
+    if !is_p2pk {
+        builder = builder.push_bytes(&serialized_pubkey);
+    }
+    Ok(builder.build())
+}
+
+impl LegacyTransaction {
+    // Signs a Taproot key-path spend with the untweaked internal key and sets
+    // the input's witness. `merkle_root` is the script tree the output commits
+    // to, if any.
+    pub fn sign_taproot_key_spend(
+        &mut self,
+        input_index: usize,
+        internal_key: &PrivateKey,
+        prevouts: &[TxOutput],
+        merkle_root: Option<[u8; 32]>,
+        hash_type: u8,
+    ) -> Result<(), BitcoinError> {
+        let key = taproot::tweak_private_key(internal_key, merkle_root)?;
+        let output_key = key.x_only_public_key().0.serialize();
+        let prevout = prevouts.get(input_index).ok_or(BitcoinError::Usage(
+            UsageError::InputIndexOutOfRange(input_index),
+        ))?;
+        if prevout.script_pubkey != p2tr(&output_key) {
+            return Err(BitcoinError::Crypto(CryptoError::KeyMismatch));
+        }
+
+        let digest = sighash::taproot(self, input_index, prevouts, None, None, hash_type)?;
+        let signature = sign_schnorr(&digest, &key)?;
+        self.inputs[input_index].witness =
+            Witness::from(vec![taproot_signature(signature, hash_type)]);
+        Ok(())
+    }
+
+    // Signature for a tapscript leaf, to be placed in the witness ahead of the
+    // script and control block
+    pub fn taproot_script_signature(
+        &self,
+        input_index: usize,
+        key: &PrivateKey,
+        prevouts: &[TxOutput],
+        leaf: &TapScriptSpend,
+        hash_type: u8,
+    ) -> Result<Vec<u8>, BitcoinError> {
+        let digest = sighash::taproot(self, input_index, prevouts, None, Some(leaf), hash_type)?;
+        Ok(taproot_signature(sign_schnorr(&digest, key)?, hash_type))
+    }
+}
+
+// How an output the signer holds a key for is spent
+pub(crate) enum Spend<'a> {
+    // P2PK and P2PKH, signed by LegacyTransaction::sign_input
+    Legacy(&'a PrivateKey),
+    P2wpkh(&'a PrivateKey),
+    // P2WPKH inside P2SH, with the witness program as redeem script
+    P2shP2wpkh(&'a PrivateKey),
+    // BIP86 key-path spend, with the key already tweaked
+    P2tr(PrivateKey),
+}
+
+// Signs every input it holds a key for, working out from the output each
+// input spends which script type it is and which sighash to sign
+#[derive(Debug, Clone, Default)]
+pub struct Signer {
+    keys: Vec<PrivateKey>,
+    // None for SIGHASH_ALL, or SIGHASH_DEFAULT on Taproot inputs
+    sighash_type: Option<SigHashType>,
+}
+
+impl Signer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(mut self, key: PrivateKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn sighash_type(mut self, sighash_type: SigHashType) -> Self {
+        self.sighash_type = Some(sighash_type);
+        self
+    }
+
+    // Fills the scriptSig or witness of each input spending a P2PK, P2PKH,
+    // P2WPKH, P2SH-P2WPKH or key-path P2TR output of one of the keys,
+    // returning the indexes of the inputs signed. Inputs whose prevout is
+    // missing, or that need scripts or other keys, are left as they are.
+    // Taproot signatures commit to every prevout, so signing a Taproot
+    // input needs all of them.
+    pub fn sign(
+        &self,
+        tx: &mut LegacyTransaction,
+        prevouts: &HashMap<OutPoint, TxOutput>,
+    ) -> Result<Vec<usize>, BitcoinError> {
+        let sighash_type = self.sighash_type.unwrap_or(SigHashType::All);
+        let ecdsa_type = sighash_type.to_u32();
+        let taproot_type = self
+            .sighash_type
+            .map_or(TAPROOT_SIGHASH_DEFAULT, |t| t.to_u32() as u8);
+        // The prevouts in input order, or the first one missing
+        let ordered_prevouts: Result<Vec<TxOutput>, OutPoint> = tx
+            .inputs
+            .iter()
+            .map(|input| {
+                let outpoint = &input.previous_output;
+                prevouts
+                    .get(outpoint)
+                    .cloned()
+                    .ok_or_else(|| outpoint.clone())
+            })
+            .collect();
+        // No digest commits to the scriptSigs and witnesses signing fills,
+        // so every input's can come from the unsigned transaction
+        let unsigned = tx.clone();
+        let cache = match &ordered_prevouts {
+            Ok(ordered) => SighashCache::new(&unsigned).prevouts(ordered),
+            Err(_) => SighashCache::new(&unsigned),
+        };
+
+        let mut signed = Vec::new();
+        for index in 0..tx.inputs.len() {
+            let Some(prevout) = prevouts.get(&tx.inputs[index].previous_output) else {
+                continue;
+            };
+            let script_pubkey = &prevout.script_pubkey;
+            let Some(spend) = self.spend(script_pubkey)? else {
+                continue;
+            };
+            match spend {
+                Spend::Legacy(key) => {
+                    tx.inputs[index].script_sig =
+                        legacy_script_sig(&cache, index, key, script_pubkey, sighash_type)?;
+                }
+                Spend::P2wpkh(key) | Spend::P2shP2wpkh(key) => {
+                    let pubkey = key.public_key();
+                    let script_code = Script::new_p2pkh(&pubkey.pubkey_hash()).into_bytes();
+                    let digest = cache.segwit_v0_signature_hash(
+                        index,
+                        &script_code,
+                        prevout.value,
+                        ecdsa_type,
+                    )?;
+                    let mut signature = sign_ecdsa(&digest, key)?;
+                    signature.push(ecdsa_type as u8);
+                    let input = &mut tx.inputs[index];
+                    if let Spend::P2shP2wpkh(_) = spend {
+                        let redeem_script = Script::new_p2wpkh(&pubkey.pubkey_hash());
+                        input.script_sig = ScriptBuilder::new()
+                            .push_bytes(redeem_script.as_bytes())
+                            .build();
+                    }
+                    input.witness = Witness::from(vec![signature, pubkey.serialize()]);
+                }
+                Spend::P2tr(key) => {
+                    if let Err(outpoint) = &ordered_prevouts {
+                        return Err(BitcoinError::Validation(ValidationError::UnknownOutput(
+                            outpoint.clone(),
+                        )));
+                    }
+                    let digest = cache.taproot_signature_hash(index, None, None, taproot_type)?;
+                    let signature = taproot_signature(sign_schnorr(&digest, &key)?, taproot_type);
+                    tx.inputs[index].witness = Witness::from(vec![signature]);
+                }
+            }
+            signed.push(index);
+        }
+        Ok(signed)
+    }
+
+    pub(crate) fn spend(&self, script_pubkey: &[u8]) -> Result<Option<Spend<'_>>, BitcoinError> {
+        for key in &self.keys {
+            let pubkey = key.public_key();
+            let pubkey_hash = pubkey.pubkey_hash();
+            if p2pk_pubkey(script_pubkey) == Some(&pubkey.serialize())
+                || (is_p2pkh(script_pubkey) && script_pubkey[3..23] == pubkey_hash.as_bytes()[..])
+            {
+                return Ok(Some(Spend::Legacy(key)));
+            }
+            // Segwit only allows compressed keys
+            if !key.compressed {
+                continue;
+            }
+            match witness_program(script_pubkey) {
+                Some((0, program)) if program == pubkey_hash.as_bytes() => {
+                    return Ok(Some(Spend::P2wpkh(key)));
+                }
+                Some((1, program)) => {
+                    let tweaked = taproot::tweak_private_key(key, None)?;
+                    if program == tweaked.x_only_public_key().0.serialize() {
+                        return Ok(Some(Spend::P2tr(tweaked)));
+                    }
+                }
+                _ => {}
+            }
+            if is_p2sh(script_pubkey) {
+                let redeem_script = Script::new_p2wpkh(&pubkey_hash);
+                if script_pubkey[2..22] == hashes::hash160(redeem_script.as_bytes()).as_bytes()[..]
+                {
+                    return Ok(Some(Spend::P2shP2wpkh(key)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
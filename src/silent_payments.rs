@@ -0,0 +1,464 @@
+// Silent payments (BIP352): a static address whose every payment lands on a
+// fresh Taproot output. The sender derives the output key from a
+// Diffie-Hellman secret between the receiver's scan key and the sum of the
+// keys spending the transaction's inputs; the receiver finds its outputs
+// by repeating the derivation from the inputs' public keys.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use k256::elliptic_curve::ops::Reduce;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::elliptic_curve::PrimeField;
+use k256::{ProjectivePoint, Scalar, U256};
+
+use crate::bech32::{self, Variant};
+use crate::hashes::{hash160, tagged_hash};
+use crate::script::{is_p2pkh, is_p2sh, p2tr, witness_program};
+use crate::sign::Spend;
+use crate::{
+    Amount, BitcoinError, CryptoError, Hash256, LegacyTransaction, LegacyTransactionBuilder,
+    Network, OutPoint, ParseError, PrivateKey, PublicKey, Signer, TxInput, TxOutput,
+    ValidationError,
+};
+
+// BIP352's limit, leaving room for later versions to append data
+const MAX_ADDRESS_LENGTH: usize = 1023;
+
+// BIP341's H, the internal key nobody has the private key of. A script-path
+// spend with it as internal key has no key to contribute.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+// The receiver's public scan and spend keys, as sp1... on mainnet and
+// tsp1... on the test networks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    pub scan_key: PublicKey,
+    pub spend_key: PublicKey,
+    // Testnet for any tsp1 address, which doesn't say which test network
+    pub network: Network,
+}
+
+impl SilentPaymentAddress {
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey, network: Network) -> Self {
+        SilentPaymentAddress {
+            scan_key,
+            spend_key,
+            network,
+        }
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hrp = match self.network {
+            Network::Mainnet => "sp",
+            _ => "tsp",
+        };
+        let keys = [
+            compressed_key(&self.scan_key),
+            compressed_key(&self.spend_key),
+        ]
+        .concat();
+        // Version 0
+        let mut data = vec![0];
+        data.extend(bech32::convert_bits(&keys, 8, 5, true).map_err(|_| fmt::Error)?);
+        let s = bech32::encode(hrp, &data, Variant::Bech32m).map_err(|_| fmt::Error)?;
+        f.write_str(&s)
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = BitcoinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: &str| BitcoinError::Parse(ParseError::InvalidAddress(reason.to_string()));
+        let (hrp, data, variant) = bech32::decode_with_max_length(s, MAX_ADDRESS_LENGTH)?;
+        let network = match hrp.as_str() {
+            "sp" => Network::Mainnet,
+            "tsp" => Network::Testnet,
+            _ => {
+                return Err(BitcoinError::Parse(ParseError::InvalidAddress(format!(
+                    "Unknown silent payment prefix {hrp:?}"
+                ))))
+            }
+        };
+        if variant != Variant::Bech32m {
+            return Err(invalid("Silent payment addresses use bech32m"));
+        }
+        let (&version, data) = data
+            .split_first()
+            .ok_or_else(|| invalid("Missing silent payment version"))?;
+        let keys = bech32::convert_bits(data, 5, 8, false)?;
+        // Later versions may append data after the keys, which this one
+        // doesn't know the meaning of but can still pay
+        match version {
+            0 if keys.len() == 66 => {}
+            1..=30 if keys.len() >= 66 => {}
+            0..=30 => return Err(invalid("Invalid silent payment address length")),
+            _ => return Err(invalid("Unsupported silent payment version")),
+        }
+        Ok(SilentPaymentAddress {
+            scan_key: PublicKey::from_slice(&keys[..33])?,
+            spend_key: PublicKey::from_slice(&keys[33..66])?,
+            network,
+        })
+    }
+}
+
+// Outputs paying each recipient its amount from a transaction spending
+// `inputs`. Every input of a kind that counts (P2PKH, P2WPKH,
+// P2SH-P2WPKH and P2TR) must be one `signer` holds the key for, since the
+// receiver adds up all of their keys; inputs of other kinds are left out.
+pub fn derive_outputs(
+    inputs: &[TxInput],
+    prevouts: &HashMap<OutPoint, TxOutput>,
+    signer: &Signer,
+    recipients: &[(SilentPaymentAddress, Amount)],
+) -> Result<Vec<TxOutput>, BitcoinError> {
+    let mut sum = Scalar::ZERO;
+    for input in inputs {
+        let outpoint = &input.previous_output;
+        let script_pubkey = &prevouts
+            .get(outpoint)
+            .ok_or_else(|| {
+                BitcoinError::Validation(ValidationError::UnknownOutput(outpoint.clone()))
+            })?
+            .script_pubkey;
+        let program = witness_program(script_pubkey);
+        // Receivers skip transactions spending later witness versions
+        if matches!(program, Some((version, _)) if version > 1) {
+            return Err(BitcoinError::Parse(ParseError::InvalidTransaction));
+        }
+        let key = match signer.spend(script_pubkey)? {
+            Some(Spend::Legacy(key)) if key.compressed && is_p2pkh(script_pubkey) => scalar(key),
+            // P2PK, or P2PKH with an uncompressed key
+            Some(Spend::Legacy(_)) => continue,
+            Some(Spend::P2wpkh(key) | Spend::P2shP2wpkh(key)) => scalar(key),
+            // The output key has even y as the receiver sees it
+            Some(Spend::P2tr(key)) if key.x_only_public_key().1 => -scalar(&key),
+            Some(Spend::P2tr(key)) => scalar(&key),
+            _ if is_p2pkh(script_pubkey)
+                || matches!(program, Some((0, p)) if p.len() == 20)
+                || matches!(program, Some((1, p)) if p.len() == 32) =>
+            {
+                return Err(BitcoinError::Crypto(CryptoError::MissingKey(
+                    outpoint.clone(),
+                )));
+            }
+            _ => continue,
+        };
+        sum += key;
+    }
+    // No keys, or keys cancelling each other out
+    if sum == Scalar::ZERO {
+        return Err(BitcoinError::Crypto(CryptoError::InvalidPrivateKey));
+    }
+    let input_hash = input_hash(inputs, &(ProjectivePoint::GENERATOR * sum))
+        .ok_or(BitcoinError::Parse(ParseError::InvalidTransaction))?;
+
+    // Payments to one scan key share a secret, told apart by a counter
+    let mut counts: HashMap<[u8; 33], u32> = HashMap::new();
+    recipients
+        .iter()
+        .map(|(address, amount)| {
+            let scan_key = compressed_key(&address.scan_key);
+            let shared_secret = point_bytes(&(parse_point(&scan_key)? * (input_hash * sum)))
+                .ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+            let k = counts.entry(scan_key).or_insert(0);
+            let tweak = output_tweak(&shared_secret, *k);
+            *k += 1;
+            let spend_key = parse_point(&compressed_key(&address.spend_key))?;
+            let output_key = point_bytes(&(spend_key + ProjectivePoint::GENERATOR * tweak))
+                .ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+            let mut x_only = [0; 32];
+            x_only.copy_from_slice(&output_key[1..]);
+            Ok(TxOutput {
+                value: *amount,
+                script_pubkey: p2tr(&x_only),
+            })
+        })
+        .collect()
+}
+
+impl LegacyTransactionBuilder {
+    // Adds outputs paying the silent payment addresses (see derive_outputs).
+    // They depend on the inputs, so add all of those first.
+    pub fn add_silent_payments(
+        mut self,
+        recipients: &[(SilentPaymentAddress, Amount)],
+        signer: &Signer,
+        prevouts: &HashMap<OutPoint, TxOutput>,
+    ) -> Result<Self, BitcoinError> {
+        let outputs = derive_outputs(&self.inputs, prevouts, signer, recipients)?;
+        self.outputs.extend(outputs);
+        Ok(self)
+    }
+}
+
+// A payment found by scanning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SilentPaymentOutput {
+    pub outpoint: OutPoint,
+    pub output: TxOutput,
+    // Added to the spend private key for the output's
+    pub tweak: [u8; 32],
+    // The label of the address paid, if not the plain one
+    pub label: Option<u32>,
+}
+
+impl SilentPaymentOutput {
+    // The key of the output, which signs its key-path spend as it is, with
+    // no Taproot tweak (LegacyTransaction::sign_taproot_key_spend)
+    pub fn private_key(&self, spend_key: &PrivateKey) -> Result<PrivateKey, BitcoinError> {
+        let tweak = Option::<Scalar>::from(Scalar::from_repr(self.tweak.into()))
+            .ok_or(BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+        PrivateKey::from_slice(&(scalar(spend_key) + tweak).to_bytes())
+    }
+}
+
+// Finds payments to an address from its scan private key and spend public
+// key, which is all scanning needs; the spend private key can stay offline
+#[derive(Debug, Clone)]
+pub struct SilentPaymentReceiver {
+    scan_key: PrivateKey,
+    spend_key: PublicKey,
+    network: Network,
+    // Each label's point, the labeled spend key minus the plain one, with
+    // its number and tweak
+    labels: HashMap<[u8; 33], (u32, Scalar)>,
+}
+
+impl SilentPaymentReceiver {
+    pub fn new(scan_key: PrivateKey, spend_key: PublicKey, network: Network) -> Self {
+        SilentPaymentReceiver {
+            scan_key,
+            spend_key,
+            network,
+            labels: HashMap::new(),
+        }
+    }
+
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(self.scan_key.public_key(), self.spend_key, self.network)
+    }
+
+    // An address telling its payments apart from other addresses' by label
+    // `m`, with the same scan key. BIP352 keeps label 0 for change.
+    pub fn labeled_address(&self, m: u32) -> Result<SilentPaymentAddress, BitcoinError> {
+        let spend_key = parse_point(&compressed_key(&self.spend_key))?
+            + ProjectivePoint::GENERATOR * self.label_tweak(m);
+        let spend_key =
+            point_bytes(&spend_key).ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+        Ok(SilentPaymentAddress::new(
+            self.scan_key.public_key(),
+            PublicKey::from_slice(&spend_key)?,
+            self.network,
+        ))
+    }
+
+    // Finds payments to labeled_address(m) too
+    pub fn label(mut self, m: u32) -> Self {
+        let tweak = self.label_tweak(m);
+        if let Some(point) = point_bytes(&(ProjectivePoint::GENERATOR * tweak)) {
+            self.labels.insert(point, (m, tweak));
+        }
+        self
+    }
+
+    fn label_tweak(&self, m: u32) -> Scalar {
+        reduce(tagged_hash(
+            "BIP0352/Label",
+            &[&self.scan_key.to_bytes(), &m.to_be_bytes()],
+        ))
+    }
+
+    // The transaction's outputs paying this receiver. `prevouts` must hold
+    // every output the transaction spends, as the inputs' keys are read from
+    // them; a coinbase or a transaction with no inputs that count pays none.
+    pub fn scan(
+        &self,
+        tx: &LegacyTransaction,
+        prevouts: &HashMap<OutPoint, TxOutput>,
+    ) -> Result<Vec<SilentPaymentOutput>, BitcoinError> {
+        if tx.is_coinbase() {
+            return Ok(Vec::new());
+        }
+        let mut sum = ProjectivePoint::IDENTITY;
+        for input in &tx.inputs {
+            let outpoint = &input.previous_output;
+            let script_pubkey = &prevouts
+                .get(outpoint)
+                .ok_or_else(|| {
+                    BitcoinError::Validation(ValidationError::UnknownOutput(outpoint.clone()))
+                })?
+                .script_pubkey;
+            if matches!(witness_program(script_pubkey), Some((version, _)) if version > 1) {
+                return Ok(Vec::new());
+            }
+            if let Some(key) = input_public_key(input, script_pubkey) {
+                sum += key;
+            }
+        }
+        let Some(input_hash) = input_hash(&tx.inputs, &sum) else {
+            return Ok(Vec::new());
+        };
+        let Some(shared_secret) = point_bytes(&(sum * (input_hash * scalar(&self.scan_key))))
+        else {
+            return Ok(Vec::new());
+        };
+
+        // Taproot outputs, with their keys lifted to even y
+        let mut candidates: Vec<(usize, ProjectivePoint)> = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .filter_map(
+                |(vout, output)| match witness_program(&output.script_pubkey) {
+                    Some((1, program)) if program.len() == 32 => {
+                        let point = parse_point(&[&[0x02], program].concat()).ok()?;
+                        Some((vout, point))
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+        let spend_key = parse_point(&compressed_key(&self.spend_key))?;
+        let mut found = Vec::new();
+        // Payments to the address are numbered from 0, and scanning stops
+        // at the first number with none
+        for k in 0.. {
+            let tweak = output_tweak(&shared_secret, k);
+            let expected = spend_key + ProjectivePoint::GENERATOR * tweak;
+            let Some(expected_x) = point_bytes(&expected).map(|p| p[1..].to_vec()) else {
+                break;
+            };
+            let matched = candidates.iter().enumerate().find_map(|(i, (_, output))| {
+                if point_bytes(output).is_some_and(|p| p[1..] == expected_x[..]) {
+                    return Some((i, None));
+                }
+                // A labeled payment's key minus the expected one is the
+                // label's point, for either y of the output key
+                [*output - expected, -*output - expected]
+                    .iter()
+                    .find_map(|diff| self.labels.get(&point_bytes(diff)?))
+                    .map(|label| (i, Some(*label)))
+            });
+            let Some((i, label)) = matched else { break };
+            let (vout, _) = candidates.remove(i);
+            let tweak = tweak + label.map_or(Scalar::ZERO, |(_, tweak)| tweak);
+            found.push(SilentPaymentOutput {
+                outpoint: OutPoint::new(tx.txid(), vout as u32),
+                output: tx.outputs[vout].clone(),
+                tweak: tweak.to_bytes().into(),
+                label: label.map(|(m, _)| m),
+            });
+        }
+        Ok(found)
+    }
+}
+
+// The key an input contributes, as BIP352 reads it from the scriptSig or
+// witness, or None for an input that doesn't count
+fn input_public_key(input: &TxInput, script_pubkey: &[u8]) -> Option<ProjectivePoint> {
+    let witness = &input.witness.items;
+    let compressed = |key: &[u8]| -> Option<ProjectivePoint> {
+        match key {
+            [0x02 | 0x03, ..] if key.len() == 33 => parse_point(key).ok(),
+            _ => None,
+        }
+    };
+    if is_p2pkh(script_pubkey) {
+        // The last 33 bytes hashing to the key hash, wherever they are in a
+        // possibly malleated scriptSig; uncompressed keys never match
+        let script_sig = &input.script_sig;
+        return (33..=script_sig.len())
+            .rev()
+            .map(|end| &script_sig[end - 33..end])
+            .find(|key| hash160(key).as_bytes()[..] == script_pubkey[3..23])
+            .and_then(compressed);
+    }
+    if is_p2sh(script_pubkey) {
+        return match input.script_sig.split_first() {
+            Some((&22, redeem_script)) if matches!(witness_program(redeem_script), Some((0, p)) if p.len() == 20) => {
+                compressed(witness.last()?)
+            }
+            _ => None,
+        };
+    }
+    if witness.is_empty() {
+        return None;
+    }
+    match witness_program(script_pubkey)? {
+        (0, program) if program.len() == 20 => compressed(witness.last()?),
+        (1, program) if program.len() == 32 => {
+            let mut stack = witness.as_slice();
+            if stack.len() > 1 && stack.last()?.first() == Some(&0x50) {
+                stack = &stack[..stack.len() - 1];
+            }
+            // A script-path spend's control block holds its internal key
+            if stack.len() > 1 && stack.last()?.get(1..33) == Some(&NUMS_H[..]) {
+                return None;
+            }
+            parse_point(&[&[0x02], program].concat()).ok()
+        }
+        _ => None,
+    }
+}
+
+// Commits the shared secret to the lowest outpoint spent, so the same keys
+// spending other coins derive other outputs. None without inputs, or for
+// keys summing to infinity.
+fn input_hash(inputs: &[TxInput], key_sum: &ProjectivePoint) -> Option<Scalar> {
+    let lowest = inputs
+        .iter()
+        .map(|input| input.previous_output.serialize())
+        .min()?;
+    let key_sum = point_bytes(key_sum)?;
+    Some(reduce(tagged_hash("BIP0352/Inputs", &[&lowest, &key_sum])))
+}
+
+fn output_tweak(shared_secret: &[u8; 33], k: u32) -> Scalar {
+    reduce(tagged_hash(
+        "BIP0352/SharedSecret",
+        &[shared_secret, &k.to_be_bytes()],
+    ))
+}
+
+fn compressed_key(pubkey: &PublicKey) -> [u8; 33] {
+    let mut pubkey = *pubkey;
+    pubkey.compressed = true;
+    let mut bytes = [0; 33];
+    bytes.copy_from_slice(&pubkey.serialize());
+    bytes
+}
+
+fn parse_point(bytes: &[u8]) -> Result<ProjectivePoint, BitcoinError> {
+    k256::PublicKey::from_sec1_bytes(bytes)
+        .map(|key| key.to_projective())
+        .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPublicKey))
+}
+
+// Compressed, or None for infinity
+fn point_bytes(point: &ProjectivePoint) -> Option<[u8; 33]> {
+    point
+        .to_affine()
+        .to_encoded_point(true)
+        .as_bytes()
+        .try_into()
+        .ok()
+}
+
+fn scalar(key: &PrivateKey) -> Scalar {
+    Option::<Scalar>::from(Scalar::from_repr(key.to_bytes().into()))
+        .expect("private keys are below the curve order")
+}
+
+fn reduce(hash: Hash256) -> Scalar {
+    <Scalar as Reduce<U256>>::reduce_bytes(&hash.to_byte_array().into())
+}
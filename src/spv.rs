@@ -0,0 +1,290 @@
+// Headers-first light client sync: every header received from peers, checked
+// for proof of work, difficulty and timestamps, with the most-work branch
+// as the best chain
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::block::HEADER_SIZE;
+use crate::pow::calculate_next_work_required;
+use crate::{
+    BitcoinError, BlockHash, BlockHeader, ChainParams, CompactTarget, CryptoError, Network, Work,
+};
+
+// How far ahead of the local clock a header's time may be, in seconds
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+// A header's time must be after the median time of this many ancestors
+const MEDIAN_TIME_SPAN: usize = 11;
+
+// Ways a header can fail to fit the chain. Messages give Bitcoin Core's
+// reject reasons.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    #[error("prev-blk-not-found")]
+    UnknownParent(BlockHash),
+    #[error("bad-diffbits")]
+    BadDifficulty,
+    #[error("time-too-old")]
+    TimeTooOld,
+    #[error("time-too-new")]
+    TimeTooNew,
+    #[error("checkpoint mismatch at height {0}")]
+    CheckpointMismatch(u32),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    header: BlockHeader,
+    height: u32,
+    // Total work of the chain ending here
+    chain_work: Work,
+    // Bits of the nearest block not mined under the min-difficulty rule,
+    // which the next block's bits must match
+    normal_bits: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    params: ChainParams,
+    entries: HashMap<BlockHash, Entry>,
+    // The best chain's hashes, indexed by height
+    best: Vec<BlockHash>,
+    checkpoints: BTreeMap<u32, BlockHash>,
+}
+
+impl HeaderChain {
+    // Just the genesis block
+    pub fn new(network: Network) -> Self {
+        let params = network.params();
+        let header = params.genesis_block().header;
+        let hash = header.block_hash();
+        let work = CompactTarget(header.bits)
+            .to_target()
+            .map_or(Work::ZERO, |target| target.to_work());
+        let genesis = Entry {
+            header,
+            height: 0,
+            chain_work: work,
+            normal_bits: header.bits,
+        };
+        HeaderChain {
+            params,
+            entries: HashMap::from([(hash, genesis)]),
+            best: vec![hash],
+            checkpoints: BTreeMap::new(),
+        }
+    }
+
+    // Rejects any header at `height` other than the one with `hash`, so a
+    // peer can't feed a fork from before a block known to be in the chain
+    pub fn checkpoint(mut self, height: u32, hash: BlockHash) -> Self {
+        self.checkpoints.insert(height, hash);
+        self
+    }
+
+    pub fn network(&self) -> Network {
+        self.params.network
+    }
+
+    pub fn height(&self) -> u32 {
+        self.best.len() as u32 - 1
+    }
+
+    pub fn tip_hash(&self) -> BlockHash {
+        *self.best.last().expect("the chain holds the genesis block")
+    }
+
+    pub fn tip(&self) -> BlockHeader {
+        self.entries[&self.tip_hash()].header
+    }
+
+    pub fn chain_work(&self) -> Work {
+        self.entries[&self.tip_hash()].chain_work
+    }
+
+    // On the best chain
+    pub fn header_at(&self, height: u32) -> Option<BlockHeader> {
+        let hash = self.best.get(height as usize)?;
+        Some(self.entries[hash].header)
+    }
+
+    // None unless the block is on the best chain
+    pub fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        let entry = self.entries.get(hash)?;
+        (self.best.get(entry.height as usize) == Some(hash)).then_some(entry.height)
+    }
+
+    // Hashes for a getheaders message: the last ten blocks, then ever
+    // sparser ones back to the genesis block, so a peer on a fork finds
+    // where it branched
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut height = self.height() as i64;
+        let mut step = 1;
+        while height > 0 {
+            locator.push(self.best[height as usize]);
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height -= step;
+        }
+        locator.push(self.best[0]);
+        locator
+    }
+
+    // Adds the headers in order, as from a headers message, returning how
+    // many were new. Each must connect to a known header; on the first
+    // that fails validation the error is returned and the rest are
+    // skipped, keeping those before it. The best chain switches to any
+    // branch with more work.
+    pub fn accept_headers(&mut self, headers: &[BlockHeader]) -> Result<usize, BitcoinError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut added = 0;
+        for header in headers {
+            if self.accept(header, now)? {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    fn accept(&mut self, header: &BlockHeader, now: u64) -> Result<bool, BitcoinError> {
+        let hash = header.block_hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(false);
+        }
+        let parent = self
+            .entries
+            .get(&header.prev_blockhash)
+            .ok_or(HeaderError::UnknownParent(header.prev_blockhash))?;
+        let height = parent.height + 1;
+        if self.checkpoints.get(&height).is_some_and(|&c| c != hash) {
+            return Err(HeaderError::CheckpointMismatch(height).into());
+        }
+        if header.bits != self.next_bits(parent, header) {
+            return Err(HeaderError::BadDifficulty.into());
+        }
+        let target = CompactTarget(header.bits)
+            .to_target()
+            .ok_or(BitcoinError::Crypto(CryptoError::BadProofOfWork))?;
+        header.validate_pow()?;
+        if header.time <= self.median_time_past(parent) {
+            return Err(HeaderError::TimeTooOld.into());
+        }
+        if header.time as u64 > now + MAX_FUTURE_BLOCK_TIME {
+            return Err(HeaderError::TimeTooNew.into());
+        }
+
+        let limit_bits = CompactTarget::from_target(self.params.pow_limit).0;
+        let interval = self.params.difficulty_adjustment_interval() as u32;
+        let entry = Entry {
+            header: *header,
+            height,
+            chain_work: parent.chain_work + target.to_work(),
+            normal_bits: if height.is_multiple_of(interval) || header.bits != limit_bits {
+                header.bits
+            } else {
+                parent.normal_bits
+            },
+        };
+        let more_work = entry.chain_work > self.chain_work();
+        self.entries.insert(hash, entry);
+        if more_work {
+            self.set_tip(hash, height);
+        }
+        Ok(true)
+    }
+
+    // Bitcoin Core's GetNextWorkRequired
+    fn next_bits(&self, parent: &Entry, header: &BlockHeader) -> u32 {
+        let params = &self.params;
+        let interval = params.difficulty_adjustment_interval() as u32;
+        if !(parent.height + 1).is_multiple_of(interval) {
+            if !params.pow_allow_min_difficulty_blocks {
+                return parent.header.bits;
+            }
+            // A block more than twenty minutes after its parent may have the
+            // easiest target
+            if header.time as u64 > parent.header.time as u64 + 2 * params.pow_target_spacing {
+                return CompactTarget::from_target(params.pow_limit).0;
+            }
+            return parent.normal_bits;
+        }
+        let mut first = parent;
+        for _ in 0..interval - 1 {
+            first = &self.entries[&first.header.prev_blockhash];
+        }
+        calculate_next_work_required(&first.header, &parent.header, params).0
+    }
+
+    fn median_time_past(&self, parent: &Entry) -> u32 {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut entry = Some(parent);
+        while let Some(e) = entry.filter(|_| times.len() < MEDIAN_TIME_SPAN) {
+            times.push(e.header.time);
+            entry = self.entries.get(&e.header.prev_blockhash);
+        }
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    // Makes the branch ending at `hash` the best chain
+    fn set_tip(&mut self, mut hash: BlockHash, height: u32) {
+        let mut height = height as usize;
+        self.best.truncate(height + 1);
+        self.best.resize(height + 1, BlockHash::all_zeros());
+        // Walk back until the branch meets the old best chain
+        while self.best[height] != hash {
+            self.best[height] = hash;
+            hash = self.entries[&hash].header.prev_blockhash;
+            height -= 1;
+        }
+    }
+
+    // The best chain's headers from the genesis block, 80 bytes each. A
+    // saved chain serves as a checkpoint: loading it resumes sync from its
+    // tip instead of the genesis block.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for hash in &self.best {
+            w.write_all(&self.entries[hash].header.serialize())?;
+        }
+        Ok(())
+    }
+
+    // Headers saved by write_to, validated as they are added. Returns how
+    // many were new; a partial header at the end, as an interrupted save
+    // leaves, is ignored.
+    pub fn read_from<R: Read>(&mut self, r: &mut R) -> Result<usize, BitcoinError> {
+        let mut added = 0;
+        let mut buf = [0; HEADER_SIZE];
+        loop {
+            match r.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let (header, _) = BlockHeader::parse(&buf)?;
+            added += self.accept_headers(&[header])?;
+        }
+        Ok(added)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BitcoinError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<usize, BitcoinError> {
+        self.read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
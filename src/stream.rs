@@ -0,0 +1,285 @@
+// Consensus encoding to any io::Write and decoding from any io::Read, for
+// data too large to hold in memory at once (block files, network streams)
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    Amount, BitcoinError, Block, BlockHeader, CompactSize, LegacyTransaction, LockTime, OutPoint,
+    ParseError, Sequence, TxInput, TxOutput, Txid, Witness,
+};
+
+// Counts the bytes written so serialize_to can report them
+pub(crate) struct Encoder<'a, W: Write> {
+    writer: &'a mut W,
+    written: usize,
+}
+
+impl<'a, W: Write> Encoder<'a, W> {
+    pub(crate) fn new(writer: &'a mut W) -> Self {
+        Encoder { writer, written: 0 }
+    }
+
+    pub(crate) fn written(&self) -> usize {
+        self.written
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.written += bytes.len();
+        Ok(())
+    }
+
+    fn write_compact_size(&mut self, n: usize) -> io::Result<()> {
+        self.write_bytes(&CompactSize(n as u64).encode())
+    }
+
+    pub(crate) fn outpoint(&mut self, outpoint: &OutPoint) -> io::Result<()> {
+        self.write_bytes(outpoint.txid.as_bytes())?;
+        self.write_bytes(&outpoint.vout.to_le_bytes())
+    }
+
+    pub(crate) fn input(&mut self, input: &TxInput) -> io::Result<()> {
+        self.outpoint(&input.previous_output)?;
+        self.write_compact_size(input.script_sig.len())?;
+        self.write_bytes(&input.script_sig)?;
+        self.write_bytes(&input.sequence.to_consensus_u32().to_le_bytes())
+    }
+
+    pub(crate) fn output(&mut self, output: &TxOutput) -> io::Result<()> {
+        self.write_bytes(&output.value.to_sat().to_le_bytes())?;
+        self.write_compact_size(output.script_pubkey.len())?;
+        self.write_bytes(&output.script_pubkey)
+    }
+
+    pub(crate) fn witness(&mut self, witness: &Witness) -> io::Result<()> {
+        self.write_compact_size(witness.items.len())?;
+        for item in &witness.items {
+            self.write_compact_size(item.len())?;
+            self.write_bytes(item)?;
+        }
+        Ok(())
+    }
+
+    // Same layout as LegacyTransaction::serialize
+    pub(crate) fn transaction(&mut self, tx: &LegacyTransaction) -> io::Result<()> {
+        let segwit = tx.has_witness();
+        self.write_bytes(&tx.version.to_le_bytes())?;
+        if segwit {
+            self.write_bytes(&[0x00, 0x01])?; // marker and flag
+        }
+        self.write_compact_size(tx.inputs.len())?;
+        for input in &tx.inputs {
+            self.input(input)?;
+        }
+        self.write_compact_size(tx.outputs.len())?;
+        for output in &tx.outputs {
+            self.output(output)?;
+        }
+        if segwit {
+            for input in &tx.inputs {
+                self.witness(&input.witness)?;
+            }
+        }
+        self.write_bytes(&tx.lock_time.to_consensus_u32().to_le_bytes())
+    }
+
+    pub(crate) fn header(&mut self, header: &BlockHeader) -> io::Result<()> {
+        self.write_bytes(&header.serialize())
+    }
+
+    pub(crate) fn block(&mut self, block: &Block) -> io::Result<()> {
+        self.header(&block.header)?;
+        self.write_compact_size(block.txdata.len())?;
+        for tx in &block.txdata {
+            self.transaction(tx)?;
+        }
+        Ok(())
+    }
+}
+
+// Counts the bytes consumed so errors carry the same offsets as the slice
+// parsers
+struct Decoder<'a, R: Read> {
+    reader: &'a mut R,
+    offset: usize,
+}
+
+impl<'a, R: Read> Decoder<'a, R> {
+    fn new(reader: &'a mut R) -> Self {
+        Decoder { reader, offset: 0 }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], BitcoinError> {
+        let mut bytes = [0u8; N];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => {
+                self.offset += N;
+                Ok(bytes)
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(BitcoinError::Parse(ParseError::UnexpectedEof {
+                    needed: N,
+                    offset: self.offset,
+                }))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // `len` comes from untrusted input, so the buffer grows as data actually
+    // arrives rather than being allocated up front
+    fn read_bytes(&mut self, len: u64) -> Result<Vec<u8>, BitcoinError> {
+        let mut bytes = Vec::new();
+        let read = (&mut *self.reader).take(len).read_to_end(&mut bytes)?;
+        if (read as u64) < len {
+            return Err(BitcoinError::Parse(ParseError::UnexpectedEof {
+                needed: usize::try_from(len).unwrap_or(usize::MAX),
+                offset: self.offset,
+            }));
+        }
+        self.offset += read;
+        Ok(bytes)
+    }
+
+    // Non-minimal encodings are rejected, as in CompactSize::decode
+    fn read_compact_size(&mut self) -> Result<u64, BitcoinError> {
+        let start = self.offset;
+        let [prefix] = self.read_array()?;
+        let (value, min) = match prefix {
+            0xFD => (u16::from_le_bytes(self.read_array()?) as u64, 0xFD),
+            0xFE => (u32::from_le_bytes(self.read_array()?) as u64, 0x1_0000),
+            0xFF => (u64::from_le_bytes(self.read_array()?), 0x1_0000_0000),
+            _ => return Ok(prefix as u64),
+        };
+        if value < min {
+            return Err(BitcoinError::Parse(ParseError::InvalidField {
+                field: "compact size",
+                offset: start,
+            }));
+        }
+        Ok(value)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, BitcoinError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    // Read as one 36-byte field, as OutPoint::parse does
+    fn outpoint(&mut self) -> Result<OutPoint, BitcoinError> {
+        let bytes: [u8; 36] = self.read_array()?;
+        let (txid, vout) = bytes.split_at(32);
+        Ok(OutPoint::new(
+            Txid::from_byte_array(txid.try_into().unwrap()),
+            u32::from_le_bytes(vout.try_into().unwrap()),
+        ))
+    }
+
+    fn input(&mut self) -> Result<TxInput, BitcoinError> {
+        let previous_output = self.outpoint()?;
+        let script_len = self.read_compact_size()?;
+        Ok(TxInput {
+            previous_output,
+            script_sig: self.read_bytes(script_len)?,
+            sequence: Sequence::from_consensus(self.read_u32()?),
+            witness: Witness::new(),
+        })
+    }
+
+    fn output(&mut self) -> Result<TxOutput, BitcoinError> {
+        let value = Amount::from_sat(u64::from_le_bytes(self.read_array()?));
+        let script_len = self.read_compact_size()?;
+        Ok(TxOutput {
+            value,
+            script_pubkey: self.read_bytes(script_len)?,
+        })
+    }
+
+    fn witness(&mut self) -> Result<Witness, BitcoinError> {
+        let count = self.read_compact_size()?;
+        let mut items = Vec::new();
+        for _ in 0..count {
+            let len = self.read_compact_size()?;
+            items.push(self.read_bytes(len)?);
+        }
+        Ok(Witness { items })
+    }
+
+    fn transaction(&mut self) -> Result<LegacyTransaction, BitcoinError> {
+        let version = i32::from_le_bytes(self.read_array()?);
+        // The input count, or the segwit marker; see LegacyTransaction::parse
+        let first = self.read_compact_size()?;
+        let mut segwit = false;
+        let (mut inputs, mut outputs) = (Vec::new(), Vec::new());
+        if first == 0 {
+            let [flag] = self.read_array()?;
+            match flag {
+                // No inputs, and this was the (empty) output count
+                0x00 => {}
+                0x01 => segwit = true,
+                _ => {
+                    return Err(BitcoinError::Parse(ParseError::InvalidField {
+                        field: "segwit flag",
+                        offset: self.offset - 1,
+                    }))
+                }
+            }
+        }
+        if first != 0 || segwit {
+            let inputs_count = if segwit {
+                self.read_compact_size()?
+            } else {
+                first
+            };
+            for _ in 0..inputs_count {
+                inputs.push(self.input()?);
+            }
+            let outputs_count = self.read_compact_size()?;
+            for _ in 0..outputs_count {
+                outputs.push(self.output()?);
+            }
+        }
+
+        if segwit {
+            for input in inputs.iter_mut() {
+                input.witness = self.witness()?;
+            }
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(BitcoinError::Parse(ParseError::Message(
+                    "Superfluous witness record".to_string(),
+                )));
+            }
+        }
+
+        Ok(LegacyTransaction {
+            version,
+            inputs,
+            outputs,
+            lock_time: LockTime::from_consensus(self.read_u32()?),
+        })
+    }
+}
+
+impl LegacyTransaction {
+    // Reads exactly one transaction, leaving the reader just after it
+    pub fn consensus_decode<R: Read>(r: &mut R) -> Result<Self, BitcoinError> {
+        Decoder::new(r).transaction()
+    }
+}
+
+impl TxInput {
+    pub fn consensus_decode<R: Read>(r: &mut R) -> Result<Self, BitcoinError> {
+        Decoder::new(r).input()
+    }
+}
+
+impl TxOutput {
+    pub fn consensus_decode<R: Read>(r: &mut R) -> Result<Self, BitcoinError> {
+        Decoder::new(r).output()
+    }
+}
+
+impl OutPoint {
+    pub fn consensus_decode<R: Read>(r: &mut R) -> Result<Self, BitcoinError> {
+        Decoder::new(r).outpoint()
+    }
+}
@@ -0,0 +1,175 @@
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::elliptic_curve::PrimeField;
+use k256::{ProjectivePoint, Scalar};
+
+use crate::hashes::tagged_hash;
+use crate::{script, BitcoinError, CompactSize, CryptoError, PrivateKey};
+
+// Leaf version for BIP342 tapscript
+pub const TAPSCRIPT_LEAF_VERSION: u8 = 0xC0;
+
+pub fn tap_leaf_hash(script: &[u8], leaf_version: u8) -> [u8; 32] {
+    let len = CompactSize(script.len() as u64).encode();
+    tagged_hash("TapLeaf", &[&[leaf_version], &len, script]).to_byte_array()
+}
+
+// Children are sorted so the branch hash doesn't depend on their order
+pub fn tap_branch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    if a <= b {
+        tagged_hash("TapBranch", &[a, b]).to_byte_array()
+    } else {
+        tagged_hash("TapBranch", &[b, a]).to_byte_array()
+    }
+}
+
+// Script tree committed to by a Taproot output
+#[derive(Debug, Clone, PartialEq)]
+pub enum TapTree {
+    Leaf { script: Vec<u8>, leaf_version: u8 },
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    pub fn leaf(script: Vec<u8>) -> Self {
+        TapTree::Leaf {
+            script,
+            leaf_version: TAPSCRIPT_LEAF_VERSION,
+        }
+    }
+
+    pub fn branch(left: TapTree, right: TapTree) -> Self {
+        TapTree::Branch(Box::new(left), Box::new(right))
+    }
+
+    // Returns the node hash and, for every leaf below it, the sibling hashes
+    // needed to climb back up to this node
+    fn collect(&self, leaves: &mut Vec<TapLeafInfo>) -> [u8; 32] {
+        match self {
+            TapTree::Leaf {
+                script,
+                leaf_version,
+            } => {
+                leaves.push(TapLeafInfo {
+                    script: script.clone(),
+                    leaf_version: *leaf_version,
+                    merkle_branch: Vec::new(),
+                });
+                tap_leaf_hash(script, *leaf_version)
+            }
+            TapTree::Branch(left, right) => {
+                let start = leaves.len();
+                let left_hash = left.collect(leaves);
+                let middle = leaves.len();
+                let right_hash = right.collect(leaves);
+                for leaf in &mut leaves[start..middle] {
+                    leaf.merkle_branch.push(right_hash);
+                }
+                for leaf in &mut leaves[middle..] {
+                    leaf.merkle_branch.push(left_hash);
+                }
+                tap_branch_hash(&left_hash, &right_hash)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapLeafInfo {
+    pub script: Vec<u8>,
+    pub leaf_version: u8,
+    pub merkle_branch: Vec<[u8; 32]>,
+}
+
+// Everything needed to pay to and spend from a Taproot output
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaprootSpendInfo {
+    pub internal_key: [u8; 32],
+    pub merkle_root: Option<[u8; 32]>,
+    pub output_key: [u8; 32],
+    pub output_key_parity: bool, // true if the tweaked key has odd y
+    pub leaves: Vec<TapLeafInfo>,
+}
+
+impl TaprootSpendInfo {
+    // Key-path only output (BIP86 style, commits to no scripts)
+    pub fn new_key_spend(internal_key: [u8; 32]) -> Result<Self, BitcoinError> {
+        Self::new(internal_key, None)
+    }
+
+    pub fn new(internal_key: [u8; 32], tree: Option<TapTree>) -> Result<Self, BitcoinError> {
+        let mut leaves = Vec::new();
+        let merkle_root = tree.as_ref().map(|tree| tree.collect(&mut leaves));
+        let (output_key, output_key_parity) = tweak_public_key(&internal_key, merkle_root)?;
+        Ok(TaprootSpendInfo {
+            internal_key,
+            merkle_root,
+            output_key,
+            output_key_parity,
+            leaves,
+        })
+    }
+
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        script::p2tr(&self.output_key)
+    }
+
+    // Control block for spending the given leaf via the script path
+    pub fn control_block(&self, script: &[u8], leaf_version: u8) -> Option<Vec<u8>> {
+        let leaf = self
+            .leaves
+            .iter()
+            .find(|leaf| leaf.script == script && leaf.leaf_version == leaf_version)?;
+        let mut v = Vec::with_capacity(33 + 32 * leaf.merkle_branch.len());
+        v.push(leaf.leaf_version | self.output_key_parity as u8);
+        v.extend(&self.internal_key);
+        for hash in &leaf.merkle_branch {
+            v.extend(hash);
+        }
+        Some(v)
+    }
+}
+
+// Q = P + hash_TapTweak(P || merkle_root) * G, returning x(Q) and its y parity
+pub fn tweak_public_key(
+    internal_key: &[u8; 32],
+    merkle_root: Option<[u8; 32]>,
+) -> Result<([u8; 32], bool), BitcoinError> {
+    let internal = k256::schnorr::VerifyingKey::from_bytes(internal_key)
+        .map_err(|_| BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+    let tweak = match merkle_root {
+        Some(root) => tagged_hash("TapTweak", &[internal_key, &root]),
+        None => tagged_hash("TapTweak", &[internal_key]),
+    };
+    let tweak = Option::<Scalar>::from(Scalar::from_repr(tweak.to_byte_array().into()))
+        .ok_or(BitcoinError::Crypto(CryptoError::InvalidPublicKey))?;
+    let point = ProjectivePoint::from(*internal.as_affine()) + ProjectivePoint::GENERATOR * tweak;
+    let encoded = point.to_affine().to_encoded_point(true);
+    let bytes = encoded.as_bytes();
+    if bytes.len() != 33 {
+        // point at infinity
+        return Err(BitcoinError::Crypto(CryptoError::InvalidPublicKey));
+    }
+    let mut output_key = [0u8; 32];
+    output_key.copy_from_slice(&bytes[1..]);
+    Ok((output_key, bytes[0] == 0x03))
+}
+
+// Private key for the tweaked output key, used to sign key-path spends. The
+// internal key is negated first if its public key has odd y.
+pub fn tweak_private_key(
+    key: &PrivateKey,
+    merkle_root: Option<[u8; 32]>,
+) -> Result<PrivateKey, BitcoinError> {
+    let (internal_key, odd) = key.x_only_public_key();
+    let internal_key = internal_key.serialize();
+    let tweak = match merkle_root {
+        Some(root) => tagged_hash("TapTweak", &[&internal_key, &root]),
+        None => tagged_hash("TapTweak", &[&internal_key]),
+    };
+    let tweak = Option::<Scalar>::from(Scalar::from_repr(tweak.to_byte_array().into()))
+        .ok_or(BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+    let secret = Option::<Scalar>::from(Scalar::from_repr(key.to_bytes().into()))
+        .ok_or(BitcoinError::Crypto(CryptoError::InvalidPrivateKey))?;
+    let secret = if odd { -secret } else { secret };
+    PrivateKey::from_slice(&(secret + tweak).to_repr())
+}
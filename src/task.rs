@@ -0,0 +1,203 @@
+// Futures for the network clients' async variants. The blocking calls run
+// on threads of their own, which wake the awaiting task as each finishes:
+// an AsyncClient gives every call a thread, for the HTTP clients, and a
+// Worker runs the calls on a client owning a connection one at a time. The
+// futures work under any executor (tokio, async-std, smol) without this
+// crate depending on one, and the decoding stays the sync paths'.
+
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+#[derive(Debug)]
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+// Resolves to the closure's result; a panic in the closure resumes in the
+// task awaiting it
+#[derive(Debug)]
+pub struct Task<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+// The other end of a Task, for whichever thread produces its result
+struct Completer<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+fn task<T>() -> (Task<T>, Completer<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let completer = Completer {
+        shared: Arc::clone(&shared),
+    };
+    (Task { shared }, completer)
+}
+
+impl<T> Completer<T> {
+    fn complete(self, result: thread::Result<T>) {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+
+    // Whether the Task was dropped, so nothing will read the result
+    fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) == 1
+    }
+}
+
+// Runs f on a thread of its own
+pub fn spawn_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Task<T> {
+    let (task, completer) = task();
+    thread::spawn(move || completer.complete(panic::catch_unwind(AssertUnwindSafe(f))));
+    task
+}
+
+impl<T> Future for Task<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// A blocking client's calls as futures, for async services. Each call runs
+// on a thread of its own, so calls made together proceed side by side;
+// clones share the client. For clients keeping no state between calls.
+pub struct AsyncClient<C> {
+    client: Arc<C>,
+}
+
+impl<C: Send + Sync + 'static> AsyncClient<C> {
+    pub fn new(client: C) -> Self {
+        AsyncClient {
+            client: Arc::new(client),
+        }
+    }
+
+    // Any of the blocking client's calls
+    pub fn run<T: Send + 'static>(&self, f: impl FnOnce(&C) -> T + Send + 'static) -> Task<T> {
+        let client = Arc::clone(&self.client);
+        spawn_blocking(move || f(&client))
+    }
+}
+
+impl<C> Clone for AsyncClient<C> {
+    fn clone(&self) -> Self {
+        AsyncClient {
+            client: Arc::clone(&self.client),
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for AsyncClient<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncClient")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+type Job<C> = Box<dyn FnOnce(&mut C) + Send>;
+
+// A thread owning a client, running the calls queued to it one at a time
+// in the order they were queued. The thread exits once every clone of the
+// Worker is dropped and the queue is empty.
+pub struct Worker<C> {
+    jobs: Sender<Job<C>>,
+}
+
+impl<C: Send + 'static> Worker<C> {
+    pub fn new(mut client: C) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job<C>>();
+        thread::spawn(move || {
+            for job in queue {
+                job(&mut client);
+            }
+        });
+        Worker { jobs }
+    }
+
+    // Queues f behind the calls already queued
+    pub fn run<T: Send + 'static>(&self, f: impl FnOnce(&mut C) -> T + Send + 'static) -> Task<T> {
+        let (task, completer) = task();
+        self.queue(Box::new(move |client| {
+            completer.complete(panic::catch_unwind(AssertUnwindSafe(|| f(client))))
+        }));
+        task
+    }
+
+    // Queues f, and while it returns None queues it again behind the calls
+    // queued since, so a wait made of short polls doesn't hold them up.
+    // Stops once the task is dropped.
+    pub fn run_until<T: Send + 'static>(
+        &self,
+        f: impl FnMut(&mut C) -> Option<T> + Send + 'static,
+    ) -> Task<T> {
+        let (task, completer) = task();
+        self.queue(poll_job(self.jobs.clone(), f, completer));
+        task
+    }
+
+    fn queue(&self, job: Job<C>) {
+        // The thread only exits once every sender is gone, and jobs catch
+        // their panics, so it is still receiving
+        self.jobs
+            .send(job)
+            .unwrap_or_else(|_| unreachable!("worker thread exited"));
+    }
+}
+
+fn poll_job<C: 'static, T: Send + 'static>(
+    jobs: Sender<Job<C>>,
+    mut f: impl FnMut(&mut C) -> Option<T> + Send + 'static,
+    completer: Completer<T>,
+) -> Job<C> {
+    Box::new(move |client| {
+        if completer.is_abandoned() {
+            return;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| f(client))) {
+            Ok(Some(value)) => completer.complete(Ok(value)),
+            Err(payload) => completer.complete(Err(payload)),
+            Ok(None) => {
+                // The worker holds the receiver while running this job
+                let _ = jobs.send(poll_job(jobs.clone(), f, completer));
+            }
+        }
+    })
+}
+
+impl<C> Clone for Worker<C> {
+    fn clone(&self) -> Self {
+        Worker {
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for Worker<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Worker").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,266 @@
+// TLS client sessions through the system's OpenSSL (1.1 or 3), for the
+// https Esplora servers. The server's certificate is checked against the
+// trusted roots and the host name; a session runs over a TcpStream the
+// caller connected, and is left without a close_notify when dropped, as
+// the HTTP client's requests end with the server closing.
+
+use std::ffi::{c_char, c_int, c_long, c_ulong, c_void, CStr, CString};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::path::Path;
+use std::ptr;
+
+// Opaque OpenSSL types
+#[repr(C)]
+struct SslMethod {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct SslCtx {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Ssl {
+    _private: [u8; 0],
+}
+
+const SSL_VERIFY_PEER: c_int = 0x01;
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const SSL_CTRL_SET_MIN_PROTO_VERSION: c_int = 123;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+const TLS1_2_VERSION: c_long = 0x0303;
+// Servers often close without a close_notify; the HTTP framing says
+// whether the response is complete
+const SSL_OP_IGNORE_UNEXPECTED_EOF: u64 = 1 << 7;
+const X509_V_OK: c_long = 0;
+
+#[link(name = "ssl")]
+extern "C" {
+    fn TLS_client_method() -> *const SslMethod;
+    fn SSL_CTX_new(method: *const SslMethod) -> *mut SslCtx;
+    fn SSL_CTX_free(ctx: *mut SslCtx);
+    fn SSL_CTX_ctrl(ctx: *mut SslCtx, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_CTX_set_options(ctx: *mut SslCtx, options: u64) -> u64;
+    fn SSL_CTX_set_verify(ctx: *mut SslCtx, mode: c_int, callback: *const c_void);
+    fn SSL_CTX_set_default_verify_paths(ctx: *mut SslCtx) -> c_int;
+    fn SSL_CTX_load_verify_locations(
+        ctx: *mut SslCtx,
+        file: *const c_char,
+        path: *const c_char,
+    ) -> c_int;
+    fn SSL_new(ctx: *mut SslCtx) -> *mut Ssl;
+    fn SSL_free(ssl: *mut Ssl);
+    fn SSL_ctrl(ssl: *mut Ssl, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_set1_host(ssl: *mut Ssl, host: *const c_char) -> c_int;
+    fn SSL_set_fd(ssl: *mut Ssl, fd: c_int) -> c_int;
+    fn SSL_connect(ssl: *mut Ssl) -> c_int;
+    fn SSL_read(ssl: *mut Ssl, buf: *mut c_void, len: c_int) -> c_int;
+    fn SSL_write(ssl: *mut Ssl, buf: *const c_void, len: c_int) -> c_int;
+    fn SSL_get_error(ssl: *const Ssl, ret: c_int) -> c_int;
+    fn SSL_get_verify_result(ssl: *const Ssl) -> c_long;
+}
+
+#[link(name = "crypto")]
+extern "C" {
+    fn ERR_get_error() -> c_ulong;
+    fn ERR_clear_error();
+    fn ERR_error_string_n(code: c_ulong, buf: *mut c_char, len: usize);
+    fn X509_verify_cert_error_string(code: c_long) -> *const c_char;
+}
+
+pub(crate) struct TlsStream {
+    ssl: *mut Ssl,
+    // The socket the session reads and writes; closed after the session
+    // is freed
+    stream: TcpStream,
+}
+
+impl TlsStream {
+    // Runs the handshake for `host`. `ca_file` is a PEM file of
+    // certificates trusted besides the system's.
+    pub(crate) fn connect(
+        stream: TcpStream,
+        host: &str,
+        ca_file: Option<&Path>,
+    ) -> io::Result<Self> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host_name = CString::new(host).map_err(|_| tls_error("invalid host name"))?;
+        // SAFETY: each pointer passed is either checked non-null or a
+        // NUL-terminated string outliving the call; the session frees
+        // itself on drop
+        unsafe {
+            ERR_clear_error();
+            let ctx = SSL_CTX_new(TLS_client_method());
+            if ctx.is_null() {
+                return Err(openssl_error("setting up TLS"));
+            }
+            let ssl = new_session(ctx, ca_file);
+            // The session holds a reference of its own
+            SSL_CTX_free(ctx);
+            let tls = TlsStream { ssl: ssl?, stream };
+            // Server Name Indication is for names only
+            if host.parse::<IpAddr>().is_err()
+                && SSL_ctrl(
+                    tls.ssl,
+                    SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                    TLSEXT_NAMETYPE_HOST_NAME,
+                    host_name.as_ptr() as *mut c_void,
+                ) != 1
+            {
+                return Err(openssl_error("setting the server name"));
+            }
+            if SSL_set1_host(tls.ssl, host_name.as_ptr()) != 1
+                || SSL_set_fd(tls.ssl, raw_socket(&tls.stream)) != 1
+            {
+                return Err(openssl_error("setting up TLS"));
+            }
+            if SSL_connect(tls.ssl) != 1 {
+                let verify = SSL_get_verify_result(tls.ssl);
+                if verify != X509_V_OK {
+                    let reason = CStr::from_ptr(X509_verify_cert_error_string(verify));
+                    return Err(tls_error(&format!(
+                        "certificate of {host} not trusted: {}",
+                        reason.to_string_lossy()
+                    )));
+                }
+                return Err(openssl_error(&format!("TLS handshake with {host}")));
+            }
+            Ok(tls)
+        }
+    }
+
+    // The error for an SSL_read or SSL_write that returned `ret`, or None
+    // for the end of the stream
+    fn error(&self, ret: c_int) -> Option<io::Error> {
+        // SAFETY: ssl is valid for as long as self
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            SSL_ERROR_ZERO_RETURN => None,
+            // What the socket's timeouts give
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => Some(io::ErrorKind::WouldBlock.into()),
+            SSL_ERROR_SYSCALL => {
+                let error = io::Error::last_os_error();
+                // Without an OS error, the peer closed the socket (OpenSSL
+                // 1.1; 3 ignores it with SSL_OP_IGNORE_UNEXPECTED_EOF)
+                error
+                    .raw_os_error()
+                    .is_some_and(|code| code != 0)
+                    .then_some(error)
+            }
+            _ => Some(openssl_error("TLS")),
+        }
+    }
+}
+
+// SAFETY: ctx must be valid
+unsafe fn new_session(ctx: *mut SslCtx, ca_file: Option<&Path>) -> io::Result<*mut Ssl> {
+    SSL_CTX_set_options(ctx, SSL_OP_IGNORE_UNEXPECTED_EOF);
+    SSL_CTX_set_verify(ctx, SSL_VERIFY_PEER, ptr::null());
+    if SSL_CTX_ctrl(
+        ctx,
+        SSL_CTRL_SET_MIN_PROTO_VERSION,
+        TLS1_2_VERSION,
+        ptr::null_mut(),
+    ) != 1
+        || SSL_CTX_set_default_verify_paths(ctx) != 1
+    {
+        return Err(openssl_error("setting up TLS"));
+    }
+    if let Some(path) = ca_file {
+        let file = path
+            .to_str()
+            .and_then(|path| CString::new(path).ok())
+            .ok_or_else(|| tls_error(&format!("invalid CA file path {path:?}")))?;
+        if SSL_CTX_load_verify_locations(ctx, file.as_ptr(), ptr::null()) != 1 {
+            return Err(openssl_error(&format!("loading {}", path.display())));
+        }
+    }
+    let ssl = SSL_new(ctx);
+    if ssl.is_null() {
+        return Err(openssl_error("setting up TLS"));
+    }
+    Ok(ssl)
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        // SAFETY: buf is writable for len bytes
+        let ret = unsafe {
+            ERR_clear_error();
+            SSL_read(self.ssl, buf.as_mut_ptr() as *mut c_void, len)
+        };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        self.error(ret).map_or(Ok(0), Err)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        // SAFETY: buf is readable for len bytes
+        let ret = unsafe {
+            ERR_clear_error();
+            SSL_write(self.ssl, buf.as_ptr() as *const c_void, len)
+        };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        Err(self
+            .error(ret)
+            .unwrap_or_else(|| io::ErrorKind::WriteZero.into()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // SAFETY: ssl came from SSL_new and is freed only here
+        unsafe { SSL_free(self.ssl) }
+    }
+}
+
+#[cfg(unix)]
+fn raw_socket(stream: &TcpStream) -> c_int {
+    use std::os::unix::io::AsRawFd;
+    stream.as_raw_fd()
+}
+
+// OpenSSL takes the SOCKET handle as an int
+#[cfg(windows)]
+fn raw_socket(stream: &TcpStream) -> c_int {
+    use std::os::windows::io::AsRawSocket;
+    stream.as_raw_socket() as c_int
+}
+
+fn tls_error(message: &str) -> io::Error {
+    io::Error::other(message.to_string())
+}
+
+// `context` with the reason OpenSSL queued, if any
+fn openssl_error(context: &str) -> io::Error {
+    // SAFETY: buf outlives the call, which NUL-terminates what it writes
+    let code = unsafe { ERR_get_error() };
+    if code == 0 {
+        return tls_error(&format!("{context} failed"));
+    }
+    let mut buf = [0 as c_char; 256];
+    let reason = unsafe {
+        ERR_error_string_n(code, buf.as_mut_ptr(), buf.len());
+        CStr::from_ptr(buf.as_ptr())
+    };
+    tls_error(&format!("{context} failed: {}", reason.to_string_lossy()))
+}
@@ -0,0 +1,195 @@
+// The set of unspent transaction outputs, as kept while replaying blocks
+
+use std::collections::hash_map::{self, HashMap};
+use std::collections::HashSet;
+
+use crate::script::is_unspendable;
+use crate::{
+    Amount, AmountError, BitcoinError, Block, LegacyTransaction, OutPoint, TxOutput,
+    ValidationError,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UtxoSet {
+    utxos: HashMap<OutPoint, TxOutput>,
+    // Kept up to date so balance() is free
+    balance: Amount,
+}
+
+// What a transaction spent, for undoing it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxUndo {
+    pub spent: Vec<(OutPoint, TxOutput)>,
+    // Unspent outputs its own outputs replaced, when its txid repeats an
+    // earlier one's (the duplicate coinbases of blocks 91842 and 91880)
+    pub replaced: Vec<(OutPoint, TxOutput)>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.utxos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.utxos.is_empty()
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&TxOutput> {
+        self.utxos.get(outpoint)
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.utxos.contains_key(outpoint)
+    }
+
+    // Total value of the unspent outputs
+    pub fn balance(&self) -> Amount {
+        self.balance
+    }
+
+    // In no particular order
+    pub fn iter(&self) -> hash_map::Iter<'_, OutPoint, TxOutput> {
+        self.utxos.iter()
+    }
+
+    // Spends the transaction's inputs and adds its outputs. Coinbase inputs
+    // spend nothing, and outputs that can never be spent are left out, as
+    // in Bitcoin Core. On error the set is unchanged.
+    pub fn apply_transaction(&mut self, tx: &LegacyTransaction) -> Result<TxUndo, BitcoinError> {
+        let spends: Vec<&OutPoint> = if tx.is_coinbase() {
+            Vec::new()
+        } else {
+            tx.inputs
+                .iter()
+                .map(|input| &input.previous_output)
+                .collect()
+        };
+        let mut seen = HashSet::new();
+        for &outpoint in &spends {
+            if !self.contains(outpoint) || !seen.insert(outpoint) {
+                return Err(BitcoinError::Validation(ValidationError::UnknownOutput(
+                    outpoint.clone(),
+                )));
+            }
+        }
+        let spent_value = Amount::checked_sum(spends.iter().map(|o| self.value_of(o)))
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+        let created = created_outputs(tx);
+        let created_value = Amount::checked_sum(created.iter().map(|(_, o)| o.value))
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+        let replaced_value =
+            Amount::checked_sum(created.iter().map(|(outpoint, _)| self.value_of(outpoint)))
+                .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+        let balance = self
+            .balance
+            .checked_sub(spent_value)
+            .and_then(|balance| balance.checked_sub(replaced_value))
+            .and_then(|balance| balance.checked_add(created_value))
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+
+        let spent = spends
+            .into_iter()
+            .map(|outpoint| {
+                let output = self.utxos.remove(outpoint).expect("checked above");
+                (outpoint.clone(), output)
+            })
+            .collect();
+        let replaced = created
+            .into_iter()
+            .filter_map(|(outpoint, output)| {
+                let old = self.utxos.insert(outpoint.clone(), output)?;
+                Some((outpoint, old))
+            })
+            .collect();
+        self.balance = balance;
+        Ok(TxUndo { spent, replaced })
+    }
+
+    // Reverses apply_transaction, given what it returned. Transactions must
+    // be undone in the opposite order they were applied. On error the set
+    // is unchanged.
+    pub fn undo_transaction(
+        &mut self,
+        tx: &LegacyTransaction,
+        undo: TxUndo,
+    ) -> Result<(), BitcoinError> {
+        let created = created_outputs(tx);
+        for (outpoint, _) in &created {
+            if !self.contains(outpoint) {
+                return Err(BitcoinError::Validation(ValidationError::UnknownOutput(
+                    outpoint.clone(),
+                )));
+            }
+        }
+        let created_value = Amount::checked_sum(created.iter().map(|(_, o)| o.value))
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+        let restored_value = Amount::checked_sum(
+            undo.spent
+                .iter()
+                .chain(&undo.replaced)
+                .map(|(_, o)| o.value),
+        )
+        .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+        let balance = self
+            .balance
+            .checked_sub(created_value)
+            .and_then(|balance| balance.checked_add(restored_value))
+            .ok_or(BitcoinError::Amount(AmountError::InvalidAmount))?;
+
+        for (outpoint, _) in created {
+            self.utxos.remove(&outpoint);
+        }
+        self.utxos.extend(undo.spent);
+        self.utxos.extend(undo.replaced);
+        self.balance = balance;
+        Ok(())
+    }
+
+    // Applies the block's transactions in order, returning their undo data
+    // in the same order. On error the transactions before the failing one
+    // are undone, leaving the set unchanged.
+    pub fn apply_block(&mut self, block: &Block) -> Result<Vec<TxUndo>, BitcoinError> {
+        let mut undos = Vec::with_capacity(block.txdata.len());
+        for tx in &block.txdata {
+            match self.apply_transaction(tx) {
+                Ok(undo) => undos.push(undo),
+                Err(error) => {
+                    let applied = &block.txdata[..undos.len()];
+                    for (tx, undo) in applied.iter().zip(undos).rev() {
+                        self.undo_transaction(tx, undo)
+                            .expect("undoing transactions just applied");
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Ok(undos)
+    }
+
+    fn value_of(&self, outpoint: &OutPoint) -> Amount {
+        self.utxos.get(outpoint).map_or(Amount::ZERO, |o| o.value)
+    }
+}
+
+impl<'a> IntoIterator for &'a UtxoSet {
+    type Item = (&'a OutPoint, &'a TxOutput);
+    type IntoIter = hash_map::Iter<'a, OutPoint, TxOutput>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+fn created_outputs(tx: &LegacyTransaction) -> Vec<(OutPoint, TxOutput)> {
+    let txid = tx.txid();
+    tx.outputs
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| !is_unspendable(&output.script_pubkey))
+        .map(|(vout, output)| (OutPoint::new(txid, vout as u32), output.clone()))
+        .collect()
+}
@@ -0,0 +1,180 @@
+// Full script validation of a transaction's inputs against the outputs they
+// spend, with signatures checked against the transaction itself
+
+use crate::locktime::LOCK_TIME_THRESHOLD;
+use crate::script::{witness_program, Interpreter, ScriptFlags, SignatureChecker};
+use crate::sighash::{SighashCache, TapScriptSpend, TAPROOT_SIGHASH_DEFAULT};
+use crate::sign::{verify_ecdsa, verify_schnorr};
+use crate::{
+    BitcoinError, LegacyTransaction, OutPoint, PublicKey, Sequence, TxOutput, ValidationError,
+    XOnlyPublicKey,
+};
+
+// The signature and lock time context of one input, shared by all the
+// scripts it runs
+struct TransactionChecker<'a> {
+    tx: &'a LegacyTransaction,
+    input_index: usize,
+    prevout: &'a TxOutput,
+    // Without prevouts when one is missing, so Taproot signatures fail
+    cache: &'a SighashCache<'a>,
+}
+
+impl SignatureChecker for TransactionChecker<'_> {
+    fn check_signature(&self, signature: &[u8], pubkey: &[u8], script_code: &[u8]) -> bool {
+        let (Some((&hash_type, der)), Ok(pubkey)) =
+            (signature.split_last(), PublicKey::from_slice(pubkey))
+        else {
+            return false;
+        };
+        self.cache
+            .legacy_signature_hash(self.input_index, script_code, hash_type as u32)
+            .is_ok_and(|digest| verify_ecdsa(&digest, der, &pubkey))
+    }
+
+    fn check_witness_v0_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+    ) -> bool {
+        let (Some((&hash_type, der)), Ok(pubkey)) =
+            (signature.split_last(), PublicKey::from_slice(pubkey))
+        else {
+            return false;
+        };
+        self.cache
+            .segwit_v0_signature_hash(
+                self.input_index,
+                script_code,
+                self.prevout.value,
+                hash_type as u32,
+            )
+            .is_ok_and(|digest| verify_ecdsa(&digest, der, &pubkey))
+    }
+
+    fn check_schnorr_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8; 32],
+        annex: Option<&[u8]>,
+        script_path: Option<&TapScriptSpend>,
+    ) -> bool {
+        let Ok(pubkey) = XOnlyPublicKey::from_slice(pubkey) else {
+            return false;
+        };
+        let (signature, hash_type) = match signature.split_at_checked(64) {
+            Some((signature, [hash_type])) => (signature, *hash_type),
+            _ => (signature, TAPROOT_SIGHASH_DEFAULT),
+        };
+        self.cache
+            .taproot_signature_hash(self.input_index, annex, script_path, hash_type)
+            .is_ok_and(|digest| verify_schnorr(&digest, signature, &pubkey))
+    }
+
+    // BIP65: the transaction's lock time must be of the same kind and at
+    // least as late, and enforced
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time.to_consensus_u32() as i64;
+        let threshold = LOCK_TIME_THRESHOLD as i64;
+        (lock_time < threshold) == (tx_lock_time < threshold)
+            && lock_time <= tx_lock_time
+            && self.tx.inputs[self.input_index].sequence != Sequence::MAX
+    }
+
+    // BIP112: likewise for the input's relative lock time
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.tx.inputs[self.input_index].sequence.to_consensus_u32() as i64;
+        // Core compares the version unsigned, so negative ones qualify
+        if (self.tx.version as u32) < 2
+            || tx_sequence & Sequence::LOCK_TIME_DISABLE_FLAG as i64 != 0
+        {
+            return false;
+        }
+        let type_flag = Sequence::LOCK_TIME_TYPE_FLAG as i64;
+        let mask = type_flag | Sequence::LOCK_TIME_MASK as i64;
+        let (sequence, tx_sequence) = (sequence & mask, tx_sequence & mask);
+        (sequence < type_flag) == (tx_sequence < type_flag) && sequence <= tx_sequence
+    }
+}
+
+impl LegacyTransaction {
+    // Runs every input's scripts under the consensus rules against the
+    // output it spends, as looked up by `prevout`. Returns the failed
+    // inputs' indexes with their errors; an input whose prevout is missing
+    // fails with UnknownOutput, as does a Taproot input missing another's.
+    pub fn verify(
+        &self,
+        prevout: impl Fn(&OutPoint) -> Option<TxOutput>,
+    ) -> Result<(), Vec<(usize, BitcoinError)>> {
+        self.verify_with_flags(ScriptFlags::CONSENSUS, prevout)
+    }
+
+    // verify with other rules, such as ScriptFlags::STANDARD for the
+    // mempool's
+    pub fn verify_with_flags(
+        &self,
+        flags: ScriptFlags,
+        prevout: impl Fn(&OutPoint) -> Option<TxOutput>,
+    ) -> Result<(), Vec<(usize, BitcoinError)>> {
+        let prevouts: Vec<Option<TxOutput>> = self
+            .inputs
+            .iter()
+            .map(|input| prevout(&input.previous_output))
+            .collect();
+        let missing = self
+            .inputs
+            .iter()
+            .zip(&prevouts)
+            .find(|(_, prevout)| prevout.is_none())
+            .map(|(input, _)| input.previous_output.clone());
+        let ordered: Vec<TxOutput> = prevouts.iter().flatten().cloned().collect();
+        let cache = match missing {
+            Some(_) => SighashCache::new(self),
+            None => SighashCache::new(self).prevouts(&ordered),
+        };
+
+        let mut errors = Vec::new();
+        for (index, (input, prevout)) in self.inputs.iter().zip(&prevouts).enumerate() {
+            let Some(prevout) = prevout else {
+                errors.push((
+                    index,
+                    BitcoinError::Validation(ValidationError::UnknownOutput(
+                        input.previous_output.clone(),
+                    )),
+                ));
+                continue;
+            };
+            let is_taproot = matches!(
+                witness_program(&prevout.script_pubkey),
+                Some((1, program)) if program.len() == 32
+            );
+            if let (true, Some(outpoint)) = (is_taproot, &missing) {
+                errors.push((
+                    index,
+                    BitcoinError::Validation(ValidationError::UnknownOutput(outpoint.clone())),
+                ));
+                continue;
+            }
+            let checker = TransactionChecker {
+                tx: self,
+                input_index: index,
+                prevout,
+                cache: &cache,
+            };
+            let result = Interpreter::new(flags, &checker).verify_with_witness(
+                &input.script_sig,
+                &prevout.script_pubkey,
+                &input.witness,
+            );
+            if let Err(e) = result {
+                errors.push((index, e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Watch-only wallet: follows the outputs paying to a set of scripts as
+// transactions and blocks are scanned
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::{
+    Address, Amount, BitcoinError, Block, Descriptor, LegacyTransaction, OutPoint, TxOutput, Txid,
+};
+
+// An output paying to a watched script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletUtxo {
+    pub outpoint: OutPoint,
+    pub output: TxOutput,
+    // Height of the block that confirmed it; None while unconfirmed
+    pub height: Option<u32>,
+}
+
+// A transaction in an address's history, as a chain source reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub txid: Txid,
+    // Of the confirming block; None while in the mempool
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Wallet {
+    scripts: HashSet<Vec<u8>>,
+    utxos: HashMap<OutPoint, WalletUtxo>,
+    // Height of the latest block scanned
+    tip_height: Option<u32>,
+}
+
+impl Wallet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watch_script(&mut self, script_pubkey: Vec<u8>) {
+        self.scripts.insert(script_pubkey);
+    }
+
+    pub fn watch_address(&mut self, address: &Address) {
+        self.watch_script(address.script_pubkey().into_bytes());
+    }
+
+    // The descriptor's script at every index in `range`, such as 0..1000
+    // for a ranged descriptor's first thousand. Nothing is watched if one
+    // fails to derive.
+    pub fn watch_descriptor(
+        &mut self,
+        descriptor: &Descriptor,
+        range: Range<u32>,
+    ) -> Result<(), BitcoinError> {
+        let scripts = range
+            .map(|index| descriptor.script_pubkey(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        for script in scripts {
+            self.watch_script(script.into_bytes());
+        }
+        Ok(())
+    }
+
+    pub fn is_watched(&self, script_pubkey: &[u8]) -> bool {
+        self.scripts.contains(script_pubkey)
+    }
+
+    pub fn watched_scripts(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn tip_height(&self) -> Option<u32> {
+        self.tip_height
+    }
+
+    // For chain sources that report the tip without its blocks
+    pub fn set_tip_height(&mut self, height: u32) {
+        self.tip_height = Some(height);
+    }
+
+    // Records outputs paying to watched scripts and forgets wallet outputs
+    // the transaction spends. `height` is that of the confirming block, or
+    // None for a transaction still in the mempool. Returns whether the
+    // transaction touched the wallet.
+    pub fn scan_transaction(&mut self, tx: &LegacyTransaction, height: Option<u32>) -> bool {
+        let mut relevant = false;
+        if !tx.is_coinbase() {
+            for input in &tx.inputs {
+                relevant |= self.utxos.remove(&input.previous_output).is_some();
+            }
+        }
+        let txid = tx.txid();
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            if !self.is_watched(&output.script_pubkey) {
+                continue;
+            }
+            relevant = true;
+            let outpoint = OutPoint::new(txid, vout as u32);
+            let utxo = self
+                .utxos
+                .entry(outpoint.clone())
+                .or_insert_with(|| WalletUtxo {
+                    outpoint,
+                    output: output.clone(),
+                    height: None,
+                });
+            // Seen in the mempool first, confirmed now
+            utxo.height = utxo.height.or(height);
+        }
+        relevant
+    }
+
+    // Scans every transaction of the block at `height`, which becomes the
+    // tip if it is the highest seen
+    pub fn scan_block(&mut self, block: &Block, height: u32) -> usize {
+        let relevant = block
+            .txdata
+            .iter()
+            .filter(|tx| self.scan_transaction(tx, Some(height)))
+            .count();
+        self.tip_height = self.tip_height.max(Some(height));
+        relevant
+    }
+
+    // Blocks confirming the output, counting its own; 0 while unconfirmed
+    pub fn confirmations(&self, utxo: &WalletUtxo) -> u32 {
+        match (utxo.height, self.tip_height) {
+            (Some(height), Some(tip)) if tip >= height => tip - height + 1,
+            _ => 0,
+        }
+    }
+
+    // Value of all unspent outputs, unconfirmed ones included
+    pub fn balance(&self) -> Amount {
+        self.balance_with(0)
+    }
+
+    // Value of the unspent outputs with at least `min_confirmations`
+    pub fn balance_with(&self, min_confirmations: u32) -> Amount {
+        let values = self
+            .utxos
+            .values()
+            .filter(|utxo| self.confirmations(utxo) >= min_confirmations)
+            .map(|utxo| utxo.output.value);
+        // Scanned outputs can't be trusted to respect the 21M cap
+        Amount::checked_sum(values).unwrap_or(Amount::MAX_MONEY)
+    }
+
+    // Oldest first, then unconfirmed outputs, then by outpoint
+    pub fn list_unspent(&self) -> Vec<&WalletUtxo> {
+        let mut utxos: Vec<&WalletUtxo> = self.utxos.values().collect();
+        utxos.sort_by_key(|utxo| {
+            (
+                utxo.height.unwrap_or(u32::MAX),
+                utxo.outpoint.txid,
+                utxo.outpoint.vout,
+            )
+        });
+        utxos
+    }
+}
@@ -0,0 +1,210 @@
+// Listener for the notifications bitcoind publishes with -zmqpubrawtx,
+// -zmqpubrawblock and -zmqpubhashblock. Speaks just enough of ZMTP 3.0 to
+// subscribe to a PUB socket: the NULL mechanism over TCP, which is all
+// bitcoind offers.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::p2p::message::MAX_MESSAGE_SIZE;
+use crate::{read_array, BitcoinError, Block, BlockHash, LegacyTransaction, ParseError};
+
+// Frame flag bits
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+const GREETING_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    RawTx,
+    RawBlock,
+    HashBlock,
+}
+
+impl Topic {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Topic::RawTx => "rawtx",
+            Topic::RawBlock => "rawblock",
+            Topic::HashBlock => "hashblock",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    // A transaction entering the mempool or confirmed in a block
+    RawTx(LegacyTransaction),
+    RawBlock(Block),
+    HashBlock(BlockHash),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub notification: Notification,
+    // Counts up per topic, so a gap means a message was dropped
+    pub sequence: u32,
+}
+
+#[derive(Debug)]
+pub struct Subscriber {
+    stream: TcpStream,
+}
+
+impl Subscriber {
+    // `address` is the host and port of a -zmqpub option, e.g.
+    // 127.0.0.1:28332 for -zmqpubrawtx=tcp://127.0.0.1:28332
+    pub fn connect(address: impl ToSocketAddrs, topics: &[Topic]) -> Result<Self, BitcoinError> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(&greeting())?;
+        let mut peer = [0; GREETING_SIZE];
+        stream.read_exact(&mut peer)?;
+        // Signature, then the version and the mechanism name
+        if peer[0] != 0xFF || peer[9] != 0x7F || peer[10] < 3 || &peer[12..17] != b"NULL\0" {
+            return Err(invalid("unsupported greeting"));
+        }
+        write_frame(&mut stream, COMMAND, &ready_command())?;
+        let (flags, ready) = read_frame(&mut stream)?;
+        if flags & COMMAND == 0 || !ready.starts_with(b"\x05READY") {
+            return Err(invalid("expected READY"));
+        }
+        // A message of 1 and the topic; ZMTP 3.0 has no SUBSCRIBE command
+        for topic in topics {
+            let mut subscribe = vec![1];
+            subscribe.extend(topic.as_str().as_bytes());
+            write_frame(&mut stream, 0, &subscribe)?;
+        }
+        Ok(Subscriber { stream })
+    }
+
+    // Waits for the next notification
+    pub fn recv(&mut self) -> Result<Message, BitcoinError> {
+        loop {
+            let parts = self.read_message()?;
+            let [topic, body, sequence] = parts.as_slice() else {
+                return Err(invalid("expected topic, body and sequence"));
+            };
+            let sequence = u32::from_le_bytes(read_array(sequence, 0)?);
+            let notification = match topic.as_slice() {
+                b"rawtx" => Notification::RawTx(LegacyTransaction::parse_exact(body)?),
+                b"rawblock" => {
+                    let (block, used) = Block::parse(body)?;
+                    if used != body.len() {
+                        return Err(BitcoinError::Parse(ParseError::TrailingBytes {
+                            remaining: body.len() - used,
+                        }));
+                    }
+                    Notification::RawBlock(block)
+                }
+                // In display order, reversed from the internal one
+                b"hashblock" => {
+                    let mut hash: [u8; 32] = read_array(body, 0)?;
+                    hash.reverse();
+                    Notification::HashBlock(BlockHash::from_byte_array(hash))
+                }
+                // Other topics sharing a subscribed prefix
+                _ => continue,
+            };
+            return Ok(Message {
+                notification,
+                sequence,
+            });
+        }
+    }
+
+    // The parts of the next message, skipping commands such as heartbeats
+    fn read_message(&mut self) -> Result<Vec<Vec<u8>>, BitcoinError> {
+        let mut parts = Vec::new();
+        loop {
+            let (flags, body) = read_frame(&mut self.stream)?;
+            if flags & COMMAND != 0 {
+                continue;
+            }
+            parts.push(body);
+            if flags & MORE == 0 {
+                return Ok(parts);
+            }
+        }
+    }
+}
+
+// Connects and delivers notifications over a channel from a new thread,
+// until an error, which is sent last, or until the receiver is dropped
+pub fn listen(
+    address: impl ToSocketAddrs,
+    topics: &[Topic],
+) -> Result<Receiver<Result<Message, BitcoinError>>, BitcoinError> {
+    let mut subscriber = Subscriber::connect(address, topics)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let message = subscriber.recv();
+        let failed = message.is_err();
+        if sender.send(message).is_err() || failed {
+            break;
+        }
+    });
+    Ok(receiver)
+}
+
+// Version 3.0 with the NULL mechanism, as a client
+fn greeting() -> [u8; GREETING_SIZE] {
+    let mut greeting = [0; GREETING_SIZE];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+fn ready_command() -> Vec<u8> {
+    let mut command = b"\x05READY".to_vec();
+    command.push(b"Socket-Type".len() as u8);
+    command.extend(b"Socket-Type");
+    command.extend((b"SUB".len() as u32).to_be_bytes());
+    command.extend(b"SUB");
+    command
+}
+
+fn write_frame(stream: &mut TcpStream, flags: u8, body: &[u8]) -> Result<(), BitcoinError> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    match u8::try_from(body.len()) {
+        Ok(size) => frame.extend([flags, size]),
+        Err(_) => {
+            frame.push(flags | LONG);
+            frame.extend((body.len() as u64).to_be_bytes());
+        }
+    }
+    frame.extend(body);
+    stream.write_all(&frame)?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), BitcoinError> {
+    let mut flags = [0];
+    stream.read_exact(&mut flags)?;
+    let [flags] = flags;
+    let size = if flags & LONG != 0 {
+        let mut size = [0; 8];
+        stream.read_exact(&mut size)?;
+        u64::from_be_bytes(size)
+    } else {
+        let mut size = [0];
+        stream.read_exact(&mut size)?;
+        size[0] as u64
+    };
+    // No notification is bigger than a block
+    if size > MAX_MESSAGE_SIZE as u64 {
+        return Err(invalid("frame too large"));
+    }
+    let mut body = vec![0; size as usize];
+    stream.read_exact(&mut body)?;
+    Ok((flags, body))
+}
+
+fn invalid(reason: &str) -> BitcoinError {
+    BitcoinError::Parse(ParseError::Message(format!("Invalid ZMQ stream: {reason}")))
+}